@@ -0,0 +1,261 @@
+// Biuletyny bezpieczeństwa z Arch Linux Security Tracker
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::process::Command;
+use anyhow::{Context, Result};
+use clap::Parser;
+use ostree_ext::ostree;
+use serde::{Deserialize, Serialize};
+
+use crate::layered_packages::{booted_state, load_sysroot};
+use crate::pacman_manager::read_packages_from_commit;
+
+/// Wszystkie grupy AVG (ten sam feed, z którego korzysta arch-audit)
+const SECURITY_TRACKER_URL: &str = "https://security.archlinux.org/all.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// Grupa AVG: jedna podatność (lub kilka CVE) dotycząca zestawu pakietów
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdvisoryGroup {
+    pub name: String,
+    pub packages: Vec<String>,
+    pub status: String,
+    pub severity: Severity,
+    pub affected: String,
+    pub fixed: Option<String>,
+    #[serde(default)]
+    pub issues: Vec<String>,
+}
+
+/// Zainstalowany pakiet objęty grupą AVG
+#[derive(Debug)]
+pub struct Vulnerability<'a> {
+    pub package: String,
+    pub version: String,
+    pub group: &'a AdvisoryGroup,
+}
+
+/// Podatność usuwana przez aktualizację pakietu (`upgrade --check`)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecurityFix {
+    pub advisory: String,
+    pub package: String,
+    pub version: String,
+    /// Wersja pakietu po aktualizacji
+    pub new_version: String,
+    pub severity: Severity,
+    pub cves: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct AdvisoriesOpts {
+    /// Only show advisories of at least this severity (low, medium, high, critical)
+    #[clap(long, default_value = "low")]
+    pub min_severity: Severity,
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "unknown" => Ok(Severity::Unknown),
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => anyhow::bail!("Unknown severity: {}", s),
+        }
+    }
+}
+
+impl AdvisoryGroup {
+    /// Czy wersja jest starsza od poprawionej; bez poprawki podatne są wszystkie wersje.
+    /// `affected` to tylko wersja, w której zgłoszono problem, a nie dolna granica.
+    pub fn affects(&self, version: &str) -> bool {
+        if self.status == "Not affected" {
+            return false;
+        }
+        match &self.fixed {
+            Some(fixed) => alpm::vercmp(version.as_bytes(), fixed.as_bytes()) == Ordering::Less,
+            None => true,
+        }
+    }
+
+    pub fn cves(&self) -> String {
+        if self.issues.is_empty() {
+            self.name.clone()
+        } else {
+            self.issues.join(", ")
+        }
+    }
+}
+
+/// Pobiera feed trackera przez curl (jak webhooki compose)
+pub fn fetch_advisories() -> Result<Vec<AdvisoryGroup>> {
+    // W trybie JSON stdout należy do dokumentu wyniku
+    if crate::output::json() {
+        eprintln!("Fetching security advisories...");
+    } else {
+        println!("Fetching security advisories...");
+    }
+    let output = crate::subprocess::output(
+        Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location"])
+            .args(crate::network::config().curl_args())
+            .arg(SECURITY_TRACKER_URL),
+    )?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Fetching {} failed: {}",
+            SECURITY_TRACKER_URL,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout).context("Parsing security tracker feed")
+}
+
+/// Grupy AVG dotyczące podanych pakietów (nazwa -> wersja)
+pub fn vulnerabilities<'a>(
+    groups: &'a [AdvisoryGroup],
+    packages: &BTreeMap<String, String>,
+) -> Vec<Vulnerability<'a>> {
+    let mut found = Vec::new();
+    for group in groups {
+        for name in &group.packages {
+            let Some(version) = packages.get(name) else {
+                continue;
+            };
+            if group.affects(version) {
+                found.push(Vulnerability {
+                    package: name.clone(),
+                    version: version.clone(),
+                    group,
+                });
+            }
+        }
+    }
+    found.sort_by(|a, b| b.group.severity.cmp(&a.group.severity).then(a.package.cmp(&b.package)));
+    found
+}
+
+/// Podatności w `current`, których nie ma już w wersjach z `pending`
+/// (pakiety, których `pending` nie zawiera, nie są liczone jako poprawione)
+pub fn fixed_by(
+    groups: &[AdvisoryGroup],
+    current: &BTreeMap<String, String>,
+    pending: &BTreeMap<String, String>,
+) -> Vec<SecurityFix> {
+    vulnerabilities(groups, current)
+        .into_iter()
+        .filter_map(|v| {
+            let new_version = pending.get(&v.package)?;
+            (!v.group.affects(new_version)).then(|| SecurityFix {
+                advisory: v.group.name.clone(),
+                package: v.package,
+                version: v.version,
+                new_version: new_version.clone(),
+                severity: v.group.severity,
+                cves: v.group.cves(),
+            })
+        })
+        .collect()
+}
+
+/// Wersje pakietów po czekających aktualizacjach: z zestage'owanego deploymentu, a bez
+/// niego z najnowszej bazy na zdalnym. `None`, gdy nie da się tego ustalić (baza nie
+/// publikuje listy pakietów albo sprawdzenie się nie udało).
+async fn pending_packages(
+    sysroot: &ostree::Sysroot,
+    booted: &ostree::Deployment,
+    installed: &BTreeMap<String, String>,
+) -> Result<Option<BTreeMap<String, String>>> {
+    let (pending, _) = sysroot.query_deployments_for(Some(booted.osname().as_str()));
+    if let Some(pending) = pending {
+        return Ok(Some(read_packages_from_commit(&sysroot.repo(), &pending.csum())?));
+    }
+    match crate::upgrade::check_for_update().await {
+        Ok(check) if !check.available() => Ok(Some(installed.clone())),
+        Ok(check) => Ok(check.packages.map(|base| {
+            // Warstwy zostają w wersjach z uruchomionego systemu, baza przechodzi na nowe
+            let mut packages = installed.clone();
+            packages.extend(base);
+            packages
+        })),
+        Err(e) => {
+            eprintln!("Warning: checking for updates failed: {:#}", e);
+            Ok(None)
+        }
+    }
+}
+
+/// Bramka `compose --fail-on-vuln`: krytyczne podatności bez poprawki przerywają build
+pub fn check_compose_packages(packages: &BTreeMap<String, String>) -> Result<()> {
+    let groups = fetch_advisories()?;
+    let unfixed: Vec<_> = vulnerabilities(&groups, packages)
+        .into_iter()
+        .filter(|v| v.group.severity == Severity::Critical && v.group.fixed.is_none())
+        .collect();
+
+    if unfixed.is_empty() {
+        return Ok(());
+    }
+    for v in &unfixed {
+        eprintln!("{} {}-{}: {} (no fix available)", v.group.name, v.package, v.version, v.group.cves());
+    }
+    anyhow::bail!("Image contains {} package(s) with unfixed critical advisories", unfixed.len())
+}
+
+/// `ex advisories` — podatne pakiety w uruchomionym deploymencie i dostępne poprawki
+pub async fn show_advisories(opts: AdvisoriesOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, _) = booted_state(&sysroot)?;
+    let packages = read_packages_from_commit(&sysroot.repo(), &booted.csum())?;
+
+    let groups = fetch_advisories()?;
+    let found: Vec<_> = vulnerabilities(&groups, &packages)
+        .into_iter()
+        .filter(|v| v.group.severity >= opts.min_severity)
+        .collect();
+
+    if found.is_empty() {
+        println!("No known vulnerabilities in installed packages");
+        return Ok(());
+    }
+    for v in &found {
+        let fix = match &v.group.fixed {
+            Some(fixed) => format!("update to {} available", fixed),
+            None => "no fix available".to_string(),
+        };
+        println!(
+            "{:<9} {} {}-{}: {} ({})",
+            format!("{:?}", v.group.severity),
+            v.group.name,
+            v.package,
+            v.version,
+            v.group.cves(),
+            fix
+        );
+    }
+    match pending_packages(&sysroot, &booted, &packages).await? {
+        Some(pending) => {
+            let fixed = fixed_by(&groups, &packages, &pending)
+                .iter()
+                .filter(|f| f.severity >= opts.min_severity)
+                .count();
+            println!("{} vulnerable package(s), {} fixed by pending updates", found.len(), fixed);
+        }
+        None => println!("{} vulnerable package(s); pending updates are unknown", found.len()),
+    }
+    Ok(())
+}
@@ -0,0 +1,180 @@
+// Skąd pochodzi każdy plik obrazu (`ex attribution`)
+//
+// Używa tego samego mapowania plików na pakiety i komponenty co podział na warstwy
+// przy enkapsulacji, więc raport pokazuje dokładnie to, co widzi chunking. Pliki,
+// których nie tłumaczy żaden pakiet, komponent ani nic, co dopisuje pacman-ostree,
+// to zwykle wynik skryptów compose albo hooków pakietów.
+
+use std::collections::BTreeMap;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use ostree_ext::{gio, ostree};
+use serde::Serialize;
+use tempfile::TempDir;
+
+use crate::banner::BannerTarget;
+use crate::layered_packages::{booted_state, load_sysroot, LayeredState};
+use crate::pacman_manager::PACMAN_DB_DIR;
+use crate::sysusers::{SYSUSERS_CONF, SYSUSERS_DIR};
+use crate::var_tmpfiles::{FACTORY_DIR, TMPFILES_CONF, TMPFILES_DIR};
+
+#[derive(Parser, Debug)]
+pub struct AttributionOpts {
+    /// Commit, ref or `ostree-…` image reference (default: the booted deployment)
+    pub commit: Option<String>,
+    /// Read from this repository instead of the system one (e.g. compose --ostree-repo)
+    #[clap(long)]
+    pub repo: Option<Utf8PathBuf>,
+    /// Only list files that no package or component accounts for
+    #[clap(long)]
+    pub unpackaged: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "source", rename_all = "kebab-case")]
+pub enum Source {
+    /// Plik z bazy `files` pakietu (kilku, jeśli pakiety się nakładają)
+    Package { owners: Vec<String> },
+    /// Plik oznaczony `user.component`
+    Component { name: String },
+    /// Plik dodany przez `ex config add`
+    ConfigFile,
+    /// Plik zapisany przez sam pacman-ostree
+    Generated { by: &'static str },
+    Unpackaged,
+}
+
+impl Source {
+    fn kind(&self) -> &'static str {
+        match self {
+            Source::Package { .. } => "package",
+            Source::Component { .. } => "component",
+            Source::ConfigFile => "config-file",
+            Source::Generated { .. } => "generated",
+            Source::Unpackaged => "unpackaged",
+        }
+    }
+
+    /// Opis do komunikatów, np. "component: kernel"
+    pub fn describe(&self) -> String {
+        match self.detail() {
+            detail if detail.is_empty() => self.kind().to_string(),
+            detail => format!("{}: {}", self.kind(), detail),
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Source::Package { owners } => owners.join(" "),
+            Source::Component { name } => name.clone(),
+            Source::Generated { by } => by.to_string(),
+            Source::ConfigFile | Source::Unpackaged => String::new(),
+        }
+    }
+}
+
+/// Pliki, które pacman-ostree dopisuje do drzewa przy compose lub rebuildzie
+fn generated_by(path: &Utf8Path) -> Option<&'static str> {
+    let rel = path.strip_prefix("/").ok()?;
+    if rel.starts_with(PACMAN_DB_DIR) {
+        return Some("pacman database");
+    }
+    if rel == Utf8Path::new(TMPFILES_DIR).join(TMPFILES_CONF) || rel.starts_with(FACTORY_DIR) {
+        return Some("var tmpfiles");
+    }
+    if rel == Utf8Path::new(SYSUSERS_DIR).join(SYSUSERS_CONF) {
+        return Some("sysusers");
+    }
+    if [BannerTarget::Issue, BannerTarget::Motd].iter().any(|t| rel == t.path()) {
+        return Some("banner");
+    }
+    if rel.starts_with("usr/lib/modules") && rel.file_name() == Some("initramfs.img") {
+        return Some("initramfs");
+    }
+    None
+}
+
+/// Baza pacmana z commita, rozpakowana, bo mapowanie czyta pliki `files` z dysku
+pub fn checkout_package_db(repo: &ostree::Repo, commit: &str, dest: &Utf8Path) -> Result<()> {
+    let mut opts = ostree::RepoCheckoutAtOptions::default();
+    opts.mode = ostree::RepoCheckoutMode::User;
+    opts.subpath = Some(Utf8Path::new("/").join(PACMAN_DB_DIR).join("local").into());
+    repo.checkout_at(Some(&opts), libc::AT_FDCWD, dest, commit, gio::Cancellable::NONE)
+        .with_context(|| format!("Checking out the package database of {}", commit))
+}
+
+pub fn attribute_commit(repo: &ostree::Repo, commit: &str) -> Result<BTreeMap<Utf8PathBuf, Source>> {
+    let tmp = TempDir::new()?;
+    let db_path = Utf8PathBuf::try_from(tmp.path().join("local"))?;
+    checkout_package_db(repo, commit, &db_path)?;
+    let owners = crate::container::path_owners(repo, commit, &db_path)?;
+
+    let config_files = match LayeredState::from_commit(repo, commit)? {
+        Some(state) => state
+            .config_files
+            .iter()
+            .map(|dest| crate::layered_files::target_path(dest).map(|p| Utf8Path::new("/").join(p)))
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    let mut report = BTreeMap::new();
+    for path in owners.paths {
+        // Kolejność jak przy nakładaniu: plik administratora zastępuje plik pakietu,
+        // a komponent ma pierwszeństwo przed pakietem także przy podziale na warstwy
+        let source = if config_files.contains(&path) {
+            Source::ConfigFile
+        } else if let Some(name) = owners.components.get(&path).and_then(|c| c.first()) {
+            Source::Component { name: name.clone() }
+        } else if let Some(pkgs) = owners.packages.get(&path) {
+            Source::Package { owners: pkgs.clone() }
+        } else if let Some(by) = generated_by(&path) {
+            Source::Generated { by }
+        } else {
+            Source::Unpackaged
+        };
+        report.insert(path, source);
+    }
+    Ok(report)
+}
+
+pub fn attribution(opts: AttributionOpts) -> Result<()> {
+    let (repo, commit) = match &opts.repo {
+        Some(path) => {
+            let repo = ostree_ext::cli::parse_repo(path)?;
+            let commit = opts
+                .commit
+                .as_deref()
+                .ok_or_else(|| anyhow!("A commit is required with --repo"))?;
+            let commit = crate::deploy::resolve_commit(&repo, commit)?;
+            (repo, commit)
+        }
+        None => {
+            let sysroot = load_sysroot()?;
+            let repo = sysroot.repo();
+            let commit = match opts.commit.as_deref() {
+                Some(c) => crate::deploy::resolve_commit(&repo, c)?,
+                None => booted_state(&sysroot)?.0.csum().to_string(),
+            };
+            (repo, commit)
+        }
+    };
+
+    let mut report = crate::output::progress(|| attribute_commit(&repo, &commit))?;
+    if opts.unpackaged {
+        report.retain(|_, source| matches!(source, Source::Unpackaged));
+    }
+    if crate::output::json() {
+        return crate::output::emit(&report);
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for (path, source) in &report {
+        println!("{:<11} {}  {}", source.kind(), path, source.detail());
+        *counts.entry(source.kind()).or_default() += 1;
+    }
+    let summary: Vec<String> = counts.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+    println!("{} file(s): {}", report.len(), summary.join(", "));
+    Ok(())
+}
@@ -0,0 +1,244 @@
+// Pakiety z AUR (`install --aur`) budowane w czystym chroocie devtools
+//
+// PKGBUILD jest klonowany z AUR i budowany przez `makechrootpkg -c`, czyli w świeżej
+// kopii chroota, więc build nie widzi systemu hosta. Pakiety chroota, które ma też baza,
+// są przed buildem ustawiane na wersje z bazy, żeby wynik linkował się z jej bibliotekami.
+// Gotowe pliki trafiają do cache i są nakładane jak lokalne pakiety; `LayeredState`
+// pamięta, które pochodzą z AUR i z którego commita PKGBUILD-a, żeby `upgrade` mógł je
+// przebudować na nowej bazie z tego samego, przejrzanego już PKGBUILD-a.
+
+use std::collections::BTreeMap;
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use ostree_ext::ostree;
+use tempfile::TempDir;
+
+use crate::layered_packages::{store_local_package, LayeredState, STATE_DIR};
+
+const AUR_URL: &str = "https://aur.archlinux.org";
+/// Zbudowane pakiety; kopie nakładanych plików są i tak w magazynie lokalnych pakietów
+const CACHE_DIR: &str = "/var/cache/pacman-ostree/aur";
+/// Użytkownik hosta, z którego UID makechrootpkg tworzy użytkownika budującego w chroocie
+const BUILD_USER: &str = "nobody";
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with(['-', '.'])
+        || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "@._+-".contains(c))
+    {
+        return Err(anyhow!("Invalid AUR package name: {}", name));
+    }
+    Ok(())
+}
+
+fn run(cmd: &mut Command, what: &str) -> Result<()> {
+    let status = crate::subprocess::status(cmd).with_context(|| format!("Running {}", what))?;
+    if !status.success() {
+        anyhow::bail!("{} failed with {}", what, status);
+    }
+    Ok(())
+}
+
+/// Chroot z base-devel; tworzony raz, przed każdym buildem odświeżane są tylko bazy sync
+fn ensure_chroot() -> Result<Utf8PathBuf> {
+    let dir = Utf8PathBuf::from(STATE_DIR).join("aur-chroot");
+    let root = dir.join("root");
+    if root.exists() {
+        run(
+            Command::new("arch-nspawn").arg(&root).args(["pacman", "-Sy", "--noconfirm"]),
+            "refreshing the AUR build chroot",
+        )?;
+    } else {
+        std::fs::create_dir_all(&dir)?;
+        println!("Creating AUR build chroot in {}...", dir);
+        run(Command::new("mkarchroot").arg(&root).arg("base-devel"), "mkarchroot")?;
+    }
+    Ok(dir)
+}
+
+/// Pakiety zainstalowane w chroocie: nazwa -> wersja
+fn chroot_packages(root: &Utf8Path) -> Result<BTreeMap<String, String>> {
+    let output = crate::subprocess::output(Command::new("arch-nspawn").arg(root).args(["pacman", "-Q"]))
+        .context("Running pacman -Q in the AUR build chroot")?;
+    if !output.status.success() {
+        anyhow::bail!("pacman -Q in the AUR build chroot failed with {}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .collect())
+}
+
+/// Ustawia pakiety chroota, które są też w bazie, na wersje z bazy (z cache albo z archiwum)
+fn sync_chroot_to_base(root: &Utf8Path, base_packages: &BTreeMap<String, String>) -> Result<()> {
+    let differing: Vec<(String, String)> = chroot_packages(root)?
+        .into_iter()
+        .filter_map(|(name, version)| {
+            let base_version = base_packages.get(&name)?;
+            (*base_version != version).then(|| (name, base_version.clone()))
+        })
+        .collect();
+    if differing.is_empty() {
+        return Ok(());
+    }
+    println!("Installing {} package(s) of the base in the AUR build chroot...", differing.len());
+    let tmp = TempDir::new()?;
+    let staging = root.join("var/cache/pacman-ostree-base");
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging)?;
+    let mut targets = Vec::new();
+    for (name, version) in &differing {
+        let Some(file) = crate::downgrade::fetch_version(name, version, &tmp)? else {
+            eprintln!("Warning: {} {} of the base is not in the cache or the archive; building against the chroot version", name, version);
+            continue;
+        };
+        let file_name = file.file_name().ok_or_else(|| anyhow!("Invalid package path {}", file))?;
+        std::fs::copy(&file, staging.join(file_name)).with_context(|| format!("Copying {}", file))?;
+        targets.push(format!("/var/cache/pacman-ostree-base/{}", file_name));
+    }
+    let result = if targets.is_empty() {
+        Ok(())
+    } else {
+        run(
+            Command::new("arch-nspawn")
+                .arg(root)
+                .args(["pacman", "-U", "--noconfirm", "--needed"])
+                .args(&targets),
+            "installing base packages in the AUR build chroot",
+        )
+    };
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+/// Pliki pakietów w katalogu PKGDEST jednego buildu
+fn built_files(pkgdest: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let mut files = Vec::new();
+    for entry in pkgdest.read_dir_utf8()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.contains(".pkg.tar") && !name.ends_with(".sig") {
+            files.push(entry.path().to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Commit, na którym stoi klon
+fn head_commit(src: &Utf8Path) -> Result<String> {
+    let output = crate::subprocess::output(Command::new("git").arg("-C").arg(src).args(["rev-parse", "HEAD"]))?;
+    if !output.status.success() {
+        anyhow::bail!("git rev-parse failed with {}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Klonuje i buduje pakiet AUR na pakietach bazy `base_commit`, z commita `pinned` albo
+/// z bieżącego HEAD. Zwraca ścieżki zbudowanych plików w cache (pakiet dzielony daje kilka)
+/// i commit PKGBUILD-a, z którego je zbudowano.
+pub fn build(repo: &ostree::Repo, base_commit: &str, pkgbase: &str, pinned: Option<&str>) -> Result<(Vec<Utf8PathBuf>, String)> {
+    validate_name(pkgbase)?;
+    crate::network::ensure_online("Building AUR packages")?;
+    let chroot = ensure_chroot()?;
+    let base_packages = crate::pacman_manager::read_packages_from_commit(repo, base_commit)?;
+    sync_chroot_to_base(&chroot.join("root"), &base_packages)?;
+    let tmp = TempDir::new()?;
+    let src = Utf8PathBuf::try_from(tmp.path().join(pkgbase))?;
+    let url = format!("{}/{}.git", AUR_URL, pkgbase);
+    crate::network::with_retries(&format!("Cloning {}", url), || {
+        let _ = std::fs::remove_dir_all(&src);
+        // Przypięty commit może być starszy niż HEAD, więc wtedy potrzebna jest cała historia
+        let mut clone = Command::new("git");
+        clone.arg("clone");
+        if pinned.is_none() {
+            clone.args(["--depth", "1"]);
+        }
+        run(clone.arg(&url).arg(&src), "git clone")
+    })?;
+    if let Some(commit) = pinned {
+        run(
+            Command::new("git").arg("-C").arg(&src).args(["checkout", "--quiet", "--detach", commit]),
+            &format!("checking out {} of {}", commit, pkgbase),
+        )?;
+    }
+    // AUR odpowiada pustym repozytorium dla nieistniejących pakietów
+    if !src.join("PKGBUILD").exists() {
+        anyhow::bail!("{} was not found in the AUR", pkgbase);
+    }
+    let commit = head_commit(&src)?;
+    // Katalog z PKGBUILD kopiowany jest do chroota jako użytkownik budujący
+    let chown = format!("{}:", BUILD_USER);
+    run(Command::new("chown").args(["-R", &chown]).arg(tmp.path()), "chown")?;
+
+    // Osobny PKGDEST dla każdego buildu: wynik to dokładnie to, co zbudował ten build
+    std::fs::create_dir_all(CACHE_DIR)?;
+    let pkgdest = tempfile::Builder::new().prefix(".build-").tempdir_in(CACHE_DIR)?;
+    let pkgdest_path = Utf8Path::from_path(pkgdest.path()).ok_or_else(|| anyhow!("Non-UTF-8 path {}", pkgdest.path().display()))?;
+    println!("Building {} from the AUR...", pkgbase);
+    run(
+        Command::new("makechrootpkg")
+            .args(["-c", "-U", BUILD_USER, "-r"])
+            .arg(&chroot)
+            .current_dir(&src)
+            .env("PKGDEST", pkgdest_path),
+        &format!("building {}", pkgbase),
+    )?;
+    let mut files = Vec::new();
+    for file in built_files(pkgdest_path)? {
+        let dest = Utf8Path::new(CACHE_DIR).join(file.file_name().unwrap_or_default());
+        std::fs::rename(&file, &dest).with_context(|| format!("Moving {} to {}", file, dest))?;
+        files.push(dest);
+    }
+    if files.is_empty() {
+        anyhow::bail!("Building {} produced no packages", pkgbase);
+    }
+    Ok((files, commit))
+}
+
+/// Buduje pakiet AUR (z commita `pinned` albo z HEAD) i zapisuje wynik w stanie jak lokalne
+/// pakiety. Zwraca nazwy zbudowanych pakietów i pliki magazynu do usunięcia po udanym deployu.
+pub fn build_into_state(
+    repo: &ostree::Repo,
+    state: &mut LayeredState,
+    pkgbase: &str,
+    pinned: Option<&str>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut names = Vec::new();
+    let mut stale = Vec::new();
+    let (files, commit) = build(repo, &state.base_commit, pkgbase, pinned)?;
+    for file in files {
+        let name = crate::pacman_manager::package_file_name(&file)?;
+        stale.extend(store_local_package(state, &name, &file)?);
+        names.push(name);
+    }
+    state.aur_packages.insert(pkgbase.to_string(), names.iter().cloned().collect());
+    state.aur_commits.insert(pkgbase.to_string(), commit);
+    Ok((names, stale))
+}
+
+/// Przebudowuje wszystkie pakiety AUR ze stanu (przy `upgrade` i `rebase`) z zapisanych commitów,
+/// a z `update` — z bieżącego HEAD w AUR. Nieudany build zostawia poprzednie pliki i jest
+/// tylko ostrzeżeniem — stary pakiet zwykle dalej działa.
+pub fn rebuild_all(repo: &ostree::Repo, state: &mut LayeredState, update: bool) -> Result<Vec<String>> {
+    // Bez sieci nie ma skąd wziąć PKGBUILD-ów — zostają poprzednie buildy
+    if crate::network::cache_only() {
+        return Ok(Vec::new());
+    }
+    let mut stale = Vec::new();
+    let bases: Vec<String> = state.aur_packages.keys().cloned().collect();
+    for pkgbase in bases {
+        let pinned = if update { None } else { state.aur_commits.get(&pkgbase).cloned() };
+        // Buildy sprzed zapisywania commitów: bez zgody nie bierzemy nieprzejrzanego HEAD
+        if !update && pinned.is_none() {
+            eprintln!("Warning: no recorded AUR commit for {}; keeping the previous build (use --update-aur to rebuild from the AUR)", pkgbase);
+            continue;
+        }
+        match build_into_state(repo, state, &pkgbase, pinned.as_deref()) {
+            Ok((_, files)) => stale.extend(files),
+            Err(e) => eprintln!("Warning: rebuilding AUR package {} failed, keeping the previous build: {:#}", pkgbase, e),
+        }
+    }
+    Ok(stale)
+}
@@ -0,0 +1,104 @@
+// Baner systemu (wersja obrazu, data buildu, ref, liczba warstw) w issue.d / motd.d
+
+use anyhow::{Context, Result};
+use cap_std::fs::Dir;
+use chrono::{DateTime, TimeZone, Utc};
+use ostree_ext::{glib, ostree};
+use serde::Deserialize;
+
+use crate::layered_packages::LayeredState;
+
+/// Klucz metadanych commita bazowego: gdzie renderować baner (`issue`/`motd`)
+pub const BANNER_META_KEY: &str = "pacman-ostree.banner";
+const BANNER_NAME: &str = "50-pacman-ostree";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BannerTarget {
+    /// /usr/lib/issue.d — pokazywany przez agetty przed logowaniem
+    Issue,
+    /// /usr/lib/motd.d — pokazywany przez pam_motd po zalogowaniu
+    Motd,
+}
+
+impl BannerTarget {
+    fn as_str(self) -> &'static str {
+        match self {
+            BannerTarget::Issue => "issue",
+            BannerTarget::Motd => "motd",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "issue" => Some(BannerTarget::Issue),
+            "motd" => Some(BannerTarget::Motd),
+            _ => None,
+        }
+    }
+
+    pub fn path(self) -> String {
+        match self {
+            BannerTarget::Issue => format!("usr/lib/issue.d/{}.issue", BANNER_NAME),
+            BannerTarget::Motd => format!("usr/lib/motd.d/{}", BANNER_NAME),
+        }
+    }
+}
+
+pub struct BannerInfo<'a> {
+    pub version: Option<&'a str>,
+    pub build_date: DateTime<Utc>,
+    pub base_ref: &'a str,
+    pub layered_packages: usize,
+}
+
+fn render(info: &BannerInfo) -> String {
+    let mut banner = format!(
+        "Image {} built {} from {}\n",
+        info.version.unwrap_or("(unversioned)"),
+        info.build_date.format("%Y-%m-%d %H:%M UTC"),
+        info.base_ref,
+    );
+    if info.layered_packages > 0 {
+        banner.push_str(&format!("Layered packages: {}\n", info.layered_packages));
+    }
+    banner
+}
+
+pub fn write_banner(rootfs: &Dir, target: BannerTarget, info: &BannerInfo) -> Result<()> {
+    let path = target.path();
+    if let Some(parent) = std::path::Path::new(&path).parent() {
+        rootfs.create_dir_all(parent)?;
+    }
+    rootfs
+        .write(&path, render(info))
+        .with_context(|| format!("Writing {}", path))?;
+    Ok(())
+}
+
+/// Zapis wyboru banera w metadanych commita, żeby rebuildy mogły go odświeżyć
+pub fn insert_banner_meta(commitmeta: &glib::VariantDict, target: BannerTarget) {
+    commitmeta.insert(BANNER_META_KEY, target.as_str());
+}
+
+/// Odświeża baner przy rebuildzie, jeśli baza go miała — z liczbą warstw ze stanu
+pub fn refresh_banner(repo: &ostree::Repo, state: &LayeredState, rootfs: &Dir) -> Result<()> {
+    let (commit_v, _) = repo.load_commit(&state.base_commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    let Some(target) = meta
+        .lookup::<String>(BANNER_META_KEY)?
+        .and_then(|t| BannerTarget::parse(&t))
+    else {
+        return Ok(());
+    };
+
+    let version = meta.lookup::<String>("version")?;
+    let timestamp = ostree::commit_get_timestamp(&commit_v) as i64;
+    let info = BannerInfo {
+        version: version.as_deref(),
+        build_date: Utc.timestamp_opt(timestamp, 0).single().unwrap_or_default(),
+        base_ref: &state.base_refspec,
+        layered_packages: state.layered_packages.len(),
+    };
+    write_banner(rootfs, target, &info)
+}
@@ -0,0 +1,217 @@
+// Czy commit da się uruchomić (`ex bootable` i sprawdzenie przed każdym deployem)
+//
+// ostree wdroży dowolny commit, także sam obraz kontenera bez jądra — wtedy wpis
+// w menu bootloadera nie wystartuje. Przed stage'owaniem sprawdzamy metadane
+// `ostree.bootable`, jądro z initramfs w /usr/lib/modules i kargs obrazu.
+
+use anyhow::{anyhow, Result};
+use camino::Utf8PathBuf;
+use cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+use ostree_ext::prelude::*;
+use serde::Serialize;
+
+use crate::layered_packages::{booted_state, commit_kargs, load_sysroot};
+
+/// Metadane ustawiane przez `ostree commit --bootable`
+pub const BOOTABLE_META_KEY: &str = "ostree.bootable";
+pub const LINUX_META_KEY: &str = "ostree.linux";
+const MODULES_DIR: &str = "usr/lib/modules";
+
+#[derive(Parser, Debug)]
+pub struct BootableOpts {
+    /// Commit, ref or `ostree-…` image reference (default: the booted deployment)
+    pub commit: Option<String>,
+    /// Read from this repository instead of the system one
+    #[clap(long)]
+    pub repo: Option<Utf8PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Kernel {
+    pub version: String,
+    pub initramfs: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Bootability {
+    pub commit: String,
+    /// `ostree.bootable` z metadanych; `None`, gdy commit go nie ma (starsze obrazy)
+    pub bootable_meta: Option<bool>,
+    pub kernels: Vec<Kernel>,
+    pub kargs: Vec<String>,
+    /// Powody, dla których commit nie wystartuje; pusto, gdy wszystko w porządku
+    pub problems: Vec<String>,
+}
+
+impl Bootability {
+    pub fn is_bootable(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Wersje jąder z `vmlinuz` w /usr/lib/modules rozpakowanego drzewa
+fn kernel_versions(rootfs: &Dir) -> Result<Vec<String>> {
+    let mut versions = Vec::new();
+    let Some(modules) = rootfs.open_dir_optional(MODULES_DIR)? else {
+        return Ok(versions);
+    };
+    for entry in modules.entries()? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() && modules.exists(format!("{}/vmlinuz", name)) {
+            versions.push(name);
+        }
+    }
+    versions.sort();
+    Ok(versions)
+}
+
+/// `ostree.bootable` i `ostree.linux` dla commita z drzewa `rootfs`, tak jak `ostree commit --bootable`
+pub fn insert_bootable_meta(commitmeta: &glib::VariantDict, rootfs: &Dir) -> Result<()> {
+    let versions = kernel_versions(rootfs)?;
+    commitmeta.insert(BOOTABLE_META_KEY, !versions.is_empty());
+    if let Some(version) = versions.last() {
+        commitmeta.insert(LINUX_META_KEY, version.as_str());
+    }
+    Ok(())
+}
+
+fn commit_kernels(repo: &ostree::Repo, commit: &str) -> Result<Vec<Kernel>> {
+    let cancellable = gio::Cancellable::NONE;
+    let (root, _) = repo.read_commit(commit, cancellable)?;
+    let modules = root.resolve_relative_path(MODULES_DIR);
+    let entries = match modules.enumerate_children(
+        "standard::name,standard::type",
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        cancellable,
+    ) {
+        Ok(e) => e,
+        Err(e) if e.matches(gio::IOErrorEnum::NotFound) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut kernels = Vec::new();
+    for info in entries {
+        let info = info?;
+        if info.file_type() != gio::FileType::Directory {
+            continue;
+        }
+        let dir = modules.child(info.name());
+        if !dir.child("vmlinuz").query_exists(cancellable) {
+            continue;
+        }
+        kernels.push(Kernel {
+            version: info.name().to_string_lossy().into_owned(),
+            initramfs: dir.child("initramfs.img").query_exists(cancellable),
+        });
+    }
+    kernels.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(kernels)
+}
+
+pub fn check(repo: &ostree::Repo, commit: &str) -> Result<Bootability> {
+    let (commit_v, _) = repo.load_commit(commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    let bootable_meta = meta.lookup::<bool>(BOOTABLE_META_KEY).ok().flatten();
+    let kernels = commit_kernels(repo, commit)?;
+
+    let mut problems = Vec::new();
+    if bootable_meta == Some(false) {
+        problems.push(format!("commit is marked {}=false (a container-only commit)", BOOTABLE_META_KEY));
+    }
+    if kernels.is_empty() {
+        problems.push(format!("no kernel: no {}/<version>/vmlinuz in the tree", MODULES_DIR));
+    }
+    for kernel in kernels.iter().filter(|k| !k.initramfs) {
+        problems.push(format!("no initramfs for kernel {}: {}/{}/initramfs.img is missing", kernel.version, MODULES_DIR, kernel.version));
+    }
+    let kargs = match commit_kargs(repo, commit) {
+        Ok(kargs) => kargs,
+        Err(e) => {
+            problems.push(format!("invalid kernel arguments metadata: {:#}", e));
+            Vec::new()
+        }
+    };
+    for karg in &kargs {
+        if karg.is_empty() || karg.contains(char::is_whitespace) {
+            problems.push(format!("malformed kernel argument {:?}", karg));
+        } else if karg.starts_with("ostree=") {
+            problems.push(format!("kernel argument {} would override the one ostree generates", karg));
+        }
+    }
+
+    Ok(Bootability {
+        commit: commit.to_string(),
+        bootable_meta,
+        kernels,
+        kargs,
+        problems,
+    })
+}
+
+/// Odmawia wdrożenia commita, który nie wystartuje, z listą powodów
+pub fn ensure_bootable(repo: &ostree::Repo, commit: &str) -> Result<()> {
+    let report = check(repo, commit)?;
+    if report.is_bootable() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Refusing to deploy {}, it would not boot:{}",
+        commit,
+        report.problems.iter().map(|p| format!("\n  {}", p)).collect::<String>()
+    ))
+}
+
+pub fn bootable(opts: BootableOpts) -> Result<()> {
+    let (repo, commit) = match &opts.repo {
+        Some(path) => {
+            let repo = ostree_ext::cli::parse_repo(path)?;
+            let commit = opts
+                .commit
+                .as_deref()
+                .ok_or_else(|| anyhow!("A commit is required with --repo"))?;
+            let commit = crate::deploy::resolve_commit(&repo, commit)?;
+            (repo, commit)
+        }
+        None => {
+            let sysroot = load_sysroot()?;
+            let repo = sysroot.repo();
+            let commit = match opts.commit.as_deref() {
+                Some(c) => crate::deploy::resolve_commit(&repo, c)?,
+                None => booted_state(&sysroot)?.0.csum().to_string(),
+            };
+            (repo, commit)
+        }
+    };
+
+    let report = check(&repo, &commit)?;
+    if crate::output::json() {
+        return crate::output::emit(&report);
+    }
+    println!("Commit: {}", report.commit);
+    let meta = match report.bootable_meta {
+        Some(true) => "true",
+        Some(false) => "false",
+        None => "not set",
+    };
+    println!("{}: {}", BOOTABLE_META_KEY, meta);
+    for kernel in &report.kernels {
+        println!("Kernel: {}{}", kernel.version, if kernel.initramfs { "" } else { " (no initramfs)" });
+    }
+    if !report.kargs.is_empty() {
+        println!("Kernel arguments: {}", report.kargs.join(" "));
+    }
+    if report.is_bootable() {
+        println!("Bootable: yes");
+        return Ok(());
+    }
+    println!("Bootable: no");
+    for problem in &report.problems {
+        println!("  {}", problem);
+    }
+    std::process::exit(1);
+}
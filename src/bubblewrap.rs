@@ -151,6 +151,15 @@ impl Bubblewrap {
         self.argv.extend(["--bind", src, dest].iter().map(|s| s.to_string()));
     }
 
+    /// Wymusza dostęp do sieci lub jego brak niezależnie od domyślnego `--unshare-net`.
+    /// Trzeba wywołać przed `append_child_argv`.
+    pub fn set_network(&mut self, enabled: bool) {
+        self.argv.retain(|a| a != "--unshare-net");
+        if !enabled {
+            self.argv.push("--unshare-net".to_string());
+        }
+    }
+
     pub fn setenv(&mut self, key: &str, val: &str) {
         self.launcher.setenv(key, val, true);
     }
@@ -194,7 +203,9 @@ impl Bubblewrap {
 
     pub fn run(&mut self) -> Result<()> {
         let (child, name) = self.spawn()?;
-        child.wait_check(None::<&gio::Cancellable>)?;
+        let watchdog = crate::subprocess::Watchdog::new("bwrap");
+        let result = child.wait_check(Some(watchdog.cancellable()));
+        watchdog.check(&child, result).context(name)?;
         Ok(())
     }
 
@@ -202,7 +213,9 @@ impl Bubblewrap {
         self.launcher.set_flags(gio::SubprocessFlags::STDOUT_PIPE);
 
         let (child, name) = self.spawn()?;
-        let (stdout, _stderr) = child.communicate(None::<&glib::Bytes>, None::<&gio::Cancellable>)?;
+        let watchdog = crate::subprocess::Watchdog::new("bwrap");
+        let result = child.communicate(None::<&glib::Bytes>, Some(watchdog.cancellable()));
+        let (stdout, _stderr) = watchdog.check(&child, result).context(name.clone())?;
 
         child.wait_check(None::<&gio::Cancellable>).context(name)?;
 
@@ -210,13 +223,24 @@ impl Bubblewrap {
         Ok(stdout.to_vec())
     }
 
+    /// Uruchamia bez sprawdzania kodu wyjścia; zwraca, czy się udało, i połączone stdout/stderr
+    pub fn run_output(&mut self) -> Result<(bool, Vec<u8>)> {
+        self.launcher.set_flags(gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_MERGE);
+
+        let (child, name) = self.spawn()?;
+        let watchdog = crate::subprocess::Watchdog::new("bwrap");
+        let result = child.communicate(None::<&glib::Bytes>, Some(watchdog.cancellable()));
+        let (stdout, _stderr) = watchdog.check(&child, result).context(name)?;
+
+        Ok((child.is_successful(), stdout.map(|b| b.to_vec()).unwrap_or_default()))
+    }
+
     pub fn run_with_stdin(&mut self, input: &[u8]) -> Result<()> {
         self.launcher.set_flags(gio::SubprocessFlags::STDIN_PIPE);
         let (child, name) = self.spawn()?;
-        child.communicate(
-        Some(&glib::Bytes::from(input)),
-        None::<&gio::Cancellable>,
-        )?;
+        let watchdog = crate::subprocess::Watchdog::new("bwrap");
+        let result = child.communicate(Some(&glib::Bytes::from(input)), Some(watchdog.cancellable()));
+        watchdog.check(&child, result).context(name.clone())?;
         child.wait_check(None::<&gio::Cancellable>).context(name)?;
         Ok(())
     }
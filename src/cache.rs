@@ -0,0 +1,352 @@
+// Współdzielony cache pakietów dla wielu równoległych compose (`cache serve`)
+//
+// Serwer udaje mirror Archa: jobs ustawiają w pacman.conf
+// `Server = http://<builder>:<port>/$repo/os/$arch`. Pakiety są pobierane z upstreamu
+// raz i trzymane w katalogu cache; bazy repozytoriów zawsze idą prosto z upstreamu.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf;
+use clap::{Parser, Subcommand};
+use ostree_ext::{gio, glib, ostree};
+use nix::fcntl::{Flock, FlockArg};
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Serve the package cache over HTTP as a caching pacman mirror
+    Serve(ServeOpts),
+    /// Remove old cached packages and unreachable objects of a build repo
+    Prune(PruneOpts),
+    /// Download packages into the pacman cache for a later offline install or upgrade
+    Prefetch(PrefetchOpts),
+}
+
+#[derive(Parser, Debug)]
+pub struct PrefetchOpts {
+    /// Packages to download (default: the layered packages of the booted deployment)
+    pub packages: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct PruneOpts {
+    /// Package cache directory (e.g. the one passed to `compose --package-cache`)
+    #[clap(long, default_value = "/var/cache/pacman-ostree/pkg")]
+    pub cache_dir: PathBuf,
+
+    /// Remove cached packages not modified for this many days
+    #[clap(long)]
+    pub max_age: Option<u64>,
+
+    /// Then remove the oldest packages until the cache is at most this big (e.g. 20G, 500M)
+    #[clap(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Build repo (compose --ostree-repo) to remove unreachable objects from
+    #[clap(long)]
+    pub ostree_repo: Option<Utf8PathBuf>,
+
+    /// Commits of history to keep per ref in the build repo (default: all)
+    #[clap(long, requires = "ostree_repo")]
+    pub depth: Option<u32>,
+
+    /// Only print what would be removed
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ServeOpts {
+    /// Upstream mirror URL; `$repo` and `$arch` are expanded like in pacman.conf
+    #[clap(long)]
+    pub upstream: String,
+
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:7878")]
+    pub listen: String,
+
+    /// Directory holding cached packages
+    #[clap(long, default_value = "/var/cache/pacman-ostree/pkg")]
+    pub cache_dir: PathBuf,
+}
+
+/// Żądany plik: repozytorium, architektura i nazwa pliku
+struct MirrorPath<'a> {
+    repo: &'a str,
+    arch: &'a str,
+    file: &'a str,
+}
+
+impl<'a> MirrorPath<'a> {
+    /// Akceptuje tylko `/<repo>/os/<arch>/<plik>` — nic innego nie może wyjść poza cache
+    fn parse(path: &'a str) -> Option<Self> {
+        let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let [repo, "os", arch, file] = parts.as_slice() else {
+            return None;
+        };
+        let valid = |s: &str| !s.is_empty() && !s.starts_with('.') && !s.contains('\\');
+        if !valid(repo) || !valid(arch) || !valid(file) {
+            return None;
+        }
+        Some(MirrorPath { repo, arch, file })
+    }
+
+    /// Bazy (`core.db`, `core.files`, ich podpisy) zmieniają się w miejscu, więc nie są cache'owane
+    fn is_database(&self) -> bool {
+        let name = self.file.trim_end_matches(".sig");
+        name.ends_with(".db") || name.ends_with(".files") || name.contains(".db.tar") || name.contains(".files.tar")
+    }
+
+    fn upstream_url(&self, upstream: &str) -> String {
+        let base = upstream.replace("$repo", self.repo).replace("$arch", self.arch);
+        format!("{}/{}", base.trim_end_matches('/'), self.file)
+    }
+}
+
+fn curl(url: &str) -> Command {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--fail", "--location", "--silent", "--show-error"])
+        .args(crate::network::config().curl_args())
+        .arg(url);
+    cmd
+}
+
+/// Zwraca plik z cache, pobierając go najpierw, jeśli go brak. Blokada na pliku
+/// sprawia, że równoległe żądania (także z innych procesów) pobierają go tylko raz.
+fn cached_package(cache_dir: &Path, url: &str, file: &str) -> Result<PathBuf> {
+    let dest = cache_dir.join(file);
+    let lock_file = File::create(cache_dir.join(format!(".{}.lock", file)))?;
+    let _lock = Flock::lock(lock_file, FlockArg::LockExclusive)
+        .map_err(|(_, e)| anyhow!("Locking {}: {}", file, e))?;
+    if dest.exists() {
+        return Ok(dest);
+    }
+
+    let part = cache_dir.join(format!(".{}.part", file));
+    let status = crate::subprocess::status(curl(url).arg("--output").arg(&part))?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&part);
+        anyhow::bail!("Downloading {} failed", url);
+    }
+    std::fs::rename(&part, &dest)?;
+    println!("Cached {}", file);
+    Ok(dest)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: Option<(u64, &mut dyn std::io::Read)>) -> Result<()> {
+    let len = body.as_ref().map(|(n, _)| *n).unwrap_or(0);
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, len)?;
+    if let Some((_, reader)) = body {
+        std::io::copy(reader, stream)?;
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, opts: &ServeOpts) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Nagłówki nas nie interesują, ale trzeba je odczytać do końca
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, "400 Bad Request", None);
+    };
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", None);
+    }
+    let Some(target) = MirrorPath::parse(path) else {
+        return respond(&mut stream, "404 Not Found", None);
+    };
+    let url = target.upstream_url(&opts.upstream);
+
+    // 404 przy błędzie upstreamu — pacman spróbuje wtedy kolejnego serwera
+    if target.is_database() {
+        let output = crate::subprocess::output(&mut curl(&url))?;
+        if !output.status.success() {
+            return respond(&mut stream, "404 Not Found", None);
+        }
+        let mut body = output.stdout.as_slice();
+        return respond(&mut stream, "200 OK", Some((output.stdout.len() as u64, &mut body)));
+    }
+    match cached_package(&opts.cache_dir, &url, target.file) {
+        Ok(path) => {
+            let mut f = File::open(&path)?;
+            let len = f.metadata()?.len();
+            respond(&mut stream, "200 OK", Some((len, &mut f)))
+        }
+        Err(e) => {
+            eprintln!("Warning: {:#}", e);
+            respond(&mut stream, "404 Not Found", None)
+        }
+    }
+}
+
+fn serve(opts: ServeOpts) -> Result<()> {
+    std::fs::create_dir_all(&opts.cache_dir)
+        .with_context(|| format!("Creating {}", opts.cache_dir.display()))?;
+    let listener = TcpListener::bind(&opts.listen).with_context(|| format!("Binding {}", opts.listen))?;
+    println!(
+        "Serving {} on http://{}/$repo/os/$arch",
+        opts.cache_dir.display(),
+        opts.listen
+    );
+
+    std::thread::scope(|s| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Warning: accepting connection failed: {}", e);
+                    continue;
+                }
+            };
+            let opts = &opts;
+            s.spawn(move || {
+                if let Err(e) = handle(stream, opts) {
+                    eprintln!("Warning: request failed: {:#}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Rozmiar z opcjonalnym sufiksem K/M/G/T (potęgi 1024)
+fn parse_size(s: &str) -> Result<u64> {
+    let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => anyhow::bail!("Invalid size {}; expected e.g. 500M or 20G", s),
+    };
+    let n: u64 = digits.parse().with_context(|| format!("Invalid size {}", s))?;
+    Ok(n << shift)
+}
+
+/// Pliki pakietów w cache: (ścieżka, rozmiar, czas modyfikacji), od najstarszego.
+/// Pomijane są pliki pomocnicze serwera (`.lock`, `.part`), których nie wolno ruszać w trakcie pobierania.
+fn cached_files(cache_dir: &Path) -> Result<Vec<(PathBuf, u64, std::time::SystemTime)>> {
+    let mut files = Vec::new();
+    let entries = match std::fs::read_dir(cache_dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", cache_dir.display())),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Podpisy znikają razem ze swoim pakietem
+        if !meta.is_file() || name.starts_with('.') || name.ends_with(".sig") {
+            continue;
+        }
+        files.push((entry.path(), meta.len(), meta.modified()?));
+    }
+    files.sort_by_key(|(_, _, mtime)| *mtime);
+    Ok(files)
+}
+
+/// Pakiety do usunięcia według polityki wieku, potem rozmiaru
+fn packages_to_prune(opts: &PruneOpts) -> Result<Vec<(PathBuf, u64)>> {
+    let files = cached_files(&opts.cache_dir)?;
+    let now = std::time::SystemTime::now();
+    let max_age = opts.max_age.map(|days| std::time::Duration::from_secs(days * 24 * 3600));
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let mut remove = Vec::new();
+    for (path, size, mtime) in files {
+        let too_old = max_age.is_some_and(|age| now.duration_since(mtime).unwrap_or_default() > age);
+        let too_big = opts.max_size.is_some_and(|max| total > max);
+        if too_old || too_big {
+            total -= size;
+            remove.push((path, size));
+        }
+    }
+    Ok(remove)
+}
+
+fn prune(opts: PruneOpts) -> Result<()> {
+    if opts.max_age.is_none() && opts.max_size.is_none() && opts.ostree_repo.is_none() {
+        anyhow::bail!("Nothing to prune; pass --max-age, --max-size or --ostree-repo");
+    }
+
+    let remove = packages_to_prune(&opts)?;
+    let freed: u64 = remove.iter().map(|(_, size)| size).sum();
+    for (path, _) in &remove {
+        if opts.dry_run {
+            println!("Would remove {}", path.display());
+        } else {
+            std::fs::remove_file(path).with_context(|| format!("Removing {}", path.display()))?;
+            // Podpis leży obok pakietu i bez niego nie ma sensu
+            let mut sig = path.clone().into_os_string();
+            sig.push(".sig");
+            let _ = std::fs::remove_file(sig);
+        }
+    }
+    println!(
+        "{} {} cached package(s), {}",
+        if opts.dry_run { "Would remove" } else { "Removed" },
+        remove.len(),
+        glib::format_size(freed)
+    );
+
+    if let Some(repo_path) = &opts.ostree_repo {
+        let repo = ostree_ext::cli::parse_repo(repo_path)?;
+        let mut flags = ostree::RepoPruneFlags::REFS_ONLY;
+        if opts.dry_run {
+            flags |= ostree::RepoPruneFlags::NO_PRUNE;
+        }
+        let depth = opts.depth.map(|d| d as i32).unwrap_or(-1);
+        let (total, pruned, freed) = repo
+            .prune(flags, depth, gio::Cancellable::NONE)
+            .context("Pruning build repo")?;
+        println!(
+            "{} {} of {} objects in {}, {}",
+            if opts.dry_run { "Would prune" } else { "Pruned" },
+            pruned,
+            total,
+            repo_path,
+            glib::format_size(freed)
+        );
+    }
+    Ok(())
+}
+
+/// Bez argumentów pobiera nowe wersje pakietów warstwy, których potrzebowałby rebuild przy `upgrade`
+fn prefetch(opts: PrefetchOpts) -> Result<()> {
+    let sysroot = crate::layered_packages::load_sysroot()?;
+    let (_, state) = crate::layered_packages::pending_state(&sysroot)?;
+    let packages = if opts.packages.is_empty() {
+        state.layered_packages.iter().cloned().collect()
+    } else {
+        opts.packages
+    };
+    if packages.is_empty() {
+        println!("No layered packages to prefetch");
+        return Ok(());
+    }
+    crate::layered_packages::download_packages(&sysroot.repo(), &state, &packages)
+}
+
+pub fn cache_command(cmd: CacheCommand) -> Result<()> {
+    match cmd {
+        CacheCommand::Serve(opts) => serve(opts),
+        CacheCommand::Prune(opts) => prune(opts),
+        CacheCommand::Prefetch(opts) => prefetch(opts),
+    }
+}
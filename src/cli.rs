@@ -0,0 +1,403 @@
+// Parser argumentów wiersza poleceń i rozdział na polecenia
+
+use crate::*;
+use clap::{Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(name = "pacman-ostree")]
+#[command(about = "Arch Linux OSTree builder", long_about = None)]
+struct Args {
+    /// Limit package download bandwidth, e.g. 500K or 2M (also `bwlimit` in /etc/pacman-ostree/network.yaml);
+    /// OSTree pulls and container image transfers are not limited
+    #[arg(long, global = true)]
+    bwlimit: Option<String>,
+
+    /// Default time limit in seconds for external commands, 0 disables it
+    /// (also `default` in /etc/pacman-ostree/timeouts.yaml)
+    #[arg(long, global = true)]
+    command_timeout: Option<u64>,
+
+    /// Print machine-readable JSON results instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// What to do when a package install script fails
+    /// (compose: overrides `scriptlet-failure` in the manifest)
+    #[arg(long, global = true, value_enum)]
+    scriptlet_failure: Option<scriptlets::ScriptletFailure>,
+
+    /// Do not run package install scripts when layering or composing
+    #[arg(long, global = true)]
+    no_scriptlets: bool,
+
+    /// Install layered packages without verifying their signatures
+    #[arg(long, global = true)]
+    skip_sig_check: bool,
+
+    /// GPG-sign commits written by compose and layering with this key
+    #[arg(long, global = true, value_name = "KEYID")]
+    gpg_sign: Option<String>,
+
+    /// GnuPG home directory holding the --gpg-sign key
+    #[arg(long, global = true, value_name = "PATH", requires = "gpg_sign")]
+    gpg_homedir: Option<std::path::PathBuf>,
+
+    /// Also sign commits with the ostree ed25519 secret key in this file
+    #[arg(long, global = true, value_name = "PATH")]
+    sign_ed25519: Option<std::path::PathBuf>,
+
+    /// Like --sign-ed25519, reading the key from this systemd credential
+    #[arg(long, global = true, value_name = "NAME", conflicts_with = "sign_ed25519")]
+    sign_ed25519_credential: Option<String>,
+
+    /// Never access the network: install only packages already in /var/cache/pacman/pkg,
+    /// using the package databases and base images downloaded before
+    #[arg(long, global = true)]
+    cache_only: bool,
+
+    /// pacman.conf for layering, upgrades and searches instead of /etc/pacman.conf
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Build an OSTree image
+    Compose(ComposeArgs),
+    /// Build a systemd-sysext extension image from the `sysext` section of a manifest
+    ComposeSysext(compose_sysext::ComposeSysextOpts),
+    /// Generate an ostree static delta between two compose outputs
+    ComposeDelta(compose_delta::ComposeDeltaOpts),
+    /// Layer packages on top of the base image
+    Install(layered_packages::InstallOpts),
+    /// Remove layered packages
+    Remove(layered_packages::RemoveOpts),
+    /// Remove packages of the base image in new deployments
+    #[command(subcommand)]
+    Override(overrides::OverrideCommand),
+    /// Install an older version of a layered package from the cache or the Arch Linux Archive
+    Downgrade(downgrade::DowngradeOpts),
+    /// Search the sync databases and show which results are installed
+    Search(search::SearchOpts),
+    /// Show package details and whether it comes from the base image or a layer
+    Info(info::InfoOpts),
+    /// Manage additional pacman repositories for layered packages
+    #[command(subcommand)]
+    Repo(layered_repos::RepoCommand),
+    /// Query package databases of deployments
+    #[command(subcommand)]
+    Db(db::DbCommand),
+    /// Share a package cache between compose jobs
+    #[command(subcommand)]
+    Cache(cache::CacheCommand),
+    /// Rank pacman mirrors by speed
+    #[command(subcommand)]
+    Mirrors(mirrors::MirrorsCommand),
+    /// Run common pacman invocations (-S, -R, -Q) against the image
+    Pacman {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Update the base image of the booted deployment
+    Upgrade(upgrade::UpgradeOpts),
+    /// Check whether a newer base is available (exit code 77 when there is none)
+    CheckUpdate,
+    /// Switch the base to another ref or container image, keeping layers
+    Rebase(rebase::RebaseOpts),
+    /// Deploy a ref or image, creating the stateroot if needed
+    Deploy(deploy::DeployOpts),
+    /// Show recorded transactions
+    History(history::HistoryOpts),
+    /// Show deployments and layered state
+    Status(status::StatusOpts),
+    /// Verify integrity of the booted deployment
+    Verify(integrity::VerifyOpts),
+    /// Make the previous deployment the default boot target
+    Rollback(rollback::RollbackOpts),
+    /// Pin a deployment so it is never garbage-collected (or unpin it)
+    Pin(pin::PinOpts),
+    /// Check the system for common problems
+    Doctor(doctor::DoctorOpts),
+    /// Experimental commands
+    #[command(subcommand)]
+    Ex(ExCommands),
+}
+
+/// `compose MANIFEST OUTPUT ...` albo jedno z poleceń pomocniczych (`compose cache prune`)
+#[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct ComposeArgs {
+    #[command(subcommand)]
+    command: Option<ComposeCommands>,
+
+    #[command(flatten)]
+    opts: Option<compose::ComposeImageOpts>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ComposeCommands {
+    /// Manage the package cache and build repo of compose jobs
+    #[command(subcommand)]
+    Cache(ComposeCacheCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum ComposeCacheCommand {
+    /// Remove old cached packages and unreachable objects of a build repo (same as `cache prune`)
+    Prune(cache::PruneOpts),
+}
+
+#[derive(Subcommand, Debug)]
+enum ExCommands {
+    /// Manage admin-provided files layered into /usr
+    #[command(subcommand)]
+    Config(layered_files::ConfigCommand),
+    /// Enable a systemd unit in new deployments
+    Enable {
+        unit: String,
+    },
+    /// Disable a systemd unit in new deployments
+    Disable {
+        unit: String,
+    },
+    /// Show security advisories affecting the booted deployment
+    Advisories(advisories::AdvisoriesOpts),
+    /// Remove old base commits and images beyond the retention policy
+    Prune(prune::PruneOpts),
+    /// Set fs-verity for layered commits and new deployment checkouts
+    Fsverity {
+        #[arg(value_enum)]
+        mode: fsverity::FsVerityMode,
+    },
+    /// Restore /etc from the snapshot taken before leaving a deployment
+    RestoreEtc(etc_snapshot::RestoreEtcOpts),
+    /// Show where every file of a commit comes from (package, component, ...)
+    Attribution(attribution::AttributionOpts),
+    /// Print OpenMetrics for monitoring (base age, pending update, last transaction, ...)
+    Metrics(metrics::MetricsOpts),
+    /// Check whether a commit can boot (kernel, initramfs, ostree.bootable, kargs)
+    Bootable(bootable::BootableOpts),
+    /// Save .pacnew files for /etc changes after booting a new deployment (for the systemd unit)
+    #[command(hide = true)]
+    FinishEtcMerge,
+}
+
+impl Commands {
+    /// Polecenia, które przy `--json` wypisują dokument JSON; pozostałe odrzucają flagę
+    fn supports_json(&self) -> bool {
+        match self {
+            Commands::Install(_) | Commands::Remove(_) | Commands::Search(_) | Commands::Info(_) => true,
+            Commands::Status(_) | Commands::CheckUpdate => true,
+            Commands::Upgrade(_) | Commands::Rebase(_) | Commands::Rollback(_) | Commands::Deploy(_) => true,
+            Commands::Override(_) | Commands::History(_) => true,
+            Commands::Downgrade(opts) => !opts.list,
+            Commands::Db(cmd) => matches!(cmd, db::DbCommand::Diff { .. } | db::DbCommand::Owns { .. }),
+            Commands::Mirrors(_) => true,
+            Commands::Ex(cmd) => matches!(cmd, ExCommands::Bootable(_) | ExCommands::Attribution(_)),
+            _ => false,
+        }
+    }
+}
+
+/// Brak aktualizacji z `upgrade --check`/`check-update` to osobny kod wyjścia, nie błąd
+fn exit_on_no_updates(result: anyhow::Result<()>) -> anyhow::Result<()> {
+    if let Err(e) = &result {
+        if e.downcast_ref::<upgrade::NoUpdates>().is_some() {
+            std::process::exit(upgrade::EXIT_NO_UPDATES);
+        }
+    }
+    result
+}
+
+/// Punkt wejścia binarki `pacman-ostree` (także wywołanej przez symlink `pacman`)
+pub async fn run() -> anyhow::Result<()> {
+    // Wywołanie przez symlink `pacman` -> tryb zgodności
+    let mut argv = std::env::args();
+    let argv0 = argv.next().unwrap_or_default();
+    if std::path::Path::new(&argv0).file_name().is_some_and(|n| n == "pacman") {
+        let rest: Vec<String> = argv.collect();
+        network::init(None)?;
+        subprocess::init(None)?;
+        return pacman_compat::run(&rest).await;
+    }
+
+    let args = Args::parse();
+    if args.json && !args.command.supports_json() {
+        anyhow::bail!("--json is not supported by this command");
+    }
+    network::init(args.bwlimit.clone())?;
+    network::set_cache_only(args.cache_only);
+    subprocess::init(args.command_timeout)?;
+    output::set_json(args.json);
+    scriptlets::init(args.scriptlet_failure);
+    scriptlets::set_enabled(!args.no_scriptlets);
+    signatures::init(args.skip_sig_check);
+    let ed25519 = match (args.sign_ed25519.clone(), args.sign_ed25519_credential.clone()) {
+        (Some(path), _) => Some(commit_signing::Ed25519Key::File(path)),
+        (None, Some(name)) => Some(commit_signing::Ed25519Key::Credential(name)),
+        (None, None) => None,
+    };
+    commit_signing::init(args.gpg_sign.clone(), args.gpg_homedir.clone(), ed25519)?;
+    layered_repos::init_pacman_conf(args.config.clone());
+
+    match args.command {
+        Commands::Compose(args) => match (args.command, args.opts) {
+            (Some(ComposeCommands::Cache(ComposeCacheCommand::Prune(opts))), _) => {
+                cache::cache_command(cache::CacheCommand::Prune(opts))?;
+            }
+            (None, Some(opts)) => {
+                if let Err(e) = compose::compose_image(opts).await {
+                    if e.downcast_ref::<warnings::TooManyWarnings>().is_some() {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(warnings::EXIT_TOO_MANY_WARNINGS);
+                    }
+                    return Err(e);
+                }
+            }
+            // Bez podpolecenia clap wymaga MANIFEST i OUTPUT
+            (None, None) => unreachable!(),
+        },
+        Commands::ComposeSysext(opts) => {
+            compose_sysext::compose_sysext(opts).await?;
+        }
+        Commands::ComposeDelta(opts) => {
+            compose_delta::compose_delta(opts)?;
+        }
+        // Bez zmiany deploymentów: nie ma czego zapisywać w historii ani po co restartować
+        Commands::Install(opts) if opts.dry_run || opts.download_only || opts.ephemeral.ephemeral => layered_packages::handle_install(opts)?,
+        Commands::Install(opts) => {
+            let packages = opts.packages.clone();
+            let reboot = opts.reboot.clone();
+            history::record_transaction("install", &packages, || layered_packages::handle_install(opts))?;
+            reboot::maybe_reboot(&reboot)?;
+        }
+        Commands::Remove(opts) if opts.dry_run || opts.ephemeral.ephemeral => layered_packages::handle_remove(opts)?,
+        Commands::Remove(opts) => {
+            let packages = opts.packages.clone();
+            let reboot = opts.reboot.clone();
+            history::record_transaction("remove", &packages, || layered_packages::handle_remove(opts))?;
+            reboot::maybe_reboot(&reboot)?;
+        }
+        Commands::Downgrade(opts) => {
+            downgrade::downgrade(opts)?;
+        }
+        Commands::Override(overrides::OverrideCommand::List) => {
+            overrides::override_command(overrides::OverrideCommand::List)?;
+        }
+        Commands::Override(cmd) => {
+            let packages = cmd.packages();
+            output::progress(|| history::record_transaction("override", &packages, || overrides::override_command(cmd)))?;
+            layered_packages::emit_transaction_report(&layered_packages::load_sysroot()?, "override", &packages)?;
+        }
+        Commands::Search(opts) => {
+            search::search(opts)?;
+        }
+        Commands::Info(opts) => {
+            info::info(opts)?;
+        }
+        Commands::Repo(layered_repos::RepoCommand::List) => {
+            layered_repos::repo_command(layered_repos::RepoCommand::List)?;
+        }
+        Commands::Repo(cmd) => {
+            history::record_transaction("repo", &[], || layered_repos::repo_command(cmd))?;
+        }
+        Commands::Db(db::DbCommand::PinForeign) => {
+            history::record_transaction("pin-foreign", &[], db::db_pin_foreign)?;
+        }
+        Commands::Db(cmd) => {
+            db::db_command(cmd)?;
+        }
+        Commands::Cache(cmd) => {
+            cache::cache_command(cmd)?;
+        }
+        Commands::Mirrors(cmd) => {
+            mirrors::mirrors_command(cmd)?;
+        }
+        Commands::Pacman { args } => {
+            pacman_compat::run(&args).await?;
+        }
+        Commands::Upgrade(opts) => {
+            exit_on_no_updates(upgrade::upgrade(opts).await)?;
+        }
+        Commands::CheckUpdate => {
+            exit_on_no_updates(upgrade::check().await)?;
+        }
+        Commands::Rebase(opts) => {
+            rebase::rebase(opts).await?;
+        }
+        Commands::Deploy(opts) => {
+            let refspec = vec![opts.refspec.clone()];
+            output::progress(|| history::record_transaction("deploy", &refspec, || deploy::deploy(&opts)))?;
+            layered_packages::emit_transaction_report(&deploy::load_target_sysroot(&opts)?, "deploy", &refspec)?;
+            reboot::maybe_reboot(&opts.reboot)?;
+        }
+        Commands::History(opts) => match opts.undo {
+            Some(id) => {
+                let ids = vec![id.to_string()];
+                output::progress(|| history::record_transaction("undo", &ids, || history::undo_transaction(id)))?;
+                layered_packages::emit_transaction_report(&layered_packages::load_sysroot()?, "undo", &ids)?;
+                reboot::maybe_reboot(&opts.reboot)?;
+            }
+            None => history::show_history(opts)?,
+        },
+        Commands::Status(opts) => {
+            status::handle_status(opts)?;
+        }
+        Commands::Verify(opts) => {
+            integrity::verify(opts)?;
+        }
+        Commands::Rollback(opts) => {
+            output::progress(|| history::record_transaction("rollback", &[], rollback::rollback))?;
+            layered_packages::emit_transaction_report(&layered_packages::load_sysroot()?, "rollback", &[])?;
+            reboot::maybe_reboot(&opts.reboot)?;
+        }
+        Commands::Pin(opts) => {
+            pin::pin(opts)?;
+        }
+        Commands::Doctor(opts) => {
+            doctor::doctor(opts)?;
+        }
+        Commands::Ex(ExCommands::Config(layered_files::ConfigCommand::List)) => {
+            layered_files::config_command(layered_files::ConfigCommand::List)?;
+        }
+        Commands::Ex(ExCommands::Config(cmd)) => {
+            history::record_transaction("config", &[], || layered_files::config_command(cmd))?;
+        }
+        Commands::Ex(ExCommands::Enable { unit }) => {
+            let units = vec![unit.clone()];
+            history::record_transaction("enable", &units, || layered_units::set_unit_enabled(&unit, true))?;
+        }
+        Commands::Ex(ExCommands::Disable { unit }) => {
+            let units = vec![unit.clone()];
+            history::record_transaction("disable", &units, || layered_units::set_unit_enabled(&unit, false))?;
+        }
+        Commands::Ex(ExCommands::Advisories(opts)) => {
+            advisories::show_advisories(opts).await?;
+        }
+        Commands::Ex(ExCommands::Prune(opts)) => {
+            prune::prune(opts)?;
+        }
+        Commands::Ex(ExCommands::Fsverity { mode }) => {
+            fsverity::set_system_fsverity(mode)?;
+        }
+        Commands::Ex(ExCommands::RestoreEtc(opts)) => {
+            etc_snapshot::restore_etc(opts)?;
+        }
+        Commands::Ex(ExCommands::Attribution(opts)) => {
+            attribution::attribution(opts)?;
+        }
+        Commands::Ex(ExCommands::Metrics(opts)) => {
+            metrics::metrics(opts)?;
+        }
+        Commands::Ex(ExCommands::Bootable(opts)) => {
+            bootable::bootable(opts)?;
+        }
+        Commands::Ex(ExCommands::FinishEtcMerge) => {
+            etc_merge::finish_pending()?;
+        }
+    }
+    Ok(())
+}
\ No newline at end of file
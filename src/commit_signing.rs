@@ -0,0 +1,130 @@
+// Podpisywanie commitów z compose i rebuildu warstw oraz weryfikacja pobieranych baz
+//
+// GPG (`--gpg-sign`, `--gpg-homedir`) i ed25519 ostree (`--sign-ed25519`, klucz z pliku
+// albo z poświadczenia systemd) trafiają do odłączonych metadanych commita, więc systemy
+// pobierające obraz mogą wymagać `gpg-verify=true` albo `sign-verify=true` dla zdalnego.
+// Jeśli istnieje TRUSTED_ED25519, `upgrade`/`rebase` pobierają bazy z refów ostree
+// w transakcji repo i zatwierdzają ją dopiero po sprawdzeniu podpisu tymi kluczami —
+// niepodpisany commit nie trafia wtedy do repo, a konfiguracja zdalnych zostaje nietknięta.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use anyhow::{anyhow, Context, Result};
+use ostree_ext::{gio, ostree};
+use ostree_ext::prelude::*;
+
+/// Klucze publiczne ed25519 (base64, po jednym w linii) wymagane dla baz z refów ostree
+const TRUSTED_ED25519: &str = "/etc/pacman-ostree/trusted-ed25519";
+const ED25519: &str = "ed25519";
+
+#[derive(Debug, Default)]
+struct Signing {
+    gpg_key: Option<String>,
+    gpg_homedir: Option<PathBuf>,
+    /// Klucz prywatny ed25519 w base64, jak w `ostree sign --keys-file`
+    ed25519_key: Option<String>,
+}
+
+static SIGNING: OnceLock<Signing> = OnceLock::new();
+
+/// Skąd wziąć klucz ed25519: plik albo nazwa poświadczenia systemd (`LoadCredential=`)
+#[derive(Debug, Clone)]
+pub enum Ed25519Key {
+    File(PathBuf),
+    Credential(String),
+}
+
+fn read_ed25519_key(source: &Ed25519Key) -> Result<String> {
+    let path = match source {
+        Ed25519Key::File(path) => path.clone(),
+        Ed25519Key::Credential(name) => {
+            let dir = std::env::var_os("CREDENTIALS_DIRECTORY")
+                .ok_or_else(|| anyhow!("Credential {} requested, but CREDENTIALS_DIRECTORY is not set", name))?;
+            Path::new(&dir).join(name)
+        }
+    };
+    let contents = std::fs::read_to_string(&path).with_context(|| format!("Reading {}", path.display()))?;
+    // Plik kluczy ostree może mieć kilka linii; podpisujemy pierwszym kluczem
+    contents
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("No ed25519 key in {}", path.display()))
+}
+
+/// Klucze dla całego procesu; bez nich commity nie są podpisywane
+pub fn init(gpg_key: Option<String>, gpg_homedir: Option<PathBuf>, ed25519: Option<Ed25519Key>) -> Result<()> {
+    let ed25519_key = ed25519.as_ref().map(read_ed25519_key).transpose()?;
+    let _ = SIGNING.set(Signing {
+        gpg_key,
+        gpg_homedir,
+        ed25519_key,
+    });
+    Ok(())
+}
+
+/// Podpisuje świeżo zapisany commit kluczami z `--gpg-sign` i `--sign-ed25519`
+pub fn sign(repo: &ostree::Repo, commit: &str) -> Result<()> {
+    let Some(signing) = SIGNING.get() else {
+        return Ok(());
+    };
+    if let Some(key_id) = &signing.gpg_key {
+        let homedir = signing.gpg_homedir.as_ref().map(|h| h.to_string_lossy().into_owned());
+        repo.sign_commit(commit, key_id, homedir.as_deref(), gio::Cancellable::NONE)
+            .with_context(|| format!("Signing commit {} with key {}", commit, key_id))?;
+        println!("Signed commit {} with key {}", commit, key_id);
+    }
+    if let Some(key) = &signing.ed25519_key {
+        let sign = ostree::Sign::by_name(ED25519)?;
+        sign.set_sk(&key.to_variant()).context("Loading the ed25519 key")?;
+        sign.commit(repo, commit, gio::Cancellable::NONE)
+            .with_context(|| format!("Signing commit {} with ed25519", commit))?;
+        println!("Signed commit {} with ed25519", commit);
+    }
+    Ok(())
+}
+
+/// Generuje summary repo i podpisuje je tymi samymi kluczami co commity
+pub fn regenerate_summary(repo: &ostree::Repo) -> Result<()> {
+    let cancellable = gio::Cancellable::NONE;
+    repo.regenerate_summary(None, cancellable).context("Regenerating summary")?;
+    let Some(signing) = SIGNING.get() else {
+        return Ok(());
+    };
+    if let Some(key_id) = &signing.gpg_key {
+        let homedir = signing.gpg_homedir.as_ref().map(|h| h.to_string_lossy().into_owned());
+        repo.add_gpg_signature_summary(&[key_id.as_str()], homedir.as_deref(), cancellable)
+            .with_context(|| format!("Signing summary with key {}", key_id))?;
+        println!("Signed summary with key {}", key_id);
+    }
+    if let Some(key) = &signing.ed25519_key {
+        let sign = ostree::Sign::by_name(ED25519)?;
+        sign.summary(repo, &vec![key.as_str()].to_variant(), cancellable)
+            .context("Signing summary with ed25519")?;
+        println!("Signed summary with ed25519");
+    }
+    Ok(())
+}
+
+/// Sprawdza podpis ed25519 bazy w repo, gdy TRUSTED_ED25519 istnieje
+pub fn verify_base(repo: &ostree::Repo, commit: &str) -> Result<()> {
+    let contents = match std::fs::read_to_string(TRUSTED_ED25519) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", TRUSTED_ED25519)),
+    };
+    let sign = ostree::Sign::by_name(ED25519)?;
+    let mut keys = 0usize;
+    for key in contents.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        sign.add_pk(&key.to_variant())
+            .with_context(|| format!("Loading public key from {}", TRUSTED_ED25519))?;
+        keys += 1;
+    }
+    if keys == 0 {
+        anyhow::bail!("{} has no public keys", TRUSTED_ED25519);
+    }
+    sign.commit_verify(repo, commit, gio::Cancellable::NONE)
+        .with_context(|| format!("Commit {} has no valid ed25519 signature from {}", commit, TRUSTED_ED25519))?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Przypisanie plików rootfs do komponentów (xattr user.component) według globów z manifestu
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+use glob::{MatchOptions, Pattern};
+use walkdir::WalkDir;
+
+/// Xattr czytany przez `container_encapsulate` przy podziale na warstwy
+pub const COMPONENT_XATTR: &CStr = c"user.component";
+
+pub(crate) const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    // `*` nie przechodzi przez `/`, do tego służy `**`
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn set_component(path: &std::path::Path, component: &str) -> Result<()> {
+    let cpath = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: wskaźniki pochodzą z żywych CString/&str o podanej długości
+    let r = unsafe {
+        libc::lsetxattr(
+            cpath.as_ptr(),
+            COMPONENT_XATTR.as_ptr(),
+            component.as_ptr().cast(),
+            component.len(),
+            0,
+        )
+    };
+    if r != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Setting {:?} on {}", COMPONENT_XATTR, path.display()));
+    }
+    Ok(())
+}
+
+/// Ustawia user.component na plikach i katalogach rootfs pasujących do globów
+/// (ścieżki absolutne względem rootfs, np. `/usr/share/fonts/noto-cjk/**`).
+/// Ścieżka pasująca do dwóch komponentów jest błędem, bo wynik zależałby od kolejności.
+pub fn assign_components(rootfs: &Utf8Path, components: &BTreeMap<String, Vec<String>>) -> Result<()> {
+    let mut rules = Vec::new();
+    for (name, globs) in components {
+        if name.trim().is_empty() {
+            anyhow::bail!("Component name must not be empty");
+        }
+        for g in globs {
+            let pattern = Pattern::new(g).with_context(|| format!("Invalid glob {} for component {}", g, name))?;
+            rules.push((name.as_str(), pattern));
+        }
+    }
+
+    let mut assigned: HashMap<&str, usize> = HashMap::new();
+    for entry in WalkDir::new(rootfs).min_depth(1) {
+        let entry = entry?;
+        let rel = entry
+            .path()
+            .strip_prefix(rootfs)
+            .map_err(|_| anyhow!("{} is outside of {}", entry.path().display(), rootfs))?;
+        let path = std::path::Path::new("/").join(rel);
+
+        let mut matched = rules
+            .iter()
+            .filter(|(_, p)| p.matches_path_with(&path, MATCH_OPTIONS))
+            .map(|(name, _)| *name);
+        let Some(name) = matched.next() else {
+            continue;
+        };
+        if let Some(other) = matched.find(|n| *n != name) {
+            anyhow::bail!("{} matches both component {} and {}", path.display(), name, other);
+        }
+        set_component(entry.path(), name)?;
+        *assigned.entry(name).or_default() += 1;
+    }
+
+    for name in components.keys() {
+        match assigned.get(name.as_str()) {
+            Some(n) => println!("Component {}: {} path(s)", name, n),
+            None => crate::warnings::warn("component", format!("component {} does not match any path", name)),
+        }
+    }
+    Ok(())
+}
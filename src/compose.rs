@@ -4,8 +4,9 @@ use cap_std::{ambient_authority, fs::Dir};
 use nix::sys::prctl::get_child_subreaper;
 //Compose config yaml structure
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, os::fd::AsRawFd};
+use std::{collections::{BTreeMap, HashMap}, os::fd::AsRawFd};
 use serde_yaml;
+use std::sync::{Arc, Mutex};
 use std::{
     collections::HashSet,
     fs,
@@ -17,16 +18,21 @@ use ostree_ext::{gio, glib};
 use glib::prelude::*;
 use ostree::MutableTree;
 use ostree::Repo;
-use crate::package_installer::{self, install_packages};
+use crate::package_installer::{self, install_packages_with_cache};
 use std::num::NonZeroU32;
 use std::error::Error;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use tempfile::TempDir;
 use cap_std::AmbientAuthority;
 use std::os::unix::fs as unix_fs;
 use anyhow::Context;
 use anyhow::{anyhow, Result};
 use crate::composepost;
+use crate::compose_hooks;
+use crate::fsverity::FsVerityMode;
+use crate::banner::{self, BannerInfo, BannerTarget};
+use crate::oci_export::Compression;
+use crate::special_files::SpecialFileRule;
 use crate::container::container_encapsulate;
 use crate::container::ContainerEncapsulateOpts;
 use ostree_ext::container::ImageReference;
@@ -51,17 +57,185 @@ pub struct ComposeImageOpts
     /// OSTree repo
     #[clap(long)]
     pub ostree_repo: Utf8PathBuf,
+
+    /// Also pull the final commit and ref into this repo (e.g. /ostree/repo), keeping
+    /// intermediate commits only in --ostree-repo
+    #[clap(long)]
+    pub pull_local_to: Option<Utf8PathBuf>,
+
+    /// Write build metadata (commit, digest, layers) as JSON to this path
+    #[clap(long)]
+    pub write_composejson_to: Option<Utf8PathBuf>,
+
+    /// Enable fs-verity on committed objects and require it for composefs (overrides manifest)
+    #[clap(long, value_enum)]
+    pub fsverity: Option<FsVerityMode>,
+
+    /// Container format version for the encapsulated image (overrides manifest)
+    #[clap(long)]
+    pub format_version: Option<u32>,
+
+    /// Package that always gets its own exclusive layer (added to the manifest list)
+    #[clap(long = "exclusive-package")]
+    pub exclusive_packages: Vec<String>,
+
+    /// Layer compression algorithm (overrides manifest)
+    #[clap(long, value_enum)]
+    pub compression: Option<Compression>,
+
+    /// Layer compression level (overrides manifest)
+    #[clap(long)]
+    pub compression_level: Option<u32>,
+
+    /// Number of layers compressed in parallel (default: all CPUs)
+    #[clap(long)]
+    pub compression_jobs: Option<std::num::NonZeroUsize>,
+
+    /// Fail when duplicated file content exceeds this many bytes (overrides manifest)
+    #[clap(long)]
+    pub max_duplicate_bytes: Option<u64>,
+
+    /// Exit with code 3 when the build produced more warnings than this (overrides manifest)
+    #[clap(long)]
+    pub max_warnings: Option<usize>,
+
+    /// Print a stage-by-stage timing breakdown at the end
+    #[clap(long)]
+    pub timings: bool,
+
+    /// Build these variants from `variant-includes` concurrently, one image per variant
+    #[clap(long, value_delimiter = ',', conflicts_with = "variant")]
+    pub variants: Vec<String>,
+
+    /// Build a single variant from `variant-includes`; output paths get a `-<variant>` suffix
+    #[clap(long)]
+    pub variant: Option<String>,
+
+    /// Also install packages listed in this file, one per line (`-` reads standard input)
+    #[clap(long)]
+    pub packages_from: Option<Utf8PathBuf>,
+
+    /// Package cache directory, e.g. shared between builds (default: inside the build root)
+    #[clap(long)]
+    pub package_cache: Option<Utf8PathBuf>,
+
+    /// Fail the build if any package has an unfixed critical security advisory
+    #[clap(long)]
+    pub fail_on_vuln: bool,
+
+    /// Command or http(s) webhook to notify with the build JSON on success
+    #[clap(long)]
+    pub on_success: Vec<String>,
+
+    /// Command or http(s) webhook to notify with the error on failure
+    #[clap(long)]
+    pub on_failure: Vec<String>,
+}
+
+/// Metadane buildu zapisywane przez `--write-composejson-to`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ComposeJson {
+    pub r#ref: String,
+    pub ostree_commit: String,
+    pub digest: String,
+    pub layers: Vec<crate::container::LayerReport>,
+    pub layer_warnings: Vec<String>,
+    pub timings: Vec<crate::timings::StageTiming>,
+    pub warnings: Vec<crate::warnings::ComposeWarning>,
+    pub dedup: crate::container::DedupReport,
+    pub licenses: crate::licenses::LicenseReport,
+    pub scriptlets: Vec<crate::scriptlets::ScriptletReport>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ConfigYaml
 {
     pub include: Option<Vec<String>>, //Inne pliki .yaml to tej strukturze
+    #[serde(rename = "variant-includes")]
+    pub variant_includes: Option<BTreeMap<String, Vec<String>>>, //Wariant -> pliki include tylko dla niego (--variant)
     pub r#ref: String, //Branch OSTree
+    pub version: Option<String>, //Wersja obrazu zapisywana w metadanych commita
+    #[serde(default)]
     pub packages: Vec<String>, //Pakiety do instalacji
+    #[serde(rename = "packages-from")]
+    pub packages_from: Option<Utf8PathBuf>, //Plik z listą pakietów (np. z `pacman -Qqe`), względem manifestu
     pub services: Option<Vec<String>>,
-    pub scripts: Option<Vec<Utf8PathBuf>>,
+    pub scripts: Option<Vec<PostScript>>, //Ścieżka albo {path, network}
     pub pacmanConf: Option<String>, //Niestandardowy plik pacman.conf
+    pub kargs: Option<Vec<String>>, //Argumenty jądra wymagane przez obraz
+    pub fsverity: Option<FsVerityMode>, //fs-verity dla obiektów i composefs
+    #[serde(rename = "format-version")]
+    pub format_version: Option<u32>, //Wersja formatu obrazu kontenera
+    pub components: Option<BTreeMap<String, Vec<String>>>, //Komponent -> globy ścieżek (user.component)
+    #[serde(rename = "exclusive-packages")]
+    pub exclusive_packages: Option<Vec<String>>, //Pakiety zawsze w osobnej warstwie
+    #[serde(rename = "banned-packages")]
+    pub banned_packages: Option<Vec<String>>, //Pakiety, których obraz nie może zawierać
+    pub compression: Option<Compression>, //gzip lub zstd dla warstw obrazu
+    #[serde(rename = "compression-level")]
+    pub compression_level: Option<u32>, //Poziom kompresji warstw
+    #[serde(rename = "on-success")]
+    pub on_success: Option<Vec<String>>, //Komendy/webhooki po udanym buildzie
+    #[serde(rename = "on-failure")]
+    pub on_failure: Option<Vec<String>>, //Komendy/webhooki po nieudanym buildzie
+    pub overrides: Option<ManifestOverrides>, //Zastąpienie/usunięcie wartości z plików include
+    pub banner: Option<BannerTarget>, //Baner z wersją obrazu w issue.d lub motd.d
+    #[serde(rename = "special-files")]
+    pub special_files: Option<Vec<SpecialFileRule>>, //Co zrobić z gniazdami/FIFO/urządzeniami
+    pub strict: Option<bool>, //false: nieudane włączenie usług to tylko ostrzeżenie
+    #[serde(rename = "max-duplicate-bytes")]
+    pub max_duplicate_bytes: Option<u64>, //Budżet zduplikowanej zawartości w bajtach
+    #[serde(rename = "max-warnings")]
+    pub max_warnings: Option<usize>, //Limit ostrzeżeń, powyżej którego build kończy się kodem 3
+    #[serde(rename = "scriptlet-failure")]
+    pub scriptlet_failure: Option<crate::scriptlets::ScriptletFailure>, //error lub warn przy nieudanym skrypcie instalacyjnym
+    #[serde(rename = "keyring-populate")]
+    pub keyring_populate: Option<Vec<String>>, //Keyringi dla `pacman-key --populate` (domyślnie archlinux, [] wyłącza)
+    #[serde(rename = "keyring-seed")]
+    pub keyring_seed: Option<Utf8PathBuf>, //Gotowy katalog gnupg kopiowany do /etc/pacman.d/gnupg zamiast --init
+    pub sysext: Option<crate::compose_sysext::SysextConfig>, //Rozszerzenie systemd-sysext dla `compose-sysext`
+    pub xattrs: Option<XattrPolicy>, //Które xattry z plików trafiają do commita
+}
+
+/// Sekcja `overrides:` — stosowana po scaleniu plików z `include`,
+/// pozwala wariantowi zastąpić listy bazowego manifestu lub usunąć z nich wpisy.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestOverrides {
+    pub packages: Option<Vec<String>>,
+    pub services: Option<Vec<String>>,
+    pub scripts: Option<Vec<PostScript>>,
+    pub remove_packages: Option<Vec<String>>,
+    pub remove_services: Option<Vec<String>>,
+}
+
+/// Wpis `scripts:` — sama ścieżka albo `{path: ..., network: false}`.
+/// Bez `network` obowiązuje domyślna izolacja bwrap (sieć odcięta poza nspawn);
+/// `network: false` odcina sieć zawsze, więc skrypt pobierający coś w trakcie compose się wysypie.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum PostScript {
+    Path(Utf8PathBuf),
+    Detailed {
+        path: Utf8PathBuf,
+        network: Option<bool>,
+    },
+}
+
+impl PostScript {
+    pub fn path(&self) -> &Utf8Path {
+        match self {
+            PostScript::Path(path) | PostScript::Detailed { path, .. } => path,
+        }
+    }
+
+    pub fn network(&self) -> Option<bool> {
+        match self {
+            PostScript::Path(_) => None,
+            PostScript::Detailed { network, .. } => *network,
+        }
+    }
 }
 
 impl ConfigYaml
@@ -71,6 +245,20 @@ impl ConfigYaml
         self.r#ref = other.r#ref;
         self.packages.extend(other.packages);
         self.pacmanConf = other.pacmanConf.or(self.pacmanConf.clone());
+        self.fsverity = other.fsverity.or(self.fsverity);
+        self.format_version = other.format_version.or(self.format_version);
+        self.compression = other.compression.or(self.compression);
+        self.compression_level = other.compression_level.or(self.compression_level);
+        self.version = other.version.or(self.version.take());
+        self.banner = other.banner.or(self.banner);
+        self.strict = other.strict.or(self.strict);
+        self.max_warnings = other.max_warnings.or(self.max_warnings);
+        self.scriptlet_failure = other.scriptlet_failure.or(self.scriptlet_failure);
+        self.keyring_populate = other.keyring_populate.or(self.keyring_populate.take());
+        self.keyring_seed = other.keyring_seed.or(self.keyring_seed.take());
+        self.sysext = other.sysext.or(self.sysext.take());
+        self.xattrs = other.xattrs.or(self.xattrs.take());
+        self.max_duplicate_bytes = other.max_duplicate_bytes.or(self.max_duplicate_bytes);
 
         // scalanie include
         match (&mut self.include, other.include) {
@@ -79,6 +267,16 @@ impl ConfigYaml
             _ => {} // nic do zrobienia jeśli other.include == None
         }
 
+        match (&mut self.variant_includes, other.variant_includes) {
+            (Some(self_vars), Some(other_vars)) => {
+                for (name, files) in other_vars {
+                    self_vars.entry(name).or_default().extend(files);
+                }
+            }
+            (None, Some(other_vars)) => self.variant_includes = Some(other_vars),
+            _ => {}
+        }
+
         match (&mut self.services, other.services) {
             (Some(self_services), Some(other_services)) => self_services.extend(other_services),
             (None, Some(other_services)) => self.services = Some(other_services),
@@ -91,13 +289,112 @@ impl ConfigYaml
             (None, Some(other_scripts)) => self.scripts = Some(other_scripts),
             _ => {} // nic do zrobienia jeśli other.repos == None
         }
+
+        match (&mut self.kargs, other.kargs) {
+            (Some(self_kargs), Some(other_kargs)) => self_kargs.extend(other_kargs),
+            (None, Some(other_kargs)) => self.kargs = Some(other_kargs),
+            _ => {}
+        }
+
+        match (&mut self.components, other.components) {
+            (Some(self_comps), Some(other_comps)) => {
+                for (name, globs) in other_comps {
+                    self_comps.entry(name).or_default().extend(globs);
+                }
+            }
+            (None, Some(other_comps)) => self.components = Some(other_comps),
+            _ => {}
+        }
+
+        match (&mut self.special_files, other.special_files) {
+            (Some(self_rules), Some(other_rules)) => self_rules.extend(other_rules),
+            (None, Some(other_rules)) => self.special_files = Some(other_rules),
+            _ => {}
+        }
+
+        match (&mut self.exclusive_packages, other.exclusive_packages) {
+            (Some(self_pkgs), Some(other_pkgs)) => self_pkgs.extend(other_pkgs),
+            (None, Some(other_pkgs)) => self.exclusive_packages = Some(other_pkgs),
+            _ => {}
+        }
+
+        match (&mut self.banned_packages, other.banned_packages) {
+            (Some(self_pkgs), Some(other_pkgs)) => self_pkgs.extend(other_pkgs),
+            (None, Some(other_pkgs)) => self.banned_packages = Some(other_pkgs),
+            _ => {}
+        }
+
+        match (&mut self.on_success, other.on_success) {
+            (Some(self_hooks), Some(other_hooks)) => self_hooks.extend(other_hooks),
+            (None, Some(other_hooks)) => self.on_success = Some(other_hooks),
+            _ => {}
+        }
+
+        match (&mut self.on_failure, other.on_failure) {
+            (Some(self_hooks), Some(other_hooks)) => self_hooks.extend(other_hooks),
+            (None, Some(other_hooks)) => self.on_failure = Some(other_hooks),
+            _ => {}
+        }
+    }
+
+    fn apply_overrides(&mut self, overrides: ManifestOverrides)
+    {
+        // Najpierw zastąpienia, potem usuwanie
+        if let Some(packages) = overrides.packages {
+            self.packages = packages;
+        }
+        if let Some(services) = overrides.services {
+            self.services = Some(services);
+        }
+        if let Some(scripts) = overrides.scripts {
+            self.scripts = Some(scripts);
+        }
+
+        if let Some(remove) = overrides.remove_packages {
+            self.packages.retain(|p| !remove.contains(p));
+        }
+        if let (Some(services), Some(remove)) = (&mut self.services, overrides.remove_services) {
+            services.retain(|s| !remove.contains(s));
+        }
     }
 }
 
+/// Lista pakietów: jeden lub więcej na linię, `#` zaczyna komentarz
+pub fn parse_package_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default())
+        .flat_map(str::split_whitespace)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Czyta listę pakietów z pliku albo ze standardowego wejścia (`-`)
+pub fn read_package_list(path: &Utf8Path) -> anyhow::Result<Vec<String>> {
+    let contents = if path == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Reading package list from stdin")?
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Reading package list {}", path))?
+    };
+    Ok(parse_package_list(&contents))
+}
+
 pub fn yaml_parse(path: &str) -> anyhow::Result<ConfigYaml> {
+    yaml_parse_variant(path, None)
+}
+
+/// Jak `yaml_parse`, a dla wariantu dodatkowo scala jego pliki z `variant-includes`
+/// (po zwykłych include, przed `overrides`)
+pub fn yaml_parse_variant(path: &str, variant: Option<&str>) -> anyhow::Result<ConfigYaml> {
     let contents = fs::read_to_string(path)?;
     let mut config: ConfigYaml = serde_yaml::from_str(&contents)?;
 
+    // Lista z pliku należy do manifestu, który ją podał, więc ścieżka jest względem niego
+    if let Some(list) = config.packages_from.take() {
+        let dir = Utf8Path::new(path).parent().unwrap_or(Utf8Path::new(""));
+        config.packages.extend(read_package_list(&dir.join(list))?);
+    }
+
     // Wczytaj i scal pliki z `include`
     if let Some(include_files) = config.include.clone() {
         for inc_path in include_files {
@@ -106,6 +403,23 @@ pub fn yaml_parse(path: &str) -> anyhow::Result<ConfigYaml> {
         }
     }
 
+    if let Some(variant) = variant {
+        let files = config
+            .variant_includes
+            .as_ref()
+            .and_then(|v| v.get(variant))
+            .cloned()
+            .ok_or_else(|| anyhow!("Variant {} is not defined in variant-includes of {}", variant, path))?;
+        for inc_path in files {
+            let included = yaml_parse(inc_path.as_str())?;
+            config.merge(included);
+        }
+    }
+
+    // overrides dotyczą tylko tego pliku i jego include
+    if let Some(overrides) = config.overrides.take() {
+        config.apply_overrides(overrides);
+    }
 
     Ok(config)
 }
@@ -115,14 +429,123 @@ const USR: &str = "usr";
 const ETC: &str = "etc";
 const USR_ETC: &str = "usr/etc";
 const OCI_ARCHIVE_TRANSPORT: &str = "oci-archive";
+/// Klucz metadanych commita z argumentami jądra z manifestu (`as`)
+pub const KARGS_META_KEY: &str = "pacman-ostree.kargs";
+/// Klucz metadanych commita z polityką xattrów z manifestu (JSON), używaną też przy rebuildzie warstw
+pub const XATTRS_META_KEY: &str = "pacman-ostree.xattrs";
+/// Klucz metadanych commita i etykiety obrazu z pakietami obrazu (JSON nazwa -> wersja),
+/// z których `upgrade --check` pokazuje zmiany bez pobierania drzewa
+pub const PACKAGES_META_KEY: &str = "pacman-ostree.packages";
 
-pub async fn compose_image(opts: ComposeImageOpts) -> anyhow::Result<()> {
+/// `obraz.ociarchive` -> `obraz-kde.ociarchive`
+pub fn variant_path(path: &Utf8Path, variant: &str) -> Utf8PathBuf {
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!("{}-{}.{}", stem, variant, ext),
+        _ => format!("{}-{}", path.file_name().unwrap_or_default(), variant),
+    };
+    path.with_file_name(name)
+}
+
+pub async fn compose_image(mut opts: ComposeImageOpts) -> anyhow::Result<()> {
+    if !opts.variants.is_empty() {
+        return crate::compose_variants::compose_variants(&opts);
+    }
+    if let Some(variant) = opts.variant.as_deref() {
+        opts.output = variant_path(&opts.output, variant);
+        opts.write_composejson_to = opts.write_composejson_to.as_deref().map(|p| variant_path(p, variant));
+    }
     println!("Reading config from: {}", opts.manifest);
     //Sprawdzenie czy plik istnieje
     if !opts.manifest.exists() {
-        return Err(anyhow!("Config file {} does not exist", opts.manifest));
+        let err = anyhow!("Config file {} does not exist", opts.manifest);
+        compose_hooks::run_on_failure(&opts.on_failure, None, &err);
+        return Err(err);
+    }
+    let mut config = match yaml_parse_variant(opts.manifest.as_str(), opts.variant.as_deref()) {
+        Ok(c) => c,
+        Err(e) => {
+            compose_hooks::run_on_failure(&opts.on_failure, None, &e);
+            return Err(e);
+        }
+    };
+
+    // Polityka z CLI (--scriptlet-failure) jest już ustawiona i ma pierwszeństwo
+    crate::scriptlets::init(config.scriptlet_failure);
+    if let Some(list) = opts.packages_from.as_deref() {
+        match read_package_list(list) {
+            Ok(packages) => config.packages.extend(packages),
+            Err(e) => {
+                compose_hooks::run_on_failure(&opts.on_failure, Some(&config.r#ref), &e);
+                return Err(e);
+            }
+        }
+    }
+    if opts.fsverity.is_some() {
+        config.fsverity = opts.fsverity;
+    }
+    if opts.format_version.is_some() {
+        config.format_version = opts.format_version;
     }
-    let config = yaml_parse(opts.manifest.as_str())?;
+    if opts.compression.is_some() {
+        config.compression = opts.compression;
+    }
+    if opts.compression_level.is_some() {
+        config.compression_level = opts.compression_level;
+    }
+    if opts.max_warnings.is_some() {
+        config.max_warnings = opts.max_warnings;
+    }
+    if opts.max_duplicate_bytes.is_some() {
+        config.max_duplicate_bytes = opts.max_duplicate_bytes;
+    }
+    // Sprawdzamy przed instalacją pakietów, żeby nie budować obrazu na próżno
+    let check = crate::container::check_format_version(config.format_version.unwrap_or(crate::container::DEFAULT_FORMAT_VERSION))
+        .and_then(|_| config.compression.unwrap_or_default().check_level(config.compression_level));
+    if let Err(e) = check {
+        compose_hooks::run_on_failure(&opts.on_failure, Some(&config.r#ref), &e);
+        return Err(e);
+    }
+
+    // Hooki z CLI uruchamiane są po hookach z manifestu
+    let on_success: Vec<String> = config.on_success.iter().flatten().chain(&opts.on_success).cloned().collect();
+    let on_failure: Vec<String> = config.on_failure.iter().flatten().chain(&opts.on_failure).cloned().collect();
+
+    match compose_image_impl(&opts, &config).await {
+        Ok(composejson) => {
+            if opts.timings {
+                crate::timings::print_report();
+            }
+            if let Some(path) = opts.write_composejson_to.as_ref() {
+                // Nieudany zapis metadanych to nieudany build — hooki on_failure też muszą ruszyć
+                let written = fs::File::create(path)
+                    .with_context(|| format!("Creating {}", path))
+                    .and_then(|f| serde_json::to_writer_pretty(f, &composejson).with_context(|| format!("Writing {}", path)));
+                if let Err(e) = written {
+                    compose_hooks::run_on_failure(&on_failure, Some(&config.r#ref), &e);
+                    return Err(e);
+                }
+                println!("Wrote build metadata to {}", path);
+            }
+            // Obraz jest zbudowany, ale build nie przechodzi polityki ostrzeżeń
+            if let Some(max) = config.max_warnings.filter(|max| composejson.warnings.len() > *max) {
+                let err = anyhow::Error::new(crate::warnings::TooManyWarnings {
+                    count: composejson.warnings.len(),
+                    max,
+                });
+                compose_hooks::run_on_failure(&on_failure, Some(&config.r#ref), &err);
+                return Err(err);
+            }
+            compose_hooks::run_on_success(&on_success, &composejson);
+            Ok(())
+        }
+        Err(e) => {
+            compose_hooks::run_on_failure(&on_failure, Some(&config.r#ref), &e);
+            Err(e)
+        }
+    }
+}
+
+async fn compose_image_impl(opts: &ComposeImageOpts, config: &ConfigYaml) -> anyhow::Result<ComposeJson> {
     //Stworzenie tymczasowego katalogu do pracy
     let temp_dir = TempDir::new()?;
     let temp_dir_cap = Dir::open_ambient_dir(temp_dir.path(), ambient_authority())?;
@@ -130,22 +553,86 @@ pub async fn compose_image(opts: ComposeImageOpts) -> anyhow::Result<()> {
     let pacman_conf = config.pacmanConf.as_ref().map(|s| vec![s.clone()]);
 
 
-    install_packages_compose(&temp_dir, config.packages.clone(), pacman_conf).await?;
+    let banned = config.banned_packages.clone().unwrap_or_default();
+    install_packages_compose(
+        &temp_dir,
+        config.packages.clone(),
+        pacman_conf,
+        opts.package_cache.as_deref(),
+        &banned,
+    )
+    .await?;
+    let licenses = crate::licenses::LicenseReport::from_descs(&crate::pacman_manager::read_descs_from_dir(temp_dir.path())?);
+    if opts.fail_on_vuln {
+        let installed = crate::pacman_manager::read_packages_from_dir(temp_dir.path())?;
+        crate::advisories::check_compose_packages(&installed)?;
+    }
+    let postprocess_timer = crate::timings::stage("postprocess");
     composepost::compose_post(
-        &config,               // &ConfigYaml
+        config,                // &ConfigYaml
         &temp_dir_cap,         // &Dir
      temp_dir.path().to_str().unwrap(), // &str
     )?;
+    if let Some(components) = config.components.as_ref() {
+        let rootfs = camino::Utf8Path::from_path(temp_dir.path())
+            .ok_or_else(|| anyhow!("Invalid UTF-8 path: {}", temp_dir.path().display()))?;
+        crate::components::assign_components(rootfs, components)?;
+    }
 
     let repo_path = opts.ostree_repo.as_str();
     if !Path::new(repo_path).exists() {
+        // Ten sam tryb co repo docelowe pozwala pull-local hardlinkować obiekty zamiast je kopiować
+        let mode = match opts.pull_local_to.as_ref() {
+            Some(target) => Repo::open_at(libc::AT_FDCWD, target.as_str(), gio::Cancellable::NONE)
+                .with_context(|| format!("Opening {}", target))?
+                .mode(),
+            None => RepoMode::BareUser,
+        };
         println!("Creating new OSTree repo at {}", repo_path);
-        Repo::create_at(libc::AT_FDCWD, repo_path, RepoMode::BareUser, None, gio::Cancellable::NONE)?;
+        Repo::create_at(libc::AT_FDCWD, repo_path, mode, None, gio::Cancellable::NONE)?;
     }
     let repo = Repo::open_at(libc::AT_FDCWD, repo_path, gio::Cancellable::NONE)?;
+    if let Some(fsverity) = config.fsverity {
+        crate::fsverity::configure_repo(&repo, fsverity)?;
+    }
     let creation_time = chrono::Utc::now().with_timezone(&chrono::FixedOffset::east(0));
+    let commitmeta = glib::VariantDict::new(None);
+    if let Some(target) = config.banner {
+        let info = BannerInfo {
+            version: config.version.as_deref(),
+            build_date: creation_time.with_timezone(&chrono::Utc),
+            base_ref: &config.r#ref,
+            layered_packages: 0,
+        };
+        banner::write_banner(&temp_dir_cap, target, &info)?;
+        banner::insert_banner_meta(&commitmeta, target);
+    }
+    let identity = crate::os_release::ImageIdentity::new(&config.r#ref, config.version.as_deref(), &creation_time);
+    crate::os_release::write_identity(&temp_dir_cap, &identity)?;
+    crate::os_release::insert_identity_meta(&commitmeta, &identity);
     println!("Generating OSTree commit from rootfs...");
-    let commit = generate_commit_from_rootfs(&repo, &temp_dir_cap, Some(&creation_time))?;
+    if let Some(version) = config.version.as_ref() {
+        commitmeta.insert("version", version.as_str());
+    }
+    if let Some(kargs) = config.kargs.as_ref() {
+        commitmeta.insert_value(KARGS_META_KEY, &kargs.to_variant());
+    }
+    let packages = crate::pacman_manager::read_packages_from_dir(temp_dir.path())?;
+    commitmeta.insert(PACKAGES_META_KEY, serde_json::to_string(&packages)?);
+    let xattrs = config.xattrs.clone().unwrap_or_default();
+    commitmeta.insert(XATTRS_META_KEY, serde_json::to_string(&xattrs)?);
+    crate::bootable::insert_bootable_meta(&commitmeta, &temp_dir_cap)?;
+    drop(postprocess_timer);
+    let commit = {
+        let _t = crate::timings::stage("commit");
+        generate_commit_from_rootfs(&repo, &temp_dir_cap, Some(&creation_time), &commitmeta, &xattrs)?
+    };
+    repo.set_ref_immediate(None, &config.r#ref, Some(&commit), gio::Cancellable::NONE)
+        .with_context(|| format!("Setting ref {}", config.r#ref))?;
+    if let Some(target) = opts.pull_local_to.as_ref() {
+        let _t = crate::timings::stage("pull-local");
+        pull_local(&opts.ostree_repo, target, &config.r#ref)?;
+    }
 
     let _repo = ostree_ext::cli::parse_repo(&opts.ostree_repo)
         .context("Parsing repo")?;
@@ -159,23 +646,66 @@ pub async fn compose_image(opts: ComposeImageOpts) -> anyhow::Result<()> {
     .join("usr/share/pacman/local");
     let container_opts = ContainerEncapsulateOpts {
         repo: opts.ostree_repo.clone(),
-        ostree_ref: commit,
+        ostree_ref: commit.clone(),
         imgref: imgreference,
         labels: vec![],
         image_config: None,
         arch: None,
         copy_meta_keys: vec![],
-        copy_meta_opt_keys: vec![],
+        copy_meta_opt_keys: vec![PACKAGES_META_KEY.to_string()],
         cmd: None,
         max_layers: opts.max_layers,
-        format_version: 2,
+        format_version: config.format_version.unwrap_or(crate::container::DEFAULT_FORMAT_VERSION),
         write_contentmeta_json: None,
         compare_with_build: None,
         previous_build_manifest: None,
         pacman_db_path: pacman_db_path,
+        exclusive_packages: config
+            .exclusive_packages
+            .iter()
+            .flatten()
+            .chain(&opts.exclusive_packages)
+            .cloned()
+            .collect(),
+        compression: config.compression,
+        compression_level: config.compression_level,
+        compression_jobs: opts.compression_jobs,
+        max_duplicate_bytes: config.max_duplicate_bytes,
+    };
+
+    let report = {
+        let _t = crate::timings::stage("export");
+        container_encapsulate(container_opts).await?
     };
 
-    let digest = container_encapsulate(container_opts).await?;
+    Ok(ComposeJson {
+        r#ref: config.r#ref.clone(),
+        ostree_commit: commit.clone(),
+        digest: report.digest,
+        layers: report.layers,
+        layer_warnings: report.layer_warnings,
+        timings: crate::timings::report(),
+        warnings: crate::warnings::collected(),
+        dedup: report.dedup,
+        licenses,
+        scriptlets: crate::scriptlets::collected(),
+    })
+}
+
+/// Kopiuje ref z repo buildu do innego repo; na tym samym systemie plików
+/// i przy zgodnym trybie repo ostree hardlinkuje obiekty
+fn pull_local(build_repo: &Utf8Path, target: &Utf8Path, r#ref: &str) -> Result<()> {
+    println!("Pulling {} into {}", r#ref, target);
+    let status = crate::subprocess::status(
+        std::process::Command::new("ostree")
+            .arg(format!("--repo={}", target))
+            .arg("pull-local")
+            .arg(build_repo)
+            .arg(r#ref),
+    )?;
+    if !status.success() {
+        anyhow::bail!("ostree pull-local into {} failed with {:?}", target, status.code());
+    }
     Ok(())
 }
 
@@ -184,6 +714,8 @@ pub async fn install_packages_compose(
     dir: &TempDir,
     package_names: Vec<String>,
     pacman_conf: Option<Vec<String>>,
+    package_cache: Option<&Utf8Path>,
+    banned: &[String],
 ) -> anyhow::Result<()> {
 
     // Vec<String> → Vec<&str>
@@ -200,7 +732,7 @@ pub async fn install_packages_compose(
         .as_ref()
         .and_then(|v| v.first())
         .map(|s| s.as_str());
-    install_packages(pkg_refs, root, pacman_conf_ref).await?;
+    install_packages_with_cache(pkg_refs, root, pacman_conf_ref, package_cache.map(|p| p.as_str()), banned).await?;
     Ok(())
 }
 
@@ -273,6 +805,150 @@ fn label_to_xattrs(label: Option<&str>) -> Option<glib::Variant> {
     xattrs.map(|x| x.to_variant())
 }
 
+/// Nazwy xattrów pliku (bez podążania za symlinkiem), każda zakończona zerem
+fn list_xattrs(path: &std::ffi::CStr) -> Vec<Vec<u8>> {
+    // SAFETY: pusty bufor — pytamy tylko o rozmiar listy
+    let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        return Vec::new();
+    }
+    let mut buf = vec![0u8; size as usize];
+    // SAFETY: bufor ma podany rozmiar
+    let n = unsafe { libc::llistxattr(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    if n <= 0 {
+        return Vec::new();
+    }
+    buf[..n as usize]
+        .split_inclusive(|b| *b == 0)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+fn get_xattr(path: &std::ffi::CStr, name: &[u8]) -> Option<Vec<u8>> {
+    let name = std::ffi::CStr::from_bytes_with_nul(name).ok()?;
+    // SAFETY: jak wyżej, najpierw rozmiar, potem odczyt do bufora tego rozmiaru
+    let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; size as usize];
+    // SAFETY: bufor ma podany rozmiar
+    let n = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    if n < 0 {
+        return None;
+    }
+    buf.truncate(n as usize);
+    Some(buf)
+}
+
+/// Co robić z xattrami plików rootfs przy zapisie commita (`policy` w sekcji `xattrs:`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum XattrPolicyKind {
+    /// Wszystkie xattry z dysku
+    KeepAll,
+    /// security.capability i nazwy pasujące do `allow`
+    #[default]
+    Allowlist,
+    /// Żadnych xattrów z dysku
+    Strip,
+}
+
+/// Sekcja `xattrs:` manifestu. Niezależnie od polityki user.component (podział na warstwy)
+/// zostaje zawsze, a security.selinux nigdy — etykiety nadaje polityka SELinux obrazu.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct XattrPolicy {
+    #[serde(default)]
+    pub policy: XattrPolicyKind,
+    /// Globy nazw dla `allowlist`, np. `security.*` albo `user.myapp.*`
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl XattrPolicy {
+    fn keeps(&self, name: &str) -> bool {
+        if name == "security.selinux" {
+            return false;
+        }
+        match self.policy {
+            XattrPolicyKind::KeepAll => true,
+            XattrPolicyKind::Strip => false,
+            XattrPolicyKind::Allowlist => {
+                name == "security.capability"
+                    || self.allow.iter().any(|p| glob::Pattern::new(p).is_ok_and(|p| p.matches(name)))
+            }
+        }
+    }
+}
+
+/// Xattr, w którym checkout z repo bare-user (np. rootless) trzyma właściciela, tryb
+/// i prawdziwe xattry pliku; sam nie może trafić do commita
+const OSTREEMETA_XATTR: &[u8] = b"user.ostreemeta\0";
+
+/// Xattry zapisane w `user.ostreemeta`, format `(uuua(ayay))` jak w repo bare-user
+fn ostreemeta_xattrs(value: &[u8]) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let ty = glib::VariantTy::new("(uuua(ayay))").ok()?;
+    let meta = glib::Variant::from_data_with_type(value.to_vec(), ty);
+    meta.child_value(3).get()
+}
+
+/// Xattry pominięte przez politykę i pliki, z których rozpakowano `user.ostreemeta`
+#[derive(Debug, Default)]
+struct XattrStats {
+    dropped: BTreeMap<String, usize>,
+    converted: usize,
+}
+
+/// Xattry z dysku przenoszone do commita według polityki. `user.ostreemeta` jest zamieniany
+/// na xattry, które w sobie przechowuje, i te dopiero przechodzą przez politykę.
+fn disk_xattrs(
+    rootfs_fd: i32,
+    relpath: &str,
+    policy: &XattrPolicy,
+    stats: &Mutex<XattrStats>,
+) -> glib::Variant {
+    let path = format!("/proc/self/fd/{}/{}", rootfs_fd, relpath.trim_start_matches('/'));
+    let Ok(cpath) = std::ffi::CString::new(path) else {
+        return Vec::<(Vec<u8>, Vec<u8>)>::new().to_variant();
+    };
+    let mut found: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for name in list_xattrs(&cpath) {
+        let Some(value) = get_xattr(&cpath, &name) else {
+            continue;
+        };
+        if name == OSTREEMETA_XATTR {
+            if let Some(embedded) = ostreemeta_xattrs(&value) {
+                found.extend(embedded);
+                if let Ok(mut stats) = stats.lock() {
+                    stats.converted += 1;
+                }
+            }
+            continue;
+        }
+        found.push((name, value));
+    }
+
+    let mut xattrs: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for (name, value) in found {
+        let text = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(&name)).into_owned();
+        let internal = name == crate::components::COMPONENT_XATTR.to_bytes_with_nul();
+        if !internal && !policy.keeps(&text) {
+            // Etykiety SELinux z dysku zastępuje polityka, to nie jest utrata danych
+            if text != "security.selinux" {
+                if let Ok(mut stats) = stats.lock() {
+                    *stats.dropped.entry(text).or_default() += 1;
+                }
+            }
+            continue;
+        }
+        if !xattrs.iter().any(|(n, _)| *n == name) {
+            xattrs.push((name, value));
+        }
+    }
+    xattrs.to_variant()
+}
+
 fn create_root_dirmeta(root: &Dir, policy: &ostree::SePolicy) -> Result<glib::Variant> {
     let finfo = gio::FileInfo::new();
     let meta = root.dir_metadata()?;
@@ -285,7 +961,13 @@ fn create_root_dirmeta(root: &Dir, policy: &ostree::SePolicy) -> Result<glib::Va
     Ok(r)
 }
 
-fn generate_commit_from_rootfs(repo: &Repo, rootfs: &Dir, creation_time: Option<&chrono::DateTime<chrono::FixedOffset>>) -> anyhow::Result<String> {
+pub(crate) fn generate_commit_from_rootfs(
+    repo: &Repo,
+    rootfs: &Dir,
+    creation_time: Option<&chrono::DateTime<chrono::FixedOffset>>,
+    commitmeta: &glib::VariantDict,
+    xattrs: &XattrPolicy,
+) -> anyhow::Result<String> {
     let root_mtree = MutableTree::new();
     let cancellable = gio::Cancellable::NONE;
     let tx = repo.auto_transaction(cancellable)?;
@@ -297,6 +979,12 @@ fn generate_commit_from_rootfs(repo: &Repo, rootfs: &Dir, creation_time: Option<
 
     let policy = ostree::SePolicy::new_at(rootfs.as_fd().as_raw_fd(), cancellable)?;
     modifier.set_sepolicy(Some(&policy));
+    // SKIP_XATTRS pomija odczyt ostree z dysku; xattry dobiera callback według polityki
+    let rootfs_fd = rootfs.as_raw_fd();
+    let xattr_policy = xattrs.clone();
+    let stats = Arc::new(Mutex::new(XattrStats::default()));
+    let stats_cb = Arc::clone(&stats);
+    modifier.set_xattr_callback(move |_repo, relpath, _info| disk_xattrs(rootfs_fd, relpath, &xattr_policy, &stats_cb));
 
     let root_dirmeta = create_root_dirmeta(rootfs, &policy)?;
     let root_metachecksum = repo.write_metadata(
@@ -319,6 +1007,15 @@ fn generate_commit_from_rootfs(repo: &Repo, rootfs: &Dir, creation_time: Option<
         cancellable,
     )?;
 
+    if let Ok(stats) = stats.lock() {
+        if stats.converted > 0 {
+            println!("Converted user.ostreemeta xattrs of {} file(s)", stats.converted);
+        }
+        for (name, files) in stats.dropped.iter() {
+            println!("Dropped xattr {} from {} file(s) (not allowed by the xattrs policy)", name, files);
+        }
+    }
+
     postprocess_mtree(repo, &root_mtree)?;
 
     let ostree_root = repo.write_mtree(&root_mtree, cancellable)?;
@@ -330,8 +1027,6 @@ fn generate_commit_from_rootfs(repo: &Repo, rootfs: &Dir, creation_time: Option<
         .try_into()
         .context("Parsing creation time")?;
 
-    let mut commitmeta = glib::VariantDict::new(None);
-
     let commit = repo.write_commit_with_time(
         None, 
         None, 
@@ -343,7 +1038,72 @@ fn generate_commit_from_rootfs(repo: &Repo, rootfs: &Dir, creation_time: Option<
     )?;
 
     println!("Generated commit: {}", commit);
+    crate::commit_signing::sign(repo, &commit)?;
 
     tx.commit(cancellable)?;
     Ok(commit.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_replace_and_remove() {
+        let mut config: ConfigYaml = serde_yaml::from_str(
+            "ref: test\npackages: [base, linux, nano]\nservices: [sshd, cups]\n",
+        ).unwrap();
+        config.apply_overrides(ManifestOverrides {
+            services: Some(vec!["NetworkManager".into()]),
+            remove_packages: Some(vec!["nano".into()]),
+            ..Default::default()
+        });
+        assert_eq!(config.packages, vec!["base", "linux"]);
+        assert_eq!(config.services, Some(vec!["NetworkManager".to_string()]));
+    }
+
+    #[test]
+    fn test_post_script_entries() {
+        let config: ConfigYaml = serde_yaml::from_str(
+            "ref: test\nscripts:\n  - a.sh\n  - path: b.sh\n    network: false\n",
+        ).unwrap();
+        let scripts = config.scripts.unwrap();
+        assert_eq!(scripts[0], PostScript::Path("a.sh".into()));
+        assert_eq!(scripts[1].path(), "b.sh");
+        assert_eq!(scripts[1].network(), Some(false));
+    }
+
+    #[test]
+    fn test_xattr_policy() {
+        let config: ConfigYaml = serde_yaml::from_str(
+            "ref: test\nxattrs:\n  allow: [\"security.*\"]\n",
+        ).unwrap();
+        let xattrs = config.xattrs.unwrap();
+        assert_eq!(xattrs.policy, XattrPolicyKind::Allowlist);
+        assert!(xattrs.keeps("security.ima"));
+        assert!(!xattrs.keeps("security.selinux"));
+        assert!(!xattrs.keeps("user.foo"));
+        assert!(XattrPolicy::default().keeps("security.capability"));
+        let strip = XattrPolicy { policy: XattrPolicyKind::Strip, allow: Vec::new() };
+        assert!(!strip.keeps("security.capability"));
+    }
+
+    #[test]
+    fn test_ostreemeta_xattrs() {
+        let embedded = vec![(b"security.capability\0".to_vec(), vec![1u8, 2, 3])];
+        let meta = (0u32, 0u32, 0o100644u32, embedded.clone()).to_variant();
+        assert_eq!(ostreemeta_xattrs(&meta.data_as_bytes()), Some(embedded));
+    }
+
+    #[test]
+    fn test_parse_package_list() {
+        let list = "base\nlinux linux-firmware\n\n# edytory\nnano # mały\n";
+        assert_eq!(parse_package_list(list), vec!["base", "linux", "linux-firmware", "nano"]);
+    }
+
+    #[test]
+    fn test_variant_path() {
+        assert_eq!(variant_path(Utf8Path::new("out/image.ociarchive"), "kde"), "out/image-kde.ociarchive");
+        assert_eq!(variant_path(Utf8Path::new("image"), "gnome"), "image-gnome");
+    }
+}
@@ -0,0 +1,76 @@
+// Delty statyczne między kolejnymi wynikami compose (`compose-delta`)
+//
+// Klient z deltą od swojej bazy pobiera przy `upgrade` jeden skompresowany plik różnic
+// zamiast pojedynczych obiektów; `upgrade` pokazuje też z niej rozmiar pobierania.
+
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+
+use crate::deploy::resolve_commit;
+
+#[derive(Parser, Debug)]
+pub struct ComposeDeltaOpts {
+    /// OSTree repo with both commits (the --ostree-repo of compose)
+    #[clap(long)]
+    pub ostree_repo: Utf8PathBuf,
+
+    /// Ref or commit clients upgrade from
+    #[clap(long, required_unless_present = "empty")]
+    pub from: Option<String>,
+
+    /// Ref or commit clients upgrade to
+    #[clap(long)]
+    pub to: String,
+
+    /// Generate a delta from nothing, for fresh installs, instead of from --from
+    #[clap(long, conflicts_with = "from")]
+    pub empty: bool,
+
+    /// Maximum size of a delta part in MiB
+    #[clap(long)]
+    pub max_chunk_size: Option<u32>,
+
+    /// Also regenerate the repo summary after writing the delta, signed with --gpg-sign/--sign-ed25519
+    #[clap(long)]
+    pub update_summary: bool,
+}
+
+pub fn compose_delta(opts: ComposeDeltaOpts) -> Result<()> {
+    let cancellable = gio::Cancellable::NONE;
+    let repo = ostree::Repo::open_at(libc::AT_FDCWD, opts.ostree_repo.as_str(), cancellable)
+        .with_context(|| format!("Opening {}", opts.ostree_repo))?;
+    let to = resolve_commit(&repo, &opts.to)?;
+    let from = opts.from.as_deref().map(|r| resolve_commit(&repo, r)).transpose()?;
+    if from.as_deref() == Some(to.as_str()) {
+        anyhow::bail!("{} and {} are the same commit", opts.from.unwrap_or_default(), opts.to);
+    }
+
+    let params = glib::VariantDict::new(None);
+    if let Some(size) = opts.max_chunk_size {
+        params.insert("max-chunk-size", size);
+    }
+    match &from {
+        Some(from) => println!("Generating static delta {} -> {}...", from, to),
+        None => println!("Generating static delta for {} from scratch...", to),
+    }
+    repo.static_delta_generate(
+        ostree::StaticDeltaGenerateOpt::Major,
+        from.as_deref(),
+        &to,
+        None,
+        Some(&params.end()),
+        cancellable,
+    )
+    .context("Generating static delta")?;
+
+    if opts.update_summary {
+        crate::commit_signing::regenerate_summary(&repo)?;
+    }
+    match &from {
+        Some(from) => println!("Wrote delta {}-{}", from, to),
+        None => println!("Wrote delta {}", to),
+    }
+    Ok(())
+}
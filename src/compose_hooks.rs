@@ -0,0 +1,79 @@
+// Powiadomienia po zakończeniu compose (on-success / on-failure)
+
+use std::process::Command;
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::compose::ComposeJson;
+
+/// Treść wysyłana do hooków on-failure
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct FailurePayload<'a> {
+    r#ref: Option<&'a str>,
+    error: String,
+}
+
+fn is_webhook(hook: &str) -> bool {
+    hook.starts_with("http://") || hook.starts_with("https://")
+}
+
+/// Webhook dostaje payload jako ciało POST, komenda na stdin
+fn run_hook(hook: &str, payload: &[u8]) -> Result<()> {
+    let mut cmd = if is_webhook(hook) {
+        let mut c = Command::new("curl");
+        c.args([
+            "--fail", "--silent", "--show-error",
+            "-X", "POST",
+            "-H", "Content-Type: application/json",
+            "--data-binary", "@-",
+        ]);
+        c.args(crate::network::config().curl_args()).arg(hook);
+        c
+    } else {
+        let mut c = Command::new("/bin/sh");
+        c.args(["-c", hook]);
+        c
+    };
+
+    let status = crate::subprocess::status_with_input(&mut cmd, payload)
+        .with_context(|| format!("Running hook {}", hook))?;
+    if !status.success() {
+        anyhow::bail!("hook {} exited with {:?}", hook, status.code());
+    }
+    Ok(())
+}
+
+/// Błędy hooków nie zmieniają wyniku compose — tylko je logujemy
+fn run_hooks(hooks: &[String], payload: &[u8]) {
+    for hook in hooks {
+        println!("Running hook {}...", hook);
+        if let Err(e) = run_hook(hook, payload) {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+}
+
+pub fn run_on_success(hooks: &[String], composejson: &ComposeJson) {
+    if hooks.is_empty() {
+        return;
+    }
+    match serde_json::to_vec(composejson) {
+        Ok(payload) => run_hooks(hooks, &payload),
+        Err(e) => eprintln!("Warning: failed to serialize build JSON: {}", e),
+    }
+}
+
+pub fn run_on_failure(hooks: &[String], r#ref: Option<&str>, error: &anyhow::Error) {
+    if hooks.is_empty() {
+        return;
+    }
+    let payload = FailurePayload {
+        r#ref,
+        error: format!("{:#}", error),
+    };
+    match serde_json::to_vec(&payload) {
+        Ok(payload) => run_hooks(hooks, &payload),
+        Err(e) => eprintln!("Warning: failed to serialize failure payload: {}", e),
+    }
+}
@@ -0,0 +1,227 @@
+// Rozszerzenia systemd-sysext budowane z manifestu (`compose-sysext`)
+//
+// Pakiety z `packages:` manifestu są instalowane tylko jako kontekst zależności; do obrazu
+// trafia to, co doinstalowały pakiety z sekcji `sysext:`, i to wyłącznie z /usr i /opt —
+// sysext nie może nieść /etc ani /var, więc zawartość /var z pakietów rozszerzenia trafia
+// do własnego pliku tmpfiles.d (jak przy compose). extension-release musi mieć ID systemu
+// bazowego, inaczej systemd-sysext odmówi nałożenia rozszerzenia.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use serde::Deserialize;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use crate::compose::{install_packages_compose, yaml_parse_variant};
+use crate::var_tmpfiles::{TMPFILES_CONF, TMPFILES_DIR};
+
+/// Katalogi, które systemd-sysext nakłada na system
+const SYSEXT_DIRS: &[&str] = &["usr", "opt"];
+const EXTENSION_RELEASE_DIR: &str = "usr/lib/extension-release.d";
+const OS_RELEASE: &str = "usr/lib/os-release";
+
+#[derive(Parser, Debug)]
+pub struct ComposeSysextOpts {
+    /// Manifest with a `sysext:` section; its `packages` are the base image the extension targets
+    pub manifest: Utf8PathBuf,
+
+    /// Output extension image, e.g. devtools.raw
+    pub output: Utf8PathBuf,
+
+    /// Build a variant from `variant-includes` of the manifest
+    #[clap(long)]
+    pub variant: Option<String>,
+
+    /// Package cache directory, e.g. shared between builds (default: inside the build root)
+    #[clap(long)]
+    pub package_cache: Option<Utf8PathBuf>,
+}
+
+/// Sekcja `sysext:` manifestu
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SysextConfig {
+    /// Nazwa rozszerzenia (extension-release.<name>); musi zgadzać się z nazwą pliku obrazu
+    pub name: String,
+    pub packages: Vec<String>,
+    /// ID z os-release systemu (domyślnie z zainstalowanej bazy); `_any` pasuje do każdego
+    pub os_id: Option<String>,
+    pub sysext_level: Option<String>,
+    pub version_id: Option<String>,
+    #[serde(default)]
+    pub format: SysextFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SysextFormat {
+    #[default]
+    Squashfs,
+    Erofs,
+}
+
+/// Ścieżki (względne) w katalogach sysext rozpakowanego drzewa
+fn tree_paths(root: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut paths = BTreeSet::new();
+    for dir in SYSEXT_DIRS {
+        if !root.join(dir).exists() {
+            continue;
+        }
+        for entry in WalkDir::new(root.join(dir)) {
+            let entry = entry?;
+            paths.insert(entry.path().strip_prefix(root)?.to_path_buf());
+        }
+    }
+    Ok(paths)
+}
+
+/// Kopiuje jeden wpis drzewa z uprawnieniami; katalogi nadrzędne tworzy WalkDir wcześniej
+fn copy_entry(src_root: &Path, dest_root: &Path, rel: &Path) -> Result<()> {
+    let src = src_root.join(rel);
+    let dest = dest_root.join(rel);
+    let meta = src.symlink_metadata()?;
+    if meta.file_type().is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(&src)?, &dest)?;
+    } else if meta.is_dir() {
+        std::fs::create_dir_all(&dest)?;
+        std::fs::set_permissions(&dest, meta.permissions())?;
+    } else {
+        std::fs::copy(&src, &dest).with_context(|| format!("Copying {}", rel.display()))?;
+    }
+    Ok(())
+}
+
+/// Wpisy tmpfiles.d dla /var z pakietów drzewa; kopie plików trafiają do usr/share/factory
+fn var_tmpfiles_lines(rootfs: &Path) -> Result<BTreeSet<String>> {
+    let root = Utf8Path::from_path(rootfs).ok_or_else(|| anyhow!("Invalid UTF-8 path {}", rootfs.display()))?;
+    crate::var_tmpfiles::generate_var_tmpfiles(root)?;
+    Ok(std::fs::read_to_string(root.join(TMPFILES_DIR).join(TMPFILES_CONF))
+        .map(|conf| conf.lines().map(str::to_string).collect())
+        .unwrap_or_default())
+}
+
+/// Wartość `ID=` z os-release drzewa
+fn os_id(rootfs: &Path) -> Result<String> {
+    let contents = std::fs::read_to_string(rootfs.join(OS_RELEASE))
+        .with_context(|| format!("Reading {}", OS_RELEASE))?;
+    contents
+        .lines()
+        .find_map(|l| l.strip_prefix("ID="))
+        .map(|id| id.trim_matches('"').to_string())
+        .ok_or_else(|| anyhow!("{} has no ID; set `os-id` in the sysext section", OS_RELEASE))
+}
+
+fn extension_release(config: &SysextConfig, os_id: &str) -> String {
+    let mut contents = format!("ID={}\n", os_id);
+    if let Some(level) = &config.sysext_level {
+        contents.push_str(&format!("SYSEXT_LEVEL={}\n", level));
+    }
+    if let Some(version) = &config.version_id {
+        contents.push_str(&format!("VERSION_ID={}\n", version));
+    }
+    contents
+}
+
+fn make_image(staging: &Path, output: &Utf8PathBuf, format: SysextFormat) -> Result<()> {
+    let _ = std::fs::remove_file(output);
+    let mut cmd = match format {
+        SysextFormat::Squashfs => {
+            let mut c = Command::new("mksquashfs");
+            c.arg(staging).arg(output).args(["-all-root", "-noappend", "-quiet"]);
+            c
+        }
+        SysextFormat::Erofs => {
+            let mut c = Command::new("mkfs.erofs");
+            c.arg("--all-root").arg(output).arg(staging);
+            c
+        }
+    };
+    let status = crate::subprocess::status(&mut cmd)?;
+    if !status.success() {
+        anyhow::bail!("Creating {} failed with {}", output, status);
+    }
+    Ok(())
+}
+
+pub async fn compose_sysext(opts: ComposeSysextOpts) -> Result<()> {
+    println!("Reading config from: {}", opts.manifest);
+    let config = yaml_parse_variant(opts.manifest.as_str(), opts.variant.as_deref())?;
+    let sysext = config
+        .sysext
+        .clone()
+        .ok_or_else(|| anyhow!("{} has no `sysext:` section", opts.manifest))?;
+    if sysext.packages.is_empty() {
+        anyhow::bail!("The sysext section of {} lists no packages", opts.manifest);
+    }
+    let pacman_conf = config.pacmanConf.as_ref().map(|s| vec![s.clone()]);
+    let banned = config.banned_packages.clone().unwrap_or_default();
+
+    let rootfs = TempDir::new()?;
+    println!("Installing base packages...");
+    install_packages_compose(&rootfs, config.packages.clone(), pacman_conf.clone(), opts.package_cache.as_deref(), &banned)
+        .await?;
+    // Przed zebraniem ścieżek bazy, żeby jej kopie w usr/share/factory nie trafiły do rozszerzenia
+    let base_var = var_tmpfiles_lines(rootfs.path())?;
+    let base_paths = tree_paths(rootfs.path())?;
+    let os_id = match &sysext.os_id {
+        Some(id) => id.clone(),
+        None => os_id(rootfs.path())?,
+    };
+
+    // Baza razem z rozszerzeniem, żeby zależności rozwiązały się tak jak na systemie
+    println!("Installing extension packages...");
+    let all: Vec<String> = config.packages.iter().chain(&sysext.packages).cloned().collect();
+    let etc_before = WalkDir::new(rootfs.path().join("etc")).into_iter().count();
+    install_packages_compose(&rootfs, all, pacman_conf, opts.package_cache.as_deref(), &banned).await?;
+    let etc_after = WalkDir::new(rootfs.path().join("etc")).into_iter().count();
+    let extension_var: Vec<String> = var_tmpfiles_lines(rootfs.path())?
+        .into_iter()
+        .filter(|line| !base_var.contains(line))
+        .collect();
+    if etc_after > etc_before {
+        crate::warnings::warn(
+            "sysext-etc",
+            format!("{} new path(s) in /etc are not part of the extension", etc_after - etc_before),
+        );
+    }
+
+    let staging = TempDir::new()?;
+    let new_paths: Vec<PathBuf> = tree_paths(rootfs.path())?
+        .into_iter()
+        .filter(|p| !base_paths.contains(p))
+        .collect();
+    if new_paths.is_empty() {
+        anyhow::bail!("The extension packages add nothing to /usr or /opt of the base");
+    }
+    for rel in &new_paths {
+        if let Some(parent) = rel.parent() {
+            std::fs::create_dir_all(staging.path().join(parent))?;
+        }
+        copy_entry(rootfs.path(), staging.path(), rel)?;
+    }
+
+    if !extension_var.is_empty() {
+        let tmpfiles_dir = staging.path().join(TMPFILES_DIR);
+        std::fs::create_dir_all(&tmpfiles_dir)?;
+        std::fs::write(
+            tmpfiles_dir.join(format!("sysext-{}-var.conf", sysext.name)),
+            extension_var.iter().map(|line| format!("{}\n", line)).collect::<String>(),
+        )?;
+    }
+
+    let release_dir = staging.path().join(EXTENSION_RELEASE_DIR);
+    std::fs::create_dir_all(&release_dir)?;
+    std::fs::write(
+        release_dir.join(format!("extension-release.{}", sysext.name)),
+        extension_release(&sysext, &os_id),
+    )?;
+
+    println!("Creating {} ({} paths)...", opts.output, new_paths.len());
+    make_image(staging.path(), &opts.output, sysext.format)?;
+    println!("Wrote system extension {} for ID={}", opts.output, os_id);
+    Ok(())
+}
@@ -0,0 +1,122 @@
+// Równoległy build kilku wariantów obrazu z jednego manifestu (`compose --variants`)
+//
+// Każdy wariant to osobny proces `compose --variant <nazwa>`, bo stan buildu
+// (ostrzeżenia, czasy etapów, anulowanie) jest globalny dla procesu. Warianty
+// dzielą cache pakietów, więc każdy pakiet jest pobierany raz.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use tempfile::TempDir;
+
+use crate::compose::{yaml_parse_variant, ComposeImageOpts};
+
+/// Argumenty bieżącego wywołania bez opcji `name` (w obu formach: `--x v` i `--x=v`)
+fn strip_option(args: Vec<OsString>, name: &str) -> Vec<OsString> {
+    let prefix = format!("{}=", name);
+    let mut out = Vec::new();
+    let mut skip_value = false;
+    for arg in args {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+        let s = arg.to_string_lossy();
+        if s == name {
+            skip_value = true;
+        } else if !s.starts_with(&prefix) {
+            out.push(arg);
+        }
+    }
+    out
+}
+
+/// Przepisuje wyjście procesu wariantu z prefiksem `[wariant]`
+fn forward(stream: impl Read + Send + 'static, variant: String, to_stderr: bool) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if to_stderr {
+                eprintln!("[{}] {}", variant, line);
+            } else {
+                println!("[{}] {}", variant, line);
+            }
+        }
+    })
+}
+
+pub fn compose_variants(opts: &ComposeImageOpts) -> Result<()> {
+    // Manifest każdego wariantu sprawdzamy przed startem, żeby nie zostawić połowy buildów
+    let mut refs: BTreeMap<String, &str> = BTreeMap::new();
+    for variant in &opts.variants {
+        let config = yaml_parse_variant(opts.manifest.as_str(), Some(variant))
+            .with_context(|| format!("Reading manifest for variant {}", variant))?;
+        if let Some(other) = refs.insert(config.r#ref.clone(), variant) {
+            anyhow::bail!("Variants {} and {} both build ref {}", other, variant, config.r#ref);
+        }
+    }
+
+    let tmp_cache;
+    let package_cache = match opts.package_cache.clone() {
+        Some(dir) => dir,
+        None => {
+            tmp_cache = TempDir::new()?;
+            Utf8PathBuf::try_from(tmp_cache.path().to_path_buf())?
+        }
+    };
+    std::fs::create_dir_all(&package_cache)?;
+
+    let exe = std::env::current_exe().context("Locating the pacman-ostree binary")?;
+    let args = strip_option(std::env::args_os().skip(1).collect(), "--variants");
+    let mut args = strip_option(args, "--package-cache");
+
+    // Standardowe wejście da się przeczytać tylko raz, więc warianty dostają kopię w pliku
+    let tmp_list;
+    if opts.packages_from.as_deref().is_some_and(|p| p == "-") {
+        tmp_list = tempfile::NamedTempFile::new()?;
+        std::fs::write(tmp_list.path(), crate::compose::read_package_list(Utf8Path::new("-"))?.join("\n"))?;
+        args = strip_option(args, "--packages-from");
+        args.push("--packages-from".into());
+        args.push(tmp_list.path().into());
+    }
+
+    println!("Building variants {} with package cache {}", opts.variants.join(", "), package_cache);
+    let mut children = Vec::new();
+    for variant in &opts.variants {
+        let mut child = Command::new(&exe)
+            .args(&args)
+            .arg("--variant")
+            .arg(variant)
+            .arg("--package-cache")
+            .arg(&package_cache)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Starting build of variant {}", variant))?;
+        let forwarders = [
+            forward(child.stdout.take().unwrap(), variant.clone(), false),
+            forward(child.stderr.take().unwrap(), variant.clone(), true),
+        ];
+        children.push((variant, child, forwarders));
+    }
+
+    let mut failed = Vec::new();
+    for (variant, mut child, forwarders) in children {
+        let status = child.wait()?;
+        for f in forwarders {
+            let _ = f.join();
+        }
+        if status.success() {
+            println!("Variant {} built", variant);
+        } else {
+            failed.push(variant.as_str());
+        }
+    }
+    if !failed.is_empty() {
+        anyhow::bail!("Failed to build variant(s): {}", failed.join(", "));
+    }
+    Ok(())
+}
@@ -3,6 +3,7 @@
 use crate::compose::ConfigYaml;
 use crate::initramfs::run_dracut;
 use crate::bubblewrap::Bubblewrap;
+use crate::fsverity::FsVerityMode;
 use anyhow::Result;
 use cap_std::fs::Dir;
 use std::fs;
@@ -16,9 +17,27 @@ use cap_std::fs::Permissions;
 use std::fs::Permissions as StdPermissions;
 use anyhow::Context;
 
-fn prepare_rootfs(root_fs: &Dir) -> Result<()> {
+/// Keyring pacmana względem korzenia obrazu
+const KEYRING_DIR: &str = "etc/pacman.d/gnupg";
+
+/// Symlinki (cel, ścieżka) wymagane przez model OSTree — sprawdzane też przez `doctor`
+pub(crate) const BASE_SYMLINKS: &[(&str, &str)] = &[
+    ("sysroot/ostree", "ostree"),
+    ("var/roothome", "root"),
+    ("var/srv", "srv"),
+    ("var/opt", "opt"),
+    ("var/mnt", "mnt"),
+    ("var/home", "home"),
+    ("../var/usrlocal", "usr/local"),
+    ("usr/share/pacman", "var/lib/pacman"),
+];
+
+fn prepare_rootfs(root_fs: &Dir, fsverity: FsVerityMode) -> Result<()> {
     println!("Preparing root filesystem...");
-    let prepare_conf = "[composefs]\nenabled = yes\n[sysroot]\nreadonly = true\n";
+    let prepare_conf = format!(
+        "[composefs]\nenabled = {}\n[sysroot]\nreadonly = true\n",
+        fsverity.composefs_enabled()
+    );
     root_fs.write("usr/lib/ostree/prepare-root.conf", prepare_conf.as_bytes())
         .context("Failed to write prepare-root.conf")?;
 
@@ -42,17 +61,7 @@ fn setup_base_dirs(root_fs: &Dir) -> Result<()> {
     }
 
     // 3. Utwórz symlinki
-    let symlinks = [
-        ("sysroot/ostree", "ostree"),
-        ("var/roothome", "root"),
-        ("var/srv", "srv"),
-        ("var/opt", "opt"),
-        ("var/mnt", "mnt"),
-        ("var/home", "home"),
-        ("../var/usrlocal", "usr/local"),
-        ("usr/share/pacman", "var/lib/pacman"),
-    ];
-    for (src, dst) in symlinks {
+    for (src, dst) in BASE_SYMLINKS {
         ensure_parent_exists(root_fs, dst)?;
         let _ = root_fs.remove_file(dst);
         let _ = root_fs.remove_dir_all(dst);
@@ -105,6 +114,64 @@ fn build_bwrap_base(dest: &str) -> anyhow::Result<Bubblewrap> {
     Ok(bwrap)
 }
 
+/// Usługa zakładająca keyring pacmana przy pierwszym starcie
+const KEYRING_UNIT: &str = "pacman-ostree-keyring.service";
+
+/// Keyring pacmana, żeby system mógł weryfikować pakiety od pierwszego uruchomienia.
+/// `pacman-key --init` tworzy klucz główny, którym podpisywane są klucze z keyringów —
+/// w obrazie byłby wspólny dla wszystkich maszyn, więc bez `keyring-seed` keyring zakłada
+/// na każdej maszynie osobno usługa uruchamiana przy starcie, póki go jeszcze nie ma.
+fn init_keyring(config: &ConfigYaml, root_fs_path: &str) -> anyhow::Result<()> {
+    let gnupg = Path::new(root_fs_path).join(KEYRING_DIR);
+    if let Some(seed) = &config.keyring_seed {
+        println!("Seeding pacman keyring from {}...", seed);
+        fs::create_dir_all(&gnupg)?;
+        let status = crate::subprocess::status(
+            Command::new("cp").arg("-a").arg(seed.join(".")).arg(&gnupg),
+        )?;
+        if !status.success() {
+            anyhow::bail!("Copying keyring seed {} failed", seed);
+        }
+        return Ok(());
+    }
+
+    let keyrings = config.keyring_populate.clone().unwrap_or_else(|| vec!["archlinux".to_string()]);
+    if keyrings.is_empty() {
+        return Ok(());
+    }
+    if !Path::new(root_fs_path).join("usr/bin/pacman-key").exists() {
+        crate::warnings::warn("keyring", "pacman-key is not installed, the image has no pacman keyring");
+        return Ok(());
+    }
+    println!("Adding {} to initialize the pacman keyring ({}) on first boot...", KEYRING_UNIT, keyrings.join(", "));
+    let unit = format!(
+        "[Unit]\n\
+         Description=Initialize the pacman keyring\n\
+         ConditionPathExists=!/{dir}/pubring.gpg\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         RemainAfterExit=yes\n\
+         ExecStart=/usr/bin/pacman-key --init\n\
+         ExecStart=/usr/bin/pacman-key --populate {keyrings}\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        dir = KEYRING_DIR,
+        keyrings = keyrings.join(" "),
+    );
+    let unit_dir = Path::new(root_fs_path).join("usr/lib/systemd/system");
+    let wants = unit_dir.join("multi-user.target.wants");
+    fs::create_dir_all(&wants)?;
+    fs::write(unit_dir.join(KEYRING_UNIT), unit).with_context(|| format!("Writing {}", KEYRING_UNIT))?;
+    let link = wants.join(KEYRING_UNIT);
+    if fs::symlink_metadata(&link).is_err() {
+        std::os::unix::fs::symlink(format!("../{}", KEYRING_UNIT), &link)
+            .with_context(|| format!("Enabling {}", KEYRING_UNIT))?;
+    }
+    Ok(())
+}
+
 fn execute_post_scripts(config: &ConfigYaml, root_fs_path: &str) -> anyhow::Result<()> {
     // Jeśli nie ma żadnych skryptów, po prostu zwracamy Ok
     let scripts = match &config.scripts {
@@ -114,9 +181,10 @@ fn execute_post_scripts(config: &ConfigYaml, root_fs_path: &str) -> anyhow::Resu
 
     println!("Executing post-install scripts...");
 
-    for script_path in scripts {
+    for script in scripts {
+        let script_path = script.path();
         if !script_path.exists() {
-            println!("Skipping missing script: {}", script_path);
+            crate::warnings::warn("missing-script", format!("skipping missing script {}", script_path));
             continue;
         }
 
@@ -126,6 +194,9 @@ fn execute_post_scripts(config: &ConfigYaml, root_fs_path: &str) -> anyhow::Resu
         // Bindujemy katalog ze skryptem w kontenerze
         let script_dir = script_path.parent().unwrap();
         bwrap.bind_read(script_dir.as_str(), script_dir.as_str());
+        if let Some(network) = script.network() {
+            bwrap.set_network(network);
+        }
 
         // Dodajemy skrypt jako polecenie do uruchomienia
         bwrap.append_child_argv([script_path.as_str()]);
@@ -152,8 +223,16 @@ fn enable_services(config: &ConfigYaml, root_fs_path: &str) -> anyhow::Result<()
         let command = format!("systemctl enable {}", service);
 
         bwrap.append_child_argv(["/bin/sh", "-c", &command]);
-        let output = bwrap.run_captured()
-            .with_context(|| format!("Failed to enable service {}", service))?;
+        let result = bwrap.run_captured()
+            .with_context(|| format!("Failed to enable service {}", service));
+        // Bez `strict: false` nieudane włączenie usługi przerywa build
+        match result {
+            Ok(_) => {}
+            Err(e) if config.strict == Some(false) => {
+                crate::warnings::warn("service-enable", format!("{:#}", e));
+            }
+            Err(e) => return Err(e),
+        }
     }
 
     Ok(())
@@ -192,9 +271,17 @@ pub fn compose_post(config: &ConfigYaml, root_fs: &Dir, root_fs_path: &str) -> a
             .with_context(|| format!("Failed to copy pacman.conf from {} to {}", pacman_conf, dest_path))?;
     }
 
-    prepare_rootfs(root_fs)?; // tu możesz dalej używać Dir
+    // Przed prepare_rootfs, który usuwa /var
+    crate::var_tmpfiles::generate_var_tmpfiles(Utf8Path::new(root_fs_path))?;
+    crate::sysusers::generate_sysusers(Utf8Path::new(root_fs_path))?;
+    prepare_rootfs(root_fs, config.fsverity.unwrap_or_default())?; // tu możesz dalej używać Dir
+    init_keyring(config, root_fs_path)?;
     execute_post_scripts(config, root_fs_path)?; // teraz używamy &str
     enable_services(config, root_fs_path)?;
+    crate::special_files::clean_special_files(
+        Utf8Path::new(root_fs_path),
+        config.special_files.as_deref().unwrap_or_default(),
+    )?;
     generate_initramfs(root_fs, root_fs_path)?;
     Ok(())
 }
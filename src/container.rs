@@ -1,7 +1,7 @@
 use ostree_ext::{bootabletree, gio, glib, ostree};
 use glib::prelude::*;
 use ostree_ext::chunking::ObjectMetaSized;
-use ostree_ext::container::{Config, ExportOpts, ImageReference};
+use ostree_ext::container::{Config, ExportOpts, ImageReference, Transport};
 use ostree_ext::containers_image_proxy;
 use ostree_ext::objectsource::{
     ContentID, ObjectMeta, ObjectMetaMap, ObjectMetaSet, ObjectSourceMeta,
@@ -13,21 +13,34 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use camino::{Utf8Path, Utf8PathBuf};
 use std::rc::Rc;
 use anyhow::{Context, Result, anyhow};
-use std::ffi::CStr;
 use clap::Parser;
 use std::str::FromStr;
 use alpm_db::desc::DbDescFileV1;
 use alpm_db::files::DbFilesV1;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 use crate::fsutil::ResolvedOstreePaths;
+use crate::oci_export::{self, Compression};
 use cap_std::fs_utf8::Dir;
 use std::fs::File;
 use std::io::BufReader;
 use cap_std_ext::dirext::CapStdExtDirExtUtf8;
 use crate::fsutil::FileHelpers;
-
-
-const COMPONENT_XATTR: &CStr = c"user.component";
+use serde::Serialize;
+use crate::components::COMPONENT_XATTR;
+
+/// Adnotacja warstwy, w której ostree-ext zapisuje listę komponentów
+const CONTENT_ANNOTATION: &str = "ostree.components";
+/// Warstwa z wieloma pakietami większa niż 1/N obrazu jest zgłaszana jako słabo podzielona
+const OVERSIZED_LAYER_DIVISOR: u64 = 4;
+
+/// Wersje formatu kontenera obsługiwane przez zlinkowane ostree-ext,
+/// wraz z ostrzeżeniem o zgodności (jeśli jest). Nowy format = nowy wpis tutaj.
+const FORMAT_VERSIONS: &[(u32, Option<&str>)] = &[
+    (1, Some("format version 1 is deprecated; clients should move to version 2")),
+    (2, None),
+];
+/// Wersja używana przez compose, gdy manifest jej nie podaje
+pub const DEFAULT_FORMAT_VERSION: u32 = 2;
 
 #[derive(Debug, Parser)]
 pub struct ContainerEncapsulateOpts {
@@ -51,7 +64,8 @@ pub struct ContainerEncapsulateOpts {
     pub cmd: Option<Vec<String>>,
     #[clap(long)]
     pub max_layers: Option<NonZeroU32>,
-    #[clap(long, default_value = "1")]
+    /// The encapsulated container format version
+    #[clap(long, default_value_t = DEFAULT_FORMAT_VERSION)]
     pub format_version: u32,
     #[clap(long)]
     pub write_contentmeta_json: Option<Utf8PathBuf>,
@@ -61,8 +75,70 @@ pub struct ContainerEncapsulateOpts {
     pub previous_build_manifest: Option<Utf8PathBuf>,
     #[clap(long)]
     pub pacman_db_path: Utf8PathBuf,
+    /// Package that always gets its own exclusive layer (can be repeated)
+    #[clap(long = "exclusive-package")]
+    pub exclusive_packages: Vec<String>,
+    /// Layer compression algorithm (default: gzip)
+    #[clap(long, value_enum)]
+    pub compression: Option<Compression>,
+    /// Compression level (gzip: 1-9, zstd: 1-19; default: compressor's own default)
+    #[clap(long)]
+    pub compression_level: Option<u32>,
+    /// Number of layers compressed in parallel (default: all CPUs)
+    #[clap(long)]
+    pub compression_jobs: Option<NonZeroUsize>,
+    /// Fail when identical file content duplicated in the image exceeds this many bytes
+    #[clap(long)]
+    pub max_duplicate_bytes: Option<u64>,
+}
+
+/// Rozmiar, zawartość i przewidywana częstość zmian jednej warstwy obrazu
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LayerReport {
+    pub digest: String,
+    pub size: u64,
+    pub package_count: usize,
+    pub packages: Vec<String>,
+    /// Najwyższa `change_frequency` spośród pakietów w warstwie
+    pub change_frequency: u32,
+}
+
+/// Obiekt występujący w obrazie pod kilkoma ścieżkami
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DuplicateObject {
+    pub checksum: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+}
+
+/// Statystyki zduplikowanej zawartości (z `checksum_paths`)
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DedupReport {
+    pub duplicated_objects: usize,
+    /// Bajty do odzyskania, gdyby identyczne pliki były hardlinkami/reflinkami
+    pub duplicate_bytes: u64,
+    /// Część `duplicate_bytes` powtarzająca się między różnymi pakietami
+    pub cross_package_bytes: u64,
+    /// Największe duplikaty
+    pub top: Vec<DuplicateObject>,
+}
+
+/// Wynik enkapsulacji: digest obrazu i analiza podziału na warstwy
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EncapsulateReport {
+    pub digest: String,
+    pub layers: Vec<LayerReport>,
+    pub layer_warnings: Vec<String>,
+    pub dedup: DedupReport,
 }
 
+/// Ile największych duplikatów pokazać w raporcie
+const DEDUP_TOP: usize = 10;
+
 #[derive(Debug)]
 struct MappingBuilder {
     /// Metadane każdego pakietu/komponentu — to jest `ObjectMeta.set`.
@@ -70,10 +146,12 @@ struct MappingBuilder {
     packagemeta: ObjectMetaSet,
     componentmeta: ObjectMetaSet,
     component_ids: HashSet<String>,
-    checksum_paths: BTreeMap<String, BTreeSet<Utf8PathBuf>>,
+    /// Ścieżki są internowane — ta sama ścieżka występuje w kilku mapach naraz
+    paths: HashSet<Rc<Utf8Path>>,
+    checksum_paths: BTreeMap<String, BTreeSet<Rc<Utf8Path>>>,
     /// checksum -> ContentID pakietu (do wypełnienia ObjectMeta.map)
-    path_packages: HashMap<Utf8PathBuf, BTreeSet<ContentID>>,
-    path_components: HashMap<Utf8PathBuf, BTreeSet<ContentID>>,
+    path_packages: HashMap<Rc<Utf8Path>, BTreeSet<ContentID>>,
+    path_components: HashMap<Rc<Utf8Path>, BTreeSet<ContentID>>,
     unpackaged_id: ContentID,
     skip: HashSet<Utf8PathBuf>,
     pacman_size: u64,
@@ -82,14 +160,52 @@ struct MappingBuilder {
 impl MappingBuilder {
     const UNPACKAGED_ID: &'static str = "pacmanostree-unpackaged-content";
 
-    fn duplicate_objects(&self) -> impl Iterator<Item = (&String, &BTreeSet<Utf8PathBuf>)> {
+    /// Zwraca współdzieloną kopię ścieżki, alokując ją tylko przy pierwszym użyciu
+    fn intern_path(&mut self, path: &Utf8Path) -> Rc<Utf8Path> {
+        if let Some(p) = self.paths.get(path) {
+            return Rc::clone(p);
+        }
+        let p: Rc<Utf8Path> = Rc::from(path);
+        self.paths.insert(Rc::clone(&p));
+        p
+    }
+
+    fn duplicate_objects(&self) -> impl Iterator<Item = (&String, &BTreeSet<Rc<Utf8Path>>)> {
         self.checksum_paths.iter().filter(|(_, paths)| paths.len() > 1)
     }
 
-    fn multiple_owners(&self) -> impl Iterator<Item = (&Utf8PathBuf, &BTreeSet<ContentID>)> {
+    fn multiple_owners(&self) -> impl Iterator<Item = (&Rc<Utf8Path>, &BTreeSet<ContentID>)> {
         self.path_packages.iter().filter(|(_, pkgs)| pkgs.len() > 1)
     }
 
+    fn dedup_report(&self, repo: &ostree::Repo) -> Result<DedupReport> {
+        let mut report = DedupReport::default();
+        let mut duplicates = Vec::new();
+        for (checksum, paths) in self.duplicate_objects() {
+            let (_, info, _) = repo.load_file(checksum, gio::Cancellable::NONE)?;
+            let size = info.map(|i| i.size() as u64).unwrap_or(0);
+            let copies = paths.len() as u64 - 1;
+            let owners: BTreeSet<&ContentID> = paths
+                .iter()
+                .filter_map(|p| self.path_packages.get(p))
+                .flatten()
+                .collect();
+
+            report.duplicated_objects += 1;
+            report.duplicate_bytes += size * copies;
+            report.cross_package_bytes += size * (owners.len() as u64).saturating_sub(1);
+            duplicates.push(DuplicateObject {
+                checksum: checksum.clone(),
+                size,
+                paths: paths.iter().map(|p| p.to_string()).collect(),
+            });
+        }
+        duplicates.sort_by(|a, b| (b.size * (b.paths.len() as u64 - 1)).cmp(&(a.size * (a.paths.len() as u64 - 1))));
+        duplicates.truncate(DEDUP_TOP);
+        report.top = duplicates;
+        Ok(report)
+    }
+
     fn create_meta(&self) -> (ObjectMeta, BTreeMap<ContentID, Vec<(Utf8PathBuf, String)>>) {
         let mut package_content = ObjectMetaMap::default();
         let mut component_content_map = BTreeMap::new();
@@ -101,7 +217,7 @@ impl MappingBuilder {
                         component_content_map
                             .entry(content_id.clone())
                             .or_insert_with(Vec::new)
-                            .push((path.clone(), checksum.clone()));
+                            .push((path.to_path_buf(), checksum.clone()));
                     }
                 } else if let Some(package_ids) = self.path_packages.get(path) {
                     if let Some(content_id) = package_ids.first() {
@@ -122,6 +238,47 @@ impl MappingBuilder {
     }
 }
 
+/// Odrzuca wersje formatu nieznane zlinkowanemu ostree-ext i ostrzega o przestarzałych
+pub fn check_format_version(version: u32) -> Result<()> {
+    let Some((_, warning)) = FORMAT_VERSIONS.iter().find(|(v, _)| *v == version) else {
+        let supported: Vec<String> = FORMAT_VERSIONS.iter().map(|(v, _)| v.to_string()).collect();
+        anyhow::bail!(
+            "Unsupported container format version {}; supported: {}",
+            version,
+            supported.join(", ")
+        );
+    };
+    if let Some(warning) = warning {
+        crate::warnings::warn("format-version", *warning);
+    }
+    Ok(())
+}
+
+/// Strumieniowo przechodzi przez sekcję %FILES% pliku `files` z bazy pacmana,
+/// wywołując `f` dla każdego pliku (bez katalogów) — bez wczytywania całości do pamięci.
+fn for_each_packaged_file(
+    files_path: &Utf8Path,
+    mut f: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    use std::io::BufRead;
+
+    let reader = BufReader::new(File::open(files_path).with_context(|| format!("Opening {}", files_path))?);
+    let mut in_files = false;
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.starts_with('%') && line.ends_with('%') {
+            in_files = line == "%FILES%";
+            continue;
+        }
+        if !in_files || line.is_empty() || line.ends_with('/') {
+            continue;
+        }
+        f(line)?;
+    }
+    Ok(())
+}
+
 fn get_user_component_xattr(file: &ostree::RepoFile) -> std::io::Result<Option<String>> {
     let xattrs = match file.xattrs(gio::Cancellable::NONE) {
         Ok(x) => x,
@@ -142,55 +299,133 @@ fn get_user_component_xattr(file: &ostree::RepoFile) -> std::io::Result<Option<S
     Ok(None)
 }
 
-pub async fn container_encapsulate(args: ContainerEncapsulateOpts) -> anyhow::Result<()> {
-    use crate::fsutil::FileHelpers;
-    use anyhow::Context;
+fn format_change_frequency(frequency: u32) -> String {
+    if frequency == u32::MAX {
+        "every build".to_string()
+    } else {
+        frequency.to_string()
+    }
+}
 
-    fn normalize_component(v: Option<String>) -> Option<String> {
-        v.and_then(|s| {
-            let s = s.trim().to_string();
-            if s.is_empty() { None } else { Some(s) }
-        })
+/// Odczytuje manifest wygenerowanego obrazu i opisuje każdą warstwę:
+/// rozmiar, pakiety oraz przewidywaną częstość zmian.
+async fn analyze_layers(
+    imgref: &ImageReference,
+    meta: &ObjectMetaSized,
+    max_layers: Option<NonZeroU32>,
+) -> Result<(Vec<LayerReport>, Vec<String>)> {
+    crate::network::prepare_container_pull()?;
+    let proxy = containers_image_proxy::ImageProxy::new().await?;
+    let img = proxy
+        .open_image(&imgref.to_string())
+        .await
+        .with_context(|| format!("Opening {}", imgref))?;
+    let (_, manifest) = proxy.fetch_manifest(&img).await?;
+    proxy.close_image(&img).await?;
+
+    let frequencies: HashMap<&str, u32> = meta
+        .sizes
+        .iter()
+        .map(|s| (&*s.meta.name, s.meta.change_frequency))
+        .collect();
+
+    let mut layers = Vec::new();
+    let mut warnings = Vec::new();
+
+    for layer in manifest.layers() {
+        let packages: Vec<String> = layer
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(CONTENT_ANNOTATION))
+            .map(|v| v.split(',').filter(|p| !p.is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+
+        let package_frequencies: Vec<u32> = packages
+            .iter()
+            .filter_map(|p| frequencies.get(p.as_str()).copied())
+            .collect();
+        let change_frequency = package_frequencies.iter().copied().max().unwrap_or(u32::MAX);
+        let min_frequency = package_frequencies.iter().copied().min().unwrap_or(u32::MAX);
+
+        if min_frequency != change_frequency {
+            warnings.push(format!(
+                "Layer {} mixes content changing {} and {} times; it will be re-downloaded whenever any of it changes",
+                layer.digest(),
+                format_change_frequency(min_frequency),
+                format_change_frequency(change_frequency),
+            ));
+        }
+
+        layers.push(LayerReport {
+            digest: layer.digest().to_string(),
+            size: layer.size(),
+            package_count: packages.len(),
+            packages,
+            change_frequency,
+        });
     }
 
-    let opt = args;
-    let repo = &ostree_ext::cli::parse_repo(&opt.repo)?;
-    let (root, _rev) = repo.read_commit(opt.ostree_ref.as_str(), gio::Cancellable::NONE)?;
+    let total_size: u64 = layers.iter().map(|l| l.size).sum();
+    for layer in &layers {
+        if layer.package_count > 1 && layer.size * OVERSIZED_LAYER_DIVISOR > total_size {
+            warnings.push(format!(
+                "Layer {} holds {} packages and {} of {} total; raising --max-layers would split it",
+                layer.digest,
+                layer.package_count,
+                glib::format_size(layer.size),
+                glib::format_size(total_size),
+            ));
+        }
+    }
 
-    let mut state = MappingBuilder {
-        unpackaged_id: Rc::from(MappingBuilder::UNPACKAGED_ID),
-        packagemeta: Default::default(),
-        componentmeta: Default::default(),
-        checksum_paths: Default::default(),
-        path_packages: Default::default(),
-        path_components: Default::default(),
-        skip: Default::default(),
-        component_ids: Default::default(),
-        pacman_size: 0,
-    };
+    let n_packages = meta.sizes.len();
+    if let Some(max) = max_layers {
+        if layers.len() >= max.get() as usize && n_packages > layers.len() {
+            warnings.push(format!(
+                "All {} layers are in use for {} packages; packages are being grouped to fit --max-layers",
+                max, n_packages,
+            ));
+        }
+    }
+
+    Ok((layers, warnings))
+}
 
-    // unpackaged_id musi być w secie — pliki bez właściciela trafiają do tego bucketa
-    state.packagemeta.insert(ObjectSourceMeta {
-        identifier: Rc::clone(&state.unpackaged_id),
-        name: Rc::clone(&state.unpackaged_id),
-        srcid: Rc::clone(&state.unpackaged_id),
-        change_time_offset: u32::MAX,
-        change_frequency: u32::MAX,
-    });
+fn print_layer_report(layers: &[LayerReport], warnings: &[String]) {
+    println!("Layer budget ({} layers):", layers.len());
+    for (n, layer) in layers.iter().enumerate() {
+        println!(
+            "  {:>3}  {:>10}  {:>4} packages  change frequency: {}",
+            n,
+            glib::format_size(layer.size),
+            layer.package_count,
+            format_change_frequency(layer.change_frequency),
+        );
+    }
+    for warning in warnings {
+        crate::warnings::warn("layering", warning.as_str());
+    }
+}
 
-    // ───────── PACMAN DB ─────────
-    let db_path = opt.pacman_db_path;
+fn normalize_component(v: Option<String>) -> Option<String> {
+    v.and_then(|s| {
+        let s = s.trim().to_string();
+        if s.is_empty() { None } else { Some(s) }
+    })
+}
 
+/// Wczytuje bazę pacmana: każdy pakiet trafia do `packagemeta`, a wynikiem jest
+/// mapa nevra -> plik `files` (czytany dopiero przy mapowaniu)
+fn load_package_db(state: &mut MappingBuilder, db_path: &Utf8Path) -> Result<HashMap<Rc<str>, Utf8PathBuf>> {
     if !db_path.exists() {
         return Err(anyhow!("Pacman DB path missing: {}", db_path));
     }
 
-    // Mapa nevra -> (desc, files_path).
-    // Jednocześnie od razu dodajemy każdy pakiet do packagemeta.set,
+    // Każdy pakiet od razu trafia do packagemeta.set,
     // żeby ObjectMetaSized::compute_sizes nie zgłaszał "Failed to find X in content set".
-    let mut package_meta: HashMap<Rc<str>, (DbDescFileV1, Utf8PathBuf)> = HashMap::new();
+    let mut package_meta: HashMap<Rc<str>, Utf8PathBuf> = HashMap::new();
 
-    for entry in std::fs::read_dir(&db_path)? {
+    for entry in std::fs::read_dir(db_path)? {
         let entry = entry?;
         let pkg_dir = entry.path();
         let desc_path = pkg_dir.join("desc");
@@ -223,20 +458,21 @@ pub async fn container_encapsulate(args: ContainerEncapsulateOpts) -> anyhow::Re
         let files_utf8 = Utf8PathBuf::from_path_buf(files_path)
             .map_err(|pb| anyhow!("Invalid UTF-8 path: {:?}", pb))?;
 
-        package_meta.insert(nevra, (desc, files_utf8));
+        package_meta.insert(nevra, files_utf8);
     }
+    Ok(package_meta)
+}
 
+/// Przypisuje pliki z baz `files` do pakietów (`path_packages`)
+fn map_package_files(
+    state: &mut MappingBuilder,
+    root: &gio::File,
+    package_meta: &HashMap<Rc<str>, Utf8PathBuf>,
+) -> Result<()> {
     let mut dir_cache: HashMap<Utf8PathBuf, ResolvedOstreePaths> = HashMap::new();
 
-    // ───────── MAPOWANIE PACZEK ─────────
-    for (nevra, (_desc, files_path)) in package_meta.iter() {
-        let files_data = std::fs::read_to_string(files_path)?;
-
-        for line in files_data.lines().skip(1) {
-            let rel_path = line.trim();
-            if rel_path.is_empty() { continue; }
-            if rel_path.ends_with('/') { continue; }
-
+    for (nevra, files_path) in package_meta.iter() {
+        for_each_packaged_file(files_path, |rel_path| {
             let path = Utf8PathBuf::from("/").join(rel_path);
 
             if let Some(ostree_paths) = crate::fsutil::resolve_ostree_paths(
@@ -246,89 +482,175 @@ pub async fn container_encapsulate(args: ContainerEncapsulateOpts) -> anyhow::Re
             ) {
                 if ostree_paths.path.is_regular() || ostree_paths.path.is_symlink() {
                     let checksum = ostree_paths.path.checksum().to_string();
+                    let path = state.intern_path(&path);
 
                     state
                         .checksum_paths
-                        .entry(checksum.clone())
+                        .entry(checksum)
                         .or_default()
-                        .insert(path.clone());
+                        .insert(Rc::clone(&path));
 
                     state
                         .path_packages
-                        .entry(path.clone())
+                        .entry(path)
                         .or_default()
                         .insert(Rc::clone(nevra));
                 }
             }
-        }
+            Ok(())
+        })?;
     }
+    Ok(())
+}
 
-    // ───────── SKAN OSTREE ─────────
-    fn recurse(
-        path: &mut Utf8PathBuf,
-        dir: &gio::File,
-        state: &mut MappingBuilder,
-        parent_component: Option<String>,
-    ) -> Result<()> {
-        let e = dir.enumerate_children(
-            "standard::name,standard::type",
-            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
-            gio::Cancellable::NONE,
-        )?;
-
-        for child in e {
-            let childi = child?;
-            let name: Utf8PathBuf = childi.name().try_into()?;
-            let child = dir.child(&name);
-
-            path.push(&name);
-
-            match childi.file_type() {
-                gio::FileType::Regular | gio::FileType::SymbolicLink => {
-                    let child = child.downcast::<ostree::RepoFile>().unwrap();
-
-                    if state.skip.remove(Utf8Path::new(path)) {
-                        path.pop();
-                        continue;
-                    }
+fn scan_dir(
+    path: &mut Utf8PathBuf,
+    dir: &gio::File,
+    state: &mut MappingBuilder,
+    parent_component: Option<String>,
+) -> Result<()> {
+    let e = dir.enumerate_children(
+        "standard::name,standard::type",
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        gio::Cancellable::NONE,
+    )?;
+
+    for child in e {
+        let childi = child?;
+        let name: Utf8PathBuf = childi.name().try_into()?;
+        let child = dir.child(&name);
+
+        path.push(&name);
+
+        match childi.file_type() {
+            gio::FileType::Regular | gio::FileType::SymbolicLink => {
+                let child = child.downcast::<ostree::RepoFile>().unwrap();
+
+                if state.skip.remove(Utf8Path::new(path)) {
+                    path.pop();
+                    continue;
+                }
 
-                    let file_component = normalize_component(get_user_component_xattr(&child)?);
-                    let effective_component = file_component
-                        .or_else(|| normalize_component(parent_component.clone()));
-
-                    if let Some(component_name) = effective_component {
-                        let component_id = Rc::from(component_name.clone());
-                        state.component_ids.insert(component_name);
-                        state.path_components
-                            .entry(path.clone())
-                            .or_default()
-                            .insert(Rc::clone(&component_id));
-                    }
+                let file_component = normalize_component(get_user_component_xattr(&child)?);
+                let effective_component = file_component
+                    .or_else(|| normalize_component(parent_component.clone()));
 
-                    let checksum = child.checksum().to_string();
-                    state.checksum_paths.entry(checksum).or_default().insert(path.clone());
+                if let Some(component_name) = effective_component {
+                    let component_id = Rc::from(component_name.clone());
+                    state.component_ids.insert(component_name);
+                    let interned = state.intern_path(path);
+                    state.path_components
+                        .entry(interned)
+                        .or_default()
+                        .insert(Rc::clone(&component_id));
                 }
 
-                gio::FileType::Directory => {
-                    let child_repo_file = child.clone().downcast::<ostree::RepoFile>().unwrap();
+                let checksum = child.checksum().to_string();
+                let interned = state.intern_path(path);
+                state.checksum_paths.entry(checksum).or_default().insert(interned);
+            }
 
-                    let dir_component = normalize_component(get_user_component_xattr(&child_repo_file)?);
-                    let effective_component = dir_component
-                        .or_else(|| normalize_component(parent_component.clone()));
+            gio::FileType::Directory => {
+                let child_repo_file = child.clone().downcast::<ostree::RepoFile>().unwrap();
 
-                    recurse(path, &child, state, effective_component)?;
-                }
+                let dir_component = normalize_component(get_user_component_xattr(&child_repo_file)?);
+                let effective_component = dir_component
+                    .or_else(|| normalize_component(parent_component.clone()));
 
-                o => anyhow::bail!("Unhandled file type: {o:?}"),
+                scan_dir(path, &child, state, effective_component)?;
             }
 
-            path.pop();
+            o => anyhow::bail!("Unhandled file type: {o:?}"),
         }
 
-        Ok(())
+        path.pop();
     }
 
-    recurse(&mut Utf8PathBuf::from("/"), &root, &mut state, None)?;
+    Ok(())
+}
+
+/// Przechodzi całe drzewo commita: sumy kontrolne wszystkich plików i komponenty z `user.component`
+fn scan_tree(state: &mut MappingBuilder, root: &gio::File) -> Result<()> {
+    scan_dir(&mut Utf8PathBuf::from("/"), root, state, None)
+}
+
+/// Pochodzenie plików commita według tego samego mapowania, którego używa podział na warstwy
+#[derive(Debug, Default)]
+pub struct PathOwners {
+    /// Wszystkie pliki i symlinki commita
+    pub paths: BTreeSet<Utf8PathBuf>,
+    /// Ścieżka -> pakiety (`nazwa-wersja`) z baz `files`
+    pub packages: BTreeMap<Utf8PathBuf, Vec<String>>,
+    /// Ścieżka -> komponent z `user.component` (własny lub odziedziczony po katalogu)
+    pub components: BTreeMap<Utf8PathBuf, Vec<String>>,
+}
+
+/// Mapuje pliki commita `rev` na pakiety z bazy w `db_path` i komponenty, bez enkapsulacji
+pub fn path_owners(repo: &ostree::Repo, rev: &str, db_path: &Utf8Path) -> Result<PathOwners> {
+    let (root, _) = repo.read_commit(rev, gio::Cancellable::NONE)?;
+    let mut state = MappingBuilder::new();
+    let package_meta = load_package_db(&mut state, db_path)?;
+    map_package_files(&mut state, &root, &package_meta)?;
+    scan_tree(&mut state, &root)?;
+
+    let owners = |map: &HashMap<Rc<Utf8Path>, BTreeSet<ContentID>>| {
+        map.iter()
+            .map(|(path, ids)| (path.to_path_buf(), ids.iter().map(|id| id.to_string()).collect()))
+            .collect()
+    };
+    Ok(PathOwners {
+        paths: state.paths.iter().map(|p| p.to_path_buf()).collect(),
+        packages: owners(&state.path_packages),
+        components: owners(&state.path_components),
+    })
+}
+
+pub async fn container_encapsulate(args: ContainerEncapsulateOpts) -> anyhow::Result<EncapsulateReport> {
+    use anyhow::Context;
+
+    let opt = args;
+    let repo = &ostree_ext::cli::parse_repo(&opt.repo)?;
+    let (root, _rev) = repo.read_commit(opt.ostree_ref.as_str(), gio::Cancellable::NONE)?;
+
+    let mut state = MappingBuilder::new();
+    let package_meta = load_package_db(&mut state, &opt.pacman_db_path)?;
+    // nevra -> nazwa dla pakietów przypiętych do własnej warstwy
+    let exclusive: HashMap<Rc<str>, String> = state
+        .packagemeta
+        .iter()
+        .filter(|m| opt.exclusive_packages.iter().any(|p| p.as_str() == &*m.name))
+        .map(|m| (Rc::clone(&m.identifier), m.name.to_string()))
+        .collect();
+    map_package_files(&mut state, &root, &package_meta)?;
+    scan_tree(&mut state, &root)?;
+
+    // ───────── PAKIETY Z WŁASNĄ WARSTWĄ ─────────
+    // Pliki przypiętego pakietu stają się komponentem o nazwie pakietu, dzięki czemu
+    // ostree-ext daje mu osobną warstwę. Jawny user.component ma pierwszeństwo.
+    for name in &opt.exclusive_packages {
+        if !exclusive.values().any(|n| n == name) {
+            crate::warnings::warn(
+                "exclusive-package",
+                format!("exclusive package {} is not installed in the image", name),
+            );
+        }
+    }
+    let pinned: Vec<(Rc<Utf8Path>, String)> = state
+        .path_packages
+        .iter()
+        .filter(|(path, _)| !state.path_components.contains_key(*path))
+        .filter_map(|(path, owners)| {
+            owners
+                .iter()
+                .find_map(|nevra| exclusive.get(nevra))
+                .map(|name| (Rc::clone(path), name.clone()))
+        })
+        .collect();
+    for (path, name) in pinned {
+        let component_id: ContentID = Rc::from(name.as_str());
+        state.component_ids.insert(name);
+        state.path_components.entry(path).or_default().insert(component_id);
+    }
 
     // ───────── META KOMPONENTÓW ─────────
     for component_name in state.component_ids.iter() {
@@ -344,6 +666,27 @@ pub async fn container_encapsulate(args: ContainerEncapsulateOpts) -> anyhow::Re
         });
     }
 
+    // ───────── DUPLIKATY ─────────
+    let dedup = state.dedup_report(repo)?;
+    println!(
+        "Duplicated content: {} in {} objects ({} across packages)",
+        glib::format_size(dedup.duplicate_bytes),
+        dedup.duplicated_objects,
+        glib::format_size(dedup.cross_package_bytes),
+    );
+    for dup in &dedup.top {
+        println!("  {:>10} x{}  {}", glib::format_size(dup.size), dup.paths.len(), dup.paths.join(", "));
+    }
+    if let Some(max) = opt.max_duplicate_bytes {
+        if dedup.duplicate_bytes > max {
+            anyhow::bail!(
+                "Duplicated content ({}) exceeds the budget of {}",
+                glib::format_size(dedup.duplicate_bytes),
+                glib::format_size(max)
+            );
+        }
+    }
+
     let (package_meta_obj, component_content_map) = state.create_meta();
     let package_meta_sized = ObjectMetaSized::compute_sizes(repo, package_meta_obj)?;
 
@@ -357,19 +700,80 @@ pub async fn container_encapsulate(args: ContainerEncapsulateOpts) -> anyhow::Re
     opts.max_layers = opt.max_layers;
     opts.package_contentmeta = Some(&package_meta_sized);
     opts.specific_contentmeta = Some(&component_content_map);
+    // Klucze metadanych commita kopiowane do etykiet obrazu
+    opts.copy_meta_keys = opt.copy_meta_keys.clone();
+    opts.copy_meta_opt_keys = opt.copy_meta_opt_keys.clone();
 
     println!("Generating container image");
 
-    let digest = ostree_ext::container::encapsulate(
-        repo,
-        _rev.as_str(),
-        &config,
-        Some(opts),
-        &opt.imgref,
-    )
-    .await
-    .context("Encapsulating")?;
+    let compression = opt.compression.unwrap_or_default();
+    compression.check_level(opt.compression_level)?;
+    check_format_version(opt.format_version)?;
+    // Do containers-storage warstwy zapisuje ostree-ext, bez naszej kompresji
+    if opt.imgref.transport == Transport::ContainerStorage
+        && (opt.compression.is_some() || opt.compression_level.is_some())
+    {
+        anyhow::bail!("--compression and --compression-level are not supported for containers-storage");
+    }
+
+    // Do rejestru też wysyłamy sami, żeby przy zerwanym połączeniu ponawiać tylko push
+    let own_compression = matches!(opt.imgref.transport, Transport::OciArchive | Transport::Registry)
+        || opt.compression.is_some()
+        || opt.compression_level.is_some();
+    let digest = if own_compression {
+        // ostree-ext kompresuje warstwy po kolei na jednym rdzeniu i tylko gzipem —
+        // eksportujemy je bez kompresji do katalogu OCI i kompresujemy równolegle sami
+        let tmp = tempfile::tempdir_in("/var/tmp")?;
+        let oci_dir = if opt.imgref.transport == Transport::OciDir {
+            Utf8PathBuf::from(&opt.imgref.name)
+        } else {
+            Utf8Path::from_path(tmp.path())
+                .ok_or_else(|| anyhow!("Invalid UTF-8 temporary path"))?
+                .join("oci")
+        };
+        let oci_ref = ImageReference {
+            transport: Transport::OciDir,
+            name: oci_dir.to_string(),
+        };
+        opts.skip_compression = true;
+        ostree_ext::container::encapsulate(repo, _rev.as_str(), &config, Some(opts), &oci_ref)
+            .await
+            .context("Encapsulating")?;
+        // Tylko kompresja i kopiowanie sprawdzają flagę anulowania; wcześniej (encapsulate,
+        // containers-storage) Ctrl-C musi zadziałać od razu, więc zostają domyślne handlery
+        let _cancel = crate::signals::CancelGuard::install()?;
+
+        let jobs = opt
+            .compression_jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let digest = oci_export::compress_layers(&oci_dir, compression, opt.compression_level, jobs)?;
+        match opt.imgref.transport {
+            Transport::OciDir => {}
+            Transport::OciArchive => {
+                oci_export::write_oci_archive(&oci_dir, Utf8Path::new(&opt.imgref.name))?
+            }
+            _ => oci_export::copy_image(&oci_dir, &opt.imgref)?,
+        }
+        digest
+    } else {
+        ostree_ext::container::encapsulate(repo, _rev.as_str(), &config, Some(opts), &opt.imgref)
+            .await
+            .context("Encapsulating")?
+            .to_string()
+    };
 
     println!("Pushed digest: {}", digest);
-    Ok(())
+
+    let (layers, layer_warnings) =
+        analyze_layers(&opt.imgref, &package_meta_sized, opt.max_layers).await?;
+    print_layer_report(&layers, &layer_warnings);
+
+    Ok(EncapsulateReport {
+        digest,
+        layers,
+        layer_warnings,
+        dedup,
+    })
 }
\ No newline at end of file
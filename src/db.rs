@@ -0,0 +1,456 @@
+// Zapytania o bazę pakietów zapisaną w commitach (`db`)
+
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{anyhow, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Subcommand, ValueEnum};
+use ostree_ext::ostree;
+use serde::Serialize;
+
+use crate::layered_packages::{
+    booted_state, deploy_layered_state, load_sysroot, local_packages_dir, pending_state, store_local_package,
+    LayeredState,
+};
+use crate::licenses::LicenseReport;
+use crate::pacman_manager::{
+    cached_package_file, foreign_packages, read_changelog_from_commit, read_descs_from_commit,
+    read_packages_from_commit, PACKAGE_CACHE_DIR,
+};
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// List packages in the booted deployment or a given commit
+    List {
+        /// Commit or ref to inspect instead of the booted deployment
+        #[clap(long)]
+        commit: Option<String>,
+        /// Print package names only
+        #[clap(long, short)]
+        quiet: bool,
+        /// Only list packages not found in any configured repository (refreshes the sync databases)
+        #[clap(long)]
+        foreign: bool,
+    },
+    /// Copy cached files of foreign layered packages into the managed store so rebuilds keep them
+    PinForeign,
+    /// Summarize package changes and changelogs between two commits
+    Changelog {
+        from: String,
+        to: String,
+        #[clap(long, value_enum, default_value = "text")]
+        format: ChangelogFormat,
+    },
+    /// Show packages added, removed and changed between two commits (default: booted vs. pending)
+    Diff {
+        /// Commit or ref to compare from (default: booted deployment)
+        from: Option<String>,
+        /// Commit or ref to compare to (default: pending deployment)
+        to: Option<String>,
+    },
+    /// Show which package owns files (e.g. /usr/bin/foo)
+    Owns {
+        #[clap(required = true)]
+        paths: Vec<Utf8PathBuf>,
+        /// Commit or ref to inspect instead of the booted deployment
+        #[clap(long)]
+        commit: Option<String>,
+    },
+    /// Summarize package licenses in the booted deployment, a commit or an image
+    Licenses {
+        /// Commit, ref or `ostree-…` image reference to inspect instead of the booted deployment
+        #[clap(long)]
+        commit: Option<String>,
+        #[clap(long, value_enum, default_value = "text")]
+        format: LicenseFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LicenseFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ChangelogFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+/// Różnica między bazami pakietów dwóch commitów
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageDiff {
+    pub added: Vec<(String, String)>,
+    pub removed: Vec<(String, String)>,
+    /// (nazwa, stara wersja, nowa wersja)
+    pub changed: Vec<(String, String, String)>,
+}
+
+/// Wpis raportu zmian dla pakietu, którego wersja się zmieniła
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ChangelogEntry {
+    name: String,
+    old_version: String,
+    new_version: String,
+    /// false gdy zmienił się tylko pkgrel (przebudowa bez nowej wersji upstream)
+    upstream_change: bool,
+    changelog: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ChangelogReport {
+    from: String,
+    to: String,
+    added: Vec<(String, String)>,
+    removed: Vec<(String, String)>,
+    changed: Vec<ChangelogEntry>,
+}
+
+pub fn diff_packages(from: &BTreeMap<String, String>, to: &BTreeMap<String, String>) -> PackageDiff {
+    let mut diff = PackageDiff::default();
+    for (name, new) in to {
+        match from.get(name) {
+            None => diff.added.push((name.clone(), new.clone())),
+            Some(old) if old != new => diff.changed.push((name.clone(), old.clone(), new.clone())),
+            Some(_) => {}
+        }
+    }
+    for (name, old) in from {
+        if !to.contains_key(name) {
+            diff.removed.push((name.clone(), old.clone()));
+        }
+    }
+    diff
+}
+
+/// Wersja bez pkgrel: `1:2.3-4` -> `1:2.3`
+fn upstream_version(version: &str) -> &str {
+    version.rsplit_once('-').map(|(v, _)| v).unwrap_or(version)
+}
+
+/// Wpisy changeloga nowej wersji, których nie było w starej (nowe wpisy są na górze)
+fn new_changelog_entries(old: Option<&str>, new: &str) -> String {
+    let Some(first_old) = old.and_then(|o| o.lines().find(|l| !l.trim().is_empty())) else {
+        return new.trim_end().to_string();
+    };
+    new.lines()
+        .take_while(|l| *l != first_old)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+fn changelog_entry(repo: &ostree::Repo, from: &str, to: &str, change: &(String, String, String)) -> Result<ChangelogEntry> {
+    let (name, old_version, new_version) = change;
+    let old_log = read_changelog_from_commit(repo, from, name, old_version)?;
+    let new_log = read_changelog_from_commit(repo, to, name, new_version)?;
+    let changelog = new_log
+        .map(|n| new_changelog_entries(old_log.as_deref(), &n))
+        .filter(|c| !c.is_empty());
+
+    Ok(ChangelogEntry {
+        name: name.clone(),
+        old_version: old_version.clone(),
+        new_version: new_version.clone(),
+        upstream_change: upstream_version(old_version) != upstream_version(new_version),
+        changelog,
+    })
+}
+
+fn print_text(report: &ChangelogReport) {
+    println!("Changes from {} to {}", report.from, report.to);
+    for (name, version) in &report.added {
+        println!("  + {} {}", name, version);
+    }
+    for (name, version) in &report.removed {
+        println!("  - {} {}", name, version);
+    }
+    for entry in &report.changed {
+        let kind = if entry.upstream_change { "" } else { " (rebuild)" };
+        println!("  ~ {} {} -> {}{}", entry.name, entry.old_version, entry.new_version, kind);
+        if let Some(log) = &entry.changelog {
+            for line in log.lines() {
+                println!("      {}", line);
+            }
+        }
+    }
+}
+
+fn print_markdown(report: &ChangelogReport) {
+    println!("## Changes from `{}` to `{}`\n", report.from, report.to);
+    if !report.added.is_empty() {
+        println!("### Added\n");
+        for (name, version) in &report.added {
+            println!("- **{}** {}", name, version);
+        }
+        println!();
+    }
+    if !report.removed.is_empty() {
+        println!("### Removed\n");
+        for (name, version) in &report.removed {
+            println!("- **{}** {}", name, version);
+        }
+        println!();
+    }
+    let (upstream, rebuilds): (Vec<_>, Vec<_>) = report.changed.iter().partition(|e| e.upstream_change);
+    if !upstream.is_empty() {
+        println!("### Updated\n");
+        for entry in upstream {
+            println!("- **{}** {} → {}", entry.name, entry.old_version, entry.new_version);
+            if let Some(log) = &entry.changelog {
+                println!("\n  ```\n{}\n  ```", log.lines().map(|l| format!("  {}", l)).collect::<Vec<_>>().join("\n"));
+            }
+        }
+        println!();
+    }
+    if !rebuilds.is_empty() {
+        println!("### Rebuilt\n");
+        for entry in rebuilds {
+            println!("- **{}** {} → {}", entry.name, entry.old_version, entry.new_version);
+        }
+    }
+}
+
+pub fn db_changelog(from: &str, to: &str, format: ChangelogFormat) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let from_commit = repo.require_rev(from)?.to_string();
+    let to_commit = repo.require_rev(to)?.to_string();
+
+    let diff = diff_packages(
+        &read_packages_from_commit(&repo, &from_commit)?,
+        &read_packages_from_commit(&repo, &to_commit)?,
+    );
+    let changed = diff
+        .changed
+        .iter()
+        .map(|c| changelog_entry(&repo, &from_commit, &to_commit, c))
+        .collect::<Result<Vec<_>>>()?;
+    let report = ChangelogReport {
+        from: from_commit,
+        to: to_commit,
+        added: diff.added,
+        removed: diff.removed,
+        changed,
+    };
+
+    match format {
+        ChangelogFormat::Text => print_text(&report),
+        ChangelogFormat::Markdown => print_markdown(&report),
+        ChangelogFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(())
+}
+
+pub fn db_list(commit: Option<&str>, quiet: bool, foreign: bool) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let (booted, state) = booted_state(&sysroot)?;
+
+    let commit = match commit {
+        Some(c) => repo.require_rev(c)?.to_string(),
+        None => booted.csum().to_string(),
+    };
+    let mut packages = read_packages_from_commit(&repo, &commit)?;
+    // Oznaczenie warstw ma sens tylko dla deploymentu, z którego pochodzi stan
+    let show_layered = commit == booted.csum().as_str();
+    if foreign {
+        let found = crate::output::progress(|| foreign_in_commit(&repo, &commit, &state, true))?.unwrap_or_default();
+        packages.retain(|name, _| found.contains(name));
+    }
+
+    for (name, version) in &packages {
+        if quiet {
+            println!("{}", name);
+        } else if show_layered && state.layered_packages.contains(name) {
+            println!("{} {} (layered)", name, version);
+        } else {
+            println!("{} {}", name, version);
+        }
+    }
+    if foreign && !quiet && show_layered && packages.keys().any(|name| state.layered_packages.contains(name)) {
+        println!("Run `pacman-ostree db pin-foreign` to keep layered foreign packages across rebuilds");
+    }
+    Ok(())
+}
+
+/// Obce pakiety commita: są w jego bazie, ale nie w żadnym skonfigurowanym repozytorium.
+/// Pakiety z magazynu (`local_packages`) są zarządzane, więc się nie liczą. `None`, gdy bez
+/// `refresh` nie ma jeszcze pobranych baz sync.
+pub fn foreign_in_commit(
+    repo: &ostree::Repo,
+    commit: &str,
+    state: &LayeredState,
+    refresh: bool,
+) -> Result<Option<BTreeSet<String>>> {
+    let packages = read_packages_from_commit(repo, commit)?;
+    let tmp = tempfile::tempdir()?;
+    let pacman_conf = tmp.path().join("pacman.conf");
+    crate::layered_repos::generate_pacman_conf(state, &pacman_conf)?;
+    let names = packages
+        .keys()
+        .filter(|name| !state.local_packages.contains_key(*name))
+        .map(String::as_str);
+    foreign_packages(names, &pacman_conf, refresh)
+}
+
+/// Obce pakiety warstwy zniknęłyby przy rebuildzie (`Target not found`), więc ich pliki z cache
+/// trafiają do magazynu i dalej są instalowane jako pakiety lokalne
+pub fn db_pin_foreign() -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let (booted, mut state) = pending_state(&sysroot)?;
+    let commit = booted.csum().to_string();
+    let packages = read_packages_from_commit(&repo, &commit)?;
+    let foreign = crate::output::progress(|| foreign_in_commit(&repo, &commit, &state, true))?.unwrap_or_default();
+    let targets: Vec<&String> = foreign.iter().filter(|name| state.layered_packages.contains(*name)).collect();
+    if targets.is_empty() {
+        println!("No foreign layered packages to pin");
+        return Ok(());
+    }
+
+    let mut stale_files = Vec::new();
+    for name in targets {
+        let version = &packages[name];
+        let file = cached_package_file(name, version)
+            .ok_or_else(|| anyhow!("{} {} not found in {}", name, version, PACKAGE_CACHE_DIR))?;
+        let file = Utf8PathBuf::try_from(file)?;
+        stale_files.extend(store_local_package(&mut state, name, &file)?);
+        state.layered_packages.remove(name);
+        println!("Pinned {} {}", name, version);
+    }
+
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    for file in stale_files {
+        let _ = std::fs::remove_file(local_packages_dir().join(file));
+    }
+    Ok(())
+}
+
+/// Właściciele ścieżek według mapowania z `ex attribution`
+pub fn db_owns(paths: &[Utf8PathBuf], commit: Option<&str>) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let commit = match commit {
+        Some(c) => repo.require_rev(c)?.to_string(),
+        None => booted_state(&sysroot)?.0.csum().to_string(),
+    };
+    let sources = crate::output::progress(|| crate::attribution::attribute_commit(&repo, &commit))?;
+
+    let mut report = BTreeMap::new();
+    let mut missing = false;
+    for path in paths {
+        let path = Utf8Path::new("/").join(path);
+        match sources.get(&path) {
+            Some(source) => {
+                report.insert(path, Some(source));
+            }
+            None => {
+                missing = true;
+                report.insert(path, None);
+            }
+        }
+    }
+    if crate::output::json() {
+        crate::output::emit(&report)?;
+    } else {
+        for (path, source) in &report {
+            match source {
+                Some(crate::attribution::Source::Package { owners }) => {
+                    println!("{} is owned by {}", path, owners.join(" "))
+                }
+                Some(source) => println!("{} is not owned by any package ({})", path, source.describe()),
+                None => println!("{} does not exist in {}", path, commit),
+            }
+        }
+    }
+    if missing {
+        anyhow::bail!("Some paths do not exist in {}", commit);
+    }
+    Ok(())
+}
+
+pub fn db_diff(from: Option<&str>, to: Option<&str>) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let from = match from {
+        Some(c) => repo.require_rev(c)?.to_string(),
+        None => booted_state(&sysroot)?.0.csum().to_string(),
+    };
+    let to = match to {
+        Some(c) => repo.require_rev(c)?.to_string(),
+        None => {
+            let (pending, _) = sysroot.query_deployments_for(None);
+            pending
+                .ok_or_else(|| anyhow!("No pending deployment; pass the commit to compare to"))?
+                .csum()
+                .to_string()
+        }
+    };
+
+    let diff = diff_packages(
+        &read_packages_from_commit(&repo, &from)?,
+        &read_packages_from_commit(&repo, &to)?,
+    );
+    if crate::output::json() {
+        return crate::output::emit(&diff);
+    }
+    println!("Changes from {} to {}", from, to);
+    if diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty() {
+        println!("  No package changes");
+    }
+    for (name, version) in &diff.added {
+        println!("  + {} {}", name, version);
+    }
+    for (name, version) in &diff.removed {
+        println!("  - {} {}", name, version);
+    }
+    for (name, old, new) in &diff.changed {
+        println!("  ~ {} {} -> {}", name, old, new);
+    }
+    Ok(())
+}
+
+pub fn db_licenses(commit: Option<&str>, format: LicenseFormat) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let commit = match commit {
+        Some(c) => crate::deploy::resolve_commit(&repo, c)?,
+        None => booted_state(&sysroot)?.0.csum().to_string(),
+    };
+    let report = LicenseReport::from_descs(&read_descs_from_commit(&repo, &commit)?);
+    match format {
+        LicenseFormat::Text => report.print_text(),
+        LicenseFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(())
+}
+
+pub fn db_command(cmd: DbCommand) -> Result<()> {
+    match cmd {
+        DbCommand::List { commit, quiet, foreign } => db_list(commit.as_deref(), quiet, foreign),
+        DbCommand::PinForeign => db_pin_foreign(),
+        DbCommand::Changelog { from, to, format } => db_changelog(&from, &to, format),
+        DbCommand::Diff { from, to } => db_diff(from.as_deref(), to.as_deref()),
+        DbCommand::Owns { paths, commit } => db_owns(&paths, commit.as_deref()),
+        DbCommand::Licenses { commit, format } => db_licenses(commit.as_deref(), format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_changelog_entries() {
+        let old = "2024-01-02 a\n\t* 1.1\n\n2024-01-01 a\n\t* 1.0\n";
+        let new = "2024-02-01 b\n\t* 1.2\n\n2024-01-02 a\n\t* 1.1\n\n2024-01-01 a\n\t* 1.0\n";
+        assert_eq!(new_changelog_entries(Some(old), new), "2024-02-01 b\n\t* 1.2");
+        assert_eq!(upstream_version("1:2.3-4"), "1:2.3");
+    }
+}
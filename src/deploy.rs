@@ -0,0 +1,179 @@
+// Pierwsze wdrożenie commita lub obrazu, także do nowego stateroota (`deploy`)
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::{gio, glib, ostree};
+
+use crate::layered_packages::{commit_kargs, deployment_kargs, lock_sysroot, merged_kargs};
+use crate::reboot::RebootOpts;
+
+#[derive(Parser, Debug)]
+pub struct DeployOpts {
+    /// OSTree refspec (remote:ref) or ostree-image reference already present in the repo
+    pub refspec: String,
+
+    /// Physical sysroot to deploy into (e.g. the mounted target disk)
+    #[clap(long, default_value = "/")]
+    pub sysroot: Utf8PathBuf,
+
+    /// Stateroot (osname) to deploy into; created if it does not exist yet
+    #[clap(long)]
+    pub stateroot: Option<String>,
+
+    /// Extra kernel argument for the new deployment, added to the image's and the
+    /// previous deployment's arguments
+    #[clap(long)]
+    pub karg: Vec<String>,
+
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+/// Origin nowego deploymentu, w tym samym formacie, który czyta `deployment_refspec`
+fn new_origin(sysroot: &ostree::Sysroot, refspec: &str) -> Result<glib::KeyFile> {
+    if refspec.starts_with("ostree-") {
+        // Walidacja formatu, żeby origin nie wskazywał na coś, czego upgrade nie zrozumie
+        let _: OstreeImageReference = refspec.parse()?;
+        let origin = glib::KeyFile::new();
+        origin.set_string("origin", "container-image-reference", refspec);
+        Ok(origin)
+    } else {
+        Ok(sysroot.origin_new_from_refspec(refspec))
+    }
+}
+
+/// Commit dla refspec: lokalny ref albo zaimportowany obraz kontenera
+pub fn resolve_commit(repo: &ostree::Repo, refspec: &str) -> Result<String> {
+    if refspec.starts_with("ostree-") {
+        let imgref: OstreeImageReference = refspec.parse()?;
+        let state = ostree_ext::container::store::query_image(repo, &imgref.imgref)?
+            .ok_or_else(|| anyhow!("Image {} has not been pulled into the repo", refspec))?;
+        return Ok(state.merge_commit);
+    }
+    let rev = repo
+        .resolve_rev(refspec, false)?
+        .ok_or_else(|| anyhow!("{} not found in repo; pull it first", refspec))?;
+    Ok(rev.to_string())
+}
+
+/// Argumenty jądra nowego deploymentu: jak przy upgrade (merge deployment i kargs obu
+/// obrazów), a bez merge deploymentu same kargs obrazu; `--karg` dochodzą na końcu.
+/// `None` zostawia ostree argumenty merge deploymentu.
+fn deploy_kargs(
+    repo: &ostree::Repo,
+    merge_deployment: Option<&ostree::Deployment>,
+    commit: &str,
+    extra: &[String],
+) -> Result<Option<Vec<String>>> {
+    let mut kargs = match merge_deployment {
+        Some(merge) => merged_kargs(repo, merge, commit)?,
+        None => Some(commit_kargs(repo, commit)?),
+    };
+    if !extra.is_empty() {
+        let kargs = kargs.get_or_insert_with(|| merge_deployment.map(deployment_kargs).unwrap_or_default());
+        for karg in extra {
+            if !kargs.contains(karg) {
+                kargs.push(karg.clone());
+            }
+        }
+    }
+    Ok(kargs.filter(|k| merge_deployment.is_some() || !k.is_empty()))
+}
+
+/// Odpowiednik `ostree admin os-init`, gdy /ostree/deploy/<name> jeszcze nie istnieje
+fn ensure_stateroot(sysroot: &ostree::Sysroot, name: &str) -> Result<()> {
+    let path = sysroot.path().path().map(|p| p.join("ostree/deploy").join(name));
+    if path.as_ref().is_some_and(|p| p.exists()) {
+        return Ok(());
+    }
+    println!("Initializing stateroot {}...", name);
+    sysroot
+        .init_osname(name, gio::Cancellable::NONE)
+        .with_context(|| format!("Initializing stateroot {}", name))?;
+    Ok(())
+}
+
+/// Sysroot z `--sysroot`, zainicjalizowany i wczytany
+pub fn load_target_sysroot(opts: &DeployOpts) -> Result<ostree::Sysroot> {
+    let cancellable = gio::Cancellable::NONE;
+    let sysroot = ostree::Sysroot::new(Some(&gio::File::for_path(&opts.sysroot)));
+    sysroot.ensure_initialized(cancellable).context("Initializing sysroot")?;
+    sysroot.load(cancellable).context("Loading sysroot")?;
+    Ok(sysroot)
+}
+
+pub fn deploy(opts: &DeployOpts) -> Result<()> {
+    let cancellable = gio::Cancellable::NONE;
+    let sysroot = load_target_sysroot(opts)?;
+
+    let stateroot = match opts.stateroot.as_deref() {
+        Some(s) => s.to_string(),
+        None => sysroot
+            .booted_deployment()
+            .map(|d| d.osname().to_string())
+            .ok_or_else(|| anyhow!("Not booted into an OSTree deployment; pass --stateroot"))?,
+    };
+
+    let _lock = lock_sysroot(&sysroot)?;
+    ensure_stateroot(&sysroot, &stateroot)?;
+    // os-init zmienia /ostree/deploy, więc stan trzeba wczytać ponownie
+    sysroot.load(cancellable)?;
+
+    let repo = sysroot.repo();
+    let commit = resolve_commit(&repo, &opts.refspec)?;
+    crate::bootable::ensure_bootable(&repo, &commit)?;
+    let origin = new_origin(&sysroot, &opts.refspec)?;
+    let merge_deployment = sysroot.merge_deployment(Some(&stateroot));
+
+    let kargs = deploy_kargs(&repo, merge_deployment.as_ref(), &commit, &opts.karg)?;
+    let kargs_refs: Option<Vec<&str>> = kargs
+        .as_ref()
+        .map(|k| k.iter().map(|s| s.as_str()).collect());
+    let deploy_opts = ostree::SysrootDeployTreeOpts {
+        override_kernel_argv: kargs_refs.as_deref(),
+        ..Default::default()
+    };
+
+    let deployment = if sysroot.is_booted() && merge_deployment.is_some() {
+        let deployment = sysroot
+            .stage_tree_with_options(
+                Some(&stateroot),
+                &commit,
+                Some(&origin),
+                merge_deployment.as_ref(),
+                &deploy_opts,
+                cancellable,
+            )
+            .context("Staging deployment")?;
+        println!("Staged deployment {} in stateroot {}; reboot to apply", commit, stateroot);
+        deployment
+    } else {
+        let deployment = sysroot
+            .deploy_tree_with_options(
+                Some(&stateroot),
+                &commit,
+                Some(&origin),
+                merge_deployment.as_ref(),
+                Some(&deploy_opts),
+                cancellable,
+            )
+            .context("Deploying tree")?;
+        sysroot
+            .simple_write_deployment(
+                Some(&stateroot),
+                &deployment,
+                merge_deployment.as_ref(),
+                ostree::SysrootSimpleWriteDeploymentFlags::NONE,
+                cancellable,
+            )
+            .context("Writing deployment")?;
+        println!("Deployed {} to stateroot {}", commit, stateroot);
+        deployment
+    };
+    if let Some(merge_deployment) = &merge_deployment {
+        crate::etc_merge::merge_for_deployment(&sysroot, merge_deployment, &deployment)?;
+    }
+    Ok(())
+}
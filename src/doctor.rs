@@ -0,0 +1,143 @@
+// Diagnostyka typowych problemów systemu OSTree (`doctor`)
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use anyhow::Result;
+use clap::Parser;
+use ostree_ext::ostree;
+
+use crate::composepost::BASE_SYMLINKS;
+use crate::layered_packages::load_sysroot;
+
+const STAGED_DEPLOYMENT_FILE: &str = "/run/ostree/staged-deployment";
+const REPO_TRANSACTION_LINK: &str = "/ostree/repo/transaction";
+
+#[derive(Parser, Debug)]
+pub struct DoctorOpts {
+    /// Warn when a staged deployment has been waiting for a reboot longer than this
+    #[clap(long, default_value = "7")]
+    pub max_staged_age_days: u64,
+}
+
+/// Wynik jednego sprawdzenia: opis problemu i sposób naprawy
+struct Finding {
+    problem: String,
+    remedy: String,
+}
+
+/// Initramfs uruchomionego deploymentu musi zawierać ostree-prepare-root
+fn check_initramfs(findings: &mut Vec<Finding>) {
+    let Ok(modules) = std::fs::read_dir("/usr/lib/modules") else {
+        return;
+    };
+
+    for kdir in modules.filter_map(Result::ok) {
+        let initramfs = kdir.path().join("initramfs.img");
+        if !initramfs.exists() {
+            continue;
+        }
+        let output = match Command::new("lsinitrd").arg(&initramfs).output() {
+            Ok(o) if o.status.success() => o,
+            _ => {
+                println!("Skipping initramfs check for {}: lsinitrd unavailable", initramfs.display());
+                continue;
+            }
+        };
+        if !String::from_utf8_lossy(&output.stdout).contains("ostree-prepare-root") {
+            findings.push(Finding {
+                problem: format!("{} does not contain the ostree dracut module", initramfs.display()),
+                remedy: "Rebuild the image with dracut `--add ostree` (compose does this by default)".into(),
+            });
+        }
+    }
+}
+
+fn check_var_symlinks(findings: &mut Vec<Finding>) {
+    for (target, link) in BASE_SYMLINKS {
+        let path = Path::new("/").join(link);
+        let ok = path.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false);
+        if !ok {
+            findings.push(Finding {
+                problem: format!("/{} is not a symlink", link),
+                remedy: format!("Recreate it with `ln -s {} /{}` or recompose the image", target, link),
+            });
+        }
+    }
+}
+
+fn check_repo(sysroot: &ostree::Sysroot, findings: &mut Vec<Finding>) -> Result<()> {
+    let repo = sysroot.repo();
+
+    if repo.mode() != ostree::RepoMode::Bare {
+        findings.push(Finding {
+            problem: format!("System repo mode is {:?}, expected bare", repo.mode()),
+            remedy: "Compose into a separate repo and pull-local into /ostree/repo".into(),
+        });
+    }
+
+    for remote in repo.remote_list() {
+        let gpg = repo.remote_get_gpg_verify(&remote).unwrap_or(false);
+        let sign = repo
+            .get_remote_boolean_option(&remote, "sign-verify", false)
+            .unwrap_or(false);
+        if !gpg && !sign {
+            findings.push(Finding {
+                problem: format!("Remote {} does not verify signatures", remote),
+                remedy: format!("Set gpg-verify=true or sign-verify=true for remote {}", remote),
+            });
+        }
+    }
+
+    // Link transakcji bez trzymanej blokady sysroota to pozostałość po przerwanej operacji
+    if Path::new(REPO_TRANSACTION_LINK).symlink_metadata().is_ok() && sysroot.try_lock()? {
+        sysroot.unlock();
+        findings.push(Finding {
+            problem: format!("Stale repository transaction at {}", REPO_TRANSACTION_LINK),
+            remedy: format!("Remove {} and run `ostree admin cleanup`", REPO_TRANSACTION_LINK),
+        });
+    }
+
+    Ok(())
+}
+
+fn check_staged_age(max_days: u64, findings: &mut Vec<Finding>) {
+    let Ok(meta) = std::fs::metadata(STAGED_DEPLOYMENT_FILE) else {
+        return;
+    };
+    let age = meta
+        .modified()
+        .ok()
+        .and_then(|m| SystemTime::now().duration_since(m).ok())
+        .unwrap_or_default();
+    if age > Duration::from_secs(max_days * 24 * 60 * 60) {
+        findings.push(Finding {
+            problem: format!("Staged deployment has been waiting {} days for a reboot", age.as_secs() / 86400),
+            remedy: "Reboot to apply it, or discard it with `pacman-ostree rollback`".into(),
+        });
+    }
+}
+
+pub fn doctor(opts: DoctorOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    if sysroot.booted_deployment().is_none() {
+        anyhow::bail!("Not booted into an OSTree deployment");
+    }
+
+    let mut findings = Vec::new();
+    check_initramfs(&mut findings);
+    check_var_symlinks(&mut findings);
+    check_repo(&sysroot, &mut findings)?;
+    check_staged_age(opts.max_staged_age_days, &mut findings);
+
+    if findings.is_empty() {
+        println!("No problems found");
+        return Ok(());
+    }
+
+    for f in &findings {
+        println!("✗ {}", f.problem);
+        println!("  → {}", f.remedy);
+    }
+    anyhow::bail!("{} problem(s) found", findings.len())
+}
@@ -0,0 +1,236 @@
+// Starsza wersja pakietu warstwy z cache albo z Arch Linux Archive (`downgrade`)
+//
+// Wybrana wersja trafia do magazynu jak plik z `install ./pakiet.pkg.tar.zst`, więc kolejne
+// rebuildy (także przy `upgrade`) instalują ją zamiast bieżącej z repozytorium.
+
+use std::cmp::Ordering;
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use tempfile::TempDir;
+
+use crate::layered_packages::{deploy_layered_state, load_sysroot, local_packages_dir, pending_state, store_local_package};
+use crate::pacman_manager::{read_packages_from_commit, PACKAGE_CACHE_DIR};
+use crate::reboot::{maybe_reboot, RebootOpts};
+
+const ARCHIVE_URL: &str = "https://archive.archlinux.org/packages";
+
+#[derive(Parser, Debug)]
+pub struct DowngradeOpts {
+    /// Layered package to downgrade
+    pub package: String,
+
+    /// Version to install, e.g. `1.2.3-1` (default: the newest version older than the installed one)
+    pub version: Option<String>,
+
+    /// List the versions available in the cache and the archive instead of downgrading
+    #[clap(long, conflicts_with = "version")]
+    pub list: bool,
+
+    /// Drop the pinned version and follow the repositories again
+    #[clap(long, conflicts_with_all = ["version", "list"])]
+    pub reset: bool,
+
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+/// Plik pakietu w konkretnej wersji: w cache albo do pobrania z archiwum
+#[derive(Debug, Clone)]
+enum Source {
+    Cache(Utf8PathBuf),
+    Archive(String),
+}
+
+/// Wersja z nazwy pliku `name-wersja-wydanie-arch.pkg.tar.*`
+fn file_version(name: &str, file_name: &str) -> Option<String> {
+    if file_name.ends_with(".sig") {
+        return None;
+    }
+    let (stem, _) = file_name.strip_prefix(name)?.strip_prefix('-')?.split_once(".pkg.tar")?;
+    // Wersja i arch nie zawierają `-`, więc zostają dokładnie dwa: przed wydaniem i przed arch
+    if stem.matches('-').count() != 2 {
+        return None;
+    }
+    let (version, _arch) = stem.rsplit_once('-')?;
+    Some(version.to_string())
+}
+
+fn cached_versions(name: &str) -> Vec<(String, Source)> {
+    let Ok(entries) = std::fs::read_dir(PACKAGE_CACHE_DIR) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|e| Utf8PathBuf::try_from(e.path()).ok())
+        .filter_map(|path| Some((file_version(name, path.file_name()?)?, Source::Cache(path))))
+        .collect()
+}
+
+/// Wersje z indeksu katalogu pakietu w archiwum
+fn archive_versions(name: &str) -> Result<Vec<(String, Source)>> {
+    if crate::network::cache_only() {
+        return Ok(Vec::new());
+    }
+    let first = name.chars().next().ok_or_else(|| anyhow!("Empty package name"))?;
+    let dir = format!("{}/{}/{}/", ARCHIVE_URL, first, name);
+    let index = crate::network::with_retries(&format!("Listing {}", dir), || {
+        let output = Command::new("curl")
+            .args(["--fail", "--location", "--silent", "--show-error"])
+            .args(crate::network::config().curl_args())
+            .arg(&dir)
+            .output()
+            .context("Failed to run curl")?;
+        // Pakietu nie ma w archiwum (np. z innego repozytorium) — to nie błąd sieci
+        if output.status.code() == Some(22) {
+            return Ok(String::new());
+        }
+        if !output.status.success() {
+            anyhow::bail!("Listing {} failed: {}", dir, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    })?;
+    Ok(index
+        .split("href=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .filter_map(|file| Some((file_version(name, file)?, Source::Archive(format!("{}{}", dir, file)))))
+        .collect())
+}
+
+/// Dostępne wersje od najnowszej; plik z cache ma pierwszeństwo przed archiwum
+fn available_versions(name: &str) -> Result<Vec<(String, Source)>> {
+    let mut versions = cached_versions(name);
+    for (version, source) in archive_versions(name)? {
+        if !versions.iter().any(|(v, _)| *v == version) {
+            versions.push((version, source));
+        }
+    }
+    versions.sort_by(|(a, _), (b, _)| alpm::vercmp(b.as_str(), a.as_str()));
+    Ok(versions)
+}
+
+fn curl_download(url: &str, dest: &Utf8Path) -> Result<()> {
+    crate::network::with_retries(&format!("Downloading {}", url), || {
+        let status = crate::subprocess::status(
+            Command::new("curl")
+                .args(["--fail", "--location", "--silent", "--show-error", "--output"])
+                .arg(dest)
+                .args(crate::network::config().curl_args())
+                .arg(url),
+        )?;
+        if !status.success() {
+            anyhow::bail!("Downloading {} failed", url);
+        }
+        Ok(())
+    })
+}
+
+/// Plik pakietu gotowy do skopiowania do magazynu; pobrany z archiwum razem z podpisem
+fn fetch(source: &Source, tmp: &TempDir) -> Result<Utf8PathBuf> {
+    match source {
+        Source::Cache(path) => Ok(path.clone()),
+        Source::Archive(url) => {
+            let file_name = url.rsplit('/').next().unwrap_or(url);
+            let dest = Utf8PathBuf::try_from(tmp.path().join(file_name))?;
+            curl_download(url, &dest)?;
+            let sig = Utf8PathBuf::from(format!("{}.sig", dest));
+            if let Err(e) = curl_download(&format!("{}.sig", url), &sig) {
+                eprintln!("Warning: no signature for {}: {:#}", file_name, e);
+            }
+            Ok(dest)
+        }
+    }
+}
+
+/// Plik pakietu `name` w dokładnie tej wersji z cache albo z archiwum; `None`, gdy nigdzie go nie ma
+pub(crate) fn fetch_version(name: &str, version: &str, tmp: &TempDir) -> Result<Option<Utf8PathBuf>> {
+    match available_versions(name)?.into_iter().find(|(v, _)| v == version) {
+        Some((_, source)) => fetch(&source, tmp).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn reset(opts: &DowngradeOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+    if state.downgraded.remove(&opts.package).is_none() {
+        anyhow::bail!("{} is not downgraded", opts.package);
+    }
+    let file = state.local_packages.remove(&opts.package);
+    state.layered_packages.insert(opts.package.clone());
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    if let Some(file) = file {
+        let _ = std::fs::remove_file(local_packages_dir().join(file));
+    }
+    println!("{} follows the repositories again", opts.package);
+    Ok(())
+}
+
+fn downgrade_impl(opts: &DowngradeOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+    let name = &opts.package;
+    if !state.layered_packages.contains(name) && !state.local_packages.contains_key(name) {
+        let base = read_packages_from_commit(&sysroot.repo(), &state.base_commit)?;
+        if base.contains_key(name) {
+            anyhow::bail!("{} is part of the base image; use `override replace` with an older package file", name);
+        }
+        anyhow::bail!("Package {} is not layered", name);
+    }
+    let installed = read_packages_from_commit(&sysroot.repo(), &booted.csum())?;
+    let current = installed.get(name).cloned().unwrap_or_default();
+
+    let versions = crate::output::progress(|| available_versions(name))?;
+    if opts.list {
+        for (version, source) in &versions {
+            let marker = if *version == current { " (installed)" } else { "" };
+            let from = if matches!(source, Source::Cache(_)) { "cache" } else { "archive" };
+            println!("{} [{}]{}", version, from, marker);
+        }
+        return Ok(());
+    }
+
+    let (version, source) = match &opts.version {
+        Some(wanted) => versions
+            .iter()
+            .find(|(v, _)| v == wanted)
+            .ok_or_else(|| anyhow!("{} {} not found in {} or the Arch Linux Archive", name, wanted, PACKAGE_CACHE_DIR))?,
+        None => versions
+            .iter()
+            .find(|(v, _)| alpm::vercmp(v.as_str(), current.as_str()) == Ordering::Less)
+            .ok_or_else(|| anyhow!("No version of {} older than {} found", name, current))?,
+    };
+    if *version == current {
+        anyhow::bail!("{} {} is already installed", name, version);
+    }
+
+    let tmp = TempDir::new()?;
+    let file = crate::output::progress(|| fetch(source, &tmp))?;
+    let stale = store_local_package(&mut state, name, &file)?;
+    state.layered_packages.remove(name);
+    state.downgraded.insert(name.clone(), version.clone());
+    println!("Downgrading {} {} -> {}", name, current, version);
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    if let Some(file) = stale {
+        let _ = std::fs::remove_file(local_packages_dir().join(file));
+    }
+    Ok(())
+}
+
+pub fn downgrade(opts: DowngradeOpts) -> Result<()> {
+    if opts.list {
+        return downgrade_impl(&opts);
+    }
+    let packages = vec![opts.package.clone()];
+    crate::output::progress(|| {
+        if opts.reset {
+            crate::history::record_transaction("downgrade", &packages, || reset(&opts))
+        } else {
+            crate::history::record_transaction("downgrade", &packages, || downgrade_impl(&opts))
+        }
+    })?;
+    crate::layered_packages::emit_transaction_report(&load_sysroot()?, "downgrade", &packages)?;
+    maybe_reboot(&opts.reboot)
+}
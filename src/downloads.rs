@@ -0,0 +1,116 @@
+// Pobieranie przez libalpm: równoległe połączenia i pasek postępu dla każdego pliku
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use alpm::{Alpm, AnyDownloadEvent, DownloadEvent, DownloadResult, FetchResult};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+/// Gdy pacman.conf nie ustawia ParallelDownloads — tyle co w pacman.conf z dystrybucji
+const DEFAULT_PARALLEL_DOWNLOADS: u32 = 5;
+
+/// Liczba równoległych pobrań: `parallel-downloads` z network.yaml, potem ParallelDownloads z pacman.conf
+pub fn parallel_downloads(from_pacman_conf: u64) -> u32 {
+    let from_conf = match from_pacman_conf {
+        0 => DEFAULT_PARALLEL_DOWNLOADS,
+        n => n.min(u32::MAX as u64) as u32,
+    };
+    crate::network::config().parallel_downloads.unwrap_or(from_conf).max(1)
+}
+
+/// Paski bieżących pobrań. Bez terminala (logi compose, `--json`) zostaje po linii na plik.
+struct Bars {
+    multi: MultiProgress,
+    bars: HashMap<String, ProgressBar>,
+    interactive: bool,
+}
+
+impl Bars {
+    fn event(&mut self, filename: &str, event: AnyDownloadEvent) {
+        match event.event() {
+            DownloadEvent::Init(_) if self.interactive => {
+                let style = ProgressStyle::with_template("{prefix:40!} {bytes:>10}/{total_bytes:<10} {wide_bar} {percent:>3}%")
+                    .unwrap_or_else(|_| ProgressStyle::default_bar());
+                let bar = self.multi.add(ProgressBar::new(0).with_style(style).with_prefix(filename.to_string()));
+                self.bars.insert(filename.to_string(), bar);
+            }
+            DownloadEvent::Progress(progress) => {
+                if let Some(bar) = self.bars.get(filename) {
+                    if progress.total > 0 {
+                        bar.set_length(progress.total as u64);
+                    }
+                    bar.set_position(progress.downloaded.max(0) as u64);
+                }
+            }
+            // Kolejny mirror zaczyna plik od nowa
+            DownloadEvent::Retry(_) => {
+                if let Some(bar) = self.bars.get(filename) {
+                    bar.reset();
+                }
+            }
+            DownloadEvent::Completed(completed) => {
+                let bar = self.bars.remove(filename);
+                match (completed.result, bar) {
+                    (DownloadResult::Success, Some(bar)) => bar.finish(),
+                    (DownloadResult::Success, None) => println!("downloaded {}", filename),
+                    (DownloadResult::UpToDate, Some(bar)) => bar.finish_and_clear(),
+                    // Błąd zgłasza już log libalpm
+                    (DownloadResult::Failed, Some(bar)) => bar.abandon_with_message("failed"),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Pobranie jednego pliku przez curl zamiast wbudowanego pobierania libalpm: z limitem
+/// przepustowości z `--bwlimit` i limitem czasu jak dla innych poleceń. Plik trafia najpierw
+/// do `.part`, a bez `force` istniejący plik jest pobierany tylko, gdy na serwerze jest nowszy.
+fn curl_fetch(url: &str, localpath: &str, force: bool) -> FetchResult {
+    let Some(name) = url.rsplit('/').next().filter(|n| !n.is_empty()) else {
+        return FetchResult::Err;
+    };
+    let dest = Path::new(localpath).join(name);
+    let part = Path::new(localpath).join(format!("{}.part", name));
+    let mut cmd = Command::new("curl");
+    cmd.args(["--fail", "--location", "--silent", "--show-error", "--continue-at", "-"])
+        .args(crate::network::config().curl_args());
+    if !force && dest.exists() {
+        cmd.arg("--time-cond").arg(&dest);
+    }
+    cmd.arg("--output").arg(&part).arg(url);
+    match crate::subprocess::status(&mut cmd) {
+        Ok(status) if status.success() => {}
+        Ok(_) => return FetchResult::Err,
+        Err(e) => {
+            eprintln!("Error: downloading {}: {:#}", url, e);
+            return FetchResult::Err;
+        }
+    }
+    // Z --time-cond curl nic nie zapisuje, gdy plik na serwerze się nie zmienił
+    if !part.exists() {
+        return FetchResult::FileExists;
+    }
+    match std::fs::rename(&part, &dest) {
+        Ok(()) => FetchResult::Ok,
+        Err(_) => FetchResult::Err,
+    }
+}
+
+/// Ustawia liczbę równoległych pobrań i paski postępu na uchwycie. libalpm ignoruje
+/// XferCommand i nie ma limitu przepustowości ani czasu, więc przy `--bwlimit` albo
+/// ustawionym limicie dla curl pobieranie przejmuje [`curl_fetch`] (plik po pliku).
+pub fn configure(handle: &mut Alpm, from_pacman_conf: u64) {
+    if crate::network::config().bwlimit.is_some() || crate::subprocess::configured("curl") {
+        handle.set_fetch_cb((), |url, localpath, force, _| curl_fetch(url, localpath, force));
+        return;
+    }
+    handle.set_parallel_downloads(parallel_downloads(from_pacman_conf));
+    let bars = Bars {
+        multi: MultiProgress::new(),
+        bars: HashMap::new(),
+        interactive: console::Term::stdout().is_term() && !crate::output::json(),
+    };
+    handle.set_dl_cb(bars, |filename, event, bars| bars.event(filename, event));
+}
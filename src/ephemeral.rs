@@ -0,0 +1,139 @@
+// Warstwy budowane bez uruchomionego systemu OSTree (`install/remove --ephemeral`)
+//
+// Zamiast stage'ować deployment, commit z warstwami zostaje w podanym repo i jest
+// rozpakowywany do katalogu albo eksportowany jako archiwum OCI, więc zmiany można
+// sprawdzić w kontenerze CI, zanim trafią na prawdziwe maszyny.
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Args;
+use ostree_ext::container::{ImageReference, Transport};
+use ostree_ext::{gio, ostree};
+use tempfile::TempDir;
+
+use crate::container::{container_encapsulate, ContainerEncapsulateOpts};
+use crate::layered_packages::{rebuild_with_layers, LayeredState};
+
+/// Repozytorium, gdy `--repo` nie podano
+const DEFAULT_REPO: &str = "/sysroot/ostree/repo";
+
+#[derive(Args, Debug, Clone)]
+pub struct EphemeralOpts {
+    /// Build the layered commit without a booted OSTree system (e.g. in a CI container)
+    #[clap(long, requires = "base")]
+    pub ephemeral: bool,
+
+    /// OSTree repository holding the base commit (default: /sysroot/ostree/repo)
+    #[clap(long, requires = "ephemeral")]
+    pub repo: Option<Utf8PathBuf>,
+
+    /// Base ref or commit to layer on; a commit made with --ephemeral keeps its layers
+    #[clap(long, requires = "ephemeral")]
+    pub base: Option<String>,
+
+    /// Check out the layered commit into this directory (must not exist)
+    #[clap(long, requires = "ephemeral")]
+    pub target_dir: Option<Utf8PathBuf>,
+
+    /// Export the layered commit as an OCI archive
+    #[clap(long, requires = "ephemeral")]
+    pub output_oci: Option<Utf8PathBuf>,
+}
+
+impl EphemeralOpts {
+    fn repo_path(&self) -> &Utf8Path {
+        self.repo.as_deref().unwrap_or(Utf8Path::new(DEFAULT_REPO))
+    }
+}
+
+/// Repo, commit podany w `--base` i jego stan
+pub fn load_state(opts: &EphemeralOpts) -> Result<(ostree::Repo, String, LayeredState)> {
+    if opts.target_dir.is_none() && opts.output_oci.is_none() {
+        anyhow::bail!("--ephemeral needs --target-dir or --output-oci");
+    }
+    let base = opts.base.as_deref().ok_or_else(|| anyhow!("--ephemeral needs --base"))?;
+    let repo = ostree::Repo::open_at(libc::AT_FDCWD, opts.repo_path().as_str(), gio::Cancellable::NONE)
+        .with_context(|| format!("Opening repository {}", opts.repo_path()))?;
+    let commit = repo.require_rev(base)?;
+    let state = match LayeredState::from_commit(&repo, &commit)? {
+        Some(state) => state,
+        None => LayeredState {
+            base_refspec: base.to_string(),
+            base_commit: commit.to_string(),
+            ..Default::default()
+        },
+    };
+    Ok((repo, commit.to_string(), state))
+}
+
+fn checkout(repo: &ostree::Repo, commit: &str, dest: &Utf8Path) -> Result<()> {
+    // Kopie zamiast hardlinków — katalog jest do dowolnych modyfikacji w testach
+    let mut checkout_opts = ostree::RepoCheckoutAtOptions::default();
+    checkout_opts.force_copy = true;
+    repo.checkout_at(Some(&checkout_opts), libc::AT_FDCWD, dest.as_str(), commit, gio::Cancellable::NONE)
+        .with_context(|| format!("Checking out {} to {}", commit, dest))
+}
+
+fn export_oci(opts: &EphemeralOpts, repo: &ostree::Repo, commit: &str, output: &Utf8Path) -> Result<()> {
+    // Podział na warstwy potrzebuje bazy pakietów z drzewa commita
+    let _tmp;
+    let rootfs = match opts.target_dir.as_deref() {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            let tmp = TempDir::new()?;
+            let dir = Utf8PathBuf::try_from(tmp.path().join("rootfs"))?;
+            checkout(repo, commit, &dir)?;
+            _tmp = tmp;
+            dir
+        }
+    };
+    let container_opts = ContainerEncapsulateOpts {
+        repo: opts.repo_path().to_path_buf(),
+        ostree_ref: commit.to_string(),
+        imgref: ImageReference {
+            transport: Transport::OciArchive,
+            name: output.to_string(),
+        },
+        labels: vec![],
+        image_config: None,
+        arch: None,
+        copy_meta_keys: vec![],
+        copy_meta_opt_keys: vec![],
+        cmd: None,
+        max_layers: None,
+        format_version: crate::container::DEFAULT_FORMAT_VERSION,
+        write_contentmeta_json: None,
+        compare_with_build: None,
+        previous_build_manifest: None,
+        pacman_db_path: rootfs.join("usr/share/pacman/local"),
+        exclusive_packages: vec![],
+        compression: None,
+        compression_level: None,
+        compression_jobs: None,
+        max_duplicate_bytes: None,
+    };
+    // Komendy warstw są synchroniczne, a eksport jest async
+    let report = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(container_encapsulate(container_opts))
+    })?;
+    println!("Exported {} to {} ({})", commit, output, report.digest);
+    Ok(())
+}
+
+/// Buduje commit ze stanem i zapisuje go do `--target-dir` i/lub `--output-oci`; zwraca commit
+pub fn finish(opts: &EphemeralOpts, repo: &ostree::Repo, state: &LayeredState) -> Result<String> {
+    let commit = if state.is_empty() {
+        state.base_commit.clone()
+    } else {
+        rebuild_with_layers(repo, state)?
+    };
+    println!("Built commit {} in {}", commit, opts.repo_path());
+    if let Some(dir) = opts.target_dir.as_deref() {
+        checkout(repo, &commit, dir)?;
+        println!("Checked out {} to {}", commit, dir);
+    }
+    if let Some(output) = opts.output_oci.as_deref() {
+        export_oci(opts, repo, &commit, output)?;
+    }
+    Ok(commit)
+}
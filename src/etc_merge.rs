@@ -0,0 +1,210 @@
+// Scalanie /etc przy zmianie deploymentu
+//
+// ostree przy finalizacji bierze /etc z merge deploymentu i nakłada na nie zmiany z nowego
+// /usr/etc tylko tam, gdzie administrator niczego nie ruszał — zmieniony lokalnie plik
+// wygrywa, a nowa domyślna wersja z pakietu znika bez śladu. Tu wykrywamy takie pliki
+// i zapisujemy nową wersję obok jako `.pacnew`, tak jak pacman na zwykłym systemie.
+//
+// Zestage'owany deployment dostaje swoje /etc dopiero przy finalizacji (przy wyłączaniu),
+// więc porównanie robimy po pierwszym uruchomieniu nowego deploymentu, w jego /etc —
+// `.pacnew` nie trafia do /etc deploymentu, do którego można jeszcze wrócić.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use ostree_ext::{gio, ostree};
+use ostree_ext::prelude::*;
+use walkdir::WalkDir;
+
+use crate::layered_packages::{load_sysroot, STATE_DIR};
+
+const USR_ETC: &str = "usr/etc";
+const PACNEW_SUFFIX: &str = ".pacnew";
+/// Scalanie czekające na pierwsze uruchomienie deploymentu: `<commit>.<serial> <stary commit>`
+const PENDING_FILE: &str = "etc-merge-pending";
+
+/// Pliki (zwykłe) z /usr/etc commita: ścieżka względem /etc -> plik w repo
+fn etc_files(repo: &ostree::Repo, commit: &str) -> Result<BTreeMap<Utf8PathBuf, ostree::RepoFile>> {
+    let (root, _) = repo
+        .read_commit(commit, gio::Cancellable::NONE)
+        .with_context(|| format!("Reading commit {}", commit))?;
+    let mut files = BTreeMap::new();
+    collect(&root.resolve_relative_path(USR_ETC), &mut Utf8PathBuf::new(), &mut files)?;
+    Ok(files)
+}
+
+fn collect(dir: &gio::File, rel: &mut Utf8PathBuf, files: &mut BTreeMap<Utf8PathBuf, ostree::RepoFile>) -> Result<()> {
+    let cancellable = gio::Cancellable::NONE;
+    let entries = match dir.enumerate_children(
+        "standard::name,standard::type",
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        cancellable,
+    ) {
+        Ok(e) => e,
+        Err(e) if e.matches(gio::IOErrorEnum::NotFound) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for info in entries {
+        let info = info?;
+        let name = info.name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let child = dir.child(name);
+        rel.push(name);
+        match info.file_type() {
+            gio::FileType::Directory => collect(&child, rel, files)?,
+            gio::FileType::Regular => {
+                if let Ok(file) = child.downcast::<ostree::RepoFile>() {
+                    files.insert(rel.clone(), file);
+                }
+            }
+            // Symlinki i pliki specjalne ostree scala sam
+            _ => {}
+        }
+        rel.pop();
+    }
+    Ok(())
+}
+
+fn contents(file: &ostree::RepoFile) -> Result<Vec<u8>> {
+    let (data, _) = file.load_contents(gio::Cancellable::NONE)?;
+    Ok(data.to_vec())
+}
+
+/// Plik zmieniony przez administratora, którego domyślna wersja zmieniła się w nowym drzewie
+#[derive(Debug)]
+pub struct EtcConflict {
+    /// Ścieżka w /etc
+    pub path: PathBuf,
+    /// Gdzie zapisano nową wersję
+    pub pacnew: PathBuf,
+}
+
+/// Trójstronne porównanie: /usr/etc `old_commit` (podstawa), /usr/etc `new_commit` (nowe
+/// domyślne) i `etc` administratora. Pliki zmienione po obu stronach dostają `.pacnew` w `etc`.
+pub fn merge(repo: &ostree::Repo, old_commit: &str, new_commit: &str, etc: &Path) -> Result<Vec<EtcConflict>> {
+    if old_commit == new_commit {
+        return Ok(Vec::new());
+    }
+    let old = etc_files(repo, old_commit)?;
+    let new = etc_files(repo, new_commit)?;
+
+    let mut conflicts = Vec::new();
+    for (rel, new_file) in &new {
+        let old_file = old.get(rel);
+        if old_file.is_some_and(|o| o.checksum() == new_file.checksum()) {
+            continue;
+        }
+        let path = etc.join(rel);
+        // Usunięty przez administratora plik ostree też zostawia usunięty
+        let Ok(local) = std::fs::read(&path) else {
+            continue;
+        };
+        let new_contents = contents(new_file)?;
+        if local == new_contents {
+            continue;
+        }
+        // Niezmieniony lokalnie plik ostree po prostu zastąpi nową wersją
+        if let Some(old_file) = old_file {
+            if local == contents(old_file)? {
+                continue;
+            }
+        }
+        let pacnew = etc.join(format!("{}{}", rel, PACNEW_SUFFIX));
+        std::fs::write(&pacnew, &new_contents).with_context(|| format!("Writing {}", pacnew.display()))?;
+        conflicts.push(EtcConflict {
+            path: Path::new("/etc").join(rel),
+            pacnew: Path::new("/etc").join(format!("{}{}", rel, PACNEW_SUFFIX)),
+        });
+    }
+    Ok(conflicts)
+}
+
+fn deployment_key(deployment: &ostree::Deployment) -> String {
+    format!("{}.{}", deployment.csum(), deployment.deployserial())
+}
+
+fn pending_path() -> PathBuf {
+    Path::new(STATE_DIR).join(PENDING_FILE)
+}
+
+fn warn_conflicts(conflicts: &[EtcConflict]) {
+    for conflict in conflicts {
+        eprintln!(
+            "Warning: {} is modified locally and changed in the new deployment; the new version is saved as {}",
+            conflict.path.display(),
+            conflict.pacnew.display()
+        );
+    }
+}
+
+/// Scala /etc nowego deploymentu z `merge_deployment`: od razu, jeśli /etc już istnieje
+/// (`deploy_tree`), a dla zestage'owanego przy jego pierwszym uruchomieniu
+pub fn merge_for_deployment(
+    sysroot: &ostree::Sysroot,
+    merge_deployment: &ostree::Deployment,
+    new_deployment: &ostree::Deployment,
+) -> Result<()> {
+    if merge_deployment.csum() == new_deployment.csum() {
+        return Ok(());
+    }
+    if new_deployment.is_staged() {
+        std::fs::create_dir_all(STATE_DIR)?;
+        let pending = pending_path();
+        let contents = format!("{} {}\n", deployment_key(new_deployment), merge_deployment.csum());
+        return std::fs::write(&pending, contents).with_context(|| format!("Writing {}", pending.display()));
+    }
+    let root = sysroot.path().path().unwrap_or_else(|| PathBuf::from("/"));
+    let etc = root.join(sysroot.deployment_dirpath(new_deployment).as_str()).join("etc");
+    let conflicts = merge(&sysroot.repo(), &merge_deployment.csum(), &new_deployment.csum(), &etc)?;
+    warn_conflicts(&conflicts);
+    Ok(())
+}
+
+/// Kończy scalanie zapisane przy stage'owaniu, gdy uruchomiony jest już nowy deployment
+/// (usługa systemd); po odrzuceniu albo wycofaniu deploymentu zapis po prostu znika
+pub fn finish_pending() -> Result<()> {
+    let pending = pending_path();
+    let contents = match std::fs::read_to_string(&pending) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", pending.display())),
+    };
+    let Some((key, old_commit)) = contents.trim().split_once(' ') else {
+        anyhow::bail!("Malformed {}", pending.display());
+    };
+    let sysroot = load_sysroot()?;
+    let Some(booted) = sysroot.booted_deployment() else {
+        return Ok(());
+    };
+    if deployment_key(&booted) != key {
+        // Deployment jeszcze czeka na uruchomienie — zostawiamy zapis na następny start
+        if sysroot.deployments().iter().any(|d| deployment_key(d) == key) {
+            return Ok(());
+        }
+        std::fs::remove_file(&pending)?;
+        return Ok(());
+    }
+    let conflicts = merge(&sysroot.repo(), old_commit, &booted.csum(), Path::new("/etc"))?;
+    warn_conflicts(&conflicts);
+    std::fs::remove_file(&pending)?;
+    Ok(())
+}
+
+/// Pliki `.pacnew`, które pacman zostawił w /etc przebudowywanego drzewa (konfiguracja bazy
+/// zmieniona przy compose, a pakiet przynosi inną)
+pub fn report_pacnew(etc: &Path) {
+    for entry in WalkDir::new(etc).into_iter().filter_map(Result::ok) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(rel) = entry.path().strip_prefix(etc) else {
+            continue;
+        };
+        if let Some(target) = rel.to_str().and_then(|p| p.strip_suffix(PACNEW_SUFFIX)) {
+            eprintln!("Warning: /etc/{} differs from the packaged version; kept the base file, the package version is /etc/{}", target, rel.display());
+        }
+    }
+}
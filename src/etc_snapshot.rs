@@ -0,0 +1,116 @@
+// Kopie /etc robione przed zmianą deploymentu (`ex restore-etc`)
+//
+// ostree scala /etc przy przełączeniu deploymentu, ale go nie wersjonuje — po złej
+// zmianie konfiguracji nie ma do czego wrócić. Przed każdą transakcją pakujemy więc
+// /etc uruchomionego deploymentu do tarballa nazwanego od tego deploymentu.
+
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use ostree_ext::ostree;
+
+use crate::layered_packages::{load_sysroot, STATE_DIR};
+
+const SNAPSHOT_DIR: &str = "etc-snapshots";
+const SNAPSHOT_SUFFIX: &str = ".tar.gz";
+/// Ile ostatnich kopii zostawiamy
+const KEEP_SNAPSHOTS: usize = 10;
+
+#[derive(Parser, Debug)]
+pub struct RestoreEtcOpts {
+    /// Deployment whose /etc snapshot to restore (`<commit>.<serial>`, a unique
+    /// commit prefix is enough); lists snapshots when omitted
+    pub deployment: Option<String>,
+    /// Extract the snapshot into this directory instead of /etc
+    #[clap(long)]
+    pub to: Option<Utf8PathBuf>,
+}
+
+fn snapshot_dir() -> Utf8PathBuf {
+    Utf8PathBuf::from(STATE_DIR).join(SNAPSHOT_DIR)
+}
+
+fn deployment_key(deployment: &ostree::Deployment) -> String {
+    format!("{}.{}", deployment.csum(), deployment.deployserial())
+}
+
+/// Kopie od najnowszej: (klucz deploymentu, ścieżka, czas modyfikacji)
+fn list_snapshots() -> Result<Vec<(String, Utf8PathBuf, std::time::SystemTime)>> {
+    let dir = snapshot_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        let Some(key) = entry.file_name().strip_suffix(SNAPSHOT_SUFFIX) else {
+            continue;
+        };
+        let mtime = entry.metadata()?.modified()?;
+        snapshots.push((key.to_string(), entry.path().to_path_buf(), mtime));
+    }
+    snapshots.sort_by(|a, b| b.2.cmp(&a.2));
+    Ok(snapshots)
+}
+
+fn run_tar(args: &[&str], dir: &Utf8Path) -> Result<()> {
+    let status = crate::subprocess::status(
+        Command::new("tar")
+            .args(["--xattrs", "--xattrs-include=*", "--acls", "--numeric-owner", "-C"])
+            .arg(dir)
+            .args(args),
+    )?;
+    if !status.success() {
+        anyhow::bail!("tar failed with {}", status);
+    }
+    Ok(())
+}
+
+/// Pakuje /etc uruchomionego deploymentu; kopia tego samego deploymentu jest nadpisywana
+pub fn snapshot_booted_etc() -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let Some(booted) = sysroot.booted_deployment() else {
+        return Ok(());
+    };
+    let dir = snapshot_dir();
+    std::fs::create_dir_all(&dir)?;
+    let dest = dir.join(format!("{}{}", deployment_key(&booted), SNAPSHOT_SUFFIX));
+    // Najpierw do pliku tymczasowego, żeby przerwany zapis nie zniszczył poprzedniej kopii
+    let tmp = dest.with_extension("tmp");
+    run_tar(&["-czf", tmp.as_str(), "."], Utf8Path::new("/etc"))?;
+    std::fs::rename(&tmp, &dest).with_context(|| format!("Writing {}", dest))?;
+
+    for (_, path, _) in list_snapshots()?.into_iter().skip(KEEP_SNAPSHOTS) {
+        std::fs::remove_file(&path).with_context(|| format!("Removing {}", path))?;
+    }
+    Ok(())
+}
+
+pub fn restore_etc(opts: RestoreEtcOpts) -> Result<()> {
+    let snapshots = list_snapshots()?;
+    let Some(wanted) = opts.deployment else {
+        if snapshots.is_empty() {
+            println!("No /etc snapshots");
+        }
+        for (key, _, mtime) in &snapshots {
+            let time: chrono::DateTime<chrono::Local> = (*mtime).into();
+            println!("{}  {}", time.format("%Y-%m-%d %H:%M"), key);
+        }
+        return Ok(());
+    };
+
+    let matches: Vec<_> = snapshots.iter().filter(|(key, _, _)| key.starts_with(&wanted)).collect();
+    let (key, path, _) = match matches.as_slice() {
+        [one] => *one,
+        [] => return Err(anyhow!("No /etc snapshot for deployment {}", wanted)),
+        _ => anyhow::bail!("Deployment {} is ambiguous; use the full <commit>.<serial>", wanted),
+    };
+
+    let dest = opts.to.unwrap_or_else(|| Utf8PathBuf::from("/etc"));
+    std::fs::create_dir_all(&dest)?;
+    // Pliki dodane po zrobieniu kopii zostają; nadpisywane są tylko te z kopii
+    run_tar(&["-xzpf", path.as_str(), "--overwrite"], &dest)?;
+    println!("Restored /etc of deployment {} into {}", key, dest);
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// fs-verity dla obiektów repo i checkoutów deploymentów
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use ostree_ext::{gio, ostree};
+use serde::Deserialize;
+
+use crate::layered_packages::{load_sysroot, lock_sysroot};
+
+/// Grupa konfiguracji repo, w której ostree trzyma ustawienia integralności
+const INTEGRITY_GROUP: &str = "ex-integrity";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FsVerityMode {
+    /// Do not enable fs-verity
+    #[default]
+    No,
+    /// Enable fs-verity where the filesystem supports it
+    Maybe,
+    /// Require fs-verity; fail on filesystems without support
+    Yes,
+}
+
+impl FsVerityMode {
+    fn as_config_value(self) -> &'static str {
+        match self {
+            FsVerityMode::No => "no",
+            FsVerityMode::Maybe => "maybe",
+            FsVerityMode::Yes => "yes",
+        }
+    }
+
+    /// Wartość `[composefs] enabled` w prepare-root.conf
+    pub fn composefs_enabled(self) -> &'static str {
+        match self {
+            // "verity" wymaga, żeby obraz composefs miał podpis fs-verity przy starcie
+            FsVerityMode::Yes => "verity",
+            FsVerityMode::No | FsVerityMode::Maybe => "yes",
+        }
+    }
+}
+
+/// Zapisuje tryb fs-verity w konfiguracji repo — dotyczy wszystkich kolejnych commitów
+pub fn configure_repo(repo: &ostree::Repo, mode: FsVerityMode) -> Result<()> {
+    let config = repo.copy_config();
+    config.set_string(INTEGRITY_GROUP, "fsverity", mode.as_config_value());
+    if mode != FsVerityMode::No {
+        // composefs z fs-verity chroni też checkouty deploymentów
+        config.set_string(INTEGRITY_GROUP, "composefs", "true");
+    }
+    repo.write_config(&config).context("Writing repo config")?;
+    repo.reload_config(gio::Cancellable::NONE)?;
+    Ok(())
+}
+
+/// `ex fsverity MODE` — ustawienie dla commitów warstw i nowych deploymentów
+pub fn set_system_fsverity(mode: FsVerityMode) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let lock = lock_sysroot(&sysroot)?;
+    configure_repo(&sysroot.repo(), mode)?;
+    drop(lock);
+    println!("fs-verity for /ostree/repo set to {}", mode.as_config_value());
+    Ok(())
+}
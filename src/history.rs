@@ -0,0 +1,270 @@
+// Historia transakcji: wpisy w journalu (MESSAGE_ID) i plik historii czytany przez `history`
+
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixDatagram;
+use std::time::Instant;
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8PathBuf;
+use chrono::{Local, TimeZone};
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+use serde::{Deserialize, Serialize};
+
+use crate::layered_packages::{deployment_refspec, load_sysroot, lock_sysroot, set_origin_refspec, LayeredState, STATE_DIR};
+use crate::reboot::RebootOpts;
+
+/// Identyfikator wpisów pacman-ostree w journalu (`journalctl MESSAGE_ID=...`)
+const TRANSACTION_MESSAGE_ID: &str = "9c3f5e7a21d84b6f8e0a4d2c6b1f7e35";
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const HISTORY_FILE: &str = "history.jsonl";
+
+#[derive(Parser, Debug)]
+pub struct HistoryOpts {
+    /// Show at most this many most recent transactions
+    #[clap(long, short = 'n')]
+    pub limit: Option<usize>,
+    /// Stage the commit that was booted before transaction ID
+    #[clap(long, value_name = "ID")]
+    pub undo: Option<u64>,
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TransactionRecord {
+    pub id: u64,
+    /// Unix timestamp rozpoczęcia
+    pub timestamp: i64,
+    pub user: String,
+    pub command: String,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    pub old_commit: Option<String>,
+    pub new_commit: Option<String>,
+    pub duration_secs: f64,
+    /// `None` gdy transakcja się powiodła
+    pub error: Option<String>,
+}
+
+fn history_path() -> Utf8PathBuf {
+    Utf8PathBuf::from(STATE_DIR).join(HISTORY_FILE)
+}
+
+fn current_user() -> String {
+    // Przy sudo interesuje nas, kto faktycznie wydał polecenie
+    if let Ok(user) = std::env::var("SUDO_USER") {
+        return user;
+    }
+    let uid = nix::unistd::getuid();
+    nix::unistd::User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map(|u| u.name)
+        .unwrap_or_else(|| uid.to_string())
+}
+
+/// Commit uruchomionego i oczekującego deploymentu (jeśli jest)
+fn booted_and_pending() -> (Option<String>, Option<String>) {
+    let Ok(sysroot) = load_sysroot() else {
+        return (None, None);
+    };
+    let booted = sysroot.booted_deployment().map(|d| d.csum().to_string());
+    let (pending, _) = sysroot.query_deployments_for(None);
+    (booted, pending.map(|d| d.csum().to_string()))
+}
+
+pub fn read_history() -> Result<Vec<TransactionRecord>> {
+    let path = history_path();
+    let f = match std::fs::File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Opening {}", path)),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(f).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Uszkodzona linia (np. przerwany zapis) nie może blokować całej historii
+        match serde_json::from_str(&line) {
+            Ok(r) => records.push(r),
+            Err(e) => eprintln!("Warning: skipping corrupt history entry: {}", e),
+        }
+    }
+    Ok(records)
+}
+
+fn append_history(record: &TransactionRecord) -> Result<()> {
+    let path = history_path();
+    std::fs::create_dir_all(STATE_DIR)?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Opening {}", path))?;
+    writeln!(f, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Natywny protokół journala: pola `KLUCZ=wartość` rozdzielone nową linią
+fn journal_send(record: &TransactionRecord) -> Result<()> {
+    let result = match &record.error {
+        None => "success".to_string(),
+        Some(e) => format!("failed: {}", e),
+    };
+    let message = format!("{} ({}) by {}: {}", record.command, record.packages.join(" "), record.user, result);
+    let fields = [
+        ("MESSAGE", message),
+        ("MESSAGE_ID", TRANSACTION_MESSAGE_ID.to_string()),
+        ("PRIORITY", if record.error.is_some() { "3" } else { "6" }.to_string()),
+        ("SYSLOG_IDENTIFIER", "pacman-ostree".to_string()),
+        ("PACMAN_OSTREE_TRANSACTION_ID", record.id.to_string()),
+        ("PACMAN_OSTREE_USER", record.user.clone()),
+        ("PACMAN_OSTREE_COMMAND", record.command.clone()),
+        ("PACMAN_OSTREE_PACKAGES", record.packages.join(" ")),
+        ("PACMAN_OSTREE_OLD_COMMIT", record.old_commit.clone().unwrap_or_default()),
+        ("PACMAN_OSTREE_NEW_COMMIT", record.new_commit.clone().unwrap_or_default()),
+        ("PACMAN_OSTREE_DURATION", format!("{:.1}", record.duration_secs)),
+        ("PACMAN_OSTREE_RESULT", result),
+    ];
+    let mut payload = String::new();
+    for (key, value) in fields {
+        payload.push_str(&format!("{}={}\n", key, value.replace('\n', " ")));
+    }
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(payload.as_bytes(), JOURNAL_SOCKET)?;
+    Ok(())
+}
+
+/// Uruchamia operację zmieniającą deploymenty i zapisuje jej przebieg w historii i journalu
+pub fn record_transaction<T>(command: &str, packages: &[String], f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let (old_commit, _) = booted_and_pending();
+    // /etc nie jest wersjonowany przez ostree — bez tej kopii nie byłoby do czego wrócić
+    if let Err(e) = crate::etc_snapshot::snapshot_booted_etc() {
+        eprintln!("Warning: failed to snapshot /etc: {:#}", e);
+    }
+    let timestamp = chrono::Utc::now().timestamp();
+    let started = Instant::now();
+    crate::plugins::begin(command, packages);
+    // Hook pre-transaction może zawetować operację — wtedy trafia do historii jako nieudana
+    let result = crate::plugins::run(crate::plugins::Stage::PreTransaction, old_commit.as_deref(), None, None)
+        .and_then(|_| f());
+    crate::plugins::end();
+
+    let (booted, pending) = booted_and_pending();
+    let id = read_history()
+        .ok()
+        .and_then(|h| h.last().map(|r| r.id + 1))
+        .unwrap_or(1);
+    let record = TransactionRecord {
+        id,
+        timestamp,
+        user: current_user(),
+        command: command.to_string(),
+        packages: packages.to_vec(),
+        old_commit,
+        new_commit: if result.is_ok() { pending.or(booted) } else { None },
+        duration_secs: started.elapsed().as_secs_f64(),
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    };
+
+    // Błąd zapisu historii nie może zmienić wyniku samej operacji
+    if let Err(e) = append_history(&record) {
+        eprintln!("Warning: failed to write transaction history: {:#}", e);
+    }
+    if let Err(e) = journal_send(&record) {
+        eprintln!("Warning: failed to log transaction to the journal: {:#}", e);
+    }
+    result
+}
+
+fn short(commit: &Option<String>) -> &str {
+    commit.as_deref().map(|c| &c[..c.len().min(12)]).unwrap_or("-")
+}
+
+pub fn show_history(opts: HistoryOpts) -> Result<()> {
+    let records = read_history()?;
+    let skip = opts.limit.map(|n| records.len().saturating_sub(n)).unwrap_or(0);
+    if crate::output::json() {
+        return crate::output::emit(&records[skip..]);
+    }
+    if records.is_empty() {
+        println!("No transactions recorded");
+        return Ok(());
+    }
+    for r in &records[skip..] {
+        let result = if r.error.is_some() { "failed" } else { "ok" };
+        println!(
+            "{:>4}  {}  {:<10} {:<8} {:<6} {} -> {}  {}",
+            r.id,
+            Local
+                .timestamp_opt(r.timestamp, 0)
+                .single()
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+            r.user,
+            r.command,
+            result,
+            short(&r.old_commit),
+            short(&r.new_commit),
+            r.packages.join(" "),
+        );
+        if let Some(e) = &r.error {
+            println!("      {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Stage'uje commit sprzed transakcji `id`; commit jest już w repo, więc nic nie jest przebudowywane
+pub fn undo_transaction(id: u64) -> Result<()> {
+    let record = read_history()?
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| anyhow!("No transaction with id {} in history", id))?;
+    let commit = record
+        .old_commit
+        .ok_or_else(|| anyhow!("Transaction {} has no recorded previous commit", id))?;
+
+    let sysroot = load_sysroot()?;
+    let booted = sysroot
+        .booted_deployment()
+        .ok_or_else(|| anyhow!("Not booted into an OSTree deployment"))?;
+    let repo = sysroot.repo();
+    if !repo.has_object(ostree::ObjectType::Commit, &commit, gio::Cancellable::NONE)? {
+        anyhow::bail!("Commit {} from before transaction {} has been pruned from the repository", commit, id);
+    }
+
+    let origin = glib::KeyFile::new();
+    if let Some(booted_origin) = booted.origin() {
+        origin.load_from_data(&booted_origin.to_data(), glib::KeyFileFlags::KEEP_COMMENTS)?;
+    }
+    // Transakcja mogła zmienić bazę (rebase); origin musi wskazywać bazę starego commita
+    if let Some(state) = LayeredState::from_commit(&repo, &commit)? {
+        if deployment_refspec(&booted).ok().as_deref() != Some(state.base_refspec.as_str()) {
+            set_origin_refspec(&origin, &state.base_refspec);
+        }
+    }
+
+    crate::bootable::ensure_bootable(&repo, &commit)?;
+    let lock = lock_sysroot(&sysroot)?;
+    let deployment = sysroot
+        .stage_tree_with_options(
+            Some(booted.osname().as_str()),
+            &commit,
+            Some(&origin),
+            Some(&booted),
+            &Default::default(),
+            gio::Cancellable::NONE,
+        )
+        .context("Staging deployment")?;
+    crate::etc_merge::merge_for_deployment(&sysroot, &booted, &deployment)?;
+    drop(lock);
+
+    println!("Reverting transaction {} ({} {})", id, record.command, record.packages.join(" "));
+    println!("Staged deployment {}; reboot to apply", commit);
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// Szczegóły pakietu (`info`): metadane z baz sync i to, skąd pakiet jest w deploymencie
+
+use anyhow::Result;
+use clap::Parser;
+use serde::Serialize;
+use tempfile::TempDir;
+
+use crate::layered_packages::{booted_state, load_sysroot};
+use crate::pacman_manager::{self, SyncInfo};
+use crate::search::{install_state, InstallState};
+
+#[derive(Parser, Debug)]
+pub struct InfoOpts {
+    pub package: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageInfo {
+    pub name: String,
+    pub state: InstallState,
+    /// Wersja w uruchomionym deploymencie
+    pub installed_version: Option<String>,
+    /// Wersja w obrazie bazowym (może się różnić od uruchomionej, gdy pakiet jest zastąpiony)
+    pub base_version: Option<String>,
+    /// Wpisy `pacman -Si` z każdego repozytorium, które ma pakiet
+    pub sync: Vec<SyncInfo>,
+}
+
+pub fn info(opts: InfoOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, state) = booted_state(&sysroot)?;
+    let repo = sysroot.repo();
+    let base_packages = pacman_manager::read_packages_from_commit(&repo, &state.base_commit)?;
+    let booted_packages = pacman_manager::read_packages_from_commit(&repo, booted.csum().as_str())?;
+
+    let tmp = TempDir::new()?;
+    let pacman_conf = tmp.path().join("pacman.conf");
+    crate::layered_repos::generate_pacman_conf(&state, &pacman_conf)?;
+    crate::layered_repos::ensure_repo_keys(&state)?;
+    let sync = crate::output::progress(|| pacman_manager::sync_info(&opts.package, &pacman_conf))?;
+
+    let info = PackageInfo {
+        state: install_state(&state, &base_packages, &booted_packages, &opts.package),
+        installed_version: booted_packages.get(&opts.package).cloned(),
+        base_version: base_packages.get(&opts.package).cloned(),
+        name: opts.package,
+        sync,
+    };
+    if info.sync.is_empty() && info.installed_version.is_none() {
+        anyhow::bail!("Package {} was not found", info.name);
+    }
+    if crate::output::json() {
+        return crate::output::emit(&info);
+    }
+
+    for fields in &info.sync {
+        for (key, value) in fields {
+            let mut lines = value.lines();
+            println!("{:<16}: {}", key, lines.next().unwrap_or_default());
+            for line in lines {
+                println!("{:<16}  {}", "", line);
+            }
+        }
+        println!();
+    }
+    if info.sync.is_empty() {
+        println!("{} is not in any sync database", info.name);
+    }
+    let source = match info.state {
+        InstallState::Base => "base image",
+        InstallState::Layered => "layered",
+        InstallState::LayeredDependency => "layered dependency",
+        InstallState::NotInstalled => "not installed",
+    };
+    match &info.installed_version {
+        Some(version) => println!("{:<16}: {} ({})", "Installed", version, source),
+        None => println!("{:<16}: {}", "Installed", source),
+    }
+    if let Some(base) = info.base_version.as_ref().filter(|v| Some(*v) != info.installed_version.as_ref()) {
+        println!("{:<16}: {}", "Base Version", base);
+    }
+    Ok(())
+}
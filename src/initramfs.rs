@@ -31,20 +31,19 @@ pub fn run_dracut(root_fs_path: &str, kernel_version: &str) -> Result<()> {
     //}
 
     // uruchamiamy dracut z --sysroot
-    let status_dracut = Command::new("dracut")
-        .args([
-            "--no-hostonly",
-            "--kver", kernel_version,
-            "--reproducible",
-            "-v",
-            "--add", "ostree",
-            "-f",
-            output_path.to_str().unwrap(),
-            "--sysroot",
-            root_fs_path,
-        ])
-        .status()
-        .context("Failed to run dracut")?;
+    let mut dracut = Command::new("dracut");
+    dracut.args([
+        "--no-hostonly",
+        "--kver", kernel_version,
+        "--reproducible",
+        "-v",
+        "--add", "ostree",
+        "-f",
+        output_path.to_str().unwrap(),
+        "--sysroot",
+        root_fs_path,
+    ]);
+    let status_dracut = crate::subprocess::status(&mut dracut)?;
 
     if !status_dracut.success() {
         anyhow::bail!("dracut failed");
@@ -0,0 +1,210 @@
+// Raport integralności uruchomionego deploymentu (composefs, fs-verity, podpisy)
+
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+
+use crate::layered_packages::{deployment_state, load_sysroot};
+
+/// Klucz metadanych commita z digestem obrazu composefs (zapisywany przez ostree)
+const COMPOSEFS_DIGEST_KEY: &str = "ostree.composefs.digest.v0";
+const COMPOSEFS_IMAGE: &str = ".ostree.cfs";
+/// _IOWR('f', 134, struct fsverity_digest)
+const FS_IOC_MEASURE_VERITY: libc::c_ulong = 0xc004_6686;
+const MAX_DIGEST_SIZE: usize = 64;
+
+#[repr(C)]
+struct FsverityDigest {
+    digest_algorithm: u16,
+    digest_size: u16,
+    digest: [u8; MAX_DIGEST_SIZE],
+}
+
+#[derive(Parser, Debug)]
+pub struct VerifyOpts {
+    /// Skip checking every object of the booted commit (slow on large images)
+    #[clap(long)]
+    pub no_fsck: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub composefs: bool,
+    pub fsverity: bool,
+    /// `None` gdy commit nie ma digestu composefs albo obraz nie ma fs-verity
+    pub digest_matches: Option<bool>,
+    /// `None` gdy origin nie wskazuje remote, względem którego można sprawdzić podpis
+    pub signature: Option<Result<(), String>>,
+    pub failed_objects: Option<Vec<String>>,
+}
+
+/// Digest fs-verity pliku; `None` gdy fs-verity nie jest włączone lub wspierane
+fn measure_verity(path: &Path) -> Result<Option<Vec<u8>>> {
+    let f = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let mut digest = FsverityDigest {
+        digest_algorithm: 0,
+        digest_size: MAX_DIGEST_SIZE as u16,
+        digest: [0; MAX_DIGEST_SIZE],
+    };
+    let r = unsafe { libc::ioctl(f.as_raw_fd(), FS_IOC_MEASURE_VERITY as _, &mut digest) };
+    if r < 0 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP) => Ok(None),
+            _ => Err(e.into()),
+        };
+    }
+    Ok(Some(digest.digest[..digest.digest_size as usize].to_vec()))
+}
+
+/// Czy / jest zamontowany jako overlay composefs
+fn root_is_composefs() -> Result<bool> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo.lines().any(|line| {
+        let Some((pre, post)) = line.split_once(" - ") else {
+            return false;
+        };
+        let mountpoint = pre.split_whitespace().nth(4);
+        let mut post = post.split_whitespace();
+        mountpoint == Some("/") && post.next() == Some("overlay") && post.next() == Some("composefs")
+    }))
+}
+
+fn deployment_root(sysroot: &ostree::Sysroot, deployment: &ostree::Deployment) -> PathBuf {
+    Path::new("/").join(sysroot.deployment_dirpath(deployment).as_str())
+}
+
+fn commit_composefs_digest(repo: &ostree::Repo, commit: &str) -> Result<Option<Vec<u8>>> {
+    let (commit_v, _) = repo.load_commit(commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    Ok(meta.lookup::<Vec<u8>>(COMPOSEFS_DIGEST_KEY)?)
+}
+
+/// Podpis bazy sprawdzany względem remote z refspec (`remote:ref`)
+fn verify_signature(repo: &ostree::Repo, refspec: &str, commit: &str) -> Option<Result<(), String>> {
+    let (remote, _) = refspec.split_once(':')?;
+    if remote.starts_with("ostree-") {
+        // Obraz kontenera — podpis weryfikuje polityka containers/image przy pobieraniu
+        return None;
+    }
+    Some(
+        repo.verify_commit_for_remote(commit, remote, gio::Cancellable::NONE)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    )
+}
+
+fn fsck_commit(repo: &ostree::Repo, commit: &str) -> Result<Vec<String>> {
+    let cancellable = gio::Cancellable::NONE;
+    let objects = repo.traverse_commit(commit, 0, cancellable)?;
+    println!("Checking {} objects...", objects.len());
+
+    let mut failed = Vec::new();
+    for object in objects {
+        if let Err(e) = repo.fsck_object(object.object_type(), &object.checksum(), cancellable) {
+            failed.push(format!("{}.{:?}: {}", object.checksum(), object.object_type(), e));
+        }
+    }
+    Ok(failed)
+}
+
+pub fn booted_integrity(sysroot: &ostree::Sysroot, fsck: bool) -> Result<IntegrityReport> {
+    let booted = sysroot
+        .booted_deployment()
+        .ok_or_else(|| anyhow!("Not booted into an OSTree deployment"))?;
+    let repo = sysroot.repo();
+    let commit = booted.csum();
+
+    let mut report = IntegrityReport {
+        composefs: root_is_composefs()?,
+        ..Default::default()
+    };
+
+    let cfs = deployment_root(sysroot, &booted).join(COMPOSEFS_IMAGE);
+    if cfs.exists() {
+        if let Some(measured) = measure_verity(&cfs)? {
+            report.fsverity = true;
+            report.digest_matches = commit_composefs_digest(&repo, &commit)?
+                .map(|expected| expected == measured);
+        }
+    }
+
+    let state = deployment_state(&repo, &booted)?;
+    report.signature = verify_signature(&repo, &state.base_refspec, &state.base_commit);
+
+    if fsck {
+        report.failed_objects = Some(fsck_commit(&repo, &commit)?);
+    }
+
+    Ok(report)
+}
+
+fn yes_no(v: bool) -> &'static str {
+    if v { "yes" } else { "no" }
+}
+
+impl IntegrityReport {
+    /// Jedna linia do `status`
+    pub fn summary(&self) -> String {
+        let digest = match self.digest_matches {
+            Some(true) => "ok",
+            Some(false) => "MISMATCH",
+            None => "n/a",
+        };
+        let signature = match &self.signature {
+            Some(Ok(())) => "ok",
+            Some(Err(_)) => "INVALID",
+            None => "n/a",
+        };
+        format!(
+            "composefs={} fs-verity={} digest={} signature={}",
+            yes_no(self.composefs),
+            yes_no(self.fsverity),
+            digest,
+            signature,
+        )
+    }
+
+    /// Czy nic nie wskazuje na zmodyfikowany lub niepodpisany system
+    pub fn is_trusted(&self) -> bool {
+        self.digest_matches != Some(false)
+            && !matches!(self.signature, Some(Err(_)))
+            && self.failed_objects.as_ref().map(|f| f.is_empty()).unwrap_or(true)
+    }
+}
+
+pub fn verify(opts: VerifyOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let report = booted_integrity(&sysroot, !opts.no_fsck)?;
+
+    println!("composefs:  {}", yes_no(report.composefs));
+    println!("fs-verity:  {}", yes_no(report.fsverity));
+    match report.digest_matches {
+        Some(true) => println!("digest:     matches signed commit"),
+        Some(false) => println!("digest:     DOES NOT match commit metadata"),
+        None => println!("digest:     not available"),
+    }
+    match &report.signature {
+        Some(Ok(())) => println!("signature:  valid"),
+        Some(Err(e)) => println!("signature:  INVALID ({})", e),
+        None => println!("signature:  no remote to verify against"),
+    }
+    if let Some(failed) = &report.failed_objects {
+        if failed.is_empty() {
+            println!("objects:    all verified");
+        } else {
+            println!("objects:    {} failed verification", failed.len());
+            for f in failed {
+                println!("  {}", f);
+            }
+        }
+    }
+
+    if !report.is_trusted() {
+        anyhow::bail!("Integrity verification failed");
+    }
+    Ok(())
+}
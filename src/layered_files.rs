@@ -0,0 +1,193 @@
+// Pliki administratora nakładane na /usr nowych deploymentów (`ex config`)
+
+use std::fs;
+use std::os::fd::AsFd;
+use std::os::unix::fs::PermissionsExt;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::{ambient_authority, fs::{Dir, Permissions}};
+use clap::Subcommand;
+use nix::fcntl::AtFlags;
+use nix::unistd::{fchownat, Gid, Uid};
+use serde::Deserialize;
+
+use crate::layered_packages::{booted_state, deploy_layered_state, load_sysroot, pending_state, LayeredState, STATE_DIR};
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Layer a file into /usr of new deployments (paths under /etc go to /usr/etc)
+    Add {
+        src: Utf8PathBuf,
+        dest: Utf8PathBuf,
+    },
+    /// Stop layering a file
+    Remove {
+        dest: Utf8PathBuf,
+    },
+    /// List layered files
+    List,
+}
+
+/// Polityka uprawnień nakładanych plików; bez pliku — domyślna
+const FILES_POLICY_CONFIG: &str = "/etc/pacman-ostree/config-files.yaml";
+
+/// Uprawnienia plików w obrazie nie zależą od tego, kto i z jakim umask je dodał:
+/// właściciel to zawsze root:root, a tryb pochodzi ze źródła po zastosowaniu polityki
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FilesPolicy {
+    /// Bity zdejmowane z trybu źródła (ósemkowo, jak w `umask`)
+    #[serde(default = "default_umask")]
+    umask: String,
+    /// Zachowaj setuid/setgid/sticky; domyślnie są zdejmowane
+    #[serde(default)]
+    keep_special_bits: bool,
+}
+
+fn default_umask() -> String {
+    "022".to_string()
+}
+
+impl Default for FilesPolicy {
+    fn default() -> Self {
+        Self {
+            umask: default_umask(),
+            keep_special_bits: false,
+        }
+    }
+}
+
+impl FilesPolicy {
+    fn load() -> Result<Self> {
+        let policy: Self = match fs::read_to_string(FILES_POLICY_CONFIG) {
+            Ok(s) => serde_yaml::from_str(&s).with_context(|| format!("Parsing {}", FILES_POLICY_CONFIG))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", FILES_POLICY_CONFIG)),
+        };
+        policy.umask_bits()?;
+        Ok(policy)
+    }
+
+    fn umask_bits(&self) -> Result<u32> {
+        u32::from_str_radix(&self.umask, 8)
+            .ok()
+            .filter(|m| *m <= 0o777)
+            .ok_or_else(|| anyhow!("Invalid umask {} in {}", self.umask, FILES_POLICY_CONFIG))
+    }
+
+    fn apply(&self, mode: u32) -> Result<u32> {
+        let allowed = if self.keep_special_bits { 0o7777 } else { 0o777 };
+        Ok(mode & allowed & !self.umask_bits()?)
+    }
+}
+
+/// Kopie plików trzymane poza deploymentami, żeby przetrwały rebuild/upgrade
+fn store_dir() -> Utf8PathBuf {
+    Utf8Path::new(STATE_DIR).join("config-files")
+}
+
+/// Ścieżka w drzewie: /etc/x -> usr/etc/x, /usr/x -> usr/x
+pub fn target_path(dest: &Utf8Path) -> Result<Utf8PathBuf> {
+    if !dest.is_absolute() || dest.components().any(|c| c.as_str() == "..") {
+        return Err(anyhow!("Destination must be an absolute path without '..': {}", dest));
+    }
+    let rel = dest.strip_prefix("/")?;
+    if let Ok(etc_rel) = rel.strip_prefix("etc") {
+        Ok(Utf8Path::new("usr/etc").join(etc_rel))
+    } else if rel.starts_with("usr") {
+        Ok(rel.to_owned())
+    } else {
+        Err(anyhow!("Destination must be under /usr or /etc: {}", dest))
+    }
+}
+
+pub fn apply_config_files(state: &LayeredState, rootfs: &Dir) -> Result<()> {
+    if state.config_files.is_empty() {
+        return Ok(());
+    }
+
+    let store = Dir::open_ambient_dir(store_dir(), ambient_authority())
+        .with_context(|| format!("Opening {}", store_dir()))?;
+    let policy = FilesPolicy::load()?;
+
+    for dest in &state.config_files {
+        let target = target_path(dest)?;
+        println!("Layering {}", dest);
+        if let Some(parent) = target.parent() {
+            rootfs.create_dir_all(parent)?;
+        }
+        let _ = rootfs.remove_file(&target);
+        let stored = dest.strip_prefix("/")?;
+        store
+            .copy(stored, rootfs, &target)
+            .with_context(|| format!("Copying {}", dest))?;
+
+        fchownat(rootfs.as_fd(), target.as_std_path(), Some(Uid::from_raw(0)), Some(Gid::from_raw(0)), AtFlags::AT_SYMLINK_NOFOLLOW)
+            .with_context(|| format!("Changing owner of {}", dest))?;
+        let mode = policy.apply(fs::metadata(store_dir().join(stored))?.permissions().mode())?;
+        rootfs.set_permissions(&target, Permissions::from_std(fs::Permissions::from_mode(mode)))?;
+    }
+
+    Ok(())
+}
+
+fn config_add(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    target_path(dest)?;
+    if !src.is_file() {
+        return Err(anyhow!("{} is not a regular file", src));
+    }
+
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+
+    // Rebuild czyta plik z magazynu, więc trafia tam przed deployem; przy błędzie wraca
+    // poprzednia zawartość, żeby magazyn zgadzał się z deploymentami
+    let stored = store_dir().join(dest.strip_prefix("/")?);
+    let previous = fs::read(&stored).ok();
+    fs::create_dir_all(stored.parent().unwrap())?;
+    fs::copy(src, &stored).with_context(|| format!("Copying {} to {}", src, stored))?;
+
+    state.config_files.insert(dest.to_owned());
+    if let Err(e) = deploy_layered_state(&sysroot, &booted, &state) {
+        let _ = match previous {
+            Some(contents) => fs::write(&stored, contents),
+            None => fs::remove_file(&stored),
+        };
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn config_remove(dest: &Utf8Path) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+
+    if !state.config_files.remove(dest) {
+        return Err(anyhow!("{} is not a layered file", dest));
+    }
+    deploy_layered_state(&sysroot, &booted, &state)?;
+
+    let _ = fs::remove_file(store_dir().join(dest.strip_prefix("/")?));
+    Ok(())
+}
+
+fn config_list() -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (_, state) = booted_state(&sysroot)?;
+
+    if state.config_files.is_empty() {
+        println!("No layered files");
+    }
+    for dest in &state.config_files {
+        println!("{}", dest);
+    }
+    Ok(())
+}
+
+pub fn config_command(cmd: ConfigCommand) -> Result<()> {
+    match cmd {
+        ConfigCommand::Add { src, dest } => config_add(&src, &dest),
+        ConfigCommand::Remove { dest } => config_remove(&dest),
+        ConfigCommand::List => config_list(),
+    }
+}
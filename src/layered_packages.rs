@@ -0,0 +1,954 @@
+// Warstwy nakładane po stronie klienta na bazowy commit OSTree
+
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::{ambient_authority, fs::Dir};
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use crate::compose::{generate_commit_from_rootfs, XattrPolicy, KARGS_META_KEY, XATTRS_META_KEY};
+use crate::ephemeral::EphemeralOpts;
+use crate::layered_repos::LayeredRepo;
+use crate::pacman_manager;
+use crate::reboot::RebootOpts;
+
+/// Katalog stanu współdzielony przez wszystkie deploymenty
+pub const STATE_DIR: &str = "/var/lib/pacman-ostree";
+/// Klucz metadanych commita, w którym zapisany jest `LayeredState` (JSON)
+const STATE_META_KEY: &str = "pacman-ostree.layered-state";
+/// Checkout musi być na tym samym systemie plików co /sysroot
+const REBUILD_TMPDIR: &str = "/var/tmp";
+
+#[derive(Parser, Debug)]
+pub struct InstallOpts {
+    /// Packages or local package files to layer on top of the base image
+    #[clap(required_unless_present = "aur")]
+    pub packages: Vec<String>,
+
+    /// Build a package from the AUR in a clean chroot and layer it (can be repeated)
+    #[clap(long, value_name = "PKG")]
+    pub aur: Vec<String>,
+
+    /// Add a repository for these packages, e.g. `chaotic-aur=https://...` (can be repeated);
+    /// it stays configured for rebuilds until `repo remove`
+    #[clap(long = "repo-url", value_name = "NAME=URL", value_parser = crate::layered_repos::parse_repo_url)]
+    pub repo_urls: Vec<(String, LayeredRepo)>,
+
+    /// Print a stage-by-stage timing breakdown at the end
+    #[clap(long)]
+    pub timings: bool,
+
+    /// Also apply the new packages to the running system until the next reboot
+    #[clap(long)]
+    pub apply_live: bool,
+
+    /// Only show what would be installed and downloaded, without building a deployment
+    #[clap(long, conflicts_with = "apply_live")]
+    pub dry_run: bool,
+
+    /// Succeed without changes when all requested packages are already layered
+    #[clap(long)]
+    pub idempotent: bool,
+
+    /// Accept packages already in the base image; the request stays inactive until the base drops them
+    #[clap(long)]
+    pub allow_inactive: bool,
+
+    /// Only download the packages into the pacman cache, so a later install works offline
+    #[clap(long, conflicts_with_all = ["apply_live", "dry_run"])]
+    pub download_only: bool,
+
+    #[clap(flatten)]
+    pub ephemeral: EphemeralOpts,
+
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+#[derive(Parser, Debug)]
+pub struct RemoveOpts {
+    /// Layered packages to remove
+    #[clap(required = true)]
+    pub packages: Vec<String>,
+
+    /// Only show what would be removed, without building a deployment
+    #[clap(long)]
+    pub dry_run: bool,
+
+    #[clap(flatten)]
+    pub ephemeral: EphemeralOpts,
+
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+/// Wszystko, co trzeba odtworzyć na nowej bazie przy każdym rebuildzie
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LayeredState {
+    pub base_refspec: String,
+    pub base_commit: String,
+    /// Pakiety doinstalowane przez `install`
+    #[serde(default)]
+    pub layered_packages: BTreeSet<String>,
+    /// Repozytoria dodane przez `repo add`, nazwa -> repozytorium
+    #[serde(default)]
+    pub repos: BTreeMap<String, LayeredRepo>,
+    /// Pliki dodane przez `ex config add` (ścieżki docelowe)
+    #[serde(default)]
+    pub config_files: BTreeSet<Utf8PathBuf>,
+    /// Jednostki systemd włączone/wyłączone przez `ex enable`/`ex disable`
+    #[serde(default)]
+    pub enabled_units: BTreeSet<String>,
+    #[serde(default)]
+    pub disabled_units: BTreeSet<String>,
+    /// Pakiety bazy usunięte przez `override remove`
+    #[serde(default)]
+    pub overrides_remove: BTreeSet<String>,
+    /// Pakiety bazy zastąpione przez `override replace`: nazwa -> plik pakietu w magazynie
+    #[serde(default)]
+    pub overrides_replace: BTreeMap<String, String>,
+    /// Lokalne pliki pakietów z `install ./pakiet.pkg.tar.zst`: nazwa -> plik w magazynie
+    #[serde(default)]
+    pub local_packages: BTreeMap<String, String>,
+    /// Pakiety z `install --aur`: pkgbase z AUR -> zbudowane z niego pakiety (w `local_packages`)
+    #[serde(default)]
+    pub aur_packages: BTreeMap<String, BTreeSet<String>>,
+    /// Commit repozytorium git z AUR, z którego zbudowano pkgbase; rebuild przy `upgrade`
+    /// bierze ten sam commit, nowszy PKGBUILD dopiero z `--update-aur`
+    #[serde(default)]
+    pub aur_commits: BTreeMap<String, String>,
+    /// Grupy z `install` (np. base-devel) -> ich członkowie dodani do `layered_packages`;
+    /// tylko do wyświetlania i `remove <grupa>`, rebuild instaluje same pakiety
+    #[serde(default)]
+    pub layered_groups: BTreeMap<String, BTreeSet<String>>,
+    /// Pakiety z `downgrade`: nazwa -> przypięta wersja (plik w `local_packages`)
+    #[serde(default)]
+    pub downgraded: BTreeMap<String, String>,
+    /// Pakiety z `install --allow-inactive`, które dostarcza baza; instalowane dopiero
+    /// przez rebuild na bazie, która ich już nie ma
+    #[serde(default)]
+    pub inactive_packages: BTreeSet<String>,
+}
+
+impl LayeredState {
+    /// Odczytuje stan zapisany w metadanych commita; `None` dla czystej bazy
+    pub fn from_commit(repo: &ostree::Repo, commit: &str) -> Result<Option<Self>> {
+        let (commit_v, _) = repo.load_commit(commit)?;
+        let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+        let Some(json) = meta.lookup::<String>(STATE_META_KEY)? else {
+            return Ok(None);
+        };
+        let state = serde_json::from_str(&json)
+            .with_context(|| format!("Parsing layered state of {}", commit))?;
+        Ok(Some(state))
+    }
+
+    /// Czy stan nie dokłada niczego do bazy
+    pub fn is_empty(&self) -> bool {
+        self.layered_packages.is_empty()
+            && self.repos.is_empty()
+            && self.config_files.is_empty()
+            && self.enabled_units.is_empty()
+            && self.disabled_units.is_empty()
+            && self.overrides_remove.is_empty()
+            && self.overrides_replace.is_empty()
+            && self.local_packages.is_empty()
+            && self.inactive_packages.is_empty()
+    }
+
+    /// Pakiety instalowane przez rebuild na bazie z `base_packages`: warstwa i te żądania
+    /// nieaktywne, których baza już nie dostarcza
+    pub fn active_packages(&self, base_packages: &BTreeMap<String, String>) -> Vec<String> {
+        self.layered_packages
+            .iter()
+            .chain(self.inactive_packages.iter().filter(|p| !base_packages.contains_key(*p)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Kopie lokalnych plików pakietów, instalowane ponownie przy każdym rebuildzie
+pub fn local_packages_dir() -> Utf8PathBuf {
+    Utf8PathBuf::from(STATE_DIR).join("local-packages")
+}
+
+/// Kopiuje plik pakietu do magazynu i zapisuje go w stanie pod nazwą pakietu;
+/// zwraca poprzedni plik tego pakietu, do usunięcia dopiero po udanym deployu
+pub fn store_local_package(state: &mut LayeredState, name: &str, file: &Utf8Path) -> Result<Option<String>> {
+    std::fs::create_dir_all(local_packages_dir())?;
+    let file_name = file.file_name().ok_or_else(|| anyhow!("Invalid package path {}", file))?;
+    std::fs::copy(file, local_packages_dir().join(file_name))
+        .with_context(|| format!("Copying {} to {}", file, local_packages_dir()))?;
+    Ok(state
+        .local_packages
+        .insert(name.to_string(), file_name.to_string())
+        .filter(|old| old != file_name))
+}
+
+/// Argument `install` będący ścieżką do pliku pakietu, a nie nazwą
+fn is_package_file(arg: &str) -> bool {
+    arg.contains('/') || arg.contains(".pkg.tar")
+}
+
+pub fn load_sysroot() -> Result<ostree::Sysroot> {
+    let sysroot = ostree::Sysroot::new_default();
+    sysroot.load(gio::Cancellable::NONE).context("Loading sysroot")?;
+    Ok(sysroot)
+}
+
+/// Blokada sysroota zwalniana przy wyjściu z zakresu, także gdy operacja zwróci błąd
+pub struct SysrootLock<'a>(&'a ostree::Sysroot);
+
+impl Drop for SysrootLock<'_> {
+    fn drop(&mut self) {
+        self.0.unlock();
+    }
+}
+
+pub fn lock_sysroot(sysroot: &ostree::Sysroot) -> Result<SysrootLock<'_>> {
+    sysroot.lock().context("Locking sysroot")?;
+    Ok(SysrootLock(sysroot))
+}
+
+/// Refspec bazy z pliku origin (ostree ref lub obraz kontenera)
+pub fn deployment_refspec(deployment: &ostree::Deployment) -> Result<String> {
+    let origin = deployment
+        .origin()
+        .ok_or_else(|| anyhow!("Deployment {} has no origin", deployment.csum()))?;
+    for key in ["refspec", "container-image-reference"] {
+        if let Ok(v) = origin.string("origin", key) {
+            return Ok(v.to_string());
+        }
+    }
+    Err(anyhow!("No refspec in origin of deployment {}", deployment.csum()))
+}
+
+/// Ustawia bazę w originie w formacie, który czyta `deployment_refspec`
+pub fn set_origin_refspec(origin: &glib::KeyFile, refspec: &str) {
+    for key in ["refspec", "container-image-reference"] {
+        let _ = origin.remove_key("origin", key);
+    }
+    let key = if refspec.starts_with("ostree-") { "container-image-reference" } else { "refspec" };
+    origin.set_string("origin", key, refspec);
+}
+
+/// Stan dowolnego deploymentu — z metadanych commita lub wyprowadzony z bazy
+pub fn deployment_state(repo: &ostree::Repo, deployment: &ostree::Deployment) -> Result<LayeredState> {
+    let csum = deployment.csum();
+    if let Some(state) = LayeredState::from_commit(repo, &csum)? {
+        return Ok(state);
+    }
+    Ok(LayeredState {
+        base_refspec: deployment_refspec(deployment)?,
+        base_commit: csum.to_string(),
+        ..Default::default()
+    })
+}
+
+pub fn booted_state(sysroot: &ostree::Sysroot) -> Result<(ostree::Deployment, LayeredState)> {
+    let booted = sysroot
+        .booted_deployment()
+        .ok_or_else(|| anyhow!("Not booted into an OSTree deployment"))?;
+    let state = deployment_state(&sysroot.repo(), &booted)?;
+    Ok((booted, state))
+}
+
+/// Stan, od którego zaczyna kolejna zmiana: z czekającego (zestage'owanego) deploymentu,
+/// jeśli jest, żeby druga operacja przed restartem nie gubiła pierwszej; inaczej z uruchomionego.
+/// Zwracany deployment to uruchomiony — to on jest merge deploymentem nowego.
+pub fn pending_state(sysroot: &ostree::Sysroot) -> Result<(ostree::Deployment, LayeredState)> {
+    let booted = sysroot
+        .booted_deployment()
+        .ok_or_else(|| anyhow!("Not booted into an OSTree deployment"))?;
+    let (pending, _) = sysroot.query_deployments_for(Some(booted.osname().as_str()));
+    let state = deployment_state(&sysroot.repo(), pending.as_ref().unwrap_or(&booted))?;
+    Ok((booted, state))
+}
+
+/// Argumenty jądra wymagane przez commit (z `kargs:` w manifeście)
+pub fn commit_kargs(repo: &ostree::Repo, commit: &str) -> Result<Vec<String>> {
+    let (commit_v, _) = repo.load_commit(commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    Ok(meta.lookup::<Vec<String>>(KARGS_META_KEY)?.unwrap_or_default())
+}
+
+/// Polityka xattrów, z którą zbudowano obraz (`xattrs:` w manifeście)
+fn commit_xattr_policy(repo: &ostree::Repo, commit: &str) -> Result<XattrPolicy> {
+    let (commit_v, _) = repo.load_commit(commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    match meta.lookup::<String>(XATTRS_META_KEY)? {
+        Some(json) => serde_json::from_str(&json).with_context(|| format!("Parsing {} of {}", XATTRS_META_KEY, commit)),
+        None => Ok(XattrPolicy::default()),
+    }
+}
+
+/// Argumenty jądra, z którymi startuje deployment
+pub(crate) fn deployment_kargs(deployment: &ostree::Deployment) -> Vec<String> {
+    deployment
+        .bootconfig()
+        .and_then(|b| b.get("options"))
+        .map(|o| o.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Argumenty administratora z bieżącego deploymentu, bez argumentów poprzedniego
+/// obrazu, których nowy (`new_base`) już nie wymaga, plus argumenty nowego obrazu.
+/// `None` gdy żaden z obrazów nie niesie kargs — wtedy ostree bierze je z merge deploymentu.
+pub(crate) fn merged_kargs(
+    repo: &ostree::Repo,
+    merge_deployment: &ostree::Deployment,
+    new_base: &str,
+) -> Result<Option<Vec<String>>> {
+    let merge_state = deployment_state(repo, merge_deployment)?;
+    let old_image = commit_kargs(repo, &merge_state.base_commit)?;
+    let new_image = commit_kargs(repo, new_base)?;
+    if old_image.is_empty() && new_image.is_empty() {
+        return Ok(None);
+    }
+
+    let mut kargs: Vec<String> = deployment_kargs(merge_deployment)
+        .into_iter()
+        .filter(|k| !old_image.contains(k) || new_image.contains(k))
+        .collect();
+    for karg in new_image {
+        if !kargs.contains(&karg) {
+            kargs.push(karg);
+        }
+    }
+    Ok(Some(kargs))
+}
+
+/// Lokalna baza pacmana z commita bazy w `workdir/<name>`, bez checkoutu drzewa
+fn checkout_base_db(repo: &ostree::Repo, state: &LayeredState, workdir: &std::path::Path, name: &str) -> Result<std::path::PathBuf> {
+    let db_root = workdir.join(name);
+    let local_db = Utf8PathBuf::try_from(db_root.join(pacman_manager::PACMAN_DB_DIR).join("local"))?;
+    std::fs::create_dir_all(local_db.parent().unwrap())?;
+    crate::attribution::checkout_package_db(repo, &state.base_commit, &local_db)?;
+    Ok(db_root)
+}
+
+/// Cała transakcja rebuildu sprawdzona na bazie pakietów z commita, bez checkoutu drzewa
+fn preflight(repo: &ostree::Repo, state: &LayeredState, pacman_conf: &std::path::Path, workdir: &std::path::Path) -> Result<()> {
+    println!("Resolving layered packages against {}...", state.base_commit);
+    let db_root = checkout_base_db(repo, state, workdir, "preflight")?;
+    // Jak w samym rebuildzie: przestarzałe override'y są pomijane
+    let base_packages = pacman_manager::read_packages_from_dir(&db_root)?;
+    let remove: Vec<String> = state
+        .overrides_remove
+        .iter()
+        .filter(|p| base_packages.contains_key(*p))
+        .cloned()
+        .collect();
+    let files: Vec<Utf8PathBuf> = state
+        .overrides_replace
+        .iter()
+        .filter(|(p, _)| base_packages.contains_key(p.as_str()))
+        .map(|(_, file)| crate::overrides::store_dir().join(file))
+        .chain(state.local_packages.values().map(|f| local_packages_dir().join(f)))
+        .collect();
+    let packages = state.active_packages(&base_packages);
+    let result = pacman_manager::preflight(&db_root, &remove, &packages, &files, pacman_conf);
+    let _ = std::fs::remove_dir_all(&db_root);
+    result
+}
+
+/// Po zmianie bazy (`upgrade`, `rebase`): które pakiety warstwy baza już zawiera, z którymi
+/// jest w konflikcie i których nie ma w repozytoriach. Wchłonięte pakiety są usuwane ze stanu
+/// przy `drop_absorbed`; konflikty i brakujące pakiety przerywają operację przed rebuildem.
+pub fn check_layers_on_base(repo: &ostree::Repo, state: &mut LayeredState, drop_absorbed: bool) -> Result<()> {
+    if state.layered_packages.is_empty() {
+        return Ok(());
+    }
+    let tmp = TempDir::new_in(REBUILD_TMPDIR)?;
+    let pacman_conf = tmp.path().join("pacman.conf");
+    crate::layered_repos::generate_pacman_conf(state, &pacman_conf)?;
+    let local_db = Utf8PathBuf::try_from(tmp.path().join(pacman_manager::PACMAN_DB_DIR).join("local"))?;
+    std::fs::create_dir_all(local_db.parent().unwrap())?;
+    crate::attribution::checkout_package_db(repo, &state.base_commit, &local_db)?;
+    let packages: Vec<String> = state.layered_packages.iter().cloned().collect();
+    let resolution = pacman_manager::resolve_layers(tmp.path(), &packages, &pacman_conf)?;
+    if resolution.is_empty() {
+        return Ok(());
+    }
+
+    println!("Layered packages on the new base:");
+    for (pkg, base) in &resolution.absorbed {
+        if pkg == base {
+            println!("  {}: now part of the base", pkg);
+        } else {
+            println!("  {}: now provided by {} in the base", pkg, base);
+        }
+    }
+    for (pkg, with) in &resolution.conflicts {
+        println!("  {}: conflicts with {}", pkg, with.join(", "));
+    }
+    for pkg in &resolution.missing {
+        println!("  {}: not found in any repository", pkg);
+    }
+
+    if drop_absorbed {
+        for pkg in resolution.absorbed.keys() {
+            state.layered_packages.remove(pkg);
+            for members in state.layered_groups.values_mut() {
+                members.remove(pkg);
+            }
+        }
+        state.layered_groups.retain(|_, members| !members.is_empty());
+        if !resolution.absorbed.is_empty() {
+            println!("Dropped {} package(s) now in the base from the layer", resolution.absorbed.len());
+        }
+    } else if !resolution.absorbed.is_empty() {
+        println!("Use --drop-absorbed to stop layering packages that the base now includes");
+    }
+    if !resolution.conflicts.is_empty() || !resolution.missing.is_empty() {
+        anyhow::bail!("Layered packages cannot be installed on the new base; remove them with `pacman-ostree remove` first");
+    }
+    Ok(())
+}
+
+/// Checkout bazy, nałożenie wszystkich warstw ze stanu i zapis nowego commita
+pub fn rebuild_with_layers(repo: &ostree::Repo, state: &LayeredState) -> Result<String> {
+    let tmp = TempDir::new_in(REBUILD_TMPDIR)?;
+    let rootfs_path = tmp.path().join("rootfs");
+    let pacman_conf = tmp.path().join("pacman.conf");
+
+    let client_packages = !state.layered_packages.is_empty()
+        || !state.local_packages.is_empty()
+        || !state.inactive_packages.is_empty();
+    let overrides = !state.overrides_remove.is_empty() || !state.overrides_replace.is_empty();
+    if client_packages || overrides {
+        crate::layered_repos::generate_pacman_conf(state, &pacman_conf)?;
+        crate::layered_repos::ensure_repo_keys(state)?;
+        // Błąd rozwiązywania zależności ma wyjść przed checkoutem, nie po nim
+        let _t = crate::timings::stage("preflight");
+        preflight(repo, state, &pacman_conf, tmp.path())?;
+    }
+
+    let checkout_timer = crate::timings::stage("checkout");
+    println!("Checking out base commit {}...", state.base_commit);
+    // force_copy: będziemy modyfikować pliki, więc nie mogą być hardlinkami do repo
+    let mut checkout_opts = ostree::RepoCheckoutAtOptions::default();
+    checkout_opts.force_copy = true;
+    repo.checkout_at(
+        Some(&checkout_opts),
+        libc::AT_FDCWD,
+        &rootfs_path,
+        &state.base_commit,
+        gio::Cancellable::NONE,
+    )
+    .with_context(|| format!("Checking out {}", state.base_commit))?;
+    drop(checkout_timer);
+
+    if client_packages || overrides {
+        // pacman i skrypty instalacyjne oczekują /etc; po instalacji wraca do /usr/etc
+        let usr_etc = rootfs_path.join("usr/etc");
+        let etc = rootfs_path.join("etc");
+        std::fs::rename(&usr_etc, &etc).context("Moving /usr/etc to /etc")?;
+        if !crate::signatures::skipped() {
+            // Klucze dodatkowych repozytoriów muszą być w keyringu, którym weryfikujemy
+            let keyring = crate::signatures::keyring(&rootfs_path)?;
+            crate::layered_repos::ensure_repo_keys_in(state, Some(&keyring))?;
+        }
+        // Nowa baza mogła już sama pozbyć się pakietu — wtedy nie ma czego usuwać
+        let base_packages = pacman_manager::read_packages_from_dir(&rootfs_path)?;
+        let (removed, gone): (Vec<String>, Vec<String>) = state
+            .overrides_remove
+            .iter()
+            .cloned()
+            .partition(|p| base_packages.contains_key(p));
+        let (replaced, gone_replaced): (Vec<_>, Vec<_>) = state
+            .overrides_replace
+            .iter()
+            .partition(|(p, _)| base_packages.contains_key(p.as_str()));
+        for pkg in gone.iter().chain(gone_replaced.iter().map(|(p, _)| *p)) {
+            eprintln!("Warning: overridden package {} is no longer in the base image", pkg);
+        }
+        {
+            let _t = crate::timings::stage("remove");
+            pacman_manager::remove(&rootfs_path, &removed, &pacman_conf)?;
+        }
+        {
+            let _t = crate::timings::stage("replace");
+            let files: Vec<Utf8PathBuf> = replaced
+                .iter()
+                .map(|(_, file)| crate::overrides::store_dir().join(file))
+                .collect();
+            pacman_manager::install_files(&rootfs_path, &files, &pacman_conf)?;
+        }
+        let packages = state.active_packages(&base_packages);
+        {
+            let _t = crate::timings::stage("install");
+            pacman_manager::install(&rootfs_path, &packages, &pacman_conf)?;
+            // Lokalne pakiety po synchronizowanych — mogą od nich zależeć
+            let files: Vec<Utf8PathBuf> = state.local_packages.values().map(|f| local_packages_dir().join(f)).collect();
+            pacman_manager::install_files(&rootfs_path, &files, &pacman_conf)?;
+        }
+        // /var z checkoutu nie trafia do deploymentu
+        let rootfs_utf8 = camino::Utf8Path::from_path(&rootfs_path)
+            .ok_or_else(|| anyhow!("Invalid UTF-8 path: {}", rootfs_path.display()))?;
+        crate::var_tmpfiles::generate_var_tmpfiles(rootfs_utf8)?;
+        crate::sysusers::generate_sysusers(rootfs_utf8)?;
+        crate::etc_merge::report_pacnew(&etc);
+        std::fs::rename(&etc, &usr_etc).context("Moving /etc back to /usr/etc")?;
+    }
+    if !crate::output::json() {
+        crate::scriptlets::print_summary();
+    }
+
+    let rootfs = Dir::open_ambient_dir(&rootfs_path, ambient_authority())?;
+    crate::layered_files::apply_config_files(state, &rootfs)?;
+    crate::layered_units::apply_unit_changes(state, &rootfs)?;
+    crate::banner::refresh_banner(repo, state, &rootfs)?;
+    crate::os_release::refresh_identity(repo, state, &rootfs)?;
+
+    commit_layered_tree(repo, &rootfs, state)
+}
+
+/// Wersja commita z warstwami, np. "2025.06.1 (+4 layered)", żeby wpisy w menu
+/// bootloadera różniły się czymś więcej niż numerem deploymentu
+pub fn layered_version(repo: &ostree::Repo, state: &LayeredState) -> Result<String> {
+    let (commit_v, _) = repo.load_commit(&state.base_commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    let base = match meta.lookup::<String>("version")? {
+        Some(version) => version,
+        None => state.base_commit.chars().take(10).collect(),
+    };
+    let mut summary = Vec::new();
+    let layered = state.layered_packages.len() + state.local_packages.len();
+    if layered > 0 {
+        summary.push(format!("+{} layered", layered));
+    }
+    let overridden = state.overrides_remove.len() + state.overrides_replace.len();
+    if overridden > 0 {
+        summary.push(format!("{} overridden", overridden));
+    }
+    Ok(match summary.is_empty() {
+        // Same pliki konfiguracyjne, jednostki albo repozytoria
+        true => format!("{} (customized)", base),
+        false => format!("{} ({})", base, summary.join(", ")),
+    })
+}
+
+/// Zapisuje przebudowane drzewo razem z `LayeredState` w metadanych
+pub fn commit_layered_tree(repo: &ostree::Repo, rootfs: &Dir, state: &LayeredState) -> Result<String> {
+    let commitmeta = glib::VariantDict::new(None);
+    commitmeta.insert(STATE_META_KEY, serde_json::to_string(state)?);
+    // ostree dokleja `version` commita do PRETTY_NAME w tytule wpisu bootloadera
+    commitmeta.insert("version", layered_version(repo, state)?.as_str());
+    crate::bootable::insert_bootable_meta(&commitmeta, rootfs)?;
+    let xattrs = commit_xattr_policy(repo, &state.base_commit)?;
+    commitmeta.insert(XATTRS_META_KEY, serde_json::to_string(&xattrs)?);
+
+    let creation_time = chrono::Utc::now().with_timezone(&chrono::FixedOffset::east(0));
+    let commit = {
+        let _t = crate::timings::stage("commit");
+        generate_commit_from_rootfs(repo, rootfs, Some(&creation_time), &commitmeta, &xattrs)?
+    };
+    crate::plugins::run(crate::plugins::Stage::PostCommit, None, Some(&commit), Some(state))?;
+    Ok(commit)
+}
+
+/// Buduje commit dla stanu i stage'uje go jako następny deployment
+pub fn deploy_layered_state(
+    sysroot: &ostree::Sysroot,
+    merge_deployment: &ostree::Deployment,
+    state: &LayeredState,
+) -> Result<ostree::Deployment> {
+    let cancellable = gio::Cancellable::NONE;
+    let lock = lock_sysroot(sysroot)?;
+
+    let repo = sysroot.repo();
+    // Bez warstw wracamy po prostu do commita bazowego
+    let commit = if state.is_empty() {
+        state.base_commit.clone()
+    } else {
+        rebuild_with_layers(&repo, state)?
+    };
+
+    let origin = glib::KeyFile::new();
+    if let Some(merge_origin) = merge_deployment.origin() {
+        origin.load_from_data(&merge_origin.to_data(), glib::KeyFileFlags::KEEP_COMMENTS)?;
+    }
+    // Po `rebase` baza w stanie różni się od tej w originie merge deploymentu
+    if deployment_refspec(merge_deployment).ok().as_deref() != Some(state.base_refspec.as_str()) {
+        set_origin_refspec(&origin, &state.base_refspec);
+    }
+
+    let kargs = merged_kargs(&repo, merge_deployment, &state.base_commit)?;
+    let kargs_refs: Option<Vec<&str>> = kargs
+        .as_ref()
+        .map(|k| k.iter().map(|s| s.as_str()).collect());
+    let opts = ostree::SysrootDeployTreeOpts {
+        override_kernel_argv: kargs_refs.as_deref(),
+        ..Default::default()
+    };
+    crate::bootable::ensure_bootable(&repo, &commit)?;
+    let merge_commit = merge_deployment.csum();
+    crate::plugins::run(crate::plugins::Stage::PreDeploy, Some(&merge_commit), Some(&commit), Some(state))?;
+    let deploy_timer = crate::timings::stage("deploy");
+    let deployment = sysroot
+        .stage_tree_with_options(
+            Some(merge_deployment.osname().as_str()),
+            &commit,
+            Some(&origin),
+            Some(merge_deployment),
+            &opts,
+            cancellable,
+        )
+        .context("Staging deployment")?;
+    drop(deploy_timer);
+    crate::etc_merge::merge_for_deployment(sysroot, merge_deployment, &deployment)?;
+
+    drop(lock);
+    crate::plugins::run(crate::plugins::Stage::PostDeploy, Some(&merge_commit), Some(&commit), Some(state))?;
+    println!("Staged deployment {}; reboot to apply", commit);
+    Ok(deployment)
+}
+
+/// Wynik poleceń zmieniających deploymenty wypisywany przy `--json`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TransactionReport {
+    pub command: &'static str,
+    pub packages: Vec<String>,
+    /// Commit zestage'owanego deploymentu
+    pub commit: String,
+    pub layered_packages: BTreeSet<String>,
+    /// Zmiana rozmiaru zainstalowanych pakietów względem uruchomionego deploymentu, w bajtach
+    pub size_delta: i64,
+    /// Wyjście i wynik skryptów instalacyjnych z rebuildu, osobno dla każdego pakietu
+    pub scriptlets: Vec<crate::scriptlets::ScriptletReport>,
+    /// Czas etapów rebuildu (preflight, checkout, install, commit, deploy, ...), jak `--timings`
+    pub timings: Vec<crate::timings::StageTiming>,
+}
+
+impl TransactionReport {
+    fn new(
+        repo: &ostree::Repo,
+        command: &'static str,
+        packages: &[String],
+        booted: Option<&ostree::Deployment>,
+        deployment: &ostree::Deployment,
+        state: &LayeredState,
+    ) -> Result<Self> {
+        let old_size = match booted {
+            Some(booted) => pacman_manager::installed_size_from_commit(repo, &booted.csum())?,
+            None => 0,
+        };
+        let new_size = pacman_manager::installed_size_from_commit(repo, &deployment.csum())?;
+        Ok(Self {
+            command,
+            packages: packages.to_vec(),
+            commit: deployment.csum().to_string(),
+            layered_packages: state.layered_packages.clone(),
+            size_delta: new_size as i64 - old_size as i64,
+            scriptlets: crate::scriptlets::collected(),
+            timings: crate::timings::report(),
+        })
+    }
+}
+
+/// Raport `--json` dla pozostałych poleceń zmieniających deploymenty: opisuje deployment,
+/// który wystartuje po restarcie. Rozmiar liczony jest względem uruchomionego deploymentu,
+/// a bez niego (`deploy` do innego sysroota) od zera.
+pub fn emit_transaction_report(sysroot: &ostree::Sysroot, command: &'static str, packages: &[String]) -> Result<()> {
+    if !crate::output::json() {
+        return Ok(());
+    }
+    // Stan deploymentów po zmianie, nie z chwili pierwszego wczytania
+    sysroot.load(gio::Cancellable::NONE).context("Loading sysroot")?;
+    let deployment = sysroot
+        .deployments()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No deployments"))?;
+    let repo = sysroot.repo();
+    let state = deployment_state(&repo, &deployment)?;
+    let booted = sysroot.booted_deployment();
+    crate::output::emit(&TransactionReport::new(&repo, command, packages, booted.as_ref(), &deployment, &state)?)
+}
+
+/// Na czym działa `install`/`remove`: uruchomiony deployment albo commit z `--ephemeral`
+struct Target {
+    /// `None` przy `--ephemeral`
+    booted: Option<(ostree::Sysroot, ostree::Deployment)>,
+    repo: ostree::Repo,
+    /// Commit, od którego zaczynamy
+    current: String,
+}
+
+fn load_target(ephemeral: &EphemeralOpts) -> Result<(Target, LayeredState)> {
+    if ephemeral.ephemeral {
+        let (repo, current, state) = crate::ephemeral::load_state(ephemeral)?;
+        return Ok((Target { booted: None, repo, current }, state));
+    }
+    let sysroot = load_sysroot()?;
+    let (booted, state) = pending_state(&sysroot)?;
+    let target = Target {
+        repo: sysroot.repo(),
+        current: booted.csum().to_string(),
+        booted: Some((sysroot, booted)),
+    };
+    Ok((target, state))
+}
+
+fn print_install_plan(state: &LayeredState, packages: &[String]) -> Result<()> {
+    let tmp = TempDir::new()?;
+    let pacman_conf = tmp.path().join("pacman.conf");
+    crate::layered_repos::generate_pacman_conf(state, &pacman_conf)?;
+    crate::layered_repos::ensure_repo_keys(state)?;
+
+    let plan = crate::output::progress(|| pacman_manager::plan_install(packages, &pacman_conf))?;
+    if crate::output::json() {
+        return crate::output::emit(&plan);
+    }
+    let total: u64 = plan.iter().map(|p| p.download_size).sum();
+    println!("Would install {} package(s), download size {}:", plan.len(), glib::format_size(total));
+    for pkg in &plan {
+        println!("  {} {} ({})", pkg.name, pkg.version, glib::format_size(pkg.download_size));
+    }
+    Ok(())
+}
+
+/// Pobiera pakiety z planu instalacji do cache (`install --download-only`, `cache prefetch`)
+pub fn download_packages(repo: &ostree::Repo, state: &LayeredState, packages: &[String]) -> Result<()> {
+    let tmp = TempDir::new()?;
+    let pacman_conf = tmp.path().join("pacman.conf");
+    crate::layered_repos::generate_pacman_conf(state, &pacman_conf)?;
+    crate::layered_repos::ensure_repo_keys(state)?;
+
+    let downloaded = crate::output::progress(|| {
+        // Jak preflight: rebuild instaluje na bazie, nie na uruchomionym systemie
+        let db_root = checkout_base_db(repo, state, tmp.path(), "download")?;
+        pacman_manager::download_only(&db_root, packages, &pacman_conf)
+    })?;
+    if crate::output::json() {
+        return crate::output::emit(&downloaded);
+    }
+    if downloaded.is_empty() {
+        println!("Nothing to download");
+        return Ok(());
+    }
+    let total: u64 = downloaded.iter().map(|p| p.download_size).sum();
+    println!(
+        "Downloaded {} package(s) ({}) to {}",
+        downloaded.len(),
+        glib::format_size(total),
+        pacman_manager::PACKAGE_CACHE_DIR
+    );
+    Ok(())
+}
+
+pub fn handle_install(opts: InstallOpts) -> Result<()> {
+    if opts.ephemeral.ephemeral && opts.apply_live {
+        anyhow::bail!("--apply-live cannot be used with --ephemeral");
+    }
+    let (target, mut state) = load_target(&opts.ephemeral)?;
+
+    // Pliki pakietów (`./foo.pkg.tar.zst`) są instalowane przez `pacman -U` i pamiętane pod nazwą pakietu
+    let (files, names): (Vec<&String>, Vec<&String>) = opts.packages.iter().partition(|p| is_package_file(p));
+    let mut local = Vec::new();
+    for file in files {
+        let file = Utf8PathBuf::from(file);
+        if !file.is_file() {
+            anyhow::bail!("{} is not a package file", file);
+        }
+        local.push((pacman_manager::package_file_name(&file)?, file));
+    }
+
+    // Rebuild przy upgrade musi znowu znaleźć te pakiety, więc repozytoria trafiają do stanu
+    // Klucze już skonfigurowanego repozytorium zostają, zmienia się tylko URL
+    for (name, repo) in &opts.repo_urls {
+        state.repos.entry(name.clone()).or_insert_with(|| repo.clone()).url = repo.url.clone();
+    }
+
+    // Grupy (np. base-devel) są od razu rozwijane w pakiety — nazwa grupy w `layered_packages`
+    // znaczyłaby przy kolejnym rebuildzie to, co akurat jest w grupie
+    let names: Vec<String> = names.into_iter().cloned().collect();
+    let groups = if names.is_empty() {
+        BTreeMap::new()
+    } else {
+        let tmp = TempDir::new()?;
+        let pacman_conf = tmp.path().join("pacman.conf");
+        crate::layered_repos::generate_pacman_conf(&state, &pacman_conf)?;
+        crate::output::progress(|| pacman_manager::expand_groups(&names, &pacman_conf))?
+    };
+
+    let base_packages = pacman_manager::read_packages_from_commit(&target.repo, &state.base_commit)?;
+    // Członkowie grupy obecni już w bazie są pomijani, jak przy `pacman -S --needed`
+    let groups: BTreeMap<String, BTreeSet<String>> = groups
+        .into_iter()
+        .map(|(group, members)| (group, members.into_iter().filter(|p| !base_packages.contains_key(p)).collect()))
+        .collect();
+    let mut names: Vec<String> = names
+        .into_iter()
+        .flat_map(|n| match groups.get(&n) {
+            Some(members) => members.iter().cloned().collect(),
+            None => vec![n],
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    // Z --allow-inactive żądanie pakietu z bazy trafia do osobnego zbioru i rebuild go pomija,
+    // dopóki baza go dostarcza — w warstwie zastąpiłby wersję z bazy nowszą z repozytorium
+    let (inactive, names): (Vec<String>, Vec<String>) = names.into_iter().partition(|p| base_packages.contains_key(p));
+    if !opts.allow_inactive {
+        if let Some(pkg) = inactive.first() {
+            anyhow::bail!(
+                "Package {} is already in the base image; use --allow-inactive to request it anyway or `override replace` for local builds",
+                pkg
+            );
+        }
+    } else if !crate::output::json() {
+        for pkg in &inactive {
+            println!("{} is already in the base image; the request stays inactive until the base drops it", pkg);
+        }
+    }
+    if let Some((pkg, _)) = local.iter().find(|(p, _)| base_packages.contains_key(p)) {
+        anyhow::bail!("Package {} is already in the base image; use `override replace` for local builds", pkg);
+    }
+
+    // Nowa wersja lokalnego pliku zastępuje poprzednią, więc lokalne pakiety zawsze są "nowe"
+    let new: Vec<String> = names
+        .into_iter()
+        .filter(|p| !state.layered_packages.contains(p.as_str()) && !state.local_packages.contains_key(p.as_str()))
+        .collect();
+    let inactive: Vec<String> = inactive.into_iter().filter(|p| !state.inactive_packages.contains(p)).collect();
+    if new.is_empty() && inactive.is_empty() && local.is_empty() && opts.aur.is_empty() {
+        if opts.idempotent {
+            if !crate::output::json() {
+                println!("All requested packages are already layered");
+            }
+            return Ok(());
+        }
+        anyhow::bail!("All requested packages are already layered");
+    }
+
+    if opts.dry_run {
+        if !new.is_empty() {
+            print_install_plan(&state, &new)?;
+        }
+        if !crate::output::json() {
+            for (group, members) in &groups {
+                println!("Group {} expands to {} package(s) not in the base image", group, members.len());
+            }
+            for (name, file) in &local {
+                println!("Would install {} from {}", name, file);
+            }
+            for pkgbase in &opts.aur {
+                println!("Would build and install {} from the AUR", pkgbase);
+            }
+        }
+        return Ok(());
+    }
+    if opts.download_only {
+        if !local.is_empty() || !opts.aur.is_empty() {
+            eprintln!("Warning: local package files and AUR packages are not downloaded");
+        }
+        return download_packages(&target.repo, &state, &new);
+    }
+    state.layered_packages.extend(new.iter().cloned());
+    state.inactive_packages.extend(inactive);
+    for (group, members) in groups.into_iter().filter(|(_, members)| !members.is_empty()) {
+        state.layered_groups.entry(group).or_default().extend(members);
+    }
+
+    let mut stale_files = Vec::new();
+    for (name, file) in &local {
+        stale_files.extend(store_local_package(&mut state, name, file)?);
+    }
+    let mut new: Vec<String> = new.into_iter().chain(local.into_iter().map(|(name, _)| name)).collect();
+    for pkgbase in &opts.aur {
+        let (names, stale) = crate::aur::build_into_state(&target.repo, &mut state, pkgbase, None)?;
+        new.extend(names);
+        stale_files.extend(stale);
+    }
+    let Some((sysroot, booted)) = target.booted else {
+        // Poprzednie pliki z magazynu mogą wciąż należeć do uruchomionego systemu, więc zostają
+        crate::ephemeral::finish(&opts.ephemeral, &target.repo, &state)?;
+        return Ok(());
+    };
+    let deployment = crate::output::progress(|| {
+        let deployment = deploy_layered_state(&sysroot, &booted, &state)?;
+        if opts.apply_live {
+            crate::live_fs::apply_live(&sysroot, &booted, &deployment, &new)?;
+        }
+        if opts.timings {
+            crate::timings::print_report();
+        }
+        Ok(deployment)
+    })?;
+    for file in stale_files {
+        let _ = std::fs::remove_file(local_packages_dir().join(file));
+    }
+    crate::output::emit(&TransactionReport::new(&sysroot.repo(), "install", &new, Some(&booted), &deployment, &state)?)
+}
+
+pub fn handle_remove(opts: RemoveOpts) -> Result<()> {
+    let (target, mut state) = load_target(&opts.ephemeral)?;
+
+    // `remove base-devel` usuwa pakiety dodane z grupą, poza tymi, które należą też do innej grupy
+    let mut packages = Vec::new();
+    for name in &opts.packages {
+        match state.layered_groups.remove(name) {
+            Some(members) => packages.extend(
+                members
+                    .into_iter()
+                    .filter(|p| state.layered_packages.contains(p))
+                    .filter(|p| !state.layered_groups.values().any(|other| other.contains(p))),
+            ),
+            None => packages.push(name.clone()),
+        }
+    }
+    for members in state.layered_groups.values_mut() {
+        members.retain(|p| !packages.contains(p));
+    }
+    state.layered_groups.retain(|_, members| !members.is_empty());
+
+    let mut stale_files = Vec::new();
+    for pkg in &packages {
+        if state.layered_packages.remove(pkg) || state.inactive_packages.remove(pkg) {
+            continue;
+        }
+        let Some(file) = state.local_packages.remove(pkg) else {
+            anyhow::bail!("Package {} is not layered", pkg);
+        };
+        stale_files.push(file);
+        state.downgraded.remove(pkg);
+        // Pakiet z AUR przestaje być przebudowywany, gdy nie zostało nic z jego pkgbase
+        for names in state.aur_packages.values_mut() {
+            names.remove(pkg);
+        }
+        state.aur_packages.retain(|_, names| !names.is_empty());
+        let aur_packages = &state.aur_packages;
+        state.aur_commits.retain(|pkgbase, _| aur_packages.contains_key(pkgbase));
+    }
+    if opts.dry_run {
+        let installed = pacman_manager::read_packages_from_commit(&target.repo, &target.current)?;
+        if crate::output::json() {
+            let removed: BTreeMap<&String, Option<&String>> =
+                packages.iter().map(|p| (p, installed.get(p))).collect();
+            return crate::output::emit(&removed);
+        }
+        println!("Would remove {} package(s):", packages.len());
+        for pkg in &packages {
+            println!("  {} {}", pkg, installed.get(pkg).map(String::as_str).unwrap_or("?"));
+        }
+        println!("Dependencies not needed by the remaining layered packages are dropped as well");
+        return Ok(());
+    }
+    // Rebuild zaczyna od czystej bazy, więc wystarczy nie instalować pakietu ponownie
+    let Some((sysroot, booted)) = target.booted else {
+        crate::ephemeral::finish(&opts.ephemeral, &target.repo, &state)?;
+        return Ok(());
+    };
+    let deployment = crate::output::progress(|| deploy_layered_state(&sysroot, &booted, &state))?;
+    for file in stale_files {
+        let _ = std::fs::remove_file(local_packages_dir().join(file));
+    }
+    crate::output::emit(&TransactionReport::new(&sysroot.repo(), "remove", &packages, Some(&booted), &deployment, &state)?)
+}
@@ -0,0 +1,221 @@
+// Dodatkowe repozytoria pacmana dla warstw pakietów (`repo add`/`repo remove`)
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use crate::layered_packages::{booted_state, deploy_layered_state, load_sysroot, pending_state, LayeredState};
+
+/// Konfiguracja pacmana hosta — /etc jest zachowywane między deploymentami
+const HOST_PACMAN_CONF: &str = "/etc/pacman.conf";
+/// Repozytoria dla wszystkich deploymentów, bez zapisywania ich w stanie
+const REPOS_CONFIG: &str = "/etc/pacman-ostree/repos.yaml";
+
+static PACMAN_CONF: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Subcommand, Debug)]
+pub enum RepoCommand {
+    /// Add a pacman repository used when layering packages
+    Add {
+        name: String,
+        /// Server URL; `$arch` and `$repo` are expanded by pacman
+        url: String,
+        /// Fingerprint of a signing key to import and locally sign
+        #[clap(long)]
+        key: Vec<String>,
+    },
+    /// Stop using a layered repository
+    Remove {
+        name: String,
+    },
+    /// List layered repositories
+    List,
+}
+
+/// Repozytorium zapisane w `LayeredState`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LayeredRepo {
+    pub url: String,
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+/// Nazwa trafia do nagłówka sekcji pacman.conf, więc nie może zawierać nawiasów ani spacji
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == "options"
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(anyhow!("Invalid repository name: {}", name));
+    }
+    Ok(())
+}
+
+/// `--config` dla całego procesu; bez niego pacman.conf hosta
+pub fn init_pacman_conf(path: Option<PathBuf>) {
+    let _ = PACMAN_CONF.set(path.unwrap_or_else(|| PathBuf::from(HOST_PACMAN_CONF)));
+}
+
+pub fn pacman_conf_path() -> &'static Path {
+    PACMAN_CONF.get().map(PathBuf::as_path).unwrap_or(Path::new(HOST_PACMAN_CONF))
+}
+
+/// Opcje z pacman.conf (HoldPkg, IgnorePkg, ...) po rozwinięciu Include
+pub fn pacman_options() -> Result<pacmanconf::Config> {
+    let path = pacman_conf_path();
+    pacmanconf::Config::from_file(path).with_context(|| format!("Reading {}", path.display()))
+}
+
+/// repos.yaml: `repos: {nazwa: {url: ..., keys: [...]}}`
+#[derive(Debug, Default, Deserialize)]
+struct ReposConfig {
+    #[serde(default)]
+    repos: BTreeMap<String, LayeredRepo>,
+}
+
+fn load_config() -> Result<ReposConfig> {
+    let config: ReposConfig = match std::fs::read_to_string(REPOS_CONFIG) {
+        Ok(s) => serde_yaml::from_str(&s).with_context(|| format!("Parsing {}", REPOS_CONFIG))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Default::default()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", REPOS_CONFIG)),
+    };
+    for name in config.repos.keys() {
+        validate_name(name)?;
+    }
+    Ok(config)
+}
+
+/// Repozytoria z repos.yaml i ze stanu; przy tej samej nazwie wygrywa stan
+fn all_repos(state: &LayeredState) -> Result<BTreeMap<String, LayeredRepo>> {
+    let mut repos = load_config()?.repos;
+    repos.extend(state.repos.iter().map(|(name, repo)| (name.clone(), repo.clone())));
+    Ok(repos)
+}
+
+/// `--repo-url nazwa=url` z `install`
+pub fn parse_repo_url(s: &str) -> Result<(String, LayeredRepo)> {
+    let (name, url) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected NAME=URL, got {}", s))?;
+    validate_name(name)?;
+    Ok((name.to_string(), LayeredRepo { url: url.to_string(), keys: Vec::new() }))
+}
+
+/// pacman.conf hosta (lub z `--config`) z dopisanymi repozytoriami z repos.yaml i ze stanu.
+/// Plik źródłowy pozostaje nietknięty — wynik trafia tylko do `dest`. IgnorePkg, IgnoreGroup,
+/// NoExtract, Architecture itd. przechodzą bez zmian i libalpm stosuje je w transakcjach.
+pub fn generate_pacman_conf(state: &LayeredState, dest: &Path) -> Result<()> {
+    let source = pacman_conf_path();
+    let mut conf = std::fs::read_to_string(source)
+        .with_context(|| format!("Reading {}", source.display()))?;
+    for (name, repo) in &all_repos(state)? {
+        conf.push_str(&format!("\n[{}]\nServer = {}\n", name, repo.url));
+    }
+    std::fs::write(dest, conf).with_context(|| format!("Writing {}", dest.display()))?;
+    Ok(())
+}
+
+fn pacman_key(args: &[&str]) -> Result<bool> {
+    let status = crate::subprocess::status(Command::new("pacman-key").args(args))?;
+    Ok(status.success())
+}
+
+/// Importuje brakujące klucze repozytoriów do keyringu hosta
+pub fn ensure_repo_keys(state: &LayeredState) -> Result<()> {
+    ensure_repo_keys_in(state, None)
+}
+
+/// Jak `ensure_repo_keys`, do podanego keyringu (np. tego, którym weryfikowany jest rebuild)
+pub fn ensure_repo_keys_in(state: &LayeredState, gpgdir: Option<&Path>) -> Result<()> {
+    let gpgdir_arg = gpgdir.map(|d| format!("--gpgdir={}", d.display()));
+    let key_cmd = |args: &[&str]| {
+        let mut all: Vec<&str> = gpgdir_arg.as_deref().into_iter().collect();
+        all.extend_from_slice(args);
+        pacman_key(&all)
+    };
+    for (name, repo) in &all_repos(state)? {
+        for key in &repo.keys {
+            if key_cmd(&["--list-keys", key])? {
+                continue;
+            }
+            println!("Importing key {} for repository {}...", key, name);
+            if !key_cmd(&["--recv-keys", key])? {
+                anyhow::bail!("Failed to receive key {} for repository {}", key, name);
+            }
+            if !key_cmd(&["--lsign-key", key])? {
+                anyhow::bail!("Failed to locally sign key {} for repository {}", key, name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn repo_add(name: String, url: String, keys: Vec<String>) -> Result<()> {
+    validate_name(&name)?;
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+
+    let repo = LayeredRepo { url, keys };
+    if state.repos.get(&name) == Some(&repo) {
+        println!("Repository {} is already configured", name);
+        return Ok(());
+    }
+    // Klucze importujemy od razu, żeby błąd wyszedł przed rebuildem
+    let mut check = LayeredState::default();
+    check.repos.insert(name.clone(), repo.clone());
+    ensure_repo_keys(&check)?;
+
+    state.repos.insert(name, repo);
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    Ok(())
+}
+
+fn repo_remove(name: &str) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+
+    if state.repos.remove(name).is_none() {
+        return Err(anyhow!("{} is not a layered repository", name));
+    }
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    Ok(())
+}
+
+fn repo_list() -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (_, state) = booted_state(&sysroot)?;
+
+    let config = load_config()?;
+    if state.repos.is_empty() && config.repos.is_empty() {
+        println!("No layered repositories");
+    }
+    let from_config = config.repos.iter().filter(|(name, _)| !state.repos.contains_key(*name));
+    for (name, repo) in state.repos.iter().chain(from_config) {
+        let mut notes = Vec::new();
+        if !repo.keys.is_empty() {
+            notes.push(format!("keys: {}", repo.keys.join(", ")));
+        }
+        if !state.repos.contains_key(name) {
+            notes.push(REPOS_CONFIG.to_string());
+        }
+        if notes.is_empty() {
+            println!("{} {}", name, repo.url);
+        } else {
+            println!("{} {} ({})", name, repo.url, notes.join("; "));
+        }
+    }
+    Ok(())
+}
+
+pub fn repo_command(cmd: RepoCommand) -> Result<()> {
+    match cmd {
+        RepoCommand::Add { name, url, key } => repo_add(name, url, key),
+        RepoCommand::Remove { name } => repo_remove(&name),
+        RepoCommand::List => repo_list(),
+    }
+}
@@ -0,0 +1,149 @@
+// Włączanie/wyłączanie jednostek systemd w /usr nowych deploymentów (`ex enable`/`ex disable`)
+
+use anyhow::{anyhow, Context, Result};
+use camino::Utf8Path;
+use cap_std::fs::Dir;
+use cap_std_ext::dirext::CapStdExtDirExt;
+
+use crate::layered_packages::{deploy_layered_state, load_sysroot, pending_state, LayeredState};
+
+const SYSTEMD_UNIT_DIR: &str = "usr/lib/systemd/system";
+/// /etc/systemd/system obrazu (w commicie pod /usr/etc)
+const ETC_UNIT_DIR: &str = "usr/etc/systemd/system";
+
+/// Dla instancji `foo@bar.service` plikiem jednostki jest szablon `foo@.service`
+fn unit_file_name(unit: &str) -> String {
+    match (unit.split_once('@'), unit.rsplit_once('.')) {
+        (Some((prefix, _)), Some((_, suffix))) => format!("{}@.{}", prefix, suffix),
+        _ => unit.to_string(),
+    }
+}
+
+/// Wartości WantedBy=/RequiredBy= z sekcji [Install]
+fn install_targets(unit_contents: &str) -> Vec<(String, &'static str)> {
+    let mut targets = Vec::new();
+    let mut in_install = false;
+
+    for line in unit_contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_install = line == "[Install]";
+            continue;
+        }
+        if !in_install {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let suffix = match key.trim() {
+                "WantedBy" => "wants",
+                "RequiredBy" => "requires",
+                _ => continue,
+            };
+            for target in value.split_whitespace() {
+                targets.push((target.to_string(), suffix));
+            }
+        }
+    }
+
+    targets
+}
+
+fn enable_unit(rootfs: &Dir, unit: &str) -> Result<()> {
+    let unit_dir = rootfs.open_dir(SYSTEMD_UNIT_DIR)?;
+    let unit_file = unit_file_name(unit);
+    let contents = unit_dir
+        .read_to_string(&unit_file)
+        .with_context(|| format!("Unit {} not found in new deployment", unit))?;
+
+    let targets = install_targets(&contents);
+    if targets.is_empty() {
+        return Err(anyhow!("Unit {} has no WantedBy=/RequiredBy= and cannot be enabled", unit));
+    }
+
+    for (target, suffix) in targets {
+        let link_dir = format!("{}.{}", target, suffix);
+        unit_dir.create_dir_all(&link_dir)?;
+        let link = Utf8Path::new(&link_dir).join(unit);
+        let _ = unit_dir.remove_file(&link);
+        unit_dir.symlink(format!("../{}", unit_file), &link)?;
+    }
+
+    println!("Enabled {}", unit);
+    Ok(())
+}
+
+/// Usuwa symlinki włączające jednostkę dostarczone przez bazę lub pakiety (w /usr) i przez
+/// `systemctl enable` przy compose (w /usr/etc)
+fn disable_unit(rootfs: &Dir, unit: &str) -> Result<()> {
+    let mut removed = 0usize;
+    for dir in [SYSTEMD_UNIT_DIR, ETC_UNIT_DIR] {
+        let Some(unit_dir) = rootfs.open_dir_optional(dir)? else {
+            continue;
+        };
+        for entry in unit_dir.entries()? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !(name.ends_with(".wants") || name.ends_with(".requires")) {
+                continue;
+            }
+            let link = Utf8Path::new(name.as_ref()).join(unit);
+            if unit_dir.symlink_metadata_optional(&link)?.is_some() {
+                unit_dir.remove_file(&link)?;
+                removed += 1;
+            }
+        }
+    }
+    if removed == 0 {
+        return Err(anyhow!("Unit {} is not enabled in the new deployment", unit));
+    }
+
+    println!("Disabled {}", unit);
+    Ok(())
+}
+
+pub fn apply_unit_changes(state: &LayeredState, rootfs: &Dir) -> Result<()> {
+    for unit in &state.disabled_units {
+        disable_unit(rootfs, unit)?;
+    }
+    for unit in &state.enabled_units {
+        enable_unit(rootfs, unit)?;
+    }
+    Ok(())
+}
+
+pub fn set_unit_enabled(unit: &str, enabled: bool) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+
+    if enabled {
+        state.disabled_units.remove(unit);
+        state.enabled_units.insert(unit.to_string());
+    } else {
+        state.enabled_units.remove(unit);
+        state.disabled_units.insert(unit.to_string());
+    }
+
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_targets() {
+        let unit = "[Unit]\nDescription=x\n[Install]\nWantedBy=multi-user.target graphical.target\nRequiredBy=foo.target\n";
+        assert_eq!(
+            install_targets(unit),
+            vec![
+                ("multi-user.target".to_string(), "wants"),
+                ("graphical.target".to_string(), "wants"),
+                ("foo.target".to_string(), "requires"),
+            ]
+        );
+        assert_eq!(unit_file_name("getty@tty1.service"), "getty@.service");
+        assert_eq!(unit_file_name("sshd.service"), "sshd.service");
+    }
+}
@@ -0,0 +1,80 @@
+//! pacman-ostree jako biblioteka
+//!
+//! Binarka `pacman-ostree` wywołuje tylko [`run`]. Programy, które chcą odczytać stan
+//! systemu bez uruchamiania CLI (kolektory monitoringu, rozszerzenia osquery), korzystają
+//! z [`status::query`] i typów z [`status`]; pozostałe moduły są wewnętrzne i mogą się zmieniać.
+
+pub mod status;
+
+mod cli;
+pub use cli::run;
+
+// Warstwa ALPM i solver mają szersze API, niż dziś używa CLI
+#[allow(dead_code)]
+pub(crate) mod package_manager;
+#[allow(dead_code)]
+pub(crate) mod package_solver;
+pub(crate) mod compose;
+pub(crate) mod compose_hooks;
+pub(crate) mod compose_sysext;
+pub(crate) mod compose_delta;
+pub(crate) mod compose_variants;
+pub(crate) mod composepost;
+#[allow(dead_code)]
+pub(crate) mod bubblewrap;
+pub(crate) mod initramfs;
+pub(crate) mod container;
+pub(crate) mod fsutil;
+pub(crate) mod layered_packages;
+pub(crate) mod layered_files;
+pub(crate) mod layered_units;
+pub(crate) mod reboot;
+pub(crate) mod rollback;
+pub(crate) mod doctor;
+pub(crate) mod fsverity;
+pub(crate) mod integrity;
+pub(crate) mod pacman_manager;
+pub(crate) mod layered_repos;
+pub(crate) mod advisories;
+pub(crate) mod db;
+pub(crate) mod pacman_compat;
+pub(crate) mod banner;
+pub(crate) mod bootable;
+pub(crate) mod deploy;
+pub(crate) mod network;
+pub(crate) mod downloads;
+pub(crate) mod mirrors;
+pub(crate) mod history;
+pub(crate) mod timings;
+pub(crate) mod signals;
+pub(crate) mod oci_export;
+pub(crate) mod components;
+pub(crate) mod upgrade;
+pub(crate) mod rebase;
+pub(crate) mod prune;
+pub(crate) mod cache;
+pub(crate) mod warnings;
+pub(crate) mod special_files;
+pub(crate) mod pin;
+pub(crate) mod live_fs;
+pub(crate) mod licenses;
+pub(crate) mod output;
+pub(crate) mod var_tmpfiles;
+pub(crate) mod sysusers;
+pub(crate) mod os_release;
+pub(crate) mod subprocess;
+pub(crate) mod etc_snapshot;
+pub(crate) mod etc_merge;
+pub(crate) mod overrides;
+pub(crate) mod downgrade;
+pub(crate) mod attribution;
+pub(crate) mod scriptlets;
+pub(crate) mod signatures;
+pub(crate) mod commit_signing;
+pub(crate) mod search;
+pub(crate) mod aur;
+pub(crate) mod ephemeral;
+pub(crate) mod plugins;
+pub(crate) mod info;
+pub(crate) mod metrics;
+pub(crate) mod package_installer;
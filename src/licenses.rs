@@ -0,0 +1,63 @@
+// Raport licencji pakietów w obrazie (`db licenses`, pole `licenses` w JSON compose)
+
+use std::collections::BTreeMap;
+use alpm_db::desc::DbDescFileV1;
+use serde::Serialize;
+
+/// Pakiety bez `%LICENSE%` w desc
+const UNKNOWN_LICENSE: &str = "unknown";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageLicenses {
+    pub version: String,
+    pub licenses: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LicenseReport {
+    /// Pakiet -> jego licencje
+    pub packages: BTreeMap<String, PackageLicenses>,
+    /// Licencja -> pakiety, które ją deklarują
+    pub licenses: BTreeMap<String, Vec<String>>,
+}
+
+impl LicenseReport {
+    pub fn from_descs(descs: &[DbDescFileV1]) -> Self {
+        let mut report = LicenseReport::default();
+        for desc in descs {
+            let name = desc.name.to_string();
+            let mut licenses: Vec<String> = desc.license.iter().map(|l| l.to_string()).collect();
+            if licenses.is_empty() {
+                licenses.push(UNKNOWN_LICENSE.to_string());
+            }
+            for license in &licenses {
+                report.licenses.entry(license.clone()).or_default().push(name.clone());
+            }
+            report.packages.insert(
+                name,
+                PackageLicenses {
+                    version: desc.version.to_string(),
+                    licenses,
+                },
+            );
+        }
+        for packages in report.licenses.values_mut() {
+            packages.sort();
+        }
+        report
+    }
+
+    pub fn print_text(&self) {
+        println!("Licenses ({} packages):", self.packages.len());
+        for (license, packages) in &self.licenses {
+            println!("  {} ({})", license, packages.len());
+        }
+        println!();
+        println!("Packages:");
+        for (name, pkg) in &self.packages {
+            println!("  {} {}: {}", name, pkg.version, pkg.licenses.join(", "));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Nakładanie nowo zbudowanego commita na działający system (`install --apply-live`)
+//
+// /usr jest odblokowywany przejściowo (overlayfs z górną warstwą w tmpfs, jak
+// `ostree admin unlock --transient`), a różnica między uruchomionym a nowym commitem
+// jest kopiowana do środka. Wszystko znika po restarcie — wtedy startuje już
+// zestage'owany deployment z tymi samymi zmianami.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use ostree_ext::{gio, ostree};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+/// Stan nałożeń na żywo; w /run, bo overlay /usr nie przeżywa restartu
+const LIVE_STATE_FILE: &str = "/run/pacman-ostree/live-state.json";
+/// Checkout musi być na tym samym systemie plików co repo, żeby użyć hardlinków
+const CHECKOUT_TMPDIR: &str = "/var/tmp";
+
+/// Co zostało nałożone na żywo na uruchomiony deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LiveState {
+    /// Commit uruchomionego deploymentu, na który nałożono zmiany
+    pub booted_commit: String,
+    /// Commit, którego zawartość jest teraz widoczna w /usr
+    pub commit: String,
+    pub packages: BTreeSet<String>,
+}
+
+/// Stan nałożeń dla uruchomionego commita; `None`, jeśli nic nie nałożono w tym boocie
+pub fn live_state(booted_commit: &str) -> Result<Option<LiveState>> {
+    let data = match std::fs::read_to_string(LIVE_STATE_FILE) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", LIVE_STATE_FILE)),
+    };
+    let state: LiveState = serde_json::from_str(&data).with_context(|| format!("Parsing {}", LIVE_STATE_FILE))?;
+    Ok((state.booted_commit == booted_commit).then_some(state))
+}
+
+fn write_live_state(state: &LiveState) -> Result<()> {
+    let path = Path::new(LIVE_STATE_FILE);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(path, serde_json::to_string_pretty(state)?).with_context(|| format!("Writing {}", LIVE_STATE_FILE))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// Wynik `ostree diff`: linie `A    /usr/...`, `M    ...`, `D    ...`
+fn diff_commits(from: &str, to: &str) -> Result<Vec<(ChangeKind, Utf8PathBuf)>> {
+    let output = crate::subprocess::output(Command::new("ostree").args(["diff", "--repo=/sysroot/ostree/repo", from, to]))?;
+    if !output.status.success() {
+        anyhow::bail!("ostree diff failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    String::from_utf8(output.stdout)?
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| {
+            let (kind, path) = line.split_once(char::is_whitespace).ok_or_else(|| anyhow!("Unexpected diff line {}", line))?;
+            let kind = match kind {
+                "A" => ChangeKind::Added,
+                "M" => ChangeKind::Modified,
+                "D" => ChangeKind::Removed,
+                _ => anyhow::bail!("Unexpected diff line {}", line),
+            };
+            Ok((kind, Utf8PathBuf::from(path.trim_start())))
+        })
+        .collect()
+}
+
+fn remove_path(path: &Utf8Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e),
+    }
+    .with_context(|| format!("Removing {}", path))
+}
+
+/// `cp -a` zachowuje uprawnienia, właściciela, xattry i dowiązania; katalogi kopiuje rekurencyjnie
+fn copy_path(src: &Utf8Path, dest: &Utf8Path) -> Result<()> {
+    let status = Command::new("cp")
+        .args(["-a", "--no-target-directory"])
+        .arg(src)
+        .arg(dest)
+        .status()
+        .context("Failed to run cp")?;
+    if !status.success() {
+        anyhow::bail!("Copying {} to {} failed", src, dest);
+    }
+    Ok(())
+}
+
+/// Upewnia się, że /usr uruchomionego deploymentu jest zapisywalny
+fn unlock_usr(sysroot: &ostree::Sysroot, booted: &ostree::Deployment) -> Result<()> {
+    if booted.unlocked() != ostree::DeploymentUnlockedState::None {
+        return Ok(());
+    }
+    println!("Unlocking /usr with a transient overlay...");
+    sysroot
+        .deployment_unlock(booted, ostree::DeploymentUnlockedState::Transient, gio::Cancellable::NONE)
+        .context("Unlocking /usr")
+}
+
+/// Nakłada różnicę między tym, co jest w /usr, a commitem nowego deploymentu
+pub fn apply_live(
+    sysroot: &ostree::Sysroot,
+    booted: &ostree::Deployment,
+    deployment: &ostree::Deployment,
+    packages: &[String],
+) -> Result<()> {
+    let booted_commit = booted.csum().to_string();
+    let previous = live_state(&booted_commit)?;
+    // Po wcześniejszym apply-live /usr zawiera już tamten commit
+    let from = previous.as_ref().map(|s| s.commit.clone()).unwrap_or_else(|| booted_commit.clone());
+    let to = deployment.csum().to_string();
+
+    let changes = diff_commits(&from, &to)?;
+    if changes.is_empty() {
+        println!("Nothing to apply live");
+        return Ok(());
+    }
+
+    unlock_usr(sysroot, booted)?;
+
+    let repo = sysroot.repo();
+    let tmp = TempDir::new_in(CHECKOUT_TMPDIR)?;
+    let checkout = Utf8PathBuf::try_from(tmp.path().join("rootfs"))?;
+    repo.checkout_at(None, libc::AT_FDCWD, &checkout, &to, gio::Cancellable::NONE)
+        .with_context(|| format!("Checking out {}", to))?;
+
+    let (mut applied, mut skipped) = (0usize, 0usize);
+    for (kind, path) in &changes {
+        let Ok(rel) = path.strip_prefix("/") else {
+            continue;
+        };
+        // Konfiguracja: tylko nowe pliki trafiają do /etc, zmiany w istniejących
+        // zostaną scalone przez ostree przy restarcie
+        if let Ok(etc_rel) = path.strip_prefix("/usr/etc") {
+            let dest = Utf8Path::new("/etc").join(etc_rel);
+            if *kind == ChangeKind::Added && std::fs::symlink_metadata(&dest).is_err() {
+                copy_path(&checkout.join(rel), &dest)?;
+                applied += 1;
+            } else {
+                skipped += 1;
+            }
+            continue;
+        }
+        if !path.starts_with("/usr") {
+            skipped += 1;
+            continue;
+        }
+        match kind {
+            ChangeKind::Removed => remove_path(path)?,
+            ChangeKind::Added | ChangeKind::Modified => {
+                // Zmiana typu (np. plik -> katalog) wymaga najpierw usunięcia
+                remove_path(path)?;
+                copy_path(&checkout.join(rel), path)?;
+            }
+        }
+        applied += 1;
+    }
+
+    let mut live_packages = previous.map(|s| s.packages).unwrap_or_default();
+    live_packages.extend(packages.iter().cloned());
+    write_live_state(&LiveState {
+        booted_commit,
+        commit: to,
+        packages: live_packages,
+    })?;
+    println!("Applied {} change(s) live", applied);
+    if skipped > 0 {
+        println!("{} change(s) outside /usr will take effect after reboot", skipped);
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+// Metryki w formacie OpenMetrics (`ex metrics`) dla Prometheusa
+//
+// Bez własnego serwera HTTP: wynik trafia na stdout albo do pliku czytanego przez
+// textfile collector node_exportera, uruchamiany np. timerem systemd.
+
+use std::fmt::Write as _;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use ostree_ext::prelude::*;
+use walkdir::WalkDir;
+
+use crate::layered_packages::{booted_state, load_sysroot};
+use crate::status::Health;
+
+#[derive(Parser, Debug)]
+pub struct MetricsOpts {
+    /// Write metrics to this file (atomically, e.g. for the node_exporter textfile collector)
+    #[clap(long)]
+    pub output: Option<Utf8PathBuf>,
+    /// Skip walking the repository to measure its disk usage
+    #[clap(long)]
+    pub no_disk_usage: bool,
+}
+
+/// Bufor tekstu OpenMetrics
+#[derive(Default)]
+struct Metrics(String);
+
+impl Metrics {
+    fn gauge(&mut self, name: &str, help: &str, samples: &[(String, f64)]) {
+        let _ = writeln!(self.0, "# HELP pacman_ostree_{} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE pacman_ostree_{} gauge", name);
+        for (labels, value) in samples {
+            let _ = writeln!(self.0, "pacman_ostree_{}{} {}", name, labels, value);
+        }
+    }
+
+    fn single(&mut self, name: &str, help: &str, value: f64) {
+        self.gauge(name, help, &[(String::new(), value)]);
+    }
+
+    fn finish(mut self) -> String {
+        self.0.push_str("# EOF\n");
+        self.0
+    }
+}
+
+/// Wartość etykiety w cudzysłowie, z ucieczką jak wymaga OpenMetrics
+fn label_value(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// Miejsce zajęte przez repo (bloki, nie rozmiar pozorny)
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.blocks() * 512)
+        .sum()
+}
+
+fn collect(opts: &MetricsOpts) -> Result<String> {
+    let sysroot = load_sysroot()?;
+    let repo = sysroot.repo();
+    let (_, state) = booted_state(&sysroot)?;
+    let status = crate::status::query(&sysroot)?;
+    let mut m = Metrics::default();
+
+    let (base, _) = repo.load_commit(&state.base_commit)?;
+    let built = ostree_ext::ostree::commit_get_timestamp(&base) as i64;
+    let now = chrono::Utc::now().timestamp();
+    m.single("base_build_timestamp_seconds", "Build time of the booted base commit", built as f64);
+    m.single("base_age_seconds", "Time since the booted base commit was built", (now - built).max(0) as f64);
+    m.single(
+        "pending_update",
+        "Whether a different deployment will boot next",
+        status.pending_update.is_some() as u8 as f64,
+    );
+    m.single("layered_packages", "Number of layered packages", state.layered_packages.len() as f64);
+    m.single(
+        "local_packages",
+        "Number of packages layered from local files",
+        state.local_packages.len() as f64,
+    );
+    m.single(
+        "base_overrides",
+        "Number of removed or replaced base packages",
+        (state.overrides_remove.len() + state.overrides_replace.len()) as f64,
+    );
+    m.single("deployments", "Number of deployments", status.deployments.len() as f64);
+    let trusted = match &status.health {
+        Health::Ok { .. } => Some(1.0),
+        Health::Untrusted { .. } => Some(0.0),
+        Health::Unknown { .. } => None,
+    };
+    if let Some(trusted) = trusted {
+        m.single("integrity_ok", "Whether the booted deployment passes integrity checks", trusted);
+    }
+
+    if let Some(last) = crate::history::read_history()?.last() {
+        let labels = format!("{{command={}}}", label_value(&last.command));
+        m.gauge(
+            "last_transaction_success",
+            "Whether the most recent transaction succeeded",
+            &[(labels.clone(), last.error.is_none() as u8 as f64)],
+        );
+        m.gauge(
+            "last_transaction_timestamp_seconds",
+            "Start time of the most recent transaction",
+            &[(labels, last.timestamp as f64)],
+        );
+    }
+
+    if !opts.no_disk_usage {
+        if let Some(path) = repo.path().path() {
+            m.single("repo_disk_usage_bytes", "Disk space used by the OSTree repository", disk_usage(&path) as f64);
+        }
+    }
+    Ok(m.finish())
+}
+
+pub fn metrics(opts: MetricsOpts) -> Result<()> {
+    let text = collect(&opts)?;
+    let Some(output) = opts.output else {
+        print!("{}", text);
+        return Ok(());
+    };
+    // Collector nie może przeczytać połowy pliku
+    let tmp = output.with_extension("tmp");
+    std::fs::write(&tmp, text).with_context(|| format!("Writing {}", tmp))?;
+    std::fs::rename(&tmp, &output).with_context(|| format!("Writing {}", output))?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+// Ranking mirrorów pacmana (`mirrors rank`)
+//
+// Wynik nie zmienia /etc/pacman.d/mirrorlist — jest zapisywany osobno i stosowany do każdej
+// konfiguracji pacmana, którą składamy (compose, warstwy, upgrade), przez zmianę kolejności
+// serwerów repozytoriów.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use ostree_ext::glib;
+use serde::Serialize;
+
+const MIRRORLIST: &str = "/etc/pacman.d/mirrorlist";
+/// Mirrory w kolejności od najszybszego, w formacie mirrorlist
+const RANKED_MIRRORS: &str = "/var/lib/pacman-ostree/ranked-mirrors";
+/// Ile mirrorów mierzyć naraz
+const CONCURRENCY: usize = 8;
+
+#[derive(Subcommand, Debug)]
+pub enum MirrorsCommand {
+    /// Measure mirror speed and use the fastest mirrors first for compose and layering
+    Rank(RankOpts),
+    /// Show the current mirror ranking
+    Show,
+    /// Forget the ranking and use the mirrorlist order again
+    Reset,
+}
+
+#[derive(Parser, Debug)]
+pub struct RankOpts {
+    /// Mirrorlist to rank
+    #[clap(long, default_value = MIRRORLIST)]
+    pub mirrorlist: PathBuf,
+
+    /// Repository whose database is downloaded to measure speed
+    #[clap(long, default_value = "core")]
+    pub repo: String,
+
+    /// Keep only this many fastest mirrors in the ranking
+    #[clap(long)]
+    pub count: Option<usize>,
+
+    /// Time limit per mirror in seconds
+    #[clap(long, default_value_t = 10)]
+    pub timeout: u64,
+
+    /// Only print the ranking without saving it
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+/// Wynik pomiaru jednego mirrora
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MirrorSpeed {
+    pub server: String,
+    /// Bajty na sekundę; `None`, gdy pobranie się nie udało
+    pub speed: Option<u64>,
+}
+
+/// Architektura do rozwinięcia `$arch` — `auto` znaczy architekturę hosta, jak w pacmanie
+pub fn architecture(conf: &pacmanconf::Config) -> String {
+    conf.architecture
+        .iter()
+        .find(|a| a.as_str() != "auto")
+        .cloned()
+        .unwrap_or_else(|| std::env::consts::ARCH.to_string())
+}
+
+pub fn expand_server(template: &str, repo: &str, arch: &str) -> String {
+    template.replace("$repo", repo).replace("$arch", arch)
+}
+
+/// Aktywne (niezakomentowane) wpisy `Server` z mirrorlisty
+fn parse_mirrorlist(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            (key.trim() == "Server").then(|| value.trim().to_string())
+        })
+        .filter(|server| !server.is_empty())
+        .collect()
+}
+
+fn load_ranked() -> Option<Vec<String>> {
+    let content = std::fs::read_to_string(RANKED_MIRRORS).ok()?;
+    Some(parse_mirrorlist(&content)).filter(|servers| !servers.is_empty())
+}
+
+/// Ustawia serwery repozytoriów w kolejności z rankingu; serwery spoza rankingu (np. z
+/// `package-mirrors` albo własnych repozytoriów) zostają za nimi w dotychczasowej kolejności
+pub fn apply_ranking(conf: &mut pacmanconf::Config) {
+    let Some(ranked) = load_ranked() else {
+        return;
+    };
+    let arch = architecture(conf);
+    for repo in &mut conf.repos {
+        let order: Vec<String> = ranked.iter().map(|m| expand_server(m, &repo.name, &arch)).collect();
+        repo.servers
+            .sort_by_key(|server| order.iter().position(|o| o == server).unwrap_or(usize::MAX));
+    }
+}
+
+/// Średnia prędkość pobrania bazy repozytorium przez curl
+fn measure(url: &str, timeout: u64) -> Option<u64> {
+    let output = Command::new("curl")
+        .args(["--fail", "--location", "--silent", "--output", "/dev/null"])
+        .args(["--max-time", &timeout.to_string(), "--write-out", "%{speed_download}"])
+        .args(crate::network::config().curl_args())
+        .arg(url)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let speed: f64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    Some(speed as u64)
+}
+
+fn rank(opts: &RankOpts) -> Result<Vec<MirrorSpeed>> {
+    crate::network::ensure_online("Ranking mirrors")?;
+    let content = std::fs::read_to_string(&opts.mirrorlist)
+        .with_context(|| format!("Reading {}", opts.mirrorlist.display()))?;
+    let servers = parse_mirrorlist(&content);
+    if servers.is_empty() {
+        anyhow::bail!("No Server entries in {}", opts.mirrorlist.display());
+    }
+    let arch = architecture(&crate::layered_repos::pacman_options()?);
+    println!("Ranking {} mirror(s) by downloading {}.db...", servers.len(), opts.repo);
+
+    let mut results = Vec::with_capacity(servers.len());
+    for chunk in servers.chunks(CONCURRENCY) {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|server| {
+                    let url = format!("{}/{}.db", expand_server(server, &opts.repo, &arch).trim_end_matches('/'), opts.repo);
+                    scope.spawn(move || MirrorSpeed { server: server.clone(), speed: measure(&url, opts.timeout) })
+                })
+                .collect();
+            results.extend(handles.into_iter().filter_map(|h| h.join().ok()));
+        });
+    }
+    // Szybsze najpierw, niedziałające na końcu
+    results.sort_by_key(|m| std::cmp::Reverse(m.speed));
+    Ok(results)
+}
+
+fn save(ranked: &[&MirrorSpeed]) -> Result<()> {
+    let mut content = String::from("# Generated by `pacman-ostree mirrors rank`\n");
+    for mirror in ranked {
+        content.push_str(&format!("Server = {}\n", mirror.server));
+    }
+    let path = Path::new(RANKED_MIRRORS);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content).with_context(|| format!("Writing {}", RANKED_MIRRORS))
+}
+
+fn print_speeds(results: &[MirrorSpeed]) {
+    for mirror in results {
+        match mirror.speed {
+            Some(speed) => println!("{:>12}/s  {}", glib::format_size(speed), mirror.server),
+            None => println!("{:>14}  {}", "failed", mirror.server),
+        }
+    }
+}
+
+pub fn mirrors_command(cmd: MirrorsCommand) -> Result<()> {
+    match cmd {
+        MirrorsCommand::Rank(opts) => {
+            let results = crate::output::progress(|| rank(&opts))?;
+            let mut ranked: Vec<&MirrorSpeed> = results.iter().filter(|m| m.speed.is_some()).collect();
+            if let Some(count) = opts.count {
+                ranked.truncate(count);
+            }
+            if ranked.is_empty() {
+                anyhow::bail!("No mirror could be reached");
+            }
+            if !opts.dry_run {
+                save(&ranked)?;
+            }
+            if crate::output::json() {
+                return crate::output::emit(&results);
+            }
+            print_speeds(&results);
+            if !opts.dry_run {
+                println!("Saved the {} fastest mirror(s) to {}", ranked.len(), RANKED_MIRRORS);
+            }
+            Ok(())
+        }
+        MirrorsCommand::Show => {
+            let ranked = load_ranked().unwrap_or_default();
+            if crate::output::json() {
+                return crate::output::emit(&ranked);
+            }
+            if ranked.is_empty() {
+                println!("No mirror ranking; servers are used in mirrorlist order");
+            }
+            for (i, server) in ranked.iter().enumerate() {
+                println!("{:>3}. {}", i + 1, server);
+            }
+            Ok(())
+        }
+        MirrorsCommand::Reset => {
+            match std::fs::remove_file(RANKED_MIRRORS) {
+                Ok(()) => println!("Removed the mirror ranking"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => println!("No mirror ranking"),
+                Err(e) => return Err(e).with_context(|| format!("Removing {}", RANKED_MIRRORS)),
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,272 @@
+// Ustawienia sieci: proxy i mirrory dla pobierania pakietów, pulli ostree i rejestrów oraz
+// limit przepustowości — ten tylko dla pobierania przez curl (pakiety, biuletyny, webhooki);
+// libostree i skopeo nie mają odpowiednika `--limit-rate`
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Plik konfiguracji sieci; wartości ze środowiska i CLI mają pierwszeństwo
+const NETWORK_CONFIG: &str = "/etc/pacman-ostree/network.yaml";
+const HOST_REGISTRIES_CONF: &str = "/etc/containers/registries.conf";
+/// registries.conf hosta z dopisanymi `registry-mirrors`, wskazywany skopeo przez środowisko
+const REGISTRIES_CONF: &str = "/run/pacman-ostree/registries.conf";
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY_SECS: u64 = 2;
+/// Górna granica opóźnienia, żeby backoff nie rósł w nieskończoność
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+static CONFIG: OnceLock<NetworkConfig> = OnceLock::new();
+static CACHE_ONLY: AtomicBool = AtomicBool::new(false);
+/// registries.conf z mirrorami zapisany i wskazany w środowisku
+static REGISTRIES_READY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetworkConfig {
+    /// URL proxy dla HTTP i HTTPS
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    /// Limit w składni curl `--limit-rate`, np. `500K`, `2M`; nie dotyczy pulli ostree i obrazów
+    pub bwlimit: Option<String>,
+    /// Ile razy ponowić nieudaną operację sieciową (0 = bez ponowień)
+    pub retries: Option<u32>,
+    /// Opóźnienie przed pierwszym ponowieniem w sekundach; każde kolejne jest dwa razy dłuższe
+    pub retry_delay: Option<u64>,
+    /// Liczba pakietów pobieranych naraz; domyślnie ParallelDownloads z pacman.conf
+    pub parallel_downloads: Option<u32>,
+    /// Zapasowe URL-e dla zdalnych ostree (nazwa zdalnego -> lista), próbowane po kolei,
+    /// gdy pull z URL-a zdalnego się nie uda. Podpisy są dalej sprawdzane według zdalnego.
+    #[serde(default)]
+    pub mirrors: BTreeMap<String, Vec<String>>,
+    /// Serwery pakietów (jak `Server` w pacman.conf, z `$repo` i `$arch`) próbowane przed
+    /// serwerami każdego repozytorium z pacman.conf — przy compose, warstwach i upgrade
+    #[serde(default)]
+    pub package_mirrors: Vec<String>,
+    /// Mirrory rejestrów kontenerów (rejestr -> lista), np. `quay.io: [mirror.local/quay]`
+    #[serde(default)]
+    pub registry_mirrors: BTreeMap<String, Vec<String>>,
+}
+
+fn validate_bwlimit(limit: &str) -> Result<()> {
+    let digits = limit.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G']);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) || limit.len() - digits.len() > 1 {
+        anyhow::bail!("Invalid bandwidth limit {}; expected e.g. 500K or 2M", limit);
+    }
+    Ok(())
+}
+
+fn env_proxy() -> Option<String> {
+    ["https_proxy", "HTTPS_PROXY", "http_proxy", "HTTP_PROXY", "all_proxy", "ALL_PROXY"]
+        .iter()
+        .find_map(|k| std::env::var(k).ok().filter(|v| !v.is_empty()))
+}
+
+impl NetworkConfig {
+    fn load(bwlimit: Option<String>) -> Result<Self> {
+        let mut config: NetworkConfig = match std::fs::read_to_string(NETWORK_CONFIG) {
+            Ok(s) => serde_yaml::from_str(&s).with_context(|| format!("Parsing {}", NETWORK_CONFIG))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", NETWORK_CONFIG)),
+        };
+        if let Some(proxy) = env_proxy() {
+            config.proxy = Some(proxy);
+        }
+        if let Ok(no_proxy) = std::env::var("no_proxy").or_else(|_| std::env::var("NO_PROXY")) {
+            config.no_proxy = Some(no_proxy);
+        }
+        if bwlimit.is_some() {
+            config.bwlimit = bwlimit;
+        }
+        if let Some(limit) = config.bwlimit.as_deref() {
+            validate_bwlimit(limit)?;
+        }
+        Ok(config)
+    }
+
+    /// Eksportuje proxy do środowiska — honorują je libalpm (curl), ostree (curl) i skopeo
+    fn apply_env(&self) {
+        let Some(proxy) = self.proxy.as_deref() else {
+            return;
+        };
+        // SAFETY: wywoływane raz na starcie, zanim cokolwiek zacznie czytać środowisko
+        unsafe {
+            for key in ["http_proxy", "https_proxy", "HTTP_PROXY", "HTTPS_PROXY"] {
+                std::env::set_var(key, proxy);
+            }
+            if let Some(no_proxy) = self.no_proxy.as_deref() {
+                std::env::set_var("no_proxy", no_proxy);
+                std::env::set_var("NO_PROXY", no_proxy);
+            }
+        }
+    }
+
+    /// registries.conf hosta z sekcjami `[[registry]]` dla `registry-mirrors`. Rejestr już
+    /// opisany w registries.conf zostaje bez zmian — dwie sekcje o tej samej lokalizacji to błąd.
+    fn write_registries_conf(&self) -> Result<()> {
+        let mut conf = match std::fs::read_to_string(HOST_REGISTRIES_CONF) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", HOST_REGISTRIES_CONF)),
+        };
+        for (registry, mirrors) in &self.registry_mirrors {
+            if conf.contains(&format!("location = \"{}\"", registry)) {
+                eprintln!(
+                    "Warning: {} is already configured in {}; ignoring its registry-mirrors",
+                    registry, HOST_REGISTRIES_CONF
+                );
+                continue;
+            }
+            conf.push_str(&format!("\n[[registry]]\nlocation = \"{}\"\n", registry));
+            for mirror in mirrors {
+                conf.push_str(&format!("\n[[registry.mirror]]\nlocation = \"{}\"\n", mirror));
+            }
+        }
+        let path = std::path::Path::new(REGISTRIES_CONF);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, conf).with_context(|| format!("Writing {}", REGISTRIES_CONF))
+    }
+
+    /// Argumenty dla pobierania przez curl: limit przepustowości i limit czasu dla curl,
+    /// żeby zawieszony transfer kończył sam curl, zanim dostanie SIGTERM
+    pub fn curl_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(limit) = self.bwlimit.as_deref() {
+            args.extend(["--limit-rate".to_string(), limit.to_string()]);
+        }
+        if let Some(limit) = crate::subprocess::limit("curl") {
+            args.extend(["--max-time".to_string(), limit.as_secs().to_string()]);
+        }
+        args
+    }
+
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let base = Duration::from_secs(self.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY_SECS));
+        base.saturating_mul(2u32.saturating_pow(attempt - 1)).min(MAX_RETRY_DELAY)
+    }
+
+    /// Czy po nieudanej próbie `attempt` ponawiać; loguje próbę i zwraca opóźnienie
+    fn next_retry(&self, what: &str, attempt: u32, err: &anyhow::Error) -> Option<Duration> {
+        let retries = self.retries.unwrap_or(DEFAULT_RETRIES);
+        if attempt > retries {
+            return None;
+        }
+        let delay = self.retry_delay(attempt);
+        eprintln!(
+            "Warning: {} failed (attempt {}/{}): {:#}; retrying in {}s",
+            what,
+            attempt,
+            retries + 1,
+            err,
+            delay.as_secs()
+        );
+        Some(delay)
+    }
+
+    /// Zapasowe URL-e zdalnego ostree w kolejności prób
+    pub fn mirrors(&self, remote: &str) -> &[String] {
+        self.mirrors.get(remote).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Wczytuje konfigurację (plik, środowisko, `--bwlimit`) i ustawia proxy dla procesów potomnych
+pub fn init(bwlimit: Option<String>) -> Result<()> {
+    let config = NetworkConfig::load(bwlimit)?;
+    config.apply_env();
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub fn config() -> &'static NetworkConfig {
+    CONFIG.get_or_init(Default::default)
+}
+
+/// Przed pobieraniem obrazów kontenerów: registries.conf z `registry-mirrors` dla skopeo.
+/// Zapisywany dopiero tu, żeby polecenia tylko do odczytu działały bez praw do /run.
+pub fn prepare_container_pull() -> Result<()> {
+    let config = config();
+    if config.registry_mirrors.is_empty() || REGISTRIES_READY.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    config.write_registries_conf()?;
+    // SAFETY: wywoływane przed uruchomieniem skopeo, gdy żaden inny wątek nie czyta środowiska
+    unsafe { std::env::set_var("CONTAINERS_REGISTRIES_CONF", REGISTRIES_CONF) };
+    REGISTRIES_READY.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Kolejność serwerów z `mirrors rank` i `package-mirrors` przed serwerami każdego repozytorium
+pub fn apply_package_mirrors(conf: &mut pacmanconf::Config) {
+    crate::mirrors::apply_ranking(conf);
+    let mirrors = &config().package_mirrors;
+    if mirrors.is_empty() {
+        return;
+    }
+    let arch = crate::mirrors::architecture(conf);
+    for repo in &mut conf.repos {
+        let mut servers: Vec<String> = mirrors
+            .iter()
+            .map(|m| crate::mirrors::expand_server(m, &repo.name, &arch))
+            .collect();
+        servers.append(&mut repo.servers);
+        repo.servers = servers;
+    }
+}
+
+/// `--cache-only`: pakiety tylko z cache, bazy sync z ostatniego odświeżenia, bazy obrazu
+/// tylko te już pobrane — bez żadnego dostępu do sieci
+pub fn set_cache_only(enabled: bool) {
+    CACHE_ONLY.store(enabled, Ordering::Relaxed);
+}
+
+pub fn cache_only() -> bool {
+    CACHE_ONLY.load(Ordering::Relaxed)
+}
+
+/// Błąd dla operacji, które bez sieci nie mają sensu
+pub fn ensure_online(what: &str) -> Result<()> {
+    if cache_only() {
+        anyhow::bail!("{} needs the network, but --cache-only is set", what);
+    }
+    Ok(())
+}
+
+/// Wykonuje operację sieciową z ponowieniami i wykładniczym backoffem
+pub fn with_retries<T>(what: &str, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(v) => return Ok(v),
+            Err(e) => match config().next_retry(what, attempt, &e) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Err(e),
+            },
+        }
+        attempt += 1;
+    }
+}
+
+/// Jak `with_retries`, dla operacji asynchronicznych
+pub async fn with_retries_async<T, F, Fut>(what: &str, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(v) => return Ok(v),
+            Err(e) => match config().next_retry(what, attempt, &e) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(e),
+            },
+        }
+        attempt += 1;
+    }
+}
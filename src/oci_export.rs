@@ -0,0 +1,258 @@
+// Równoległa kompresja warstw i zapis oci-archive z niekompresowanego katalogu OCI
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use ostree_ext::container::ImageReference;
+use ostree_ext::glib;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::signals;
+
+const LAYER_TAR: &str = "application/vnd.oci.image.layer.v1.tar";
+const BUF_SIZE: usize = 128 * 1024;
+
+/// Algorytm kompresji warstw obrazu
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// gzip — czytany przez wszystkie narzędzia OCI
+    #[default]
+    Gzip,
+    /// zstd — dużo szybszy przy porównywalnym rozmiarze, wymaga nowszego podmana/skopeo
+    Zstd,
+}
+
+impl Compression {
+    fn media_type(self) -> String {
+        match self {
+            Compression::Gzip => format!("{}+gzip", LAYER_TAR),
+            Compression::Zstd => format!("{}+zstd", LAYER_TAR),
+        }
+    }
+
+    fn max_level(self) -> u32 {
+        match self {
+            Compression::Gzip => 9,
+            Compression::Zstd => 19,
+        }
+    }
+
+    pub fn check_level(self, level: Option<u32>) -> Result<()> {
+        match level {
+            Some(l) if l < 1 || l > self.max_level() => Err(anyhow!(
+                "Invalid compression level {} for {:?} (expected 1-{})",
+                l,
+                self,
+                self.max_level()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn command(self, level: Option<u32>) -> Command {
+        let mut cmd = match self {
+            Compression::Gzip => {
+                let mut c = Command::new("gzip");
+                c.args(["-n", "-c"]);
+                c
+            }
+            Compression::Zstd => {
+                // Równoległość zapewniamy sami, po jednej warstwie na wątek
+                let mut c = Command::new("zstd");
+                c.args(["-q", "-c", "-T1"]);
+                c
+            }
+        };
+        if let Some(level) = level {
+            cmd.arg(format!("-{}", level));
+        }
+        cmd
+    }
+}
+
+fn blob_path(oci_dir: &Utf8Path, digest: &str) -> Result<Utf8PathBuf> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid digest {}", digest))?;
+    Ok(oci_dir.join("blobs").join(algo).join(hex))
+}
+
+fn read_json(path: &Utf8Path) -> Result<Value> {
+    let f = File::open(path).with_context(|| format!("Opening {}", path))?;
+    Ok(serde_json::from_reader(f)?)
+}
+
+/// Zapisuje blob JSON i zwraca (digest, rozmiar)
+fn write_json_blob(oci_dir: &Utf8Path, value: &Value) -> Result<(String, u64)> {
+    let data = serde_json::to_vec(value)?;
+    let digest = format!("sha256:{}", glib::compute_checksum_for_data(glib::ChecksumType::Sha256, &data).unwrap());
+    std::fs::write(blob_path(oci_dir, &digest)?, &data)?;
+    Ok((digest, data.len() as u64))
+}
+
+/// Kompresuje jeden blob zewnętrznym kompresorem, licząc sha256 wyniku w locie
+fn compress_blob(
+    oci_dir: &Utf8Path,
+    digest: &str,
+    compression: Compression,
+    level: Option<u32>,
+) -> Result<(String, u64)> {
+    let src = blob_path(oci_dir, digest)?;
+    let tmp = oci_dir.join("blobs").join(format!("{}.tmp", digest.replace(':', "-")));
+
+    let mut child = compression
+        .command(level)
+        .stdin(File::open(&src).with_context(|| format!("Opening {}", src))?)
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start {:?} compressor", compression))?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut out = File::create(&tmp).with_context(|| format!("Creating {}", tmp))?;
+    let mut checksum = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
+    let mut size = 0u64;
+    let mut buf = vec![0u8; BUF_SIZE];
+
+    loop {
+        if signals::is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = std::fs::remove_file(&tmp);
+            signals::check_cancelled()?;
+        }
+        let n = stdout.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        checksum.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&tmp);
+        anyhow::bail!("{:?} failed for layer {} with {:?}", compression, digest, status.code());
+    }
+
+    let new_digest = format!("sha256:{}", checksum.string().unwrap());
+    std::fs::rename(&tmp, blob_path(oci_dir, &new_digest)?)?;
+    std::fs::remove_file(&src)?;
+    Ok((new_digest, size))
+}
+
+/// Kompresuje niekompresowane warstwy obrazu w `oci_dir` w `jobs` wątkach
+/// i przepisuje manifest oraz index. diff_ids w konfiguracji zostają bez zmian.
+/// Zwraca digest nowego manifestu.
+pub fn compress_layers(
+    oci_dir: &Utf8Path,
+    compression: Compression,
+    level: Option<u32>,
+    jobs: usize,
+) -> Result<String> {
+    let index_path = oci_dir.join("index.json");
+    let mut index = read_json(&index_path)?;
+    let manifest_desc = index["manifests"]
+        .get_mut(0)
+        .ok_or_else(|| anyhow!("No manifest in {}", index_path))?;
+    let old_manifest_digest = manifest_desc["digest"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Manifest descriptor without digest"))?
+        .to_string();
+    let mut manifest = read_json(&blob_path(oci_dir, &old_manifest_digest)?)?;
+
+    let layers = manifest["layers"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Manifest without layers"))?;
+    // Ta sama warstwa może wystąpić w manifeście kilka razy, a blob jest jeden —
+    // kompresujemy go raz i przepisujemy wszystkie jej wystąpienia
+    let mut pending: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, l) in layers.iter().enumerate().filter(|(_, l)| l["mediaType"] == LAYER_TAR) {
+        if let Some(d) = l["digest"].as_str() {
+            pending.entry(d.to_string()).or_default().push(i);
+        }
+    }
+
+    println!(
+        "Compressing {} layer(s) with {:?} using {} job(s)...",
+        pending.len(),
+        compression,
+        jobs
+    );
+    let queue = Mutex::new(pending.into_iter());
+    let results: Mutex<Vec<(Vec<usize>, Result<(String, u64)>)>> = Mutex::new(Vec::new());
+    std::thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| loop {
+                let Some((digest, indices)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let r = compress_blob(oci_dir, &digest, compression, level);
+                let failed = r.is_err();
+                results.lock().unwrap().push((indices, r));
+                if failed {
+                    break;
+                }
+            });
+        }
+    });
+
+    let layers = manifest["layers"].as_array_mut().unwrap();
+    for (indices, r) in results.into_inner().unwrap() {
+        let (digest, size) = r?;
+        for i in indices {
+            layers[i]["mediaType"] = Value::from(compression.media_type());
+            layers[i]["digest"] = Value::from(digest.clone());
+            layers[i]["size"] = Value::from(size);
+        }
+    }
+    signals::check_cancelled()?;
+
+    let (manifest_digest, manifest_size) = write_json_blob(oci_dir, &manifest)?;
+    let manifest_desc = &mut index["manifests"][0];
+    manifest_desc["digest"] = Value::from(manifest_digest.clone());
+    manifest_desc["size"] = Value::from(manifest_size);
+    std::fs::write(&index_path, serde_json::to_vec(&index)?)?;
+    std::fs::remove_file(blob_path(oci_dir, &old_manifest_digest)?)?;
+    Ok(manifest_digest)
+}
+
+/// Kopiuje obraz z katalogu OCI do dowolnego celu obsługiwanego przez skopeo,
+/// zachowując już skompresowane warstwy
+pub fn copy_image(oci_dir: &Utf8Path, dest: &ImageReference) -> Result<()> {
+    crate::network::with_retries(&format!("Copying image to {}", dest), || {
+        signals::check_cancelled()?;
+        let status = crate::subprocess::status(
+            Command::new("skopeo")
+                .arg("copy")
+                .arg(format!("oci:{}", oci_dir))
+                .arg(dest.to_string()),
+        )?;
+        if !status.success() {
+            anyhow::bail!("Copying image to {} failed", dest);
+        }
+        Ok(())
+    })
+}
+
+/// Pakuje katalog OCI do oci-archive (zwykły tar katalogu)
+pub fn write_oci_archive(oci_dir: &Utf8Path, output: &Utf8Path) -> Result<()> {
+    signals::check_cancelled()?;
+    let status = Command::new("tar")
+        .arg("-C")
+        .arg(oci_dir)
+        .args(["-cf"])
+        .arg(output)
+        .arg(".")
+        .status()
+        .context("Failed to run tar")?;
+    if !status.success() {
+        anyhow::bail!("Writing {} failed", output);
+    }
+    Ok(())
+}
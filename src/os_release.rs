@@ -0,0 +1,87 @@
+// Identyfikacja buildu obrazu w os-release (IMAGE_ID, IMAGE_VERSION, BUILD_ID)
+
+use anyhow::{Context, Result};
+use cap_std::fs::Dir;
+use chrono::{DateTime, TimeZone};
+use ostree_ext::{glib, ostree};
+
+use crate::layered_packages::LayeredState;
+
+const OS_RELEASE: &str = "usr/lib/os-release";
+/// Klucze metadanych commita bazowego, z których rebuildy odtwarzają pola
+const IMAGE_ID_META_KEY: &str = "pacman-ostree.image-id";
+const BUILD_ID_META_KEY: &str = "pacman-ostree.build-id";
+const FIELDS: &[&str] = &["IMAGE_ID", "IMAGE_VERSION", "BUILD_ID"];
+
+pub struct ImageIdentity {
+    pub image_id: String,
+    pub image_version: Option<String>,
+    pub build_id: String,
+}
+
+impl ImageIdentity {
+    /// IMAGE_ID z refa (os-release dopuszcza tylko `[a-z0-9._-]`), BUILD_ID z czasu buildu
+    pub fn new<Tz: TimeZone>(r#ref: &str, version: Option<&str>, build_time: &DateTime<Tz>) -> Self
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let image_id = r#ref
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' })
+            .collect();
+        Self {
+            image_id,
+            image_version: version.map(str::to_string),
+            build_id: build_time.format("%Y%m%d.%H%M%S").to_string(),
+        }
+    }
+
+    fn lines(&self) -> String {
+        let mut lines = format!("IMAGE_ID={}\n", self.image_id);
+        if let Some(version) = &self.image_version {
+            lines.push_str(&format!("IMAGE_VERSION=\"{}\"\n", version.replace('"', "\\\"")));
+        }
+        lines.push_str(&format!("BUILD_ID=\"{}\"\n", self.build_id));
+        lines
+    }
+}
+
+/// Zastępuje pola identyfikacji w /usr/lib/os-release (np. `BUILD_ID=rolling` z Archa)
+pub fn write_identity(rootfs: &Dir, identity: &ImageIdentity) -> Result<()> {
+    let current = rootfs
+        .read_to_string(OS_RELEASE)
+        .with_context(|| format!("Reading {}", OS_RELEASE))?;
+    let mut contents: String = current
+        .lines()
+        .filter(|l| !FIELDS.iter().any(|f| l.starts_with(&format!("{}=", f))))
+        .map(|l| format!("{}\n", l))
+        .collect();
+    contents.push_str(&identity.lines());
+    rootfs
+        .write(OS_RELEASE, contents)
+        .with_context(|| format!("Writing {}", OS_RELEASE))
+}
+
+pub fn insert_identity_meta(commitmeta: &glib::VariantDict, identity: &ImageIdentity) {
+    commitmeta.insert(IMAGE_ID_META_KEY, identity.image_id.as_str());
+    commitmeta.insert(BUILD_ID_META_KEY, identity.build_id.as_str());
+}
+
+/// Przy rebuildzie odtwarza pola z metadanych bazy; bazy sprzed tej funkcji są pomijane
+pub fn refresh_identity(repo: &ostree::Repo, state: &LayeredState, rootfs: &Dir) -> Result<()> {
+    let (commit_v, _) = repo.load_commit(&state.base_commit)?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    let (Some(image_id), Some(build_id)) = (
+        meta.lookup::<String>(IMAGE_ID_META_KEY)?,
+        meta.lookup::<String>(BUILD_ID_META_KEY)?,
+    ) else {
+        return Ok(());
+    };
+    let identity = ImageIdentity {
+        image_id,
+        image_version: meta.lookup::<String>("version")?,
+        build_id,
+    };
+    write_identity(rootfs, &identity)
+}
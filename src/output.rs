@@ -0,0 +1,71 @@
+// Format wyjścia poleceń: tekst dla ludzi albo JSON (`--json`) dla narzędzi
+
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::Result;
+use serde::Serialize;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json(enabled: bool) {
+    JSON.store(enabled, Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Wypisuje wynik polecenia jako JSON; w trybie tekstowym nic nie robi,
+/// bo tekst jest wypisywany na bieżąco
+pub fn emit<T: Serialize>(value: &T) -> Result<()> {
+    if json() {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    }
+    Ok(())
+}
+
+/// Dopóki istnieje, stdout procesu (także procesów potomnych) trafia na stderr
+struct StdoutToStderr(libc::c_int);
+
+impl StdoutToStderr {
+    fn new() -> Result<Self> {
+        std::io::stdout().flush()?;
+        // SAFETY: operacje na deskryptorach 1 i 2, które istnieją przez cały czas życia procesu
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved < 0 || unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self(saved))
+    }
+}
+
+impl Drop for StdoutToStderr {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+        // SAFETY: `self.0` to poprawny deskryptor zwrócony przez dup
+        unsafe {
+            libc::dup2(self.0, libc::STDOUT_FILENO);
+            libc::close(self.0);
+        }
+    }
+}
+
+/// W trybie JSON komunikaty postępu (także procesów potomnych, np. pacmana)
+/// trafiają na stderr, żeby stdout zawierał tylko dokument JSON
+pub fn progress<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if !json() {
+        return f();
+    }
+    let _redirect = StdoutToStderr::new()?;
+    f()
+}
+
+/// Jak `progress`, dla operacji asynchronicznych
+pub async fn progress_async<T>(f: impl Future<Output = Result<T>>) -> Result<T> {
+    if !json() {
+        return f.await;
+    }
+    let _redirect = StdoutToStderr::new()?;
+    f.await
+}
@@ -0,0 +1,130 @@
+// Usuwanie i zastępowanie pakietów bazowego obrazu w nowych deploymentach (`override`)
+
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Subcommand;
+
+use crate::layered_packages::{deploy_layered_state, load_sysroot, pending_state, STATE_DIR};
+use crate::pacman_manager;
+
+#[derive(Subcommand, Debug)]
+pub enum OverrideCommand {
+    /// Remove packages that are part of the base image
+    Remove {
+        #[clap(required = true)]
+        packages: Vec<String>,
+        /// Also remove packages listed in HoldPkg of pacman.conf
+        #[clap(long)]
+        force: bool,
+    },
+    /// Replace base packages with local package files (e.g. a patched build)
+    Replace {
+        #[clap(required = true)]
+        files: Vec<Utf8PathBuf>,
+    },
+    /// Bring back removed or replaced base packages
+    Reset {
+        #[clap(required = true)]
+        packages: Vec<String>,
+    },
+    /// List removed and replaced base packages
+    List,
+}
+
+impl OverrideCommand {
+    /// Pakiety, których dotyczy polecenie (do historii transakcji)
+    pub fn packages(&self) -> Vec<String> {
+        match self {
+            OverrideCommand::Remove { packages, .. } | OverrideCommand::Reset { packages } => packages.clone(),
+            OverrideCommand::Replace { files } => files.iter().map(|f| f.to_string()).collect(),
+            OverrideCommand::List => Vec::new(),
+        }
+    }
+}
+
+/// Kopie plików pakietów z `override replace`, instalowane przy każdym rebuildzie
+pub fn store_dir() -> Utf8PathBuf {
+    Utf8Path::new(STATE_DIR).join("override-packages")
+}
+
+pub fn override_command(cmd: OverrideCommand) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+    // Pliki usuwane z magazynu dopiero po udanym deployu, jak w `ex config remove`
+    let mut stale_files = Vec::new();
+
+    match cmd {
+        OverrideCommand::Remove { packages, force } => {
+            let base_packages = pacman_manager::read_packages_from_commit(&sysroot.repo(), &state.base_commit)?;
+            // pacman pyta przed usunięciem HoldPkg; bez interakcji wymagamy --force
+            let hold = crate::layered_repos::pacman_options()?.hold_pkg;
+            for pkg in &packages {
+                if hold.contains(pkg) && !force {
+                    anyhow::bail!("Package {} is in HoldPkg; pass --force to remove it anyway", pkg);
+                }
+                if state.layered_packages.contains(pkg) {
+                    anyhow::bail!("Package {} is layered; use `remove` instead", pkg);
+                }
+                if !base_packages.contains_key(pkg) {
+                    anyhow::bail!("Package {} is not in the base image", pkg);
+                }
+                if state.overrides_replace.contains_key(pkg) || !state.overrides_remove.insert(pkg.clone()) {
+                    anyhow::bail!("Package {} is already overridden", pkg);
+                }
+            }
+        }
+        OverrideCommand::Replace { files } => {
+            let base_packages = pacman_manager::read_packages_from_commit(&sysroot.repo(), &state.base_commit)?;
+            std::fs::create_dir_all(store_dir())?;
+            for file in &files {
+                let name = pacman_manager::package_file_name(file)?;
+                if !base_packages.contains_key(&name) {
+                    anyhow::bail!("Package {} from {} is not in the base image", name, file);
+                }
+                if state.overrides_remove.contains(&name) {
+                    anyhow::bail!("Package {} is removed; reset it first", name);
+                }
+                let file_name = file
+                    .file_name()
+                    .ok_or_else(|| anyhow!("Invalid package path {}", file))?;
+                std::fs::copy(file, store_dir().join(file_name))
+                    .with_context(|| format!("Copying {} to {}", file, store_dir()))?;
+                // Poprzednia wersja zastępstwa zostaje w magazynie do czasu udanego deployu
+                if let Some(old) = state.overrides_replace.insert(name, file_name.to_string()) {
+                    if old != file_name {
+                        stale_files.push(old);
+                    }
+                }
+            }
+        }
+        OverrideCommand::Reset { packages } => {
+            for pkg in &packages {
+                if state.overrides_remove.remove(pkg) {
+                    continue;
+                }
+                let Some(file) = state.overrides_replace.remove(pkg) else {
+                    anyhow::bail!("Package {} has no override", pkg);
+                };
+                stale_files.push(file);
+            }
+        }
+        OverrideCommand::List => {
+            if state.overrides_remove.is_empty() && state.overrides_replace.is_empty() {
+                println!("No base package overrides");
+            }
+            for pkg in &state.overrides_remove {
+                println!("remove   {}", pkg);
+            }
+            for (pkg, file) in &state.overrides_replace {
+                println!("replace  {} ({})", pkg, file);
+            }
+            return Ok(());
+        }
+    }
+
+    deploy_layered_state(&sysroot, &booted, &state)?;
+    for file in stale_files {
+        let _ = std::fs::remove_file(store_dir().join(file));
+    }
+    Ok(())
+}
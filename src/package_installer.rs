@@ -1,7 +1,7 @@
 // Obsługa instalacji pakietów i odinstalowania
 
 use crate::package_manager::{AlpmRepository, InstallResult, PackageManager, PackageInfo, PacmanHook, InstallReason};
-use crate::package_manager::pacman_hooks::{HookWhen, HookOperation, hook_matches, load_hooks};
+use crate::package_manager::pacman_hooks::{self, HookWhen, HookOperation, load_hooks};
 use crate::{AlpmPool, AlpmPackage};
 
 use anyhow::Context;
@@ -11,8 +11,6 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use crate::bubblewrap::{Bubblewrap, BubblewrapMutability};
-use std::os::unix::fs::FileTypeExt;
-use walkdir::WalkDir;
 
 use indicatif::ProgressStyle;
 use console::style;
@@ -40,15 +38,12 @@ struct PkgInfo {
 
 const DEFAULT_PACMAN_CONF_PATH: &str = "/etc/pacman.conf";
 
-pub async fn install_packages(package_names: Vec<&str>, dest: &str, pacman_conf: Option<&str>) -> anyhow::Result<()> {
-    install_packages_with_cache(package_names, dest, pacman_conf, None).await
-}
-
 pub async fn install_packages_with_cache(
     package_names: Vec<&str>,
     dest: &str,
     pacman_conf: Option<&str>,
     cache_dir: Option<&str>,
+    banned: &[String],
 ) -> anyhow::Result<()> {
     let pacman_conf = pacman_conf.unwrap_or(DEFAULT_PACMAN_CONF_PATH);
     let default_cache = format!("{}/var/cache/pacman/pkg", dest);
@@ -62,13 +57,62 @@ pub async fn install_packages_with_cache(
 
     fs::create_dir_all(cache_dir)?;
 
-    let install_result = resolve_package_install(package_names, pacman_conf, dest).await?;
-    download_packages(&install_result, dest, cache_dir, pacman_conf).await?;
-    unpack_packages(&install_result, dest).await?;
+    let install_result = {
+        let _t = crate::timings::stage("resolve");
+        resolve_package_install(package_names, pacman_conf, dest).await?
+    };
+    check_banned_packages(&install_result, banned)?;
+    {
+        let _t = crate::timings::stage("download");
+        download_packages(&install_result, dest, cache_dir, pacman_conf).await?;
+    }
+    {
+        let _t = crate::timings::stage("install");
+        unpack_packages(&install_result, dest, cache_dir).await?;
+    }
 
     Ok(())
 }
 
+/// Łańcuch zależności od jawnie żądanego pakietu do `name`, np. `base -> systemd -> name`
+fn dependency_chain(install_result: &InstallResult, name: &str) -> Vec<String> {
+    let mut chain = vec![name.to_string()];
+    let mut current = name;
+    while let Some(parent) = install_result
+        .packages
+        .iter()
+        .find(|p| p.package.name == current)
+        .and_then(|p| p.required_by.as_deref())
+    {
+        if chain.iter().any(|c| c == parent) {
+            break;
+        }
+        chain.push(parent.to_string());
+        current = parent;
+    }
+    chain.reverse();
+    chain
+}
+
+/// Przerywa instalację, jeśli rozwiązanie zawiera pakiet z `banned-packages`
+fn check_banned_packages(install_result: &InstallResult, banned: &[String]) -> anyhow::Result<()> {
+    let found: Vec<String> = install_result
+        .packages
+        .iter()
+        .filter(|p| banned.contains(&p.package.name))
+        .map(|p| match p.reason {
+            InstallReason::Explicit => format!("  {} (requested explicitly)", p.package.name),
+            InstallReason::AsDependency => {
+                format!("  {} (pulled in by {})", p.package.name, dependency_chain(install_result, &p.package.name).join(" -> "))
+            }
+        })
+        .collect();
+    if !found.is_empty() {
+        anyhow::bail!("Resolution would install banned packages:\n{}", found.join("\n"));
+    }
+    Ok(())
+}
+
 async fn resolve_package_install(
     package_names: Vec<&str>,
     _pacman_conf: &str,
@@ -126,15 +170,18 @@ async fn download_packages(
 
     println!("Downloading {} packages...", pkg_names.len());
 
+    // Cache może być współdzielony przez równoległe buildy (compose --variants)
+    let lock_file = fs::File::create(Path::new(cache_dir).join(".pacman-ostree.lock"))?;
+    let _lock = nix::fcntl::Flock::lock(lock_file, nix::fcntl::FlockArg::LockExclusive)
+        .map_err(|(_, e)| anyhow::anyhow!("Locking {}: {}", cache_dir, e))?;
+
     repo.download_packages_to_cache(&pkg_names, cache_dir)
         .map_err(|e| anyhow::anyhow!("Download failed: {}", e))?;
 
     Ok(())
 }
 
-pub async fn unpack_packages(install_result: &InstallResult, dest: &str) -> anyhow::Result<()> {
-    let cache_dir = format!("{}/var/cache/pacman/pkg", dest);
-
+pub async fn unpack_packages(install_result: &InstallResult, dest: &str, cache_dir: &str) -> anyhow::Result<()> {
     // Przy zwykłej instalacji wszystkie pakiety traktujemy jako Install.
     let active_operations = vec![HookOperation::Install];
 
@@ -168,7 +215,7 @@ pub async fn unpack_packages(install_result: &InstallResult, dest: &str) -> anyh
     }
 
     let hooks = load_hooks(dest)?;
-    let installed_files = collect_installed_files(install_result, &cache_dir)?;
+    let installed_files = collect_installed_files(install_result, cache_dir)?;
 
     let mut all_scripts: Vec<PackageScripts> = Vec::new();
 
@@ -190,12 +237,13 @@ pub async fn unpack_packages(install_result: &InstallResult, dest: &str) -> anyh
         };
 
         let scripts = if let Some(content) = extract_install_script(&pkg_file)? {
-            let has_pre  = content.contains("pre_install()");
-            let has_post = content.contains("post_install()");
+            let functions = crate::scriptlets::defined_functions(Path::new(dest), pkg_name, &content)?;
+            let has_pre  = functions.contains("pre_install");
+            let has_post = functions.contains("post_install");
 
             if has_pre {
                 println!("Running pre_install for {}...", pkg_name);
-                run_install_script_sandboxed(&content, dest, pkg_name, "pre_install")?;
+                crate::scriptlets::run_scriptlet(dest, pkg_name, &content, "pre_install", &[])?;
             }
 
             PackageScripts {
@@ -221,12 +269,7 @@ pub async fn unpack_packages(install_result: &InstallResult, dest: &str) -> anyh
 
     for scripts in &all_scripts {
         if scripts.has_post {
-            run_install_script_sandboxed(
-                &scripts.script_content,
-                dest,
-                &scripts.pkg_name,
-                "post_install",
-            )?;
+            crate::scriptlets::run_scriptlet(dest, &scripts.pkg_name, &scripts.script_content, "post_install", &[])?;
             println!("Ran post_install for {}", scripts.pkg_name);
         }
     }
@@ -240,37 +283,14 @@ pub async fn unpack_packages(install_result: &InstallResult, dest: &str) -> anyh
             .filter(|p| p.contains(&package_info.package.name))
             .cloned()
             .collect();
-        write_package_to_database(package_info, dest, &files_for_pkg).await.ok();
+        write_package_to_database(package_info, dest, cache_dir, &files_for_pkg).await.ok();
     }
 
-    cleanup_special_files(dest)?;
-
     Ok(())
 }
 
 // ───────────────── helpers ─────────────────
 
-fn cleanup_special_files(root: &str) -> anyhow::Result<()> {
-    for entry in WalkDir::new(root).follow_links(false) {
-        let entry = entry?;
-        let path = entry.path();
-
-        let metadata = fs::symlink_metadata(path)?;
-        let file_type = metadata.file_type();
-
-        if file_type.is_socket()
-            || file_type.is_fifo()
-            || file_type.is_block_device()
-            || file_type.is_char_device()
-        {
-            println!("Removing special file: {}", path.display());
-            fs::remove_file(path)?;
-        }
-    }
-
-    Ok(())
-}
-
 fn extract_install_script(pkg_file: &Path) -> anyhow::Result<Option<String>> {
     let output = Command::new("tar")
         .arg("--extract")
@@ -300,15 +320,7 @@ fn run_hooks(
         .map(|p| p.package.name.clone())
         .collect();
 
-    for hook in hooks.iter().filter(|h| h.action.when == when) {
-        let matched = hook_matches(hook, active_operations, &installed_packages, installed_files);
-        if !matched.is_empty() {
-            println!("Running hook {}...", hook.name);
-            run_hook_sandboxed(hook, dest, &matched)?;
-        }
-    }
-
-    Ok(())
+    pacman_hooks::run_hooks(hooks, when, active_operations, &installed_packages, installed_files, dest)
 }
 
 fn collect_installed_files(
@@ -346,11 +358,11 @@ fn collect_installed_files(
 pub async fn write_package_to_database(
     pkg_info: &PackageInfo,
     dest: &str,
+    cache_dir: &str,
     files: &[String],
 ) -> anyhow::Result<()> {
     let pkg = &pkg_info.package;
 
-    let cache_dir = format!("{}/var/cache/pacman/pkg", dest);
     let pattern  = format!("{}/{}-*.pkg.tar.zst", cache_dir, pkg.name);
 
     let pkg_file = glob::glob(&pattern)?
@@ -533,7 +545,7 @@ pub fn load_local_db_from_files(dest: &str) -> anyhow::Result<Vec<AlpmPackage>>
     Ok(packages)
 }
 
-fn build_bwrap_base(dest: &str) -> anyhow::Result<Bubblewrap> {
+pub fn build_bwrap_base(dest: &str) -> anyhow::Result<Bubblewrap> {
     let mut bwrap = Bubblewrap::new(dest)?;
     bwrap.prepend_rootfs_bind(dest, "/");
 
@@ -548,33 +560,8 @@ fn build_bwrap_base(dest: &str) -> anyhow::Result<Bubblewrap> {
     Ok(bwrap)
 }
 
-// === Run install script sandboxed ===
-fn run_install_script_sandboxed(
-    script_content: &str,
-    dest: &str,
-    pkg_name: &str,
-    function_name: &str,
-) -> anyhow::Result<()> {
-    let script_path = format!("/tmp/.install-{}-{}.sh", pkg_name, function_name);
-    let full_script = format!(
-        "#!/bin/bash\nset -e\n\n{}\n\n{}\n",
-        script_content, function_name
-    );
-    std::fs::write(&script_path, &full_script)?;
-
-    let mut bwrap = build_bwrap_base(dest)?;
-    bwrap.bind_read(&script_path, "/run/script.sh");
-    bwrap.append_child_argv(["/bin/bash", "/run/script.sh"]);
-
-    let status = bwrap.run()?;
-
-    let _ = std::fs::remove_file(&script_path);
-
-    Ok(())
-}
-
 // === Run hook sandboxed ===
-fn run_hook_sandboxed(
+pub fn run_hook_sandboxed(
     hook: &PacmanHook,
     dest: &str,
     matched_targets: &[String],
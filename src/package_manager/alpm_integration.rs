@@ -13,19 +13,23 @@ pub struct AlpmRepository {
 impl AlpmRepository {
     /// Create a new repository with default pacman config
     pub fn new() -> Result<Self> {
-        let config = Config::new()
+        let mut config = Config::new()
             .context("Failed to load pacman config")?;
-        let alpm = alpm_utils::alpm_with_conf(&config)
+        crate::network::apply_package_mirrors(&mut config);
+        let mut alpm = alpm_utils::alpm_with_conf(&config)
             .context("Failed to initialize ALPM")?;
+        crate::downloads::configure(&mut alpm, config.parallel_downloads);
         Ok(Self { alpm })
     }
 
     /// Create a repository from a custom pacman.conf path
     pub fn with_config(config_path: &Path) -> Result<Self> {
-        let config = Config::from_file(config_path)
+        let mut config = Config::from_file(config_path)
             .context("Failed to load custom pacman config")?;
-        let alpm = alpm_utils::alpm_with_conf(&config)
+        crate::network::apply_package_mirrors(&mut config);
+        let mut alpm = alpm_utils::alpm_with_conf(&config)
             .context("Failed to initialize ALPM with custom config")?;
+        crate::downloads::configure(&mut alpm, config.parallel_downloads);
         Ok(Self { alpm })
     }
 
@@ -35,8 +39,10 @@ impl AlpmRepository {
             .context("Failed to load custom pacman config")?;
         config.root_dir = rootdir.to_string();
         config.db_path = format!("{}/var/lib/pacman", rootdir);
-        let alpm = alpm_utils::alpm_with_conf(&config)
+        crate::network::apply_package_mirrors(&mut config);
+        let mut alpm = alpm_utils::alpm_with_conf(&config)
             .context("Failed to initialize ALPM with custom rootdir")?;
+        crate::downloads::configure(&mut alpm, config.parallel_downloads);
         Ok(Self { alpm })
     }
 
@@ -133,12 +139,16 @@ impl AlpmRepository {
             return Ok(());
         }
 
-        let mut url_list: AlpmListMut<String> = AlpmListMut::new();
-        for url in &urls {
-            url_list.push(url.clone());
-        }
-
-        let fetched = self.alpm.fetch_pkgurl(url_list)?;
+        // Przy --bwlimit albo limicie czasu dla curl fetch_pkgurl pobiera przez curl
+        // (crate::downloads::configure), inaczej wbudowanym pobieraniem libalpm
+        // Pobrane już pliki zostają w cache, więc ponowienie ściąga tylko brakujące
+        let fetched = crate::network::with_retries("Downloading packages", || {
+            let mut url_list: AlpmListMut<String> = AlpmListMut::new();
+            for url in &urls {
+                url_list.push(url.clone());
+            }
+            Ok(self.alpm.fetch_pkgurl(url_list)?)
+        })?;
         println!("Downloaded {} packages", fetched.len());
 
         Ok(())
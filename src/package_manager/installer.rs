@@ -5,12 +5,16 @@ use super::Package;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::rc::Rc;
-use resolvo::NameId;
+use resolvo::{NameId, SolvableId};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageInfo {
     pub package: Package,
     pub reason: InstallReason,
+    /// Pakiet, którego zależność wciągnęła ten pakiet (dla zależności)
+    #[serde(default)]
+    pub required_by: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -76,6 +80,7 @@ impl PackageManager {
 
         match solver.solve(problem) {
             Ok(solution) => {
+                let required_by = self.dependency_parents(&solution, &explicit_packages);
                 let mut result_packages = Vec::new();
                 let mut total_size = 0u64;
 
@@ -112,6 +117,7 @@ impl PackageManager {
                         } else {
                             InstallReason::AsDependency
                         },
+                        required_by: required_by.get(solvable.name.as_str()).cloned(),
                     });
                 }
 
@@ -136,6 +142,49 @@ impl PackageManager {
         }
     }
 
+    /// Dla każdego pakietu z rozwiązania: pakiet, który go wciągnął (BFS od jawnie
+    /// żądanych, więc łańcuchy są najkrótsze). Zależności wirtualne są mapowane
+    /// na dostawcę wybranego przez solver.
+    fn dependency_parents(&self, solution: &[SolvableId], explicit: &[&str]) -> HashMap<String, String> {
+        let in_solution: HashMap<&str, SolvableId> = solution
+            .iter()
+            .map(|&id| (self.pool.get_package_name(id), id))
+            .collect();
+        let provider_of = |dep: &str| -> Option<SolvableId> {
+            if let Some(&id) = in_solution.get(dep) {
+                return Some(id);
+            }
+            self.pool
+                .virtuals
+                .get(dep)?
+                .iter()
+                .find(|(name, _, _)| in_solution.contains_key(name.as_str()))
+                .map(|(_, _, id)| *id)
+        };
+
+        let mut parents = HashMap::new();
+        let mut seen: HashSet<SolvableId> = HashSet::new();
+        let mut queue: VecDeque<SolvableId> = explicit
+            .iter()
+            .filter_map(|name| in_solution.get(name).copied())
+            .collect();
+        seen.extend(queue.iter().copied());
+
+        while let Some(id) = queue.pop_front() {
+            let parent = self.pool.get_package_name(id);
+            for dep in self.pool.get_deps(id) {
+                let Some(child) = provider_of(&dep.name) else {
+                    continue;
+                };
+                if seen.insert(child) {
+                    parents.insert(self.pool.get_package_name(child).to_string(), parent.to_string());
+                    queue.push_back(child);
+                }
+            }
+        }
+        parents
+    }
+
     pub async fn plan_uninstall(&self, package_names: Vec<&str>) -> Result<UninstallResult> {
         self.check_reverse_dependencies(&package_names)?;
 
@@ -163,6 +212,7 @@ impl PackageManager {
                         result_packages.push(PackageInfo {
                             package,
                             reason: InstallReason::Explicit,
+                            required_by: None,
                         });
                     }
                 } else {
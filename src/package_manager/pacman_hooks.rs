@@ -44,15 +44,21 @@ pub struct PacmanHook {
     pub name: String,
     pub trigger: HookTrigger,
     pub action: HookAction,
+    /// Powód pominięcia hooka z [`IGNORED_HOOKS`]
+    pub skip_reason: Option<&'static str>,
 }
 
-const IGNORED_HOOKS: &[&str] = &[
-    "60-mkinitcpio-remove.hook",
-    "70-dkms-install.hook",
-    "70-dkms-upgrade.hook",
-    "71-dkms-remove.hook",
-    "90-mkinitcpio-install.hook",
-    "35-systemd-udev-reload.hook",
+/// Hooki bez sensu przy budowie drzewa (plik, powód) — pasujące są tylko zgłaszane
+const IGNORED_HOOKS: &[(&str, &str)] = &[
+    ("60-mkinitcpio-remove.hook", "the initramfs is generated by pacman-ostree"),
+    ("90-mkinitcpio-install.hook", "the initramfs is generated by pacman-ostree"),
+    ("70-dkms-install.hook", "DKMS modules are not built into images"),
+    ("70-dkms-upgrade.hook", "DKMS modules are not built into images"),
+    ("71-dkms-remove.hook", "DKMS modules are not built into images"),
+    ("30-systemd-daemon-reload-system.hook", "no running systemd"),
+    ("30-systemd-daemon-reload-user.hook", "no running systemd"),
+    ("30-systemd-restart-marked.hook", "no running services"),
+    ("35-systemd-udev-reload.hook", "no running udev"),
 ];
 
 pub fn parse_hook_file(path: &Path) -> anyhow::Result<PacmanHook> {
@@ -132,7 +138,6 @@ pub fn parse_hook_file(path: &Path) -> anyhow::Result<PacmanHook> {
     }
 
     Ok(PacmanHook {
-        name,
         trigger: HookTrigger {
             trigger_type: trigger_type.ok_or_else(|| anyhow::anyhow!("Missing Trigger.Type"))?,
             operations,
@@ -145,6 +150,8 @@ pub fn parse_hook_file(path: &Path) -> anyhow::Result<PacmanHook> {
             description,
             needs_targets,
         },
+        skip_reason: IGNORED_HOOKS.iter().find(|(ignored, _)| *ignored == name).map(|(_, reason)| *reason),
+        name,
     })
 }
 
@@ -175,10 +182,6 @@ pub fn load_hooks(dest: &str) -> anyhow::Result<Vec<PacmanHook>> {
                 .to_string_lossy()
                 .to_string();
 
-            if IGNORED_HOOKS.contains(&filename.as_str()) {
-                continue;
-            }
-
             match parse_hook_file(&path) {
                 Ok(hook) => hooks.push(hook),
                 Err(e) => eprintln!("Warning: failed to parse hook {}: {}", filename, e),
@@ -282,6 +285,31 @@ pub fn hook_matches(
     matched
 }
 
+/// Uruchamia w piaskownicy na `dest` hooki etapu `when` pasujące do transakcji;
+/// pasujące hooki z [`IGNORED_HOOKS`] są tylko wypisywane
+pub fn run_hooks(
+    hooks: &[PacmanHook],
+    when: HookWhen,
+    active_operations: &[HookOperation],
+    packages: &[String],
+    files: &[String],
+    dest: &str,
+) -> anyhow::Result<()> {
+    for hook in hooks.iter().filter(|h| h.action.when == when) {
+        let matched = hook_matches(hook, active_operations, packages, files);
+        if matched.is_empty() {
+            continue;
+        }
+        if let Some(reason) = hook.skip_reason {
+            println!("Skipping hook {} ({})", hook.name, reason);
+            continue;
+        }
+        println!("Running hook {}...", hook.name);
+        crate::package_installer::run_hook_sandboxed(hook, dest, &matched)?;
+    }
+    Ok(())
+}
+
 pub fn glob_match(pattern: &str, s: &str) -> bool {
     glob::Pattern::new(pattern)
         .map(|p| p.matches(s))
@@ -0,0 +1,127 @@
+// Tryb zgodności z wywołaniami pacmana (`pacman-ostree pacman -S ...` lub symlink `pacman`)
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::history;
+use crate::info::{info, InfoOpts};
+use crate::layered_packages::{handle_install, handle_remove, InstallOpts, RemoveOpts};
+use crate::search::{search, SearchOpts};
+use crate::upgrade::{upgrade, UpgradeOpts};
+
+const IMAGE_MODEL_NOTE: &str = "Note: this system is image based. Packages are layered into a new \
+deployment instead of being installed in place; changes take effect after a reboot.";
+
+/// Operacja pacmana (-S/-R/-Q/-U) i jej flagi z pierwszego argumentu
+struct Operation {
+    op: char,
+    flags: String,
+}
+
+/// Długie opcje pacmana, które biorą wartość jako osobny argument (`--config plik`)
+const LONG_OPTIONS_WITH_VALUE: &[&str] = &[
+    "--arch",
+    "--assume-installed",
+    "--cachedir",
+    "--color",
+    "--config",
+    "--dbpath",
+    "--gpgdir",
+    "--hookdir",
+    "--ignore",
+    "--ignoregroup",
+    "--logfile",
+    "--overwrite",
+    "--print-format",
+    "--root",
+    "--sysroot",
+];
+
+/// Rozdziela argumenty na operację, flagi i cele. Długie opcje (`--noconfirm`, `--needed`)
+/// są ignorowane razem ze swoimi wartościami — model obrazu i tak ich nie potrzebuje.
+fn parse_args(args: &[String]) -> Result<(Operation, Vec<String>)> {
+    let mut operation: Option<Operation> = None;
+    let mut targets = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") {
+            // Wartość w osobnym argumencie nie jest celem; `--config=plik` to jeden argument
+            if LONG_OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+                args.next();
+            }
+            continue;
+        }
+        if let Some(short) = arg.strip_prefix('-') {
+            let mut chars = short.chars();
+            let Some(first) = chars.next() else {
+                continue;
+            };
+            match &mut operation {
+                None => {
+                    operation = Some(Operation {
+                        op: first,
+                        flags: chars.collect(),
+                    })
+                }
+                Some(o) => o.flags.push_str(short),
+            }
+        } else {
+            targets.push(arg.clone());
+        }
+    }
+
+    let operation = operation.ok_or_else(|| anyhow::anyhow!("No operation specified (use -S, -R, -Q)"))?;
+    Ok((operation, targets))
+}
+
+pub async fn run(args: &[String]) -> Result<()> {
+    let (operation, targets) = parse_args(args)?;
+    let has = |c: char| operation.flags.contains(c);
+
+    match operation.op {
+        'S' if has('s') => {
+            let opts = SearchOpts::try_parse_from(std::iter::once("search").chain(targets.iter().map(String::as_str)))?;
+            search(opts)
+        }
+        'S' if has('i') => {
+            if targets.is_empty() {
+                anyhow::bail!("No targets specified");
+            }
+            for package in targets {
+                info(InfoOpts { package })?;
+            }
+            Ok(())
+        }
+        'S' if targets.is_empty() && has('u') => {
+            eprintln!("{}", IMAGE_MODEL_NOTE);
+            let opts = UpgradeOpts::try_parse_from(["upgrade"])?;
+            upgrade(opts).await
+        }
+        'S' if targets.is_empty() && has('y') => {
+            println!("Package databases are refreshed by every operation; nothing to do");
+            Ok(())
+        }
+        'S' | 'R' | 'U' if targets.is_empty() => anyhow::bail!("No targets specified"),
+        // Pliki pakietów nakłada `install` tak samo jak nazwy z repozytoriów
+        'S' | 'U' => {
+            eprintln!("{}", IMAGE_MODEL_NOTE);
+            if has('u') {
+                eprintln!("Warning: ignoring -u; only the requested packages are layered, use `pacman -Syu` to upgrade");
+            }
+            let opts = InstallOpts::try_parse_from(std::iter::once("install").chain(targets.iter().map(String::as_str)))?;
+            history::record_transaction("install", &targets, || handle_install(opts))
+        }
+        'R' => {
+            eprintln!("{}", IMAGE_MODEL_NOTE);
+            if has('s') || has('n') {
+                eprintln!("Warning: dependencies are dropped automatically on the next rebuild");
+            }
+            let opts = RemoveOpts::try_parse_from(std::iter::once("remove").chain(targets.iter().map(String::as_str)))?;
+            history::record_transaction("remove", &targets, || handle_remove(opts))
+        }
+        'Q' if targets.is_empty() => crate::db::db_list(None, has('q'), has('m')),
+        'Q' => anyhow::bail!("Querying individual packages is not supported; use `pacman-ostree db list`"),
+        op => anyhow::bail!("Unsupported pacman operation -{}", op),
+    }
+}
@@ -0,0 +1,908 @@
+// Transakcje libalpm na checkoutcie deploymentu (warstwy pakietów po stronie klienta)
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use alpm::{Alpm, AlpmListMut, CommitData, LogLevel, PackageFrom, PrepareData, Progress, Question, SigLevel, TransFlag};
+use alpm_db::desc::DbDescFileV1;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use ostree_ext::{gio, glib, ostree};
+use ostree_ext::prelude::*;
+use serde::Serialize;
+
+use crate::package_manager::pacman_hooks::{self, HookOperation, HookWhen};
+use crate::scriptlets::PackageScript;
+
+/// Baza pacmana w obrazie leży w /usr, bo /var nie jest częścią commita
+pub const PACMAN_DB_DIR: &str = "usr/share/pacman";
+const LOCAL_DB_DIR: &str = "usr/share/pacman/local";
+/// Cache hosta — współdzielony między rebuildami
+pub const PACKAGE_CACHE_DIR: &str = "/var/cache/pacman/pkg";
+
+/// Uchwyt libalpm na drzewo `rootfs` z podaną konfiguracją. Baza i keyring wskazują na
+/// `rootfs`, żeby konfiguracja hosta nie przeciekała do obrazu. Hooków libalpm nie uruchamia
+/// (sam chroot bez /proc i /dev) — robi to [`transaction`] w piaskownicy, jak przy compose.
+fn alpm_handle(rootfs: &Path, pacman_conf: &Path) -> Result<Alpm> {
+    let mut config = pacmanconf::Config::from_file(pacman_conf)
+        .with_context(|| format!("Reading {}", pacman_conf.display()))?;
+    config.root_dir = rootfs.to_string_lossy().into_owned();
+    config.db_path = rootfs.join(PACMAN_DB_DIR).to_string_lossy().into_owned();
+    config.cache_dir = vec![PACKAGE_CACHE_DIR.to_string()];
+    config.hook_dir = Vec::new();
+    crate::signatures::apply(&mut config, rootfs)?;
+    crate::network::apply_package_mirrors(&mut config);
+    let mut handle = alpm_utils::alpm_with_conf(&config).context("Initializing libalpm")?;
+    crate::downloads::configure(&mut handle, config.parallel_downloads);
+    set_callbacks(&mut handle);
+    Ok(handle)
+}
+
+/// Odpowiedzi jak `pacman --noconfirm` i postęp operacji na stdout
+fn set_callbacks(handle: &mut Alpm) {
+    handle.set_question_cb((), |question, _| match question.question() {
+        Question::Replace(mut q) => q.set_replace(true),
+        Question::ImportKey(mut q) => q.set_import(true),
+        Question::InstallIgnorepkg(mut q) => q.set_install(true),
+        _ => {}
+    });
+    handle.set_progress_cb((), |progress, pkgname, percent, howmany, current, _| {
+        // Tylko zakończone kroki, bez paska — wyjście trafia też do logów compose
+        if percent != 100 || pkgname.is_empty() {
+            return;
+        }
+        let what = match progress {
+            Progress::AddStart => "installed",
+            Progress::UpgradeStart | Progress::ReinstallStart => "upgraded",
+            Progress::DowngradeStart => "downgraded",
+            Progress::RemoveStart => "removed",
+            _ => return,
+        };
+        println!("({}/{}) {} {}", current, howmany, what, pkgname);
+    });
+    handle.set_log_cb((), |level, msg, _| {
+        if level.contains(LogLevel::ERROR) {
+            eprint!("Error: {}", msg);
+        } else if level.contains(LogLevel::WARNING) {
+            eprint!("Warning: {}", msg);
+        }
+    });
+}
+
+/// Pakiet z baz sync dla nazwy z wiersza poleceń; `repo/nazwa` szuka tylko w tym
+/// repozytorium, jak `pacman -S`
+fn find_sync<'a>(handle: &'a Alpm, name: &str) -> Option<&'a alpm::Package> {
+    match name.split_once('/') {
+        Some((repo, name)) => handle
+            .syncdbs()
+            .iter()
+            .find(|db| db.name() == repo)?
+            .pkgs()
+            .find_satisfier(name),
+        None => handle.syncdbs().find_satisfier(name),
+    }
+}
+
+/// Opis problemów zgłoszonych przez `trans_prepare`
+fn prepare_error(data: PrepareData, err: alpm::Error) -> anyhow::Error {
+    let details: Vec<String> = match data {
+        PrepareData::PkgInvalidArch(pkgs) => pkgs
+            .iter()
+            .map(|p| format!("{}: invalid architecture {}", p.name(), p.arch().unwrap_or("?")))
+            .collect(),
+        PrepareData::UnsatisfiedDeps(missing) => missing
+            .iter()
+            .map(|m| format!("{}: unsatisfied dependency {}", m.target(), m.depend()))
+            .collect(),
+        PrepareData::ConflictingDeps(conflicts) => conflicts
+            .iter()
+            .map(|c| format!("{} conflicts with {}", c.package1().name(), c.package2().name()))
+            .collect(),
+    };
+    anyhow!("{}{}", err, details.iter().map(|d| format!("\n  {}", d)).collect::<String>())
+}
+
+/// Opis problemów zgłoszonych przez `trans_commit`
+fn commit_error(data: CommitData, err: alpm::Error) -> anyhow::Error {
+    let details: Vec<String> = match data {
+        CommitData::FileConflict(conflicts) => conflicts
+            .iter()
+            .map(|c| match c.conflicting_target() {
+                Some(other) => format!("{}: {} exists in {}", c.target(), c.file(), other),
+                None => format!("{}: {} exists in filesystem", c.target(), c.file()),
+            })
+            .collect(),
+        CommitData::PkgInvalid(files) => files.iter().map(|f| format!("{} is invalid or corrupted", f)).collect(),
+    };
+    anyhow!("{}{}", err, details.iter().map(|d| format!("\n  {}", d)).collect::<String>())
+}
+
+/// Co zmieniła transakcja — do hooków PostTransaction, uruchamianych dopiero po skryptach pakietów
+#[derive(Default)]
+struct Changes {
+    operations: Vec<HookOperation>,
+    packages: Vec<String>,
+    files: Vec<String>,
+    /// Skrypty pakietów; `pre_*` już wykonane, `post_*` uruchamia wywołujący
+    scripts: Vec<PackageScript>,
+}
+
+/// Plik pakietu z transakcji: wczytany plik albo plik w cache. Brakujący w cache jest
+/// pobierany od razu, bo `.INSTALL` potrzebny jest przed rozpakowaniem. Pliki z repozytoriów
+/// są sprawdzane z poziomem podpisów ich bazy — `pre_*` z NO_SCRIPTLET rusza przed
+/// weryfikacją w `trans_commit`, więc niezweryfikowany skrypt nie może się wykonać.
+fn package_archive(handle: &Alpm, pkg: &alpm::Package) -> Result<PathBuf> {
+    let file = pkg.filename().ok_or_else(|| anyhow!("{} has no package file", pkg.name()))?;
+    // Wczytane przez pkg_load z local_file_siglevel, czyli już sprawdzone
+    if pkg.origin() == PackageFrom::File {
+        return Ok(PathBuf::from(file));
+    }
+    let path = match handle.cachedirs().iter().map(|dir| Path::new(dir).join(file)).find(|p| p.is_file()) {
+        Some(path) => path,
+        None => fetch_archive(handle, pkg, file)?,
+    };
+    let level = pkg.db().map(|db| db.siglevel()).unwrap_or_else(|| handle.remote_file_siglevel());
+    handle
+        .pkg_load(path.to_string_lossy().as_ref(), true, level)
+        .with_context(|| format!("Verifying {}", path.display()))?;
+    Ok(path)
+}
+
+/// Pobiera brakujący w cache plik pakietu z pierwszego serwera jego bazy
+fn fetch_archive(handle: &Alpm, pkg: &alpm::Package, file: &str) -> Result<PathBuf> {
+    let server = pkg
+        .db()
+        .and_then(|db| db.servers().iter().next().map(str::to_string))
+        .ok_or_else(|| anyhow!("No server to download {} from", pkg.name()))?;
+    let url = format!("{}/{}", server.trim_end_matches('/'), file);
+    let fetched = crate::network::with_retries(&format!("Downloading {}", file), || {
+        let mut urls: AlpmListMut<String> = AlpmListMut::new();
+        urls.push(url.clone());
+        Ok(handle.fetch_pkgurl(urls)?)
+    })?;
+    fetched
+        .iter()
+        .next()
+        .map(|path| PathBuf::from(path.to_string()))
+        .ok_or_else(|| anyhow!("Downloading {} failed", url))
+}
+
+/// Skrypty instalacyjne pakietów transakcji: z archiwów dodawanych i z lokalnej bazy usuwanych
+fn package_scripts(handle: &Alpm) -> Result<Vec<PackageScript>> {
+    let root = Path::new(handle.root());
+    let mut scripts = Vec::new();
+    for pkg in handle.trans_add() {
+        let archive = package_archive(handle, &pkg)?;
+        let old = handle.localdb().pkg(pkg.name()).ok().map(|old| old.version().to_string());
+        scripts.extend(PackageScript::from_archive(root, &archive, pkg.name(), pkg.version().as_str(), old.as_deref())?);
+    }
+    for pkg in handle.trans_remove() {
+        scripts.extend(PackageScript::from_local_db(root, pkg.name(), pkg.version().as_str())?);
+    }
+    Ok(scripts)
+}
+
+/// Transakcja libalpm: `add` dodaje pakiety do transakcji, reszta jak w pacmanie. Hooki
+/// PreTransaction są uruchamiane przed zatwierdzeniem, w piaskownicy jak przy compose.
+fn transaction(
+    handle: &mut Alpm,
+    flags: TransFlag,
+    what: &str,
+    add: impl FnOnce(&Alpm) -> Result<()>,
+) -> Result<Changes> {
+    handle.trans_init(flags).with_context(|| format!("Starting {} transaction", what))?;
+    let result = (|| {
+        add(handle)?;
+        handle.trans_prepare().map_err(|(data, err)| prepare_error(data, err))?;
+        ensure_cached(handle)?;
+        if handle.trans_add().is_empty() && handle.trans_remove().is_empty() {
+            return Ok(Changes::default());
+        }
+
+        // Pre-hooki widzą pliki w wersjach sprzed transakcji: usuwane i zastępowane
+        let mut changes = Changes::default();
+        let mut old_files = Vec::new();
+        for pkg in handle.trans_add() {
+            match handle.localdb().pkg(pkg.name()) {
+                Ok(old) => {
+                    changes.operations.push(HookOperation::Upgrade);
+                    old_files.extend(file_names(&old));
+                }
+                Err(_) => changes.operations.push(HookOperation::Install),
+            }
+            changes.packages.push(pkg.name().to_string());
+        }
+        for pkg in handle.trans_remove() {
+            changes.operations.push(HookOperation::Remove);
+            changes.packages.push(pkg.name().to_string());
+            old_files.extend(file_names(&pkg));
+        }
+        let root = handle.root().to_string();
+        let hooks = pacman_hooks::load_hooks(&root)?;
+        pacman_hooks::run_hooks(&hooks, HookWhen::PreTransaction, &changes.operations, &changes.packages, &old_files, &root)?;
+        // Jak w pacmanie: pre_* po hookach PreTransaction, zanim pliki zostaną rozpakowane lub usunięte
+        if flags.contains(TransFlag::NO_SCRIPTLET) {
+            changes.scripts = package_scripts(handle)?;
+            crate::scriptlets::run(Path::new(&root), &changes.scripts, "pre")?;
+        }
+
+        handle.trans_commit().map_err(|(data, err)| commit_error(data, err))?;
+        changes.files = old_files;
+        for name in &changes.packages {
+            if let Ok(pkg) = handle.localdb().pkg(name.as_str()) {
+                changes.files.extend(file_names(&pkg));
+            }
+        }
+        changes.files.sort();
+        changes.files.dedup();
+        Ok(changes)
+    })();
+    let _ = handle.trans_release();
+    result.with_context(|| format!("pacman ({})", what))
+}
+
+/// Hooki PostTransaction z drzewa po transakcji (także te, które właśnie doszły z pakietami)
+fn run_post_hooks(rootfs: &Path, changes: &Changes) -> Result<()> {
+    if changes.packages.is_empty() {
+        return Ok(());
+    }
+    let root = rootfs.to_string_lossy();
+    let hooks = pacman_hooks::load_hooks(&root)?;
+    pacman_hooks::run_hooks(&hooks, HookWhen::PostTransaction, &changes.operations, &changes.packages, &changes.files, &root)
+}
+
+/// Odświeża bazy sync uchwytu i zostawia ich kopię w [`SYNC_DB_CACHE`]; z `--cache-only`
+/// bierze zamiast tego bazy z ostatniego odświeżenia
+fn refresh(handle: &mut Alpm) -> Result<()> {
+    let sync_dir = Path::new(handle.dbpath()).join("sync");
+    let saved = Path::new(SYNC_DB_CACHE).join("sync");
+    if crate::network::cache_only() {
+        if sync_dir != saved {
+            copy_sync_dbs(&saved, &sync_dir)
+                .context("No saved package databases; run once without --cache-only")?;
+        }
+        return Ok(());
+    }
+    crate::network::with_retries("Refreshing package databases", || Ok(handle.syncdbs_mut().update(false)?))?;
+    if sync_dir != saved {
+        if let Err(e) = copy_sync_dbs(&sync_dir, &saved) {
+            eprintln!("Warning: saving package databases for --cache-only: {:#}", e);
+        }
+    }
+    Ok(())
+}
+
+fn copy_sync_dbs(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from).with_context(|| format!("Reading {}", from.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Z `--cache-only` wszystkie pakiety z repozytoriów muszą już być w cache; brakujące
+/// są zgłaszane razem, zanim libalpm spróbuje je pobrać
+fn ensure_cached(handle: &Alpm) -> Result<()> {
+    if !crate::network::cache_only() {
+        return Ok(());
+    }
+    let missing: Vec<String> = handle
+        .trans_add()
+        .iter()
+        .filter(|pkg| pkg.origin() == PackageFrom::SyncDb)
+        .filter(|pkg| {
+            pkg.filename()
+                .is_some_and(|file| !handle.cachedirs().iter().any(|dir| Path::new(dir).join(file).is_file()))
+        })
+        .map(|pkg| format!("{}-{}", pkg.name(), pkg.version()))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "{} package(s) are not in the cache (--cache-only):{}",
+            missing.len(),
+            missing.iter().map(|p| format!("\n  {}", p)).collect::<String>()
+        );
+    }
+    Ok(())
+}
+
+/// Instaluje pakiety w checkoutcie; bazy sync są odświeżane, żeby rebuild brał bieżące wersje
+pub fn install(rootfs: &Path, packages: &[String], pacman_conf: &Path) -> Result<()> {
+    if packages.is_empty() {
+        return Ok(());
+    }
+    println!("Installing {} layered package(s)...", packages.len());
+    let mut handle = alpm_handle(rootfs, pacman_conf)?;
+    refresh(&mut handle)?;
+    // Skrypty uruchamiamy sami, żeby mieć wyjście i wynik każdego z osobna
+    let changes = transaction(&mut handle, TransFlag::NEEDED | TransFlag::NO_SCRIPTLET, "install", |handle| {
+        for name in packages {
+            let pkg = find_sync(handle, name).ok_or_else(|| anyhow!("Target not found: {}", name))?;
+            if pkg.should_ignore() {
+                if let Some(file) = held_package_file(pkg.name()) {
+                    println!("Keeping {} at the installed version (IgnorePkg/IgnoreGroup)", pkg.name());
+                    let level = handle.local_file_siglevel();
+                    let held = handle
+                        .pkg_load(file.to_string_lossy().as_ref(), true, level)
+                        .with_context(|| format!("Loading {}", file.display()))?;
+                    handle.trans_add_pkg(held).map_err(|e| anyhow!("Adding {}: {}", name, e.error()))?;
+                    continue;
+                }
+                eprintln!(
+                    "Warning: {} is ignored, but its installed version is not in {}; installing {}",
+                    pkg.name(),
+                    PACKAGE_CACHE_DIR,
+                    pkg.version()
+                );
+            }
+            handle.trans_add_pkg(pkg).map_err(|e| anyhow!("Adding {}: {}", name, e.error()))?;
+        }
+        Ok(())
+    })?;
+    crate::scriptlets::run(rootfs, &changes.scripts, "post")?;
+    run_post_hooks(rootfs, &changes)
+}
+
+/// Plik w cache z wersją pakietu zainstalowaną na uruchomionym systemie. Pakiety z IgnorePkg
+/// zostają przy rebuildzie w tej wersji, tak jak `pacman -Syu` ich nie aktualizuje.
+fn held_package_file(name: &str) -> Option<std::path::PathBuf> {
+    let installed = read_packages_from_dir(Path::new("/")).ok()?;
+    cached_package_file(name, installed.get(name)?)
+}
+
+/// Plik pakietu `name` w wersji `version` z cache hosta
+pub fn cached_package_file(name: &str, version: &str) -> Option<std::path::PathBuf> {
+    let prefix = format!("{}-{}-", name, version);
+    std::fs::read_dir(PACKAGE_CACHE_DIR)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .find(|path| {
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            // Po wersji zostaje tylko architektura, np. `x86_64.pkg.tar.zst`
+            file_name.strip_prefix(&prefix).is_some_and(|rest| {
+                !rest.ends_with(".sig") && rest.split_once(".pkg.tar").is_some_and(|(arch, _)| !arch.contains('-'))
+            })
+        })
+}
+
+/// Usuwa pakiety bazy z checkoutu; pakiety bazy, które ich wymagają, blokują usunięcie
+pub fn remove(rootfs: &Path, packages: &[String], pacman_conf: &Path) -> Result<()> {
+    if packages.is_empty() {
+        return Ok(());
+    }
+    println!("Removing {} base package(s)...", packages.len());
+    let mut handle = alpm_handle(rootfs, pacman_conf)?;
+    let changes = transaction(&mut handle, TransFlag::NO_SCRIPTLET, "remove", |handle| {
+        for name in packages {
+            let pkg = handle.localdb().pkg(name.as_str()).with_context(|| format!("Package {}", name))?;
+            handle.trans_remove_pkg(pkg)?;
+        }
+        Ok(())
+    })?;
+    crate::scriptlets::run(rootfs, &changes.scripts, "post")?;
+    run_post_hooks(rootfs, &changes)
+}
+
+/// Instaluje lokalne pliki pakietów (`pacman -U`); wersje z bazy są zastępowane
+pub fn install_files(rootfs: &Path, files: &[Utf8PathBuf], pacman_conf: &Path) -> Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    println!("Installing {} local package file(s)...", files.len());
+    let mut handle = alpm_handle(rootfs, pacman_conf)?;
+    let changes = transaction(&mut handle, TransFlag::NO_SCRIPTLET, "install from files", |handle| {
+        for file in files {
+            let level = handle.local_file_siglevel();
+            let pkg = handle
+                .pkg_load(file.as_str(), true, level)
+                .with_context(|| format!("Loading {}", file))?;
+            handle.trans_add_pkg(pkg).map_err(|e| anyhow!("Adding {}: {}", file, e.error()))?;
+        }
+        Ok(())
+    })?;
+    crate::scriptlets::run(rootfs, &changes.scripts, "post")?;
+    run_post_hooks(rootfs, &changes)
+}
+
+/// Rozwiązuje całą transakcję rebuildu na samej bazie pakietów z commita (`db_root` zawiera
+/// tylko usr/share/pacman/local), zanim zacznie się powolny checkout: brakujące pakiety,
+/// zależności, konflikty i kolizje plików. Pakiety z repozytoriów lądują przy tym w cache,
+/// więc rebuild już ich nie pobiera.
+pub fn preflight(
+    db_root: &Path,
+    remove: &[String],
+    packages: &[String],
+    files: &[Utf8PathBuf],
+    pacman_conf: &Path,
+) -> Result<()> {
+    let mut handle = alpm_handle(db_root, pacman_conf)?;
+    if !packages.is_empty() {
+        refresh(&mut handle)?;
+    }
+    handle
+        .trans_init(TransFlag::NEEDED | TransFlag::DOWNLOAD_ONLY)
+        .context("Starting preflight transaction")?;
+    let result = (|| {
+        for name in remove {
+            let pkg = handle.localdb().pkg(name.as_str()).with_context(|| format!("Package {}", name))?;
+            handle.trans_remove_pkg(pkg)?;
+        }
+        for file in files {
+            let level = handle.local_file_siglevel();
+            let pkg = handle
+                .pkg_load(file.as_str(), true, level)
+                .with_context(|| format!("Loading {}", file))?;
+            handle.trans_add_pkg(pkg).map_err(|e| anyhow!("Adding {}: {}", file, e.error()))?;
+        }
+        for name in packages {
+            let pkg = find_sync(&handle, name).ok_or_else(|| anyhow!("Target not found: {}", name))?;
+            handle.trans_add_pkg(pkg).map_err(|e| anyhow!("Adding {}: {}", name, e.error()))?;
+        }
+        handle.trans_prepare().map_err(|(data, err)| prepare_error(data, err))?;
+        if handle.trans_add().is_empty() {
+            return Ok(());
+        }
+        ensure_cached(&handle)?;
+        // Listy plików pakietów z repozytoriów są dopiero w pobranych plikach
+        handle.trans_commit().map_err(|(data, err)| commit_error(data, err))?;
+        let conflicts = file_conflicts(&handle)?;
+        if !conflicts.is_empty() {
+            anyhow::bail!("conflicting files:{}", conflicts.iter().map(|c| format!("\n  {}", c)).collect::<String>());
+        }
+        Ok(())
+    })();
+    let _ = handle.trans_release();
+    result.context("pacman (preflight)")
+}
+
+/// Jak pakiety warstwy wypadają na innej bazie
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LayerResolution {
+    /// Pakiet warstwy -> pakiet bazy, który go zawiera albo dostarcza
+    pub absorbed: BTreeMap<String, String>,
+    /// Pakiet warstwy -> pakiety bazy, z którymi jest w konflikcie
+    pub conflicts: BTreeMap<String, Vec<String>>,
+    /// Pakiety warstwy, których nie ma już w żadnym repozytorium
+    pub missing: Vec<String>,
+}
+
+impl LayerResolution {
+    pub fn is_empty(&self) -> bool {
+        self.absorbed.is_empty() && self.conflicts.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Sprawdza pakiety warstwy względem bazy pakietów innej bazy (`db_root` jak w [`preflight`])
+pub fn resolve_layers(db_root: &Path, packages: &[String], pacman_conf: &Path) -> Result<LayerResolution> {
+    let mut handle = alpm_handle(db_root, pacman_conf)?;
+    refresh(&mut handle)?;
+    let local = handle.localdb();
+    let mut resolution = LayerResolution::default();
+    for name in packages {
+        if let Some(base) = local.pkgs().find_satisfier(name.rsplit('/').next().unwrap_or(name)) {
+            resolution.absorbed.insert(name.clone(), base.name().to_string());
+            continue;
+        }
+        let Some(pkg) = find_sync(&handle, name) else {
+            resolution.missing.push(name.clone());
+            continue;
+        };
+        // Konflikty w obie strony: zadeklarowane przez pakiet warstwy i przez pakiety bazy
+        let mut with: Vec<String> = pkg
+            .conflicts()
+            .iter()
+            .filter_map(|dep| local.pkgs().find_satisfier(dep.to_string()))
+            .map(|base| base.name().to_string())
+            .collect();
+        with.extend(
+            local
+                .pkgs()
+                .iter()
+                .filter(|base| base.conflicts().iter().any(|dep| dep.name() == pkg.name()))
+                .map(|base| base.name().to_string()),
+        );
+        with.sort();
+        with.dedup();
+        if !with.is_empty() {
+            resolution.conflicts.insert(name.clone(), with);
+        }
+    }
+    Ok(resolution)
+}
+
+fn file_names(pkg: &alpm::Package) -> Vec<String> {
+    pkg.files()
+        .files()
+        .iter()
+        .map(|f| {
+            let name: &[u8] = f.name().as_ref();
+            String::from_utf8_lossy(name).into_owned()
+        })
+        .filter(|name| !name.ends_with('/'))
+        .collect()
+}
+
+/// Pliki, które nowe pakiety transakcji zapisałyby na plikach innych pakietów.
+/// Pliki pakietów usuwanych i zastępowanych nowymi wersjami się nie liczą.
+fn file_conflicts(handle: &Alpm) -> Result<Vec<String>> {
+    let replaced: Vec<&str> = handle
+        .trans_remove()
+        .iter()
+        .chain(handle.trans_add().iter())
+        .map(|pkg| pkg.name())
+        .collect();
+    let mut owners: BTreeMap<String, String> = BTreeMap::new();
+    for pkg in handle.localdb().pkgs() {
+        if replaced.contains(&pkg.name()) {
+            continue;
+        }
+        for file in file_names(&pkg) {
+            owners.insert(file, pkg.name().to_string());
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for pkg in handle.trans_add() {
+        let files = match pkg.filename().filter(|_| pkg.files().files().is_empty()) {
+            // Pakiet z repozytorium: lista plików z pobranego archiwum w cache
+            Some(filename) => {
+                let path = Path::new(PACKAGE_CACHE_DIR).join(filename);
+                let loaded = handle
+                    .pkg_load(path.to_string_lossy().as_ref(), true, SigLevel::NONE)
+                    .with_context(|| format!("Loading {}", path.display()))?;
+                file_names(&loaded)
+            }
+            None => file_names(&pkg),
+        };
+        for file in files {
+            match owners.get(&file) {
+                Some(owner) => conflicts.push(format!("{}: /{} exists in {}", pkg.name(), file, owner)),
+                None => {
+                    owners.insert(file, pkg.name().to_string());
+                }
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Nazwa pakietu z pliku `.pkg.tar.*`
+pub fn package_file_name(file: &Utf8Path) -> Result<String> {
+    let db_path = Path::new("/").join(PACMAN_DB_DIR);
+    let handle = Alpm::new("/", db_path.to_string_lossy().as_ref()).context("Initializing libalpm")?;
+    let pkg = handle
+        .pkg_load(file.as_str(), false, SigLevel::NONE)
+        .with_context(|| format!("{} is not a valid package", file))?;
+    Ok(pkg.name().to_string())
+}
+
+/// Wpisy `desc` z lokalnej bazy pacmana w rozpakowanym drzewie
+pub fn read_descs_from_dir(rootfs: &Path) -> Result<Vec<DbDescFileV1>> {
+    let local_db = rootfs.join(LOCAL_DB_DIR);
+    let mut descs = Vec::new();
+    if !local_db.exists() {
+        return Ok(descs);
+    }
+
+    for entry in std::fs::read_dir(&local_db)? {
+        let desc_path = entry?.path().join("desc");
+        if !desc_path.exists() {
+            continue;
+        }
+        let contents = std::fs::read_to_string(&desc_path)?;
+        let desc = DbDescFileV1::from_str(&contents)
+            .with_context(|| format!("Parsing {}", desc_path.display()))?;
+        descs.push(desc);
+    }
+
+    Ok(descs)
+}
+
+/// Uchwyt ze świeżymi bazami sync w katalogu tymczasowym obok lokalnej bazy z /usr —
+/// do zapytań, które nie mogą ruszać deploymentu. Katalog musi żyć tak długo jak uchwyt.
+fn refreshed_sync_dbs(pacman_conf: &Path) -> Result<(tempfile::TempDir, Alpm)> {
+    let dbpath = tempfile::TempDir::new()?;
+    std::os::unix::fs::symlink(Path::new("/").join(LOCAL_DB_DIR), dbpath.path().join("local"))?;
+    let mut config = pacmanconf::Config::from_file(pacman_conf)
+        .with_context(|| format!("Reading {}", pacman_conf.display()))?;
+    config.root_dir = "/".to_string();
+    config.db_path = dbpath.path().to_string_lossy().into_owned();
+    crate::network::apply_package_mirrors(&mut config);
+    let mut handle = alpm_utils::alpm_with_conf(&config).context("Initializing libalpm")?;
+    crate::downloads::configure(&mut handle, config.parallel_downloads);
+    refresh(&mut handle)?;
+    Ok((dbpath, handle))
+}
+
+/// Kopia baz sync z ostatniego odświeżenia — `status` sprawdza na niej obce pakiety,
+/// a `--cache-only` instaluje z niej bez sieci
+const SYNC_DB_CACHE: &str = "/var/lib/pacman-ostree/sync-dbs";
+
+/// Uchwyt na bazy sync w [`SYNC_DB_CACHE`]; `None`, gdy nigdy ich nie pobrano, a `refresh` jest wyłączone
+fn cached_sync_dbs(pacman_conf: &Path, refresh_dbs: bool) -> Result<Option<Alpm>> {
+    let dbpath = Path::new(SYNC_DB_CACHE);
+    if !refresh_dbs && !dbpath.join("sync").is_dir() {
+        return Ok(None);
+    }
+    std::fs::create_dir_all(dbpath)?;
+    let local = dbpath.join("local");
+    if std::fs::symlink_metadata(&local).is_err() {
+        std::os::unix::fs::symlink(Path::new("/").join(LOCAL_DB_DIR), &local)?;
+    }
+    let mut config = pacmanconf::Config::from_file(pacman_conf)
+        .with_context(|| format!("Reading {}", pacman_conf.display()))?;
+    config.root_dir = "/".to_string();
+    config.db_path = dbpath.to_string_lossy().into_owned();
+    crate::network::apply_package_mirrors(&mut config);
+    let mut handle = alpm_utils::alpm_with_conf(&config).context("Initializing libalpm")?;
+    crate::downloads::configure(&mut handle, config.parallel_downloads);
+    if refresh_dbs {
+        refresh(&mut handle)?;
+    }
+    Ok(Some(handle))
+}
+
+/// Pakiety spośród `names`, których nie ma w żadnej bazie sync — zainstalowane przez
+/// `pacman -U`, z AUR albo z repozytorium, którego już nie ma w konfiguracji
+pub fn foreign_packages<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    pacman_conf: &Path,
+    refresh_dbs: bool,
+) -> Result<Option<BTreeSet<String>>> {
+    let Some(handle) = cached_sync_dbs(pacman_conf, refresh_dbs)? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        names
+            .into_iter()
+            .filter(|name| !handle.syncdbs().iter().any(|db| db.pkg(*name).is_ok()))
+            .map(String::from)
+            .collect(),
+    ))
+}
+
+/// Pakiet, który pacman pobrałby przy instalacji
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedPackage {
+    pub name: String,
+    pub version: String,
+    pub download_size: u64,
+}
+
+/// Co `install` doinstalowałby na uruchomionym systemie, bez checkoutu i bez commita.
+/// Bazy sync są odświeżane do katalogu tymczasowego, a lokalna baza to ta z /usr.
+pub fn plan_install(packages: &[String], pacman_conf: &Path) -> Result<Vec<PlannedPackage>> {
+    let (_dbpath, mut handle) = refreshed_sync_dbs(pacman_conf)?;
+    handle.trans_init(TransFlag::NO_LOCK).context("Starting plan transaction")?;
+    let result = (|| {
+        for name in packages {
+            let pkg = find_sync(&handle, name).ok_or_else(|| anyhow!("Target not found: {}", name))?;
+            handle.trans_add_pkg(pkg).map_err(|e| anyhow!("Adding {}: {}", name, e.error()))?;
+        }
+        // Samo rozwiązanie zależności; transakcja nie jest zatwierdzana
+        handle.trans_prepare().map_err(|(data, err)| prepare_error(data, err))?;
+        Ok(handle
+            .trans_add()
+            .iter()
+            .map(|pkg| PlannedPackage {
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                download_size: pkg.download_size().max(0) as u64,
+            })
+            .collect())
+    })();
+    let _ = handle.trans_release();
+    result.context("pacman (plan)")
+}
+
+/// Pobiera do cache hosta wszystko, co rebuild doinstalowałby na bazie z lokalną bazą pacmana
+/// w `db_root` — bez checkoutu i bez commita, żeby później nie potrzebował sieci
+pub fn download_only(db_root: &Path, packages: &[String], pacman_conf: &Path) -> Result<Vec<PlannedPackage>> {
+    let mut handle = alpm_handle(db_root, pacman_conf)?;
+    refresh(&mut handle)?;
+    handle
+        .trans_init(TransFlag::DOWNLOAD_ONLY | TransFlag::NO_LOCK)
+        .context("Starting download transaction")?;
+    let result = (|| {
+        for name in packages {
+            let pkg = find_sync(&handle, name).ok_or_else(|| anyhow!("Target not found: {}", name))?;
+            handle.trans_add_pkg(pkg).map_err(|e| anyhow!("Adding {}: {}", name, e.error()))?;
+        }
+        handle.trans_prepare().map_err(|(data, err)| prepare_error(data, err))?;
+        let planned: Vec<PlannedPackage> = handle
+            .trans_add()
+            .iter()
+            .map(|pkg| PlannedPackage {
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                download_size: pkg.download_size().max(0) as u64,
+            })
+            .collect();
+        ensure_cached(&handle)?;
+        if !planned.is_empty() {
+            handle.trans_commit().map_err(|(data, err)| commit_error(data, err))?;
+        }
+        Ok(planned)
+    })();
+    let _ = handle.trans_release();
+    result.context("pacman (download)")
+}
+
+/// Grupy wśród `names` rozwinięte w pakiety ze wszystkich baz sync (jak `pacman -S base-devel`).
+/// Nazwa, która jest też pakietem lub jest przez jakiś dostarczana, zostaje pakietem.
+pub fn expand_groups(names: &[String], pacman_conf: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+    let (_dbpath, handle) = refreshed_sync_dbs(pacman_conf)?;
+    let mut groups = BTreeMap::new();
+    for name in names {
+        if find_sync(&handle, name).is_some() {
+            continue;
+        }
+        let mut members = Vec::new();
+        for db in handle.syncdbs() {
+            if let Ok(group) = db.group(name.as_str()) {
+                members.extend(group.packages().iter().map(|pkg| pkg.name().to_string()));
+            }
+        }
+        if !members.is_empty() {
+            members.sort();
+            members.dedup();
+            groups.insert(name.clone(), members);
+        }
+    }
+    Ok(groups)
+}
+
+/// Pakiet z bazy sync znaleziony przez `search_sync`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SyncPackage {
+    pub repo: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+}
+
+/// Szuka w bazach sync (jak `pacman -Ss`) bez dotykania deploymentu
+pub fn search_sync(terms: &[String], pacman_conf: &Path) -> Result<Vec<SyncPackage>> {
+    let (_dbpath, handle) = refreshed_sync_dbs(pacman_conf)?;
+    let mut results = Vec::new();
+    for db in handle.syncdbs() {
+        for pkg in db.search(terms.iter())?.iter() {
+            results.push(SyncPackage {
+                repo: db.name().to_string(),
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                description: pkg.desc().unwrap_or_default().to_string(),
+            });
+        }
+    }
+    Ok(results)
+}
+
+/// Pola jednego pakietu jak w `pacman -Si`, w tej samej kolejności
+pub type SyncInfo = Vec<(String, String)>;
+
+fn join_list<'a>(items: impl Iterator<Item = String> + 'a) -> String {
+    let items: Vec<String> = items.collect();
+    if items.is_empty() { "None".to_string() } else { items.join("  ") }
+}
+
+fn package_info(db: &alpm::Db, pkg: &alpm::Package) -> SyncInfo {
+    let size = |bytes: i64| glib::format_size(bytes.max(0) as u64).to_string();
+    vec![
+        ("Repository".to_string(), db.name().to_string()),
+        ("Name".to_string(), pkg.name().to_string()),
+        ("Version".to_string(), pkg.version().to_string()),
+        ("Description".to_string(), pkg.desc().unwrap_or_default().to_string()),
+        ("URL".to_string(), pkg.url().unwrap_or_default().to_string()),
+        ("Licenses".to_string(), join_list(pkg.licenses().iter().map(str::to_string))),
+        ("Groups".to_string(), join_list(pkg.groups().iter().map(str::to_string))),
+        ("Provides".to_string(), join_list(pkg.provides().iter().map(|d| d.to_string()))),
+        ("Depends On".to_string(), join_list(pkg.depends().iter().map(|d| d.to_string()))),
+        (
+            "Optional Deps".to_string(),
+            match pkg.optdepends().iter().map(|d| d.to_string()).collect::<Vec<_>>() {
+                deps if deps.is_empty() => "None".to_string(),
+                deps => deps.join("\n"),
+            },
+        ),
+        ("Conflicts With".to_string(), join_list(pkg.conflicts().iter().map(|d| d.to_string()))),
+        ("Replaces".to_string(), join_list(pkg.replaces().iter().map(|d| d.to_string()))),
+        ("Download Size".to_string(), size(pkg.download_size())),
+        ("Installed Size".to_string(), size(pkg.isize())),
+        ("Packager".to_string(), pkg.packager().unwrap_or_default().to_string()),
+    ]
+}
+
+/// Metadane pakietu z baz sync (jak `pacman -Si`); pusto, gdy żadne repozytorium go nie ma
+pub fn sync_info(package: &str, pacman_conf: &Path) -> Result<Vec<SyncInfo>> {
+    let (_dbpath, handle) = refreshed_sync_dbs(pacman_conf)?;
+    Ok(handle
+        .syncdbs()
+        .iter()
+        .filter_map(|db| db.pkg(package).ok().map(|pkg| package_info(db, pkg)))
+        .collect())
+}
+
+/// Pakiety z lokalnej bazy pacmana w rozpakowanym drzewie: nazwa -> wersja
+pub fn read_packages_from_dir(rootfs: &Path) -> Result<BTreeMap<String, String>> {
+    Ok(read_descs_from_dir(rootfs)?
+        .into_iter()
+        .map(|d| (d.name.to_string(), d.version.to_string()))
+        .collect())
+}
+
+/// Parsuje `desc` jednego pakietu z lokalnej bazy
+fn parse_desc(file: &gio::File) -> Result<DbDescFileV1> {
+    let (contents, _) = file.load_contents(gio::Cancellable::NONE)?;
+    let contents = std::str::from_utf8(&contents)?;
+    Ok(DbDescFileV1::from_str(contents)?)
+}
+
+/// Wpisy `desc` z lokalnej bazy pacmana zapisanej w commicie
+pub fn read_descs_from_commit(repo: &ostree::Repo, commit: &str) -> Result<Vec<DbDescFileV1>> {
+    let cancellable = gio::Cancellable::NONE;
+    let (root, _) = repo
+        .read_commit(commit, cancellable)
+        .with_context(|| format!("Reading commit {}", commit))?;
+    let local_db = root.resolve_relative_path(LOCAL_DB_DIR);
+
+    let mut descs = Vec::new();
+    let entries = match local_db.enumerate_children(
+        "standard::name,standard::type",
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        cancellable,
+    ) {
+        Ok(e) => e,
+        Err(e) if e.matches(gio::IOErrorEnum::NotFound) => return Ok(descs),
+        Err(e) => return Err(e.into()),
+    };
+
+    for info in entries {
+        let info = info?;
+        if info.file_type() != gio::FileType::Directory {
+            continue;
+        }
+        let desc_file = local_db.child(info.name()).child("desc");
+        let desc = parse_desc(&desc_file)
+            .with_context(|| format!("Parsing {}/{}/desc", LOCAL_DB_DIR, info.name().display()))?;
+        descs.push(desc);
+    }
+
+    Ok(descs)
+}
+
+/// Pakiety z lokalnej bazy pacmana zapisanej w commicie: nazwa -> wersja
+pub fn read_packages_from_commit(repo: &ostree::Repo, commit: &str) -> Result<BTreeMap<String, String>> {
+    Ok(read_descs_from_commit(repo, commit)?
+        .into_iter()
+        .map(|d| (d.name.to_string(), d.version.to_string()))
+        .collect())
+}
+
+/// Suma rozmiarów zainstalowanych pakietów z lokalnej bazy w commicie
+pub fn installed_size_from_commit(repo: &ostree::Repo, commit: &str) -> Result<u64> {
+    Ok(read_descs_from_commit(repo, commit)?.iter().map(|d| d.size).sum())
+}
+
+/// Changelog pakietu z lokalnej bazy w commicie (`pacman -Qc`); `None` gdy pakiet go nie ma
+pub fn read_changelog_from_commit(
+    repo: &ostree::Repo,
+    commit: &str,
+    name: &str,
+    version: &str,
+) -> Result<Option<String>> {
+    let (root, _) = repo.read_commit(commit, gio::Cancellable::NONE)?;
+    let file = root.resolve_relative_path(format!("{}/{}-{}/changelog", LOCAL_DB_DIR, name, version));
+    match file.load_contents(gio::Cancellable::NONE) {
+        Ok((contents, _)) => Ok(Some(String::from_utf8_lossy(&contents).into_owned())),
+        Err(e) if e.matches(gio::IOErrorEnum::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
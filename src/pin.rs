@@ -0,0 +1,59 @@
+// Przypinanie deploymentów, żeby cleanup i nowe deploymenty ich nie usuwały (`pin`)
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+
+use crate::layered_packages::{load_sysroot, lock_sysroot};
+
+#[derive(Parser, Debug)]
+pub struct PinOpts {
+    /// Index of the deployment as listed by `status`, starting at 0 (default: booted)
+    pub deployment: Option<usize>,
+
+    /// Remove the pin instead of setting it
+    #[clap(long)]
+    pub unpin: bool,
+}
+
+pub fn pin(opts: PinOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let lock = lock_sysroot(&sysroot)?;
+
+    let deployment = match opts.deployment {
+        Some(index) => sysroot
+            .deployments()
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| anyhow!("No deployment with index {}", index))?,
+        None => sysroot
+            .booted_deployment()
+            .ok_or_else(|| anyhow!("Not booted into an OSTree deployment"))?,
+    };
+    if deployment.is_staged() {
+        anyhow::bail!("Cannot pin a staged deployment; reboot into it first");
+    }
+
+    let pinned = !opts.unpin;
+    if deployment.is_pinned() == pinned {
+        println!(
+            "Deployment {}.{} is already {}",
+            deployment.csum(),
+            deployment.deployserial(),
+            if pinned { "pinned" } else { "unpinned" }
+        );
+        return Ok(());
+    }
+    // Pin trafia do originu deploymentu, tak jak w `ostree admin pin`
+    sysroot
+        .deployment_set_pinned(&deployment, pinned)
+        .context("Updating deployment pin")?;
+    drop(lock);
+
+    println!(
+        "Deployment {}.{} is now {}",
+        deployment.csum(),
+        deployment.deployserial(),
+        if pinned { "pinned" } else { "unpinned" }
+    );
+    Ok(())
+}
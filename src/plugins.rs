@@ -0,0 +1,142 @@
+// Hooki administratora wokół transakcji (/etc/pacman-ostree/hooks.d)
+//
+// Każdy wykonywalny plik z katalogu dostaje na stdin JSON z etapem i szczegółami
+// transakcji, a etap także w PACMAN_OSTREE_HOOK_STAGE. Hooki uruchamiane są w kolejności
+// nazw. Nieudany hook `pre-*` przerywa transakcję; błędy hooków `post-*` są tylko
+// ostrzeżeniem, bo zmiana już się dokonała.
+
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+
+use crate::layered_packages::LayeredState;
+
+const HOOKS_DIR: &str = "/etc/pacman-ostree/hooks.d";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// Przed jakąkolwiek zmianą
+    PreTransaction,
+    /// Commit z warstwami jest w repo, jeszcze niewdrożony
+    PostCommit,
+    /// Tuż przed stage'owaniem deploymentu
+    PreDeploy,
+    /// Deployment zestage'owany
+    PostDeploy,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::PreTransaction => "pre-transaction",
+            Stage::PostCommit => "post-commit",
+            Stage::PreDeploy => "pre-deploy",
+            Stage::PostDeploy => "post-deploy",
+        }
+    }
+
+    fn is_pre(self) -> bool {
+        matches!(self, Stage::PreTransaction | Stage::PreDeploy)
+    }
+}
+
+/// Komenda i pakiety bieżącej transakcji z `history::record_transaction`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Transaction {
+    command: String,
+    packages: Vec<String>,
+}
+
+static CURRENT: Mutex<Option<Transaction>> = Mutex::new(None);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Payload<'a> {
+    stage: Stage,
+    /// `None` poza `record_transaction` (np. `--ephemeral`)
+    transaction: Option<Transaction>,
+    /// Commit przed zmianą (uruchomiony deployment)
+    old_commit: Option<&'a str>,
+    /// Nowy commit, od `post-commit`
+    new_commit: Option<&'a str>,
+    state: Option<&'a LayeredState>,
+}
+
+/// Ustawia transakcję opisywaną w payloadach kolejnych hooków
+pub fn begin(command: &str, packages: &[String]) {
+    if let Ok(mut current) = CURRENT.lock() {
+        *current = Some(Transaction {
+            command: command.to_string(),
+            packages: packages.to_vec(),
+        });
+    }
+}
+
+pub fn end() {
+    if let Ok(mut current) = CURRENT.lock() {
+        *current = None;
+    }
+}
+
+/// Wykonywalne pliki z hooks.d posortowane po nazwie; brak katalogu to brak hooków
+fn hooks() -> Result<Vec<Utf8PathBuf>> {
+    let entries = match Utf8Path::new(HOOKS_DIR).read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", HOOKS_DIR)),
+    };
+    let mut hooks = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().starts_with('.') {
+            continue;
+        }
+        // metadata() idzie za symlinkami, więc hook może wskazywać na plik spoza katalogu
+        let meta = entry.path().metadata()?;
+        if meta.is_file() && meta.permissions().mode() & 0o111 != 0 {
+            hooks.push(entry.path().to_path_buf());
+        }
+    }
+    hooks.sort();
+    Ok(hooks)
+}
+
+fn run_hook(hook: &Utf8Path, stage: Stage, payload: &[u8]) -> Result<()> {
+    let status = crate::subprocess::status_with_input(
+        Command::new(hook).env("PACMAN_OSTREE_HOOK_STAGE", stage.name()),
+        payload,
+    )
+    .with_context(|| format!("Running hook {}", hook))?;
+    if !status.success() {
+        anyhow::bail!("{} hook {} exited with {:?}", stage.name(), hook, status.code());
+    }
+    Ok(())
+}
+
+/// Uruchamia hooki etapu; błąd zwracany tylko dla etapów `pre-*`
+pub fn run(stage: Stage, old_commit: Option<&str>, new_commit: Option<&str>, state: Option<&LayeredState>) -> Result<()> {
+    let hooks = hooks()?;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let payload = serde_json::to_vec(&Payload {
+        stage,
+        transaction: CURRENT.lock().ok().and_then(|c| c.clone()),
+        old_commit,
+        new_commit,
+        state,
+    })?;
+    for hook in hooks {
+        match run_hook(&hook, stage, &payload) {
+            Ok(()) => {}
+            Err(e) if stage.is_pre() => return Err(e),
+            Err(e) => eprintln!("Warning: {:#}", e),
+        }
+    }
+    Ok(())
+}
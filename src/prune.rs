@@ -0,0 +1,151 @@
+// Usuwanie starych baz po udanym uruchomieniu nowej (`ex prune`)
+
+use std::collections::BTreeSet;
+use std::process::Command;
+use anyhow::{Context, Result};
+use clap::Parser;
+use ostree_ext::container::ImageReference;
+use ostree_ext::{gio, ostree};
+use serde::Deserialize;
+
+use crate::layered_packages::{deployment_state, load_sysroot, lock_sysroot, STATE_DIR};
+
+/// Polityka przycinania; bez pliku automatyczne przycinanie jest wyłączone
+const PRUNE_CONFIG: &str = "/etc/pacman-ostree/prune.yaml";
+/// Baza, po której uruchomieniu ostatnio przycinano — żeby robić to raz na aktualizację
+const LAST_PRUNED_FILE: &str = "last-pruned-base";
+/// Cel osiągany przez systemd dopiero po przejściu wszystkich health checków
+const BOOT_COMPLETE_TARGET: &str = "boot-complete.target";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PruneConfig {
+    /// Przycinaj automatycznie po udanym uruchomieniu nowej bazy
+    #[serde(default)]
+    pub auto: bool,
+    /// Ile nieużywanych baz zostawić ponad te, z których korzystają deploymenty
+    #[serde(default)]
+    pub keep: usize,
+}
+
+impl PruneConfig {
+    fn load() -> Result<Self> {
+        match std::fs::read_to_string(PRUNE_CONFIG) {
+            Ok(s) => serde_yaml::from_str(&s).with_context(|| format!("Parsing {}", PRUNE_CONFIG)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(e) => Err(e).with_context(|| format!("Reading {}", PRUNE_CONFIG)),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct PruneOpts {
+    /// Number of unused base commits/images to keep (overrides prune.yaml)
+    #[clap(long)]
+    pub keep: Option<usize>,
+
+    /// Run only if automatic pruning is enabled, boot-complete.target was reached
+    /// and the booted base has not been pruned after yet (for the systemd unit)
+    #[clap(long)]
+    pub after_boot: bool,
+}
+
+fn boot_complete() -> bool {
+    Command::new("systemctl")
+        .args(["is-active", "--quiet", BOOT_COMPLETE_TARGET])
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Bazy, na których opierają się istniejące deploymenty — nigdy nie są usuwane
+fn bases_in_use(sysroot: &ostree::Sysroot) -> Result<BTreeSet<String>> {
+    let repo = sysroot.repo();
+    let mut bases = BTreeSet::new();
+    for d in sysroot.deployments() {
+        bases.insert(d.csum().to_string());
+        bases.insert(deployment_state(&repo, &d)?.base_commit);
+    }
+    Ok(bases)
+}
+
+/// Usuwa obrazy kontenerów spoza deploymentów, zostawiając `keep` najnowszych
+fn prune_images(repo: &ostree::Repo, in_use: &BTreeSet<String>, keep: usize) -> Result<()> {
+    let mut unused = Vec::new();
+    for name in ostree_ext::container::store::list_images(repo)? {
+        let imgref = ImageReference::try_from(name.as_str())?;
+        let Some(state) = ostree_ext::container::store::query_image(repo, &imgref)? else {
+            continue;
+        };
+        if in_use.contains(&state.merge_commit) {
+            continue;
+        }
+        let (commit, _) = repo.load_commit(&state.merge_commit)?;
+        unused.push((ostree::commit_get_timestamp(&commit), imgref));
+    }
+    unused.sort_by(|a, b| b.0.cmp(&a.0));
+    let remove: Vec<ImageReference> = unused.into_iter().skip(keep).map(|(_, r)| r).collect();
+    for imgref in &remove {
+        println!("Removing image {}", imgref);
+    }
+    if !remove.is_empty() {
+        ostree_ext::container::store::remove_images(repo, &remove)?;
+    }
+    let layers = ostree_ext::container::store::gc_image_layers(repo)?;
+    if layers > 0 {
+        println!("Removed {} unreferenced image layer(s)", layers);
+    }
+    Ok(())
+}
+
+pub fn prune(opts: PruneOpts) -> Result<()> {
+    let config = PruneConfig::load()?;
+    let sysroot = load_sysroot()?;
+    let booted = sysroot
+        .booted_deployment()
+        .ok_or_else(|| anyhow::anyhow!("Not booted into an OSTree deployment"))?;
+    let repo = sysroot.repo();
+    let booted_base = deployment_state(&repo, &booted)?.base_commit;
+    let marker = std::path::Path::new(STATE_DIR).join(LAST_PRUNED_FILE);
+
+    if opts.after_boot {
+        if !config.auto {
+            return Ok(());
+        }
+        if !boot_complete() {
+            println!("{} not reached; keeping previous bases", BOOT_COMPLETE_TARGET);
+            return Ok(());
+        }
+        if std::fs::read_to_string(&marker).is_ok_and(|m| m.trim() == booted_base) {
+            return Ok(());
+        }
+    }
+    let keep = opts.keep.unwrap_or(config.keep);
+
+    let cancellable = gio::Cancellable::NONE;
+    let lock = lock_sysroot(&sysroot)?;
+    // Najpierw deploymenty, żeby ich commity przestały być osiągalne. Bez przycinania repo —
+    // `cleanup` przycina je z głębokością 0 i zgubiłby historię zachowywaną przez `keep`
+    sysroot.prepare_cleanup(cancellable).context("Cleaning up deployments")?;
+    let in_use = bases_in_use(&sysroot)?;
+    prune_images(&repo, &in_use, keep)?;
+
+    // Historia refów ostree: zostaje `keep` poprzednich commitów bazy, ale co najmniej
+    // jeden — na nim zwykle opiera się deployment rollback
+    let depth = keep.max(1) as i32;
+    let (total, pruned, freed) = repo
+        .prune(ostree::RepoPruneFlags::REFS_ONLY, depth, cancellable)
+        .context("Pruning repo")?;
+    repo.prune_static_deltas(None, cancellable)
+        .context("Pruning static deltas")?;
+    drop(lock);
+
+    println!(
+        "Pruned {} of {} objects, freed {}",
+        pruned,
+        total,
+        ostree_ext::glib::format_size(freed)
+    );
+    std::fs::create_dir_all(STATE_DIR)?;
+    std::fs::write(&marker, &booted_base)?;
+    Ok(())
+}
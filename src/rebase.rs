@@ -0,0 +1,59 @@
+// Przełączenie bazy na inny ref lub obraz z zachowaniem warstw (`rebase`)
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::layered_packages::{deploy_layered_state, emit_transaction_report, load_sysroot, pending_state};
+use crate::reboot::{maybe_reboot, RebootOpts};
+
+#[derive(Parser, Debug)]
+pub struct RebaseOpts {
+    /// New base: OSTree refspec (remote:ref) or ostree-image reference
+    pub refspec: String,
+
+    /// Stop layering packages that the new base already includes
+    #[clap(long)]
+    pub drop_absorbed: bool,
+
+    /// Rebuild AUR packages from the current AUR PKGBUILDs instead of the commits
+    /// they were last built from (review the PKGBUILD changes first)
+    #[clap(long)]
+    pub update_aur: bool,
+
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+pub async fn rebase(opts: RebaseOpts) -> Result<()> {
+    let refspec = vec![opts.refspec.clone()];
+    crate::output::progress_async(stage_rebase(&opts)).await?;
+    emit_transaction_report(&load_sysroot()?, "rebase", &refspec)?;
+    maybe_reboot(&opts.reboot)
+}
+
+async fn stage_rebase(opts: &RebaseOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+    if state.base_refspec == opts.refspec {
+        anyhow::bail!("Already on {}; use `upgrade` to update it", opts.refspec);
+    }
+
+    let new_base = crate::upgrade::pull_base(&sysroot.repo(), &opts.refspec).await?;
+    println!("Rebasing {} -> {}", state.base_refspec, opts.refspec);
+    state.base_refspec = opts.refspec.clone();
+    state.base_commit = new_base;
+    crate::layered_packages::check_layers_on_base(&sysroot.repo(), &mut state, opts.drop_absorbed)?;
+
+    let refspec = vec![opts.refspec.clone()];
+    let repo = sysroot.repo();
+    crate::history::record_transaction("rebase", &refspec, || {
+        // Pakiety z AUR są budowane od nowa, żeby linkowały się z bibliotekami nowej bazy
+        let stale_files = crate::aur::rebuild_all(&repo, &mut state, opts.update_aur)?;
+        deploy_layered_state(&sysroot, &booted, &state)?;
+        for file in stale_files {
+            let _ = std::fs::remove_file(crate::layered_packages::local_packages_dir().join(file));
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+// Restart systemu po zastage'owaniu deploymentu (--reboot / --when)
+
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveTime};
+use clap::Args;
+
+/// Jak często sprawdzamy, czy można już zrestartować
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebootWhen {
+    Now,
+    /// Brak aktywnych blokad wyłączenia (systemd-inhibit)
+    Idle,
+    /// Okno czasowe w czasie lokalnym, np. 02:00-04:00
+    Window(NaiveTime, NaiveTime),
+}
+
+impl FromStr for RebootWhen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "now" => Ok(Self::Now),
+            "idle" => Ok(Self::Idle),
+            window => {
+                let (start, end) = window
+                    .split_once('-')
+                    .ok_or_else(|| anyhow!("Expected now, idle or HH:MM-HH:MM, got {}", s))?;
+                let start = NaiveTime::parse_from_str(start, "%H:%M")?;
+                let end = NaiveTime::parse_from_str(end, "%H:%M")?;
+                Ok(Self::Window(start, end))
+            }
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RebootOpts {
+    /// Reboot after the new deployment has been staged
+    #[clap(long, short = 'r')]
+    pub reboot: bool,
+
+    /// When to reboot: now, idle (no shutdown inhibitors) or a HH:MM-HH:MM window
+    #[clap(long, default_value = "now", requires = "reboot")]
+    pub when: RebootWhen,
+}
+
+impl RebootWhen {
+    fn in_window(start: NaiveTime, end: NaiveTime, now: NaiveTime) -> bool {
+        if start <= end {
+            now >= start && now < end
+        } else {
+            // okno przez północ, np. 23:00-01:00
+            now >= start || now < end
+        }
+    }
+}
+
+/// Aktywne blokady wyłączenia (tryb "block") — restart by je zignorował
+fn shutdown_inhibitors() -> Result<Vec<String>> {
+    let output = crate::subprocess::output(
+        Command::new("systemd-inhibit").args(["--list", "--no-legend", "--no-pager", "--mode=block"]),
+    )?;
+
+    if !output.status.success() {
+        anyhow::bail!("systemd-inhibit --list failed");
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| l.contains("shutdown"))
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect())
+}
+
+fn wait_for(when: &RebootWhen) -> Result<()> {
+    loop {
+        let ready = match when {
+            RebootWhen::Now => true,
+            RebootWhen::Idle => {
+                let inhibitors = shutdown_inhibitors()?;
+                for i in &inhibitors {
+                    println!("Waiting for inhibitor: {}", i);
+                }
+                inhibitors.is_empty()
+            }
+            RebootWhen::Window(start, end) => {
+                RebootWhen::in_window(*start, *end, Local::now().time())
+            }
+        };
+        if ready {
+            return Ok(());
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Restartuje system zgodnie z opcjami; nic nie robi bez `--reboot`
+pub fn maybe_reboot(opts: &RebootOpts) -> Result<()> {
+    if !opts.reboot {
+        return Ok(());
+    }
+
+    if opts.when == RebootWhen::Now {
+        let inhibitors = shutdown_inhibitors().unwrap_or_default();
+        if !inhibitors.is_empty() {
+            return Err(anyhow!(
+                "Reboot is blocked by:\n  {}\nUse --when=idle to wait for them",
+                inhibitors.join("\n  ")
+            ));
+        }
+    }
+
+    wait_for(&opts.when)?;
+
+    println!("Rebooting...");
+    let status = crate::subprocess::status(Command::new("systemctl").arg("reboot"))?;
+    if !status.success() {
+        anyhow::bail!("systemctl reboot failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+// Przełączenie na poprzedni deployment
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use ostree_ext::{gio, ostree};
+
+use crate::layered_packages::{load_sysroot, lock_sysroot};
+use crate::reboot::RebootOpts;
+
+#[derive(Parser, Debug)]
+pub struct RollbackOpts {
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+pub fn same_deployment(a: &ostree::Deployment, b: &ostree::Deployment) -> bool {
+    a.osname() == b.osname() && a.csum() == b.csum() && a.deployserial() == b.deployserial()
+}
+
+pub fn rollback() -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let lock = lock_sysroot(&sysroot)?;
+
+    let booted = sysroot
+        .booted_deployment()
+        .ok_or_else(|| anyhow!("Not booted into an OSTree deployment"))?;
+    let (pending, rollback) = sysroot.query_deployments_for(Some(booted.osname().as_str()));
+
+    // Jeśli czeka nowy deployment, "rollback" oznacza powrót do bieżącego
+    let target = match pending {
+        Some(_) => booted.clone(),
+        None => rollback.ok_or_else(|| anyhow!("No rollback deployment found"))?,
+    };
+
+    let mut deployments = vec![target.clone()];
+    for d in sysroot.deployments() {
+        if same_deployment(&d, &target) {
+            continue;
+        }
+        if d.is_staged() {
+            println!("Discarding staged deployment {}", d.csum());
+            continue;
+        }
+        deployments.push(d);
+    }
+
+    sysroot
+        .write_deployments(&deployments, gio::Cancellable::NONE)
+        .context("Writing deployments")?;
+    drop(lock);
+
+    println!("Moving {}.{} to be first deployment", target.csum(), target.deployserial());
+    Ok(())
+}
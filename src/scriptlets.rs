@@ -0,0 +1,235 @@
+// Skrypty instalacyjne pakietów (.INSTALL) uruchamiane osobno dla każdego pakietu
+//
+// Wyjście każdego skryptu jest zbierane oddzielnie i trafia do raportu operacji
+// (`scriptlets` w JSON), a `--scriptlet-failure` decyduje, czy nieudany skrypt
+// przerywa operację, czy jest tylko ostrzeżeniem. `--no-scriptlets` pomija je całkowicie —
+// pominięte skrypty też trafiają do raportu.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+static POLICY: OnceLock<ScriptletFailure> = OnceLock::new();
+static REPORTS: Mutex<Vec<ScriptletReport>> = Mutex::new(Vec::new());
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+const LOCAL_DB_DIR: &str = "usr/share/pacman/local";
+/// Funkcje skryptu, które wywołuje pacman
+const SCRIPT_FUNCTIONS: &[&str] = &["pre_install", "post_install", "pre_upgrade", "post_upgrade", "pre_remove", "post_remove"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptletFailure {
+    /// Abort the operation
+    #[default]
+    Error,
+    /// Record a warning and continue
+    Warn,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScriptletReport {
+    pub package: String,
+    /// Funkcja skryptu, np. `post_install`
+    pub function: String,
+    pub success: bool,
+    /// Nie uruchomiony przez `--no-scriptlets`
+    pub skipped: bool,
+    /// Połączone stdout i stderr skryptu
+    pub output: String,
+}
+
+/// Ustawia politykę dla całego procesu (`--scriptlet-failure`, w compose także z manifestu)
+pub fn init(policy: Option<ScriptletFailure>) {
+    if let Some(policy) = policy {
+        let _ = POLICY.set(policy);
+    }
+}
+
+fn policy() -> ScriptletFailure {
+    POLICY.get().copied().unwrap_or_default()
+}
+
+/// Wyłącza skrypty instalacyjne dla całego procesu (`--no-scriptlets`)
+pub fn set_enabled(enabled: bool) {
+    DISABLED.store(!enabled, Ordering::Relaxed);
+}
+
+pub fn collected() -> Vec<ScriptletReport> {
+    REPORTS.lock().map(|r| r.clone()).unwrap_or_default()
+}
+
+fn record(report: ScriptletReport) {
+    if let Ok(mut reports) = REPORTS.lock() {
+        reports.push(report);
+    }
+}
+
+/// Podsumowanie skryptów z tej operacji w trybie tekstowym
+pub fn print_summary() {
+    let reports = collected();
+    if reports.is_empty() {
+        return;
+    }
+    println!("Install scriptlets:");
+    for report in &reports {
+        let result = match (report.skipped, report.success) {
+            (true, _) => "skipped",
+            (false, true) => "ok",
+            (false, false) => "failed",
+        };
+        println!("  {} {}: {}", report.package, report.function, result);
+    }
+}
+
+/// Skrypt do podmontowania w piaskownicy: plik o losowej nazwie i prawach 0600, usuwany
+/// przy drop — przewidywalna ścieżka w /tmp pozwalałaby podmienić skrypt uruchamiany jako root
+fn script_file(content: &str) -> Result<NamedTempFile> {
+    let mut file = tempfile::Builder::new().prefix(".install-").suffix(".sh").tempfile()?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Uruchamia funkcję `function` skryptu pakietu w piaskownicy na `rootfs`
+pub fn run_scriptlet(rootfs: &str, package: &str, script: &str, function: &str, args: &[&str]) -> Result<()> {
+    if DISABLED.load(Ordering::Relaxed) {
+        println!("Skipping {} of {} (--no-scriptlets)", function, package);
+        record(ScriptletReport {
+            package: package.to_string(),
+            function: function.to_string(),
+            success: true,
+            skipped: true,
+            output: String::new(),
+        });
+        return Ok(());
+    }
+    let full_script = format!("#!/bin/bash\nset -e\n\n{}\n\n{} \"$@\"\n", script, function);
+    let script_file = script_file(&full_script)?;
+
+    let mut bwrap = crate::package_installer::build_bwrap_base(rootfs)?;
+    bwrap.bind_read(&script_file.path().to_string_lossy(), "/run/script.sh");
+    bwrap.append_child_argv(["/bin/bash", "/run/script.sh"].into_iter().chain(args.iter().copied()));
+    let (success, output) = bwrap.run_output().with_context(|| format!("Running {} of {}", function, package))?;
+
+    let output = String::from_utf8_lossy(&output).into_owned();
+    for line in output.lines() {
+        println!("{}: {}", package, line);
+    }
+    record(ScriptletReport {
+        package: package.to_string(),
+        function: function.to_string(),
+        success,
+        skipped: false,
+        output,
+    });
+
+    if !success {
+        match policy() {
+            ScriptletFailure::Error => anyhow::bail!("{} of {} failed", function, package),
+            ScriptletFailure::Warn => crate::warnings::warn("scriptlet", format!("{} of {} failed", function, package)),
+        }
+    }
+    Ok(())
+}
+
+/// Skrypt instalacyjny pakietu z transakcji
+#[derive(Debug, Clone)]
+pub struct PackageScript {
+    package: String,
+    /// `install`, `upgrade` albo `remove` — przyrostek funkcji `pre_*`/`post_*`
+    stage: &'static str,
+    /// Argumenty funkcji: nowa wersja (i stara przy upgrade), przy remove usuwana
+    args: Vec<String>,
+    script: String,
+    /// Funkcje zdefiniowane przez skrypt
+    functions: BTreeSet<String>,
+}
+
+/// Funkcje, które skrypt definiuje po wczytaniu przez bash (`declare -F`) — szukanie
+/// `nazwa()` w tekście myli się przy `function nazwa {`, spacjach przed nawiasem
+/// i komentarzach. Skrypt jest wczytywany w piaskownicy na `rootfs`, jak przy uruchamianiu.
+/// Z `--no-scriptlets` nic nie jest wykonywane — wtedy wystarczy tekst, bo funkcje trafiają
+/// tylko do raportu pominiętych.
+pub(crate) fn defined_functions(rootfs: &Path, package: &str, script: &str) -> Result<BTreeSet<String>> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return Ok(SCRIPT_FUNCTIONS
+            .iter()
+            .filter(|f| script.contains(&format!("{}()", f)))
+            .map(|f| f.to_string())
+            .collect());
+    }
+    let script_file = script_file(script)?;
+    let mut bwrap = crate::package_installer::build_bwrap_base(&rootfs.to_string_lossy())?;
+    bwrap.bind_read(&script_file.path().to_string_lossy(), "/run/script.sh");
+    bwrap.append_child_argv(["/bin/bash", "-c", "source /run/script.sh >/dev/null 2>&1; declare -F"]);
+    let output = bwrap.run_captured().with_context(|| format!("Reading the install script of {}", package))?;
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .filter_map(|line| line.strip_prefix("declare -f "))
+        .map(str::to_string)
+        .collect())
+}
+
+impl PackageScript {
+    fn new(rootfs: &Path, package: &str, stage: &'static str, args: Vec<String>, script: String) -> Result<Self> {
+        let functions = defined_functions(rootfs, package, &script)?;
+        Ok(Self {
+            package: package.to_string(),
+            stage,
+            args,
+            script,
+            functions,
+        })
+    }
+
+    /// `.INSTALL` z pliku pakietu, instalowanego na `rootfs` (przy `old_version` — aktualizowanego)
+    pub fn from_archive(
+        rootfs: &Path,
+        archive: &Path,
+        package: &str,
+        version: &str,
+        old_version: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let output = crate::subprocess::output(Command::new("bsdtar").arg("-xOf").arg(archive).arg(".INSTALL"))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Ok(None);
+        }
+        let script = String::from_utf8_lossy(&output.stdout).into_owned();
+        let (stage, args) = match old_version {
+            Some(old) => ("upgrade", vec![version.to_string(), old.to_string()]),
+            None => ("install", vec![version.to_string()]),
+        };
+        Self::new(rootfs, package, stage, args, script).map(Some)
+    }
+
+    /// Skrypt usuwanego pakietu z lokalnej bazy w `rootfs`
+    pub fn from_local_db(rootfs: &Path, package: &str, version: &str) -> Result<Option<Self>> {
+        let install = rootfs.join(LOCAL_DB_DIR).join(format!("{}-{}", package, version)).join("install");
+        let Ok(script) = std::fs::read_to_string(&install) else {
+            return Ok(None);
+        };
+        Self::new(rootfs, package, "remove", vec![version.to_string()], script).map(Some)
+    }
+}
+
+/// Funkcje `pre_*` (przed rozpakowaniem albo usunięciem plików) lub `post_*` (po transakcji)
+pub fn run(rootfs: &Path, scripts: &[PackageScript], when: &str) -> Result<()> {
+    let rootfs_str = rootfs.to_string_lossy();
+    for script in scripts {
+        let function = format!("{}_{}", when, script.stage);
+        if script.functions.contains(&function) {
+            let args: Vec<&str> = script.args.iter().map(String::as_str).collect();
+            run_scriptlet(&rootfs_str, &script.package, &script.script, &function, &args)?;
+        }
+    }
+    Ok(())
+}
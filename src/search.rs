@@ -0,0 +1,120 @@
+// Wyszukiwanie w bazach sync (`search`) z informacją, czy pakiet jest już w systemie
+
+use std::collections::BTreeMap;
+use anyhow::Result;
+use clap::Parser;
+use serde::Serialize;
+use tempfile::TempDir;
+
+use crate::layered_packages::{booted_state, load_sysroot, LayeredState};
+use crate::pacman_manager::{self, SyncPackage};
+
+#[derive(Parser, Debug)]
+pub struct SearchOpts {
+    /// Search terms (regular expressions, as for `pacman -Ss`)
+    #[clap(required = true)]
+    pub terms: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallState {
+    /// Pakiet jest częścią obrazu bazowego
+    Base,
+    /// Pakiet jest nałożony przez `install`
+    Layered,
+    /// Pakiet jest w warstwie jako zależność nałożonego pakietu
+    LayeredDependency,
+    NotInstalled,
+}
+
+impl InstallState {
+    pub fn label(self) -> &'static str {
+        match self {
+            InstallState::Base => "[base]",
+            InstallState::Layered => "[layered]",
+            InstallState::LayeredDependency => "[layered dependency]",
+            InstallState::NotInstalled => "",
+        }
+    }
+}
+
+/// Skąd pakiet pochodzi w deploymencie o stanie `state`; `deployed_packages` to pakiety
+/// samego deploymentu, w których są też zależności ściągnięte przez warstwę
+pub fn install_state(
+    state: &LayeredState,
+    base_packages: &BTreeMap<String, String>,
+    deployed_packages: &BTreeMap<String, String>,
+    name: &str,
+) -> InstallState {
+    if state.layered_packages.contains(name) || state.local_packages.contains_key(name) {
+        InstallState::Layered
+    } else if base_packages.contains_key(name) && !state.overrides_remove.contains(name) {
+        InstallState::Base
+    } else if deployed_packages.contains_key(name) {
+        InstallState::LayeredDependency
+    } else {
+        InstallState::NotInstalled
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub package: SyncPackage,
+    pub state: InstallState,
+    /// Wersja w uruchomionym deploymencie, jeśli inna niż w bazie sync
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed_version: Option<String>,
+}
+
+pub fn search(opts: SearchOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+    let (booted, state) = booted_state(&sysroot)?;
+    let repo = sysroot.repo();
+    let base_packages = pacman_manager::read_packages_from_commit(&repo, &state.base_commit)?;
+    let booted_packages = pacman_manager::read_packages_from_commit(&repo, booted.csum().as_str())?;
+
+    // Bazy sync z tą samą konfiguracją, co przy rebuildzie, więc widać też dodatkowe repozytoria
+    let tmp = TempDir::new()?;
+    let pacman_conf = tmp.path().join("pacman.conf");
+    crate::layered_repos::generate_pacman_conf(&state, &pacman_conf)?;
+    crate::layered_repos::ensure_repo_keys(&state)?;
+    let found = crate::output::progress(|| pacman_manager::search_sync(&opts.terms, &pacman_conf))?;
+
+    let results: Vec<SearchResult> = found
+        .into_iter()
+        .map(|package| {
+            let install_state = install_state(&state, &base_packages, &booted_packages, &package.name);
+            let installed_version = booted_packages
+                .get(&package.name)
+                .filter(|v| **v != package.version && install_state != InstallState::NotInstalled)
+                .cloned();
+            SearchResult {
+                package,
+                state: install_state,
+                installed_version,
+            }
+        })
+        .collect();
+
+    if crate::output::json() {
+        return crate::output::emit(&results);
+    }
+    if results.is_empty() {
+        println!("No packages found");
+    }
+    for result in &results {
+        let pkg = &result.package;
+        let mut line = format!("{}/{} {} {}", pkg.repo, pkg.name, pkg.version, result.state.label());
+        if let Some(installed) = &result.installed_version {
+            line.push_str(&format!(" (installed: {})", installed));
+        }
+        println!("{}", line.trim_end());
+        if !pkg.description.is_empty() {
+            println!("    {}", pkg.description);
+        }
+    }
+    Ok(())
+}
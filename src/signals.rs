@@ -0,0 +1,51 @@
+// Przerywanie długich operacji przez SIGINT/SIGTERM bez zostawiania połowicznych wyników
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::Result;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Dopóki istnieje, SIGINT/SIGTERM tylko ustawiają flagę anulowania;
+/// po zniszczeniu przywraca poprzednie handlery
+pub struct CancelGuard {
+    previous: Vec<(Signal, SigAction)>,
+}
+
+impl CancelGuard {
+    pub fn install() -> Result<Self> {
+        CANCELLED.store(false, Ordering::SeqCst);
+        let action = SigAction::new(SigHandler::Handler(on_signal), SaFlags::SA_RESTART, SigSet::empty());
+        let mut previous = Vec::new();
+        for sig in [Signal::SIGINT, Signal::SIGTERM] {
+            // SAFETY: handler tylko zapisuje do atomika
+            let old = unsafe { signal::sigaction(sig, &action)? };
+            previous.push((sig, old));
+        }
+        Ok(CancelGuard { previous })
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        for (sig, old) in &self.previous {
+            // SAFETY: przywracamy handler, który był ustawiony wcześniej
+            let _ = unsafe { signal::sigaction(*sig, old) };
+        }
+    }
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+pub fn check_cancelled() -> Result<()> {
+    if is_cancelled() {
+        anyhow::bail!("Operation cancelled");
+    }
+    Ok(())
+}
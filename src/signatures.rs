@@ -0,0 +1,80 @@
+// Weryfikacja podpisów pakietów przy nakładaniu warstw
+//
+// Pakiety z repozytoriów muszą mieć ważny podpis niezależnie od SigLevel w pacman.conf
+// hosta. libalpm sprawdza podpisy przed rozpakowaniem, więc niezweryfikowany pakiet
+// przerywa transakcję, zanim cokolwiek trafi do commita ostree.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+const SIGNATURES_CONFIG: &str = "/etc/pacman-ostree/signatures.yaml";
+/// Keyring pacmana względem korzenia drzewa
+const KEYRING_DIR: &str = "etc/pacman.d/gnupg";
+
+static SKIP: OnceLock<bool> = OnceLock::new();
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SignatureConfig {
+    /// Keyring do weryfikacji zamiast keyringu drzewa docelowego
+    keyring: Option<PathBuf>,
+}
+
+fn load_config() -> Result<SignatureConfig> {
+    match std::fs::read_to_string(SIGNATURES_CONFIG) {
+        Ok(s) => serde_yaml::from_str(&s).with_context(|| format!("Parsing {}", SIGNATURES_CONFIG)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", SIGNATURES_CONFIG)),
+    }
+}
+
+/// `--skip-sig-check` dla całego procesu
+pub fn init(skip: bool) {
+    let _ = SKIP.set(skip);
+}
+
+pub fn skipped() -> bool {
+    SKIP.get().copied().unwrap_or(false)
+}
+
+/// Keyring do weryfikacji pakietów instalowanych w `rootfs`: z signatures.yaml,
+/// potem keyring samego drzewa, a gdy obraz go nie ma — keyring hosta
+pub fn keyring(rootfs: &Path) -> Result<PathBuf> {
+    if let Some(keyring) = load_config()?.keyring {
+        return Ok(keyring);
+    }
+    let target = rootfs.join(KEYRING_DIR);
+    if target.join("pubring.gpg").exists() {
+        return Ok(target);
+    }
+    Ok(Path::new("/").join(KEYRING_DIR))
+}
+
+/// Wymusza podpisy pakietów z repozytoriów; przy `--skip-sig-check` wyłącza weryfikację
+pub fn apply(config: &mut pacmanconf::Config, rootfs: &Path) -> Result<()> {
+    if skipped() {
+        config.sig_level = vec!["Never".to_string()];
+        config.local_file_sig_level = vec!["Never".to_string()];
+        config.remote_file_sig_level = vec!["Never".to_string()];
+        for repo in &mut config.repos {
+            repo.sig_level = vec!["Never".to_string()];
+        }
+        return Ok(());
+    }
+    config.gpg_dir = keyring(rootfs)?.to_string_lossy().into_owned();
+    // Ustawienia baz zostają, poziom dla pakietów jest zawsze `PackageRequired`.
+    // Lokalne pliki (`override replace`, `install ./x.pkg.tar.zst`) idą według LocalFileSigLevel.
+    let require_packages = |levels: &mut Vec<String>| {
+        levels.retain(|l| l.starts_with("Database"));
+        levels.push("PackageRequired".to_string());
+    };
+    require_packages(&mut config.sig_level);
+    for repo in &mut config.repos {
+        if !repo.sig_level.is_empty() {
+            require_packages(&mut repo.sig_level);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+// Gniazda, FIFO i urządzenia w rootfs — OSTree nie potrafi ich zapisać w commicie
+
+use std::fmt::Write as _;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use glob::Pattern;
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::components::MATCH_OPTIONS;
+
+/// Wpisy odtwarzające pliki specjalne przy starcie systemu
+const TMPFILES_CONF: &str = "usr/lib/tmpfiles.d/pacman-ostree-special-files.conf";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecialFilePolicy {
+    /// Przerwij build
+    Error,
+    /// Usuń i zgłoś ostrzeżenie
+    #[default]
+    Warn,
+    /// Usuń po cichu
+    Delete,
+    /// Usuń i odtwórz przez tmpfiles.d (tylko FIFO i urządzenia poza /usr)
+    Tmpfiles,
+}
+
+/// Reguła `special-files:` z manifestu; pierwsza pasująca wygrywa
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpecialFileRule {
+    pub path: String,
+    pub policy: SpecialFilePolicy,
+}
+
+/// Linia tmpfiles.d dla pliku; `None` dla typów, których tmpfiles nie tworzy (gniazda)
+fn tmpfiles_line(path: &Path, meta: &std::fs::Metadata) -> Option<String> {
+    let ft = meta.file_type();
+    let (kind, arg) = if ft.is_fifo() {
+        ('p', "-".to_string())
+    } else if ft.is_char_device() || ft.is_block_device() {
+        let dev = meta.rdev();
+        let kind = if ft.is_char_device() { 'c' } else { 'b' };
+        (kind, format!("{}:{}", nix::sys::stat::major(dev), nix::sys::stat::minor(dev)))
+    } else {
+        return None;
+    };
+    Some(format!(
+        "{} {} {:04o} {} {} - {}",
+        kind,
+        path.display(),
+        meta.mode() & 0o7777,
+        meta.uid(),
+        meta.gid(),
+        arg
+    ))
+}
+
+/// Stosuje reguły do wszystkich plików specjalnych w rootfs; bez pasującej reguły — `warn`
+pub fn clean_special_files(rootfs: &Utf8Path, rules: &[SpecialFileRule]) -> Result<()> {
+    let patterns = rules
+        .iter()
+        .map(|r| {
+            Pattern::new(&r.path)
+                .map(|p| (p, r.policy))
+                .with_context(|| format!("Invalid special-files glob {}", r.path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut tmpfiles = String::new();
+    for entry in WalkDir::new(rootfs).min_depth(1) {
+        let entry = entry?;
+        let ft = entry.file_type();
+        if !(ft.is_socket() || ft.is_fifo() || ft.is_char_device() || ft.is_block_device()) {
+            continue;
+        }
+        let path = Path::new("/").join(entry.path().strip_prefix(rootfs)?);
+        let policy = patterns
+            .iter()
+            .find(|(p, _)| p.matches_path_with(&path, MATCH_OPTIONS))
+            .map(|(_, policy)| *policy)
+            .unwrap_or_default();
+
+        match policy {
+            SpecialFilePolicy::Error => {
+                anyhow::bail!("Special file {} cannot be committed", path.display())
+            }
+            SpecialFilePolicy::Warn => {
+                crate::warnings::warn("special-file", format!("removing special file {}", path.display()))
+            }
+            SpecialFilePolicy::Delete => {}
+            SpecialFilePolicy::Tmpfiles => {
+                // /usr jest w działającym systemie tylko do odczytu
+                if path.starts_with("/usr") {
+                    anyhow::bail!("{} is under /usr and cannot be recreated by tmpfiles.d", path.display());
+                }
+                let meta = entry.metadata()?;
+                let line = tmpfiles_line(&path, &meta).ok_or_else(|| {
+                    anyhow::anyhow!("{} is a socket; tmpfiles.d cannot recreate it", path.display())
+                })?;
+                writeln!(tmpfiles, "{}", line)?;
+            }
+        }
+        std::fs::remove_file(entry.path()).with_context(|| format!("Removing {}", path.display()))?;
+    }
+
+    if !tmpfiles.is_empty() {
+        let dest = rootfs.join(TMPFILES_CONF);
+        std::fs::create_dir_all(dest.parent().unwrap())?;
+        std::fs::write(&dest, tmpfiles).with_context(|| format!("Writing {}", dest))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,334 @@
+// Stan deploymentów (`status`)
+
+use std::time::{Duration, SystemTime};
+use anyhow::Result;
+use clap::Parser;
+use console::style;
+use ostree_ext::{gio, glib, ostree};
+use serde::Serialize;
+
+use crate::db::diff_packages;
+use crate::layered_packages::{deployment_state, load_sysroot};
+use crate::pacman_manager::read_packages_from_commit;
+use crate::rollback::same_deployment;
+
+// Typy pól `DeploymentStatus` — część publicznego API razem z `query`
+pub use crate::db::PackageDiff;
+pub use crate::layered_packages::LayeredState;
+pub use crate::layered_repos::LayeredRepo;
+pub use crate::live_fs::LiveState;
+
+const STAGED_DEPLOYMENT_FILE: &str = "/run/ostree/staged-deployment";
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Parser, Debug)]
+pub struct StatusOpts {
+    /// Keep running and re-render whenever deployments change
+    #[clap(long)]
+    pub watch: bool,
+}
+
+fn commit_version(repo: &ostree::Repo, commit: &str) -> Option<String> {
+    let (commit_v, _) = repo.load_commit(commit).ok()?;
+    let meta = glib::VariantDict::new(Some(&commit_v.child_value(0)));
+    meta.lookup::<String>("version").ok().flatten()
+}
+
+fn print_deployment(
+    repo: &ostree::Repo,
+    deployment: &ostree::Deployment,
+    booted: Option<&ostree::Deployment>,
+    is_pending: bool,
+) -> Result<()> {
+    let is_booted = booted.map(|b| same_deployment(b, deployment)).unwrap_or(false);
+    let marker = if is_booted { style("●").green().to_string() } else { " ".to_string() };
+    let mut flags = Vec::new();
+    if is_booted {
+        flags.push("booted");
+    }
+    if is_pending {
+        flags.push("pending");
+    }
+    if deployment.is_staged() {
+        flags.push("staged");
+    }
+    if deployment.is_pinned() {
+        flags.push("pinned");
+    }
+
+    let state = deployment_state(repo, deployment)?;
+    let flags = if flags.is_empty() { String::new() } else { format!(" ({})", flags.join(", ")) };
+    println!(
+        "{} {}:{}.{}{}",
+        marker,
+        deployment.osname(),
+        deployment.csum(),
+        deployment.deployserial(),
+        flags,
+    );
+    println!("    Base: {} ({})", state.base_refspec, state.base_commit);
+    if let Some(version) = commit_version(repo, &state.base_commit) {
+        println!("    Version: {}", version);
+    }
+    if !state.layered_packages.is_empty() {
+        println!("    LayeredPackages: {}", state.layered_packages.iter().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.layered_groups.is_empty() {
+        println!("    LayeredGroups: {}", state.layered_groups.keys().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.local_packages.is_empty() {
+        println!("    LocalPackages: {}", state.local_packages.keys().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.downgraded.is_empty() {
+        println!(
+            "    DowngradedPackages: {}",
+            state.downgraded.iter().map(|(name, version)| format!("{}-{}", name, version)).collect::<Vec<_>>().join(" ")
+        );
+    }
+    if !state.aur_packages.is_empty() {
+        println!("    AurPackages: {}", state.aur_packages.keys().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.overrides_remove.is_empty() {
+        println!("    RemovedBasePackages: {}", state.overrides_remove.iter().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.overrides_replace.is_empty() {
+        println!("    ReplacedBasePackages: {}", state.overrides_replace.keys().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.repos.is_empty() {
+        println!("    LayeredRepos: {}", state.repos.keys().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.config_files.is_empty() {
+        println!("    LayeredFiles: {}", state.config_files.iter().map(|p| p.as_str()).collect::<Vec<_>>().join(" "));
+    }
+    if !state.enabled_units.is_empty() {
+        println!("    EnabledUnits: {}", state.enabled_units.iter().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if !state.disabled_units.is_empty() {
+        println!("    DisabledUnits: {}", state.disabled_units.iter().cloned().collect::<Vec<_>>().join(" "));
+    }
+    if let Some(foreign) = booted_foreign(repo, deployment, is_booted, &state).filter(|f| !f.is_empty()) {
+        println!("    ForeignPackages: {}", foreign.join(" "));
+    }
+    if is_booted {
+        if let Ok(Some(live)) = crate::live_fs::live_state(&deployment.csum()) {
+            println!(
+                "    LiveCommit: {} ({})",
+                live.commit,
+                live.packages.iter().cloned().collect::<Vec<_>>().join(" ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Obce pakiety uruchomionego deploymentu według baz sync z ostatniego `db list --foreign`;
+/// `status` nie odświeża baz, więc bez nich nic nie pokazuje
+fn booted_foreign(
+    repo: &ostree::Repo,
+    deployment: &ostree::Deployment,
+    is_booted: bool,
+    state: &LayeredState,
+) -> Option<Vec<String>> {
+    if !is_booted {
+        return None;
+    }
+    crate::db::foreign_in_commit(repo, &deployment.csum(), state, false)
+        .ok()
+        .flatten()
+        .map(|f| f.into_iter().collect())
+}
+
+/// Deployment w wyjściu `status --json` i w [`SystemStatus`]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeploymentStatus {
+    pub osname: String,
+    pub checksum: String,
+    pub serial: i32,
+    pub booted: bool,
+    pub pending: bool,
+    pub staged: bool,
+    pub pinned: bool,
+    pub version: Option<String>,
+    #[serde(flatten)]
+    pub state: LayeredState,
+    pub live: Option<LiveState>,
+    /// Zmiany pakietów względem uruchomionego deploymentu (tylko dla oczekującego)
+    pub package_diff: Option<PackageDiff>,
+    /// Pakiety spoza skonfigurowanych repozytoriów (tylko dla uruchomionego)
+    pub foreign_packages: Option<Vec<String>>,
+}
+
+/// Stan integralności uruchomionego deploymentu
+#[derive(Debug, Serialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum Health {
+    Ok { integrity: String },
+    /// Niezgodny digest, zły podpis albo uszkodzone obiekty
+    Untrusted { integrity: String },
+    /// Nie udało się sprawdzić (np. system nie jest uruchomiony z ostree)
+    Unknown { error: String },
+}
+
+/// Stan systemu do odczytu z biblioteki, bez uruchamiania CLI
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SystemStatus {
+    pub deployments: Vec<DeploymentStatus>,
+    /// Oczekujący deployment, jeśli następny restart zmieni system
+    pub pending_update: Option<DeploymentStatus>,
+    pub health: Health,
+}
+
+/// Różnica pakietów między uruchomionym a oczekującym deploymentem
+fn pending_diff(repo: &ostree::Repo, booted: &ostree::Deployment, pending: &ostree::Deployment) -> Result<PackageDiff> {
+    Ok(diff_packages(
+        &read_packages_from_commit(repo, &booted.csum())?,
+        &read_packages_from_commit(repo, &pending.csum())?,
+    ))
+}
+
+pub fn print_package_diff(diff: &PackageDiff) {
+    if diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty() {
+        println!("    PackageDiff: no package changes");
+        return;
+    }
+    println!("    PackageDiff:");
+    for (name, version) in &diff.added {
+        println!("      + {} {}", name, version);
+    }
+    for (name, version) in &diff.removed {
+        println!("      - {} {}", name, version);
+    }
+    for (name, old, new) in &diff.changed {
+        println!("      ~ {} {} -> {}", name, old, new);
+    }
+}
+
+fn deployment_status(
+    repo: &ostree::Repo,
+    deployment: &ostree::Deployment,
+    booted: Option<&ostree::Deployment>,
+    is_pending: bool,
+) -> Result<DeploymentStatus> {
+    let is_booted = booted.map(|b| same_deployment(b, deployment)).unwrap_or(false);
+    let state = deployment_state(repo, deployment)?;
+    let package_diff = match booted {
+        Some(booted) if is_pending => Some(pending_diff(repo, booted, deployment)?),
+        _ => None,
+    };
+    Ok(DeploymentStatus {
+        osname: deployment.osname().to_string(),
+        checksum: deployment.csum().to_string(),
+        serial: deployment.deployserial(),
+        booted: is_booted,
+        pending: is_pending,
+        staged: deployment.is_staged(),
+        pinned: deployment.is_pinned(),
+        version: commit_version(repo, &state.base_commit),
+        package_diff,
+        foreign_packages: booted_foreign(repo, deployment, is_booted, &state),
+        live: if is_booted { crate::live_fs::live_state(&deployment.csum())? } else { None },
+        state,
+    })
+}
+
+/// Deployment, który wystartuje przy następnym restarcie (staged lub wdrożony bez stage'owania)
+fn pending_deployment(sysroot: &ostree::Sysroot) -> Option<ostree::Deployment> {
+    sysroot.query_deployments_for(None).0
+}
+
+/// Typowany stan deploymentów, oczekującej aktualizacji i integralności.
+/// Integralność sprawdzana jest bez fsck, więc zapytanie jest tanie.
+pub fn query(sysroot: &ostree::Sysroot) -> Result<SystemStatus> {
+    let repo = sysroot.repo();
+    let booted = sysroot.booted_deployment();
+    let pending = pending_deployment(sysroot);
+    let is_pending = |d: &ostree::Deployment| pending.as_ref().is_some_and(|p| same_deployment(p, d));
+
+    let deployments = sysroot
+        .deployments()
+        .iter()
+        .map(|d| deployment_status(&repo, d, booted.as_ref(), is_pending(d)))
+        .collect::<Result<Vec<_>>>()?;
+    let pending_update = match (&pending, &booted) {
+        (Some(p), Some(b)) if !same_deployment(p, b) => Some(deployment_status(&repo, p, Some(b), true)?),
+        _ => None,
+    };
+    let health = match crate::integrity::booted_integrity(sysroot, false) {
+        Ok(report) if report.is_trusted() => Health::Ok { integrity: report.summary() },
+        Ok(report) => Health::Untrusted { integrity: report.summary() },
+        Err(e) => Health::Unknown { error: e.to_string() },
+    };
+    Ok(SystemStatus {
+        deployments,
+        pending_update,
+        health,
+    })
+}
+
+fn print_status(sysroot: &ostree::Sysroot) -> Result<()> {
+    if crate::output::json() {
+        return crate::output::emit(&query(sysroot)?);
+    }
+
+    let repo = sysroot.repo();
+    let booted = sysroot.booted_deployment();
+    let pending = pending_deployment(sysroot);
+    let is_pending = |d: &ostree::Deployment| pending.as_ref().is_some_and(|p| same_deployment(p, d));
+
+    println!("Deployments:");
+    for deployment in sysroot.deployments() {
+        print_deployment(&repo, &deployment, booted.as_ref(), is_pending(&deployment))?;
+        if let Some(booted) = booted.as_ref().filter(|_| is_pending(&deployment)) {
+            match pending_diff(&repo, booted, &deployment) {
+                Ok(diff) => print_package_diff(&diff),
+                Err(e) => println!("    PackageDiff: unknown ({})", e),
+            }
+        }
+        if booted.as_ref().map(|b| same_deployment(b, &deployment)).unwrap_or(false) {
+            match crate::integrity::booted_integrity(sysroot, false) {
+                Ok(report) => println!("    Integrity: {}", report.summary()),
+                Err(e) => println!("    Integrity: unknown ({})", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn staged_mtime() -> Option<SystemTime> {
+    std::fs::metadata(STAGED_DEPLOYMENT_FILE).and_then(|m| m.modified()).ok()
+}
+
+pub fn handle_status(opts: StatusOpts) -> Result<()> {
+    let sysroot = load_sysroot()?;
+
+    if !opts.watch {
+        return print_status(&sysroot);
+    }
+
+    let term = console::Term::stdout();
+    let mut last_staged = staged_mtime();
+    loop {
+        // W trybie JSON każda zmiana to kolejny dokument, bez czyszczenia ekranu
+        if !crate::output::json() {
+            term.clear_screen()?;
+        }
+        print_status(&sysroot)?;
+
+        // Czekamy, aż zmieni się lista deploymentów lub plik staged deploymentu
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let staged = staged_mtime();
+            let changed = sysroot.load_if_changed(gio::Cancellable::NONE)?;
+            if changed || staged != last_staged {
+                if staged != last_staged {
+                    // Staged deployment nie zmienia mtime /ostree/deploy
+                    sysroot.load(gio::Cancellable::NONE)?;
+                }
+                last_staged = staged;
+                break;
+            }
+        }
+    }
+}
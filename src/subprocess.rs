@@ -0,0 +1,275 @@
+// Limity czasu dla zewnętrznych poleceń (pacman, dracut, ostree, skopeo...)
+//
+// Zawieszony mirror albo dracut nie może blokować automatyzacji w nieskończoność:
+// polecenie po przekroczeniu limitu dostaje SIGTERM, a po chwili SIGKILL. Wyjście
+// dziecka idzie na bieżąco na terminal, a przy długiej ciszy wypisujemy, na co czekamy.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use ostree_ext::{gio, glib};
+use ostree_ext::prelude::*;
+use serde::Deserialize;
+
+/// Limity per program; `--command-timeout` zastępuje wartość domyślną
+const TIMEOUTS_CONFIG: &str = "/etc/pacman-ostree/timeouts.yaml";
+/// Limit, gdy konfiguracja nie podaje innego
+const DEFAULT_TIMEOUT_SECS: u64 = 2 * 60 * 60;
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Co ile przypominamy, że polecenie nadal działa
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// Czas między SIGTERM a SIGKILL
+const KILL_GRACE: Duration = Duration::from_secs(10);
+
+static CONFIG: OnceLock<TimeoutConfig> = OnceLock::new();
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TimeoutConfig {
+    /// Limit w sekundach dla poleceń bez własnego wpisu (0 = bez limitu)
+    pub default: Option<u64>,
+    /// Nazwa programu -> limit w sekundach (0 = bez limitu)
+    #[serde(default)]
+    pub commands: BTreeMap<String, u64>,
+}
+
+impl TimeoutConfig {
+    fn load(default: Option<u64>) -> Result<Self> {
+        let mut config: TimeoutConfig = match std::fs::read_to_string(TIMEOUTS_CONFIG) {
+            Ok(s) => serde_yaml::from_str(&s).with_context(|| format!("Parsing {}", TIMEOUTS_CONFIG))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e).with_context(|| format!("Reading {}", TIMEOUTS_CONFIG)),
+        };
+        if default.is_some() {
+            config.default = default;
+        }
+        Ok(config)
+    }
+
+    fn limit_for(&self, program: &str) -> Option<Duration> {
+        let secs = self
+            .commands
+            .get(program)
+            .copied()
+            .or(self.default)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+/// Wczytuje limity (plik i `--command-timeout`)
+pub fn init(default: Option<u64>) -> Result<()> {
+    let _ = CONFIG.set(TimeoutConfig::load(default)?);
+    Ok(())
+}
+
+fn config() -> &'static TimeoutConfig {
+    CONFIG.get_or_init(Default::default)
+}
+
+/// Limit czasu dla programu (`None` = bez limitu)
+pub fn limit(program: &str) -> Option<Duration> {
+    config().limit_for(program)
+}
+
+/// Czy limit dla programu ustawiono jawnie (plik albo `--command-timeout`)
+pub fn configured(program: &str) -> bool {
+    config().default.is_some() || config().commands.contains_key(program)
+}
+
+fn program_name(cmd: &Command) -> String {
+    let program = std::path::Path::new(cmd.get_program());
+    program
+        .file_name()
+        .unwrap_or(program.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn terminate(child: &mut Child) {
+    let _ = kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM);
+    let deadline = Instant::now() + KILL_GRACE;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Czeka na zakończenie dziecka, pilnując limitu czasu i anulowania
+fn wait(mut child: Child, program: &str) -> Result<ExitStatus> {
+    let limit = config().limit_for(program);
+    let started = Instant::now();
+    let mut last_heartbeat = started;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        let elapsed = started.elapsed();
+        if let Some(limit) = limit.filter(|l| elapsed >= *l) {
+            terminate(&mut child);
+            anyhow::bail!("{} timed out after {}s", program, limit.as_secs());
+        }
+        if crate::signals::is_cancelled() {
+            terminate(&mut child);
+            anyhow::bail!("Operation cancelled");
+        }
+        if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+            eprintln!("Still running {} ({}s)...", program, elapsed.as_secs());
+            last_heartbeat = Instant::now();
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Odpowiednik `Command::status` z limitem czasu
+pub fn status(cmd: &mut Command) -> Result<ExitStatus> {
+    let program = program_name(cmd);
+    let child = cmd.spawn().with_context(|| format!("Failed to run {}", program))?;
+    wait(child, &program)
+}
+
+/// Jak [`status`], z `input` na stdin dziecka. Dziecko, które nie czyta wejścia do końca
+/// (BrokenPipe), nie jest błędem — liczy się jego kod wyjścia.
+pub fn status_with_input(cmd: &mut Command, input: &[u8]) -> Result<ExitStatus> {
+    let program = program_name(cmd);
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    // Zapis w osobnym wątku, żeby dziecko z pełnym potokiem nie zablokowało limitu czasu
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_vec();
+        std::thread::spawn(move || match stdin.write_all(&input) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+            _ => Ok(()),
+        })
+    });
+    let status = wait(child, &program)?;
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Writing to {} panicked", program))?
+            .with_context(|| format!("Writing to {}", program))?;
+    }
+    Ok(status)
+}
+
+/// Odpowiednik `Command::output` z limitem czasu; strumienie są czytane w osobnych
+/// wątkach, żeby dziecko nie zablokowało się na pełnym potoku
+pub fn output(cmd: &mut Command) -> Result<Output> {
+    let program = program_name(cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    let readers: Vec<_> = [
+        child.stdout.take().map(|s| Box::new(s) as Box<dyn Read + Send>),
+        child.stderr.take().map(|s| Box::new(s) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .map(|stream| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut stream) = stream {
+                let _ = stream.read_to_end(&mut buf);
+            }
+            buf
+        })
+    })
+    .collect();
+
+    let status = wait(child, &program)?;
+    let mut streams = readers.into_iter().map(|r| r.join().unwrap_or_default());
+    Ok(Output {
+        status,
+        stdout: streams.next().unwrap_or_default(),
+        stderr: streams.next().unwrap_or_default(),
+    })
+}
+
+/// Limit czasu dla procesów uruchamianych przez gio (bwrap): [`Watchdog::cancellable`]
+/// jest anulowany po przekroczeniu limitu albo przy przerwaniu operacji
+pub struct Watchdog {
+    program: String,
+    cancellable: gio::Cancellable,
+    done: Arc<AtomicBool>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn new(program: &str) -> Self {
+        let cancellable = gio::Cancellable::new();
+        let done = Arc::new(AtomicBool::new(false));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let limit = config().limit_for(program);
+        {
+            let cancellable = cancellable.clone();
+            let done = Arc::clone(&done);
+            let timed_out = Arc::clone(&timed_out);
+            let program = program.to_string();
+            let started = Instant::now();
+            std::thread::spawn(move || {
+                let mut last_heartbeat = started;
+                while !done.load(Ordering::Relaxed) {
+                    if limit.is_some_and(|l| started.elapsed() >= l) {
+                        timed_out.store(true, Ordering::Relaxed);
+                        cancellable.cancel();
+                        return;
+                    }
+                    if crate::signals::is_cancelled() {
+                        cancellable.cancel();
+                        return;
+                    }
+                    if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                        eprintln!("Still running {} ({}s)...", program, started.elapsed().as_secs());
+                        last_heartbeat = Instant::now();
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            });
+        }
+        Self {
+            program: program.to_string(),
+            cancellable,
+            done,
+            timed_out,
+        }
+    }
+
+    pub fn cancellable(&self) -> &gio::Cancellable {
+        &self.cancellable
+    }
+
+    /// Wynik oczekiwania na `child`; po anulowaniu dziecko jest zabijane, a błąd mówi dlaczego
+    pub fn check<T>(&self, child: &gio::Subprocess, result: Result<T, glib::Error>) -> Result<T> {
+        if !self.cancellable.is_cancelled() {
+            return Ok(result?);
+        }
+        child.force_exit();
+        let _ = child.wait(gio::Cancellable::NONE);
+        if self.timed_out.load(Ordering::Relaxed) {
+            let secs = config().limit_for(&self.program).map(|l| l.as_secs()).unwrap_or_default();
+            anyhow::bail!("{} timed out after {}s", self.program, secs);
+        }
+        anyhow::bail!("Operation cancelled")
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,176 @@
+// Użytkownicy i grupy zakładani przez skrypty instalacyjne, odtwarzani przez sysusers.d
+//
+// Skrypt z `useradd`/`groupadd` zmienia /etc/passwd obrazu, ale przy deploymencie wygrywa
+// /etc/passwd administratora i nowego użytkownika po prostu nie ma. Dla każdego takiego
+// wywołania zapisujemy wpis sysusers.d z identyfikatorami, które dostał w obrazie, żeby
+// systemd-sysusers założył go przy starcie. Użytkownicy z plików sysusers.d pakietów są pomijani.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+
+pub const SYSUSERS_DIR: &str = "usr/lib/sysusers.d";
+pub const SYSUSERS_CONF: &str = "pacman-ostree-users.conf";
+const LOCAL_DB_DIR: &str = "usr/share/pacman/local";
+
+/// Konto wspomniane w skrypcie pakietu
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Account {
+    User(String),
+    Group(String),
+}
+
+/// Nazwa z wywołania `useradd`/`groupadd` w linii skryptu: ostatni argument polecenia,
+/// przed przekierowaniem albo kolejnym poleceniem. `Err` z treścią, gdy nazwa jest dynamiczna.
+fn command_name(line: &str, command: &str) -> Option<Result<String, String>> {
+    let start = line.find(command)?;
+    let before = line[..start].chars().next_back();
+    if before.is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let rest = &line[start + command.len()..];
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let end = rest.find([';', '&', '|', '>', '<', ')']).unwrap_or(rest.len());
+    let name = rest[..end].split_whitespace().last()?.trim_matches(['"', '\'']);
+    if name.starts_with('-') {
+        return None;
+    }
+    if name.contains(['$', '`']) {
+        return Some(Err(line.trim().to_string()));
+    }
+    Some(Ok(name.to_string()))
+}
+
+/// Konta zakładane przez skrypt; wywołania z nazwą ze zmiennej trafiają do `dynamic`
+fn script_accounts(script: &str, dynamic: &mut Vec<String>) -> BTreeSet<Account> {
+    let mut accounts = BTreeSet::new();
+    for line in script.lines().map(str::trim).filter(|l| !l.starts_with('#')) {
+        for (command, user) in [("useradd", true), ("groupadd", false)] {
+            match command_name(line, command) {
+                Some(Ok(name)) if user => {
+                    accounts.insert(Account::User(name));
+                }
+                Some(Ok(name)) => {
+                    accounts.insert(Account::Group(name));
+                }
+                Some(Err(line)) => dynamic.push(line),
+                None => {}
+            }
+        }
+    }
+    accounts
+}
+
+/// Konta zadeklarowane w plikach sysusers.d obrazu (poza naszym)
+fn declared_accounts(rootfs: &Utf8Path) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    let dir = rootfs.join(SYSUSERS_DIR);
+    if !dir.exists() {
+        return Ok(names);
+    }
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        if entry.file_name() == SYSUSERS_CONF || !entry.file_name().ends_with(".conf") {
+            continue;
+        }
+        let contents = std::fs::read_to_string(entry.path())?;
+        for line in contents.lines().filter(|l| !l.trim_start().starts_with('#')) {
+            let mut fields = line.split_whitespace();
+            if let (Some("u" | "u!" | "g"), Some(name)) = (fields.next(), fields.next()) {
+                names.insert(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Wpisy passwd/group obrazu: nazwa -> pola
+fn read_db(rootfs: &Utf8Path, file: &str) -> BTreeMap<String, Vec<String>> {
+    std::fs::read_to_string(rootfs.join("etc").join(file))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<String> = line.split(':').map(str::to_string).collect();
+            Some((fields.first()?.clone(), fields))
+        })
+        .collect()
+}
+
+/// Zapisuje wpisy sysusers.d dla kont ze skryptów pakietów; wywoływane po instalacji,
+/// póki /etc obrazu leży jeszcze w /etc
+pub fn generate_sysusers(rootfs: &Utf8Path) -> Result<()> {
+    let local_db = rootfs.join(LOCAL_DB_DIR);
+    if !local_db.exists() {
+        return Ok(());
+    }
+    let mut accounts = BTreeMap::new();
+    for entry in local_db.read_dir_utf8()? {
+        let entry = entry?;
+        let Ok(script) = std::fs::read_to_string(entry.path().join("install")) else {
+            continue;
+        };
+        let mut dynamic = Vec::new();
+        for account in script_accounts(&script, &mut dynamic) {
+            accounts.entry(account).or_insert_with(|| entry.file_name().to_string());
+        }
+        for line in dynamic {
+            crate::warnings::warn(
+                "dynamic-user",
+                format!("{}: cannot tell which account `{}` creates; add a sysusers.d entry for it", entry.file_name(), line),
+            );
+        }
+    }
+
+    let declared = declared_accounts(rootfs)?;
+    let passwd = read_db(rootfs, "passwd");
+    let group = read_db(rootfs, "group");
+    let gid_names: BTreeMap<&str, &str> = group
+        .values()
+        .filter_map(|g| Some((g.get(2)?.as_str(), g.first()?.as_str())))
+        .collect();
+
+    // Grupy przed użytkownikami, którzy się do nich odwołują
+    let mut groups = String::new();
+    let mut users = String::new();
+    let mut implied_groups = BTreeSet::new();
+    let mut count = 0usize;
+    for (account, package) in &accounts {
+        match account {
+            Account::Group(name) if !declared.contains(name) => match group.get(name).and_then(|g| g.get(2)) {
+                Some(gid) => writeln!(groups, "g {} {}", name, gid)?,
+                None => {
+                    crate::warnings::warn("dynamic-user", format!("group {} from {} is not in /etc/group of the image", name, package));
+                    continue;
+                }
+            },
+            Account::User(name) if !declared.contains(name) => {
+                let Some(fields) = passwd.get(name).filter(|f| f.len() >= 7) else {
+                    crate::warnings::warn("dynamic-user", format!("user {} from {} is not in /etc/passwd of the image", name, package));
+                    continue;
+                };
+                let primary = gid_names.get(fields[3].as_str()).copied().unwrap_or(fields[3].as_str());
+                let explicit = accounts.contains_key(&Account::Group(primary.to_string()));
+                if !declared.contains(primary) && !explicit && implied_groups.insert(primary) {
+                    writeln!(groups, "g {} {}", primary, fields[3])?;
+                }
+                let gecos = if fields[4].is_empty() { "-".to_string() } else { format!("\"{}\"", fields[4]) };
+                writeln!(users, "u {} {}:{} {} {} {}", name, fields[2], primary, gecos, fields[5], fields[6])?;
+            }
+            _ => continue,
+        }
+        count += 1;
+    }
+
+    let dest = rootfs.join(SYSUSERS_DIR).join(SYSUSERS_CONF);
+    if groups.is_empty() && users.is_empty() {
+        let _ = std::fs::remove_file(&dest);
+        return Ok(());
+    }
+    std::fs::create_dir_all(rootfs.join(SYSUSERS_DIR))?;
+    std::fs::write(&dest, groups + &users).with_context(|| format!("Writing {}", dest))?;
+    println!("Generated sysusers.d entries for {} account(s) created by install scripts", count);
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// Czas trwania poszczególnych etapów compose/install (`--timings`)
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+static STAGES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StageTiming {
+    pub stage: &'static str,
+    pub seconds: f64,
+}
+
+/// Mierzy etap od utworzenia do zniszczenia strażnika
+pub struct StageGuard {
+    stage: &'static str,
+    started: Instant,
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        if let Ok(mut stages) = STAGES.lock() {
+            stages.push((self.stage, elapsed));
+        }
+    }
+}
+
+pub fn stage(stage: &'static str) -> StageGuard {
+    StageGuard {
+        stage,
+        started: Instant::now(),
+    }
+}
+
+/// Zebrane etapy; powtórzenia tego samego etapu są sumowane, kolejność pierwszego wystąpienia
+pub fn report() -> Vec<StageTiming> {
+    let stages = STAGES.lock().map(|s| s.clone()).unwrap_or_default();
+    let mut report: Vec<StageTiming> = Vec::new();
+    for (stage, elapsed) in stages {
+        match report.iter_mut().find(|t| t.stage == stage) {
+            Some(t) => t.seconds += elapsed.as_secs_f64(),
+            None => report.push(StageTiming {
+                stage,
+                seconds: elapsed.as_secs_f64(),
+            }),
+        }
+    }
+    report
+}
+
+pub fn print_report() {
+    let report = report();
+    if report.is_empty() {
+        return;
+    }
+    let total: f64 = report.iter().map(|t| t.seconds).sum();
+    println!("Timings:");
+    for t in &report {
+        println!("  {:<12} {:>8.1}s {:>5.1}%", t.stage, t.seconds, t.seconds / total * 100.0);
+    }
+    println!("  {:<12} {:>8.1}s", "total", total);
+}
@@ -0,0 +1,509 @@
+// Aktualizacja bazy deploymentu z ponownym nałożeniem warstw (`upgrade`)
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IsTerminal, Write};
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::containers_image_proxy;
+use ostree_ext::{gio, glib, ostree};
+use serde::Serialize;
+
+use crate::advisories::SecurityFix;
+use crate::compose::PACKAGES_META_KEY;
+use crate::db::{diff_packages, PackageDiff};
+use crate::layered_packages::{booted_state, deploy_layered_state, load_sysroot, pending_state};
+use crate::pacman_manager::read_packages_from_commit;
+use crate::reboot::{maybe_reboot, RebootOpts};
+use crate::status::print_package_diff;
+
+#[derive(Parser, Debug)]
+pub struct UpgradeOpts {
+    /// Only check whether a newer base is available, without downloading it
+    /// (exit code 77 when there is none)
+    #[clap(long)]
+    pub check: bool,
+
+    /// Do not estimate the download size or ask for confirmation before downloading
+    #[clap(long)]
+    pub skip_size_check: bool,
+
+    /// Stop layering packages that the new base already includes
+    #[clap(long)]
+    pub drop_absorbed: bool,
+
+    /// Rebuild AUR packages from the current AUR PKGBUILDs instead of the commits
+    /// they were last built from (review the PKGBUILD changes first)
+    #[clap(long)]
+    pub update_aur: bool,
+
+    #[clap(flatten)]
+    pub reboot: RebootOpts,
+}
+
+/// Kod wyjścia `upgrade --check`/`check-update`, gdy nowszej bazy nie ma
+pub const EXIT_NO_UPDATES: i32 = 77;
+
+/// Błąd zwracany przez sprawdzenie, gdy baza jest aktualna; `main` zamienia go na EXIT_NO_UPDATES
+#[derive(Debug)]
+pub struct NoUpdates;
+
+impl std::fmt::Display for NoUpdates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No update available")
+    }
+}
+
+impl std::error::Error for NoUpdates {}
+
+/// Wynik sprawdzenia bazy: obecna i najnowsza wersja (digest obrazu lub commit)
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateCheck {
+    pub current: String,
+    pub latest: String,
+    /// `version` najnowszej bazy
+    pub version: Option<String>,
+    /// Pakiety najnowszej bazy, jeśli obraz je publikuje (PACKAGES_META_KEY)
+    #[serde(skip)]
+    pub packages: Option<BTreeMap<String, String>>,
+    /// Zmiany pakietów względem bazy uruchomionego deploymentu
+    pub package_diff: Option<PackageDiff>,
+    /// Podatności (AVG) usuwane przez aktualizację; `None`, gdy nie da się tego sprawdzić
+    pub security_fixes: Option<Vec<SecurityFix>>,
+}
+
+impl UpdateCheck {
+    pub fn available(&self) -> bool {
+        self.current != self.latest
+    }
+}
+
+fn parse_packages(json: Option<&str>) -> Option<BTreeMap<String, String>> {
+    serde_json::from_str(json?).ok()
+}
+
+/// Digest manifestu w rejestrze i etykiety z konfiguracji obrazu — bez warstw
+async fn registry_latest(imgref: &OstreeImageReference) -> Result<(String, HashMap<String, String>)> {
+    crate::network::prepare_container_pull()?;
+    let proxy = containers_image_proxy::ImageProxy::new().await?;
+    let img = proxy
+        .open_image(&imgref.imgref.to_string())
+        .await
+        .with_context(|| format!("Opening {}", imgref.imgref))?;
+    let (digest, _) = proxy.fetch_manifest(&img).await?;
+    let config = proxy.fetch_config(&img).await?;
+    proxy.close_image(&img).await?;
+    let labels = config
+        .config()
+        .as_ref()
+        .and_then(|c| c.labels().clone())
+        .unwrap_or_default();
+    Ok((digest.to_string(), labels))
+}
+
+/// Commit refa na zdalnym — pobiera tylko summary
+fn remote_commit(repo: &ostree::Repo, refspec: &str) -> Result<String> {
+    let (remote, branch) = ostree::parse_refspec(refspec)?;
+    let remote = remote.ok_or_else(|| anyhow!("Base {} has no remote to check", refspec))?;
+    let refs = repo
+        .remote_list_refs(&remote, gio::Cancellable::NONE)
+        .with_context(|| format!("Listing refs of remote {}", remote))?;
+    refs.get(branch.as_str())
+        .map(|c| c.to_string())
+        .ok_or_else(|| anyhow!("Ref {} not found on remote {}", branch, remote))
+}
+
+/// Metadane commita ze zdalnego: pobiera sam obiekt commita, po sumie, żeby nie
+/// przestawić lokalnego refa na commit bez drzewa
+fn remote_commit_meta(repo: &ostree::Repo, refspec: &str, commit: &str) -> Result<glib::VariantDict> {
+    let (remote, _) = ostree::parse_refspec(refspec)?;
+    let remote = remote.ok_or_else(|| anyhow!("Base {} has no remote to check", refspec))?;
+    let options = glib::VariantDict::new(None);
+    options.insert_value("refs", &glib::Variant::from(vec![commit]));
+    options.insert("flags", ostree::RepoPullFlags::COMMIT_ONLY.bits() as i32);
+    repo.pull_with_options(&remote, &options.end(), None, gio::Cancellable::NONE)
+        .with_context(|| format!("Fetching commit {} from {}", commit, remote))?;
+    let (commit_v, _) = repo.load_commit(commit)?;
+    Ok(glib::VariantDict::new(Some(&commit_v.child_value(0))))
+}
+
+/// Porównuje bazę uruchomionego deploymentu z tym, co jest dostępne zdalnie
+pub async fn check_for_update() -> Result<UpdateCheck> {
+    let sysroot = load_sysroot()?;
+    let (_, state) = booted_state(&sysroot)?;
+    let repo = sysroot.repo();
+
+    let mut check = if state.base_refspec.starts_with("ostree-") {
+        let imgref: OstreeImageReference = state.base_refspec.parse()?;
+        // Digest obrazu, z którego pochodzi uruchomiona baza, a nie ostatnio pobranego
+        let image = ostree_ext::container::store::query_image_commit(&repo, &state.base_commit)
+            .with_context(|| format!("Querying image state of {}", state.base_commit))?;
+        let (latest, labels) = registry_latest(&imgref).await?;
+        UpdateCheck {
+            current: image.manifest_digest.to_string(),
+            latest,
+            version: labels
+                .get("org.opencontainers.image.version")
+                .or_else(|| labels.get("version"))
+                .cloned(),
+            packages: parse_packages(labels.get(PACKAGES_META_KEY).map(String::as_str)),
+            package_diff: None,
+            security_fixes: None,
+        }
+    } else {
+        let latest = remote_commit(&repo, &state.base_refspec)?;
+        let (version, packages) = if latest != state.base_commit {
+            let meta = remote_commit_meta(&repo, &state.base_refspec, &latest)?;
+            let packages = meta.lookup::<String>(PACKAGES_META_KEY)?;
+            (meta.lookup::<String>("version")?, parse_packages(packages.as_deref()))
+        } else {
+            (None, None)
+        };
+        UpdateCheck {
+            current: state.base_commit.clone(),
+            latest,
+            version,
+            packages,
+            package_diff: None,
+            security_fixes: None,
+        }
+    };
+    if let Some(packages) = &check.packages {
+        check.package_diff = Some(diff_packages(&read_packages_from_commit(&repo, &state.base_commit)?, packages));
+    }
+    Ok(check)
+}
+
+/// `upgrade --check` i `check-update`: nic nie pobiera poza metadanymi; przy braku
+/// aktualizacji kończy się błędem NoUpdates
+pub async fn check() -> Result<()> {
+    crate::network::ensure_online("Checking for updates")?;
+    let mut check = check_for_update().await?;
+    if check.available() {
+        check.security_fixes = security_fixes(&check)?;
+    }
+    if crate::output::json() {
+        crate::output::emit(&check)?;
+    } else if check.available() {
+        println!("Update available: {} -> {}", check.current, check.latest);
+        if let Some(version) = &check.version {
+            println!("    Version: {}", version);
+        }
+        match &check.package_diff {
+            Some(diff) => print_package_diff(diff),
+            None => println!("    PackageDiff: unknown (the new base does not list its packages)"),
+        }
+        match &check.security_fixes {
+            Some(fixes) if fixes.is_empty() => println!("    SecurityFixes: none"),
+            Some(fixes) => {
+                println!("    SecurityFixes: {}", fixes.len());
+                for fix in fixes {
+                    println!(
+                        "      {:?} {} {} {} -> {}: {}",
+                        fix.severity, fix.advisory, fix.package, fix.version, fix.new_version, fix.cves
+                    );
+                }
+            }
+            None => println!("    SecurityFixes: unknown"),
+        }
+    } else {
+        println!("Base is up to date ({})", check.current);
+    }
+    if !check.available() {
+        return Err(NoUpdates.into());
+    }
+    Ok(())
+}
+
+/// Podatności pakietów bazy, które usuwa aktualizacja; bez listy pakietów nowej bazy
+/// albo przy nieudanym pobraniu biuletynów `None` (sprawdzenie aktualizacji i tak się udaje)
+fn security_fixes(check: &UpdateCheck) -> Result<Option<Vec<SecurityFix>>> {
+    let Some(packages) = &check.packages else {
+        return Ok(None);
+    };
+    let groups = match crate::advisories::fetch_advisories() {
+        Ok(groups) => groups,
+        Err(e) => {
+            eprintln!("Warning: {:#}", e);
+            return Ok(None);
+        }
+    };
+    let sysroot = load_sysroot()?;
+    let (_, state) = booted_state(&sysroot)?;
+    let current = read_packages_from_commit(&sysroot.repo(), &state.base_commit)?;
+    Ok(Some(crate::advisories::fixed_by(&groups, &current, packages)))
+}
+
+/// Szacunek tego, ile trzeba pobrać, żeby zaktualizować bazę
+pub struct DownloadEstimate {
+    pub bytes: u64,
+    /// Skąd wiadomo (np. "3 of 40 layers")
+    pub detail: String,
+}
+
+/// Warstwy obrazu, których jeszcze nie ma w repo — pobiera tylko manifest i konfigurację
+async fn image_estimate(repo: &ostree::Repo, imgref: &OstreeImageReference) -> Result<DownloadEstimate> {
+    use ostree_ext::container::store::{ImageImporter, PrepareResult};
+
+    crate::network::prepare_container_pull()?;
+    let mut importer = ImageImporter::new(repo, imgref, Default::default()).await?;
+    match importer.prepare().await? {
+        PrepareResult::AlreadyPresent(_) => Ok(DownloadEstimate {
+            bytes: 0,
+            detail: "image already downloaded".to_string(),
+        }),
+        PrepareResult::Ready(prep) => {
+            let layers: Vec<_> = prep.all_layers().collect();
+            let missing: Vec<_> = layers.iter().filter(|l| l.commit.is_none()).collect();
+            Ok(DownloadEstimate {
+                bytes: missing.iter().map(|l| l.layer().size()).sum(),
+                detail: format!("{} of {} layers", missing.len(), layers.len()),
+            })
+        }
+    }
+}
+
+/// Rozmiar statycznej delty do nowego commita (pull `dry-run` czyta tylko superblok);
+/// `None`, gdy zdalne nie publikuje delty
+fn delta_estimate(repo: &ostree::Repo, refspec: &str) -> Result<Option<DownloadEstimate>> {
+    let (remote, branch) = ostree::parse_refspec(refspec)?;
+    let Some(remote) = remote else {
+        return Ok(Some(DownloadEstimate {
+            bytes: 0,
+            detail: "local ref".to_string(),
+        }));
+    };
+    let options = glib::VariantDict::new(None);
+    options.insert_value("refs", &glib::Variant::from(vec![branch.as_str()]));
+    options.insert("dry-run", true);
+    options.insert("require-static-deltas", true);
+    let progress = ostree::AsyncProgress::new();
+    if repo
+        .pull_with_options(&remote, &options.end(), Some(&progress), gio::Cancellable::NONE)
+        .is_err()
+    {
+        return Ok(None);
+    }
+    Ok(Some(DownloadEstimate {
+        bytes: progress.uint64("total-delta-part-size"),
+        detail: "static delta".to_string(),
+    }))
+}
+
+pub async fn download_estimate(repo: &ostree::Repo, refspec: &str) -> Result<Option<DownloadEstimate>> {
+    if refspec.starts_with("ostree-") {
+        let imgref: OstreeImageReference = refspec.parse()?;
+        Ok(Some(image_estimate(repo, &imgref).await?))
+    } else {
+        delta_estimate(repo, refspec)
+    }
+}
+
+/// Pyta tylko na terminalu; w automatyzacji (bez TTY) odpowiedź to "tak"
+fn confirm(question: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Pobiera najnowszą wersję obrazu do repo i zwraca jej commit
+async fn pull_image(repo: &ostree::Repo, imgref: &OstreeImageReference) -> Result<String> {
+    use ostree_ext::container::store::{ImageImporter, PrepareResult};
+
+    crate::network::prepare_container_pull()?;
+    crate::network::with_retries_async(&format!("Pulling {}", imgref), || async {
+        let mut importer = ImageImporter::new(repo, imgref, Default::default()).await?;
+        let state = match importer.prepare().await? {
+            PrepareResult::AlreadyPresent(state) => state,
+            PrepareResult::Ready(prep) => {
+                println!("Pulling {}...", imgref);
+                importer.import(prep).await.with_context(|| format!("Importing {}", imgref))?
+            }
+        };
+        Ok(state.merge_commit.clone())
+    })
+    .await
+}
+
+/// Pobiera `branch` ze zdalnego (albo z `url` zamiast URL-a zdalnego) w transakcji repo,
+/// którą zatwierdza dopiero po sprawdzeniu podpisu pobranego commita (TRUSTED_ED25519)
+fn pull_verified(repo: &ostree::Repo, remote: &str, branch: &str, url: Option<&str>) -> Result<()> {
+    let cancellable = gio::Cancellable::NONE;
+    let options = glib::VariantDict::new(None);
+    options.insert_value("refs", &glib::Variant::from(vec![branch]));
+    if let Some(url) = url {
+        options.insert("override-url", url);
+    }
+    // Ref zdalnego zmienia się dopiero przy zatwierdzeniu transakcji
+    options.insert("inherit-transaction", true);
+    repo.prepare_transaction(cancellable)?;
+    let result = repo
+        .pull_with_options(remote, &options.end(), None, cancellable)
+        .map_err(anyhow::Error::new)
+        .and_then(|()| {
+            let refspec = format!("{}:{}", remote, branch);
+            let commit = repo
+                .resolve_rev(&refspec, false)?
+                .ok_or_else(|| anyhow!("{} not found after pull", refspec))?;
+            crate::commit_signing::verify_base(repo, &commit)
+        });
+    match result {
+        Ok(()) => {
+            repo.commit_transaction(cancellable)?;
+            Ok(())
+        }
+        Err(e) => {
+            repo.abort_transaction(cancellable)?;
+            Err(e)
+        }
+    }
+}
+
+/// Po nieudanym pullu z URL-a zdalnego próbuje kolejnych mirrorów z network.yaml;
+/// zwraca błąd `first_err`, jeśli mirrorów nie ma, albo ostatni, jeśli wszystkie zawiodły
+fn pull_from_mirrors(repo: &ostree::Repo, remote: &str, branch: &str, first_err: anyhow::Error) -> Result<()> {
+    let mut last_err = first_err;
+    for url in crate::network::config().mirrors(remote) {
+        eprintln!("Warning: {:#}; trying mirror {}", last_err, url);
+        // Tylko URL się zmienia; weryfikacja GPG/podpisów zostaje ze zdalnego
+        match pull_verified(repo, remote, branch, Some(url.as_str())) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_err = e.context(format!("Pulling {} from {}", branch, url)),
+        }
+    }
+    Err(last_err)
+}
+
+/// Pobiera najnowszy commit refa ze zdalnego
+fn pull_ref(repo: &ostree::Repo, refspec: &str) -> Result<String> {
+    let (remote, branch) = ostree::parse_refspec(refspec)?;
+    if let Some(remote) = remote.as_deref() {
+        println!("Pulling {}...", refspec);
+        let result = crate::network::with_retries(&format!("Pulling {}", refspec), || {
+            pull_verified(repo, remote, &branch, None).with_context(|| format!("Pulling {}", refspec))
+        });
+        if let Err(e) = result {
+            pull_from_mirrors(repo, remote, &branch, e)?;
+        }
+    }
+    let rev = repo
+        .resolve_rev(refspec, false)?
+        .ok_or_else(|| anyhow!("{} not found in repo after pull", refspec))?;
+    Ok(rev.to_string())
+}
+
+/// Najnowsza baza, która jest już w repo (z `--cache-only`)
+fn local_base(repo: &ostree::Repo, refspec: &str) -> Result<String> {
+    if refspec.starts_with("ostree-") {
+        let imgref: OstreeImageReference = refspec.parse()?;
+        let state = ostree_ext::container::store::query_image(repo, &imgref.imgref)?
+            .ok_or_else(|| anyhow!("{} has not been pulled yet (--cache-only)", imgref))?;
+        Ok(state.merge_commit.clone())
+    } else {
+        let rev = repo
+            .resolve_rev(refspec, false)?
+            .ok_or_else(|| anyhow!("{} has not been pulled yet (--cache-only)", refspec))?;
+        Ok(rev.to_string())
+    }
+}
+
+/// Pobiera bazę wskazaną przez refspec (ref ostree lub obraz `ostree-…`) i zwraca jej commit
+pub async fn pull_base(repo: &ostree::Repo, refspec: &str) -> Result<String> {
+    // Obrazy kontenerów weryfikuje polityka containers (`ostree-image-signed:`)
+    if refspec.starts_with("ostree-") {
+        if crate::network::cache_only() {
+            return local_base(repo, refspec);
+        }
+        let imgref: OstreeImageReference = refspec.parse()?;
+        return pull_image(repo, &imgref).await;
+    }
+    let commit = match crate::network::cache_only() {
+        true => local_base(repo, refspec)?,
+        false => pull_ref(repo, refspec)?,
+    };
+    // Pobrany commit sprawdził już `pull_verified`; to obejmuje też bazy z `--cache-only`
+    // i refy lokalne
+    crate::commit_signing::verify_base(repo, &commit)?;
+    Ok(commit)
+}
+
+pub async fn upgrade(opts: UpgradeOpts) -> Result<()> {
+    if opts.check {
+        return check().await;
+    }
+    let staged = crate::output::progress_async(stage_upgrade(&opts)).await?;
+    crate::layered_packages::emit_transaction_report(&load_sysroot()?, "upgrade", &[])?;
+    if staged {
+        maybe_reboot(&opts.reboot)?;
+    }
+    Ok(())
+}
+
+/// Pobiera nową bazę i stage'uje na niej warstwy; `false`, gdy nie było czego aktualizować
+async fn stage_upgrade(opts: &UpgradeOpts) -> Result<bool> {
+    let sysroot = load_sysroot()?;
+    let (booted, mut state) = pending_state(&sysroot)?;
+    let repo = sysroot.repo();
+
+    // Najpierw tanie sprawdzenie (summary albo manifest), żeby nie pytać o pobranie, gdy nie ma czego
+    let has_remote = state.base_refspec.starts_with("ostree-") || ostree::parse_refspec(&state.base_refspec)?.0.is_some();
+    if has_remote && !crate::network::cache_only() {
+        // Nieudane sprawdzenie nie blokuje upgrade — pull i tak ustali, czy jest coś nowego
+        match check_for_update().await {
+            Ok(check) if !check.available() => {
+                println!("No upgrade available");
+                return Ok(false);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: checking for an update failed, pulling anyway: {:#}", e),
+        }
+    }
+    if !opts.skip_size_check && !crate::network::cache_only() {
+        match download_estimate(&repo, &state.base_refspec).await? {
+            Some(estimate) => println!(
+                "Estimated download: {} ({})",
+                glib::format_size(estimate.bytes),
+                estimate.detail
+            ),
+            None => println!("Download size unknown (no static delta published for {})", state.base_refspec),
+        }
+        if !confirm("Download and stage the upgrade?")? {
+            println!("Upgrade deferred");
+            return Ok(false);
+        }
+    }
+
+    let new_base = pull_base(&repo, &state.base_refspec).await?;
+    if new_base == state.base_commit {
+        println!("No upgrade available");
+        return Ok(false);
+    }
+
+    println!("Upgrading base {} -> {}", state.base_commit, new_base);
+    if !state.downgraded.is_empty() {
+        println!(
+            "Keeping downgraded package(s) {} (`downgrade --reset` to follow the repositories)",
+            state.downgraded.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    state.base_commit = new_base;
+    crate::layered_packages::check_layers_on_base(&repo, &mut state, opts.drop_absorbed)?;
+    let layered: Vec<String> = state.layered_packages.iter().cloned().collect();
+    // Rebuild odtwarza na nowej bazie wszystko z LayeredState: pakiety, repo, pliki, jednostki
+    crate::history::record_transaction("upgrade", &layered, || {
+        // Pakiety z AUR są budowane od nowa, żeby linkowały się z bibliotekami nowej bazy
+        let stale_files = crate::aur::rebuild_all(&repo, &mut state, opts.update_aur)?;
+        deploy_layered_state(&sysroot, &booted, &state)?;
+        for file in stale_files {
+            let _ = std::fs::remove_file(crate::layered_packages::local_packages_dir().join(file));
+        }
+        Ok(())
+    })?;
+    Ok(true)
+}
@@ -0,0 +1,144 @@
+// Zawartość /var z pakietów odtwarzana przez tmpfiles.d
+//
+// /var nie jest częścią obrazu (ostree kopiuje go tylko przy pierwszym deploymencie,
+// a compose usuwa go całkiem), więc katalogi i pliki, które pakiety instalują w /var
+// według swojej bazy `files`, są odtwarzane przy starcie przez systemd-tmpfiles.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::os::unix::fs::MetadataExt;
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+
+use crate::composepost::BASE_SYMLINKS;
+
+pub const TMPFILES_DIR: &str = "usr/lib/tmpfiles.d";
+pub const TMPFILES_CONF: &str = "pacman-ostree-var.conf";
+/// Kopie plików z /var, z których `C` odtwarza je przy starcie
+pub const FACTORY_DIR: &str = "usr/share/factory";
+const LOCAL_DB_DIR: &str = "usr/share/pacman/local";
+
+/// Ścieżki pod var/ z baz `files` wszystkich pakietów (katalogi kończą się `/`)
+fn package_var_paths(rootfs: &Utf8Path) -> Result<BTreeSet<String>> {
+    let mut paths = BTreeSet::new();
+    let local_db = rootfs.join(LOCAL_DB_DIR);
+    if !local_db.exists() {
+        return Ok(paths);
+    }
+    for entry in local_db.read_dir_utf8()? {
+        let files = entry?.path().join("files");
+        let Ok(contents) = std::fs::read_to_string(&files) else {
+            continue;
+        };
+        let mut in_files = false;
+        for line in contents.lines() {
+            if line.starts_with('%') {
+                in_files = line == "%FILES%";
+                continue;
+            }
+            if in_files && line.starts_with("var/") && line != "var/" {
+                paths.insert(line.to_string());
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Ścieżki, które już deklaruje któryś plik tmpfiles.d w obrazie
+fn declared_paths(rootfs: &Utf8Path) -> Result<BTreeSet<String>> {
+    let mut paths = BTreeSet::new();
+    let dir = rootfs.join(TMPFILES_DIR);
+    if !dir.exists() {
+        return Ok(paths);
+    }
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        if entry.file_name() == TMPFILES_CONF || !entry.file_name().ends_with(".conf") {
+            continue;
+        }
+        let contents = std::fs::read_to_string(entry.path())?;
+        for line in contents.lines().filter(|l| !l.trim_start().starts_with('#')) {
+            if let Some(path) = line.split_whitespace().nth(1) {
+                paths.insert(path.trim_end_matches('/').to_string());
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Wpisy z poprzednio wygenerowanego pliku: ścieżka -> linia. Przy rebuildzie warstw /var
+/// checkoutu jest pusty, więc ścieżki pakietów bazy trzeba przenieść z pliku z compose.
+fn previous_entries(rootfs: &Utf8Path) -> BTreeMap<String, String> {
+    let Ok(contents) = std::fs::read_to_string(rootfs.join(TMPFILES_DIR).join(TMPFILES_CONF)) else {
+        return BTreeMap::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| Some((line.split_whitespace().nth(1)?.to_string(), line.to_string())))
+        .collect()
+}
+
+/// Nazwa użytkownika/grupy z passwd/group obrazu; liczba, jeśli jej tam nie ma
+fn id_name(rootfs: &Utf8Path, file: &str, id: u32) -> String {
+    ["etc", "usr/etc"]
+        .iter()
+        .filter_map(|dir| std::fs::read_to_string(rootfs.join(dir).join(file)).ok())
+        .find_map(|contents| {
+            contents.lines().find_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                (fields.get(2) == Some(&id.to_string().as_str())).then(|| fields[0].to_string())
+            })
+        })
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Zapisuje wpisy tmpfiles.d dla zawartości /var z pakietów; wywoływane,
+/// zanim /var zostanie odrzucony
+pub fn generate_var_tmpfiles(rootfs: &Utf8Path) -> Result<()> {
+    let declared = declared_paths(rootfs)?;
+    let previous = previous_entries(rootfs);
+    let mut conf = String::new();
+    let mut count = 0usize;
+
+    for path in package_var_paths(rootfs)? {
+        let rel = path.trim_end_matches('/');
+        let abs = format!("/{}", rel);
+        // Symlinki modelu ostree (np. /var/lib/pacman) tworzy compose
+        if declared.contains(&abs) || BASE_SYMLINKS.iter().any(|(_, dst)| *dst == rel) {
+            continue;
+        }
+        let full = rootfs.join(rel);
+        let Ok(meta) = full.symlink_metadata() else {
+            if let Some(line) = previous.get(&abs) {
+                writeln!(conf, "{}", line)?;
+                count += 1;
+            }
+            continue;
+        };
+        let user = id_name(rootfs, "passwd", meta.uid());
+        let group = id_name(rootfs, "group", meta.gid());
+
+        if meta.is_dir() {
+            writeln!(conf, "d {} {:04o} {} {} -", abs, meta.mode() & 0o7777, user, group)?;
+        } else if meta.is_symlink() {
+            writeln!(conf, "L {} - - - - {}", abs, full.read_link_utf8()?)?;
+        } else {
+            let factory = Utf8Path::new(FACTORY_DIR).join(rel);
+            let dest = rootfs.join(&factory);
+            std::fs::create_dir_all(dest.parent().unwrap())?;
+            std::fs::copy(&full, &dest).with_context(|| format!("Copying {} to {}", abs, factory))?;
+            writeln!(conf, "C {} - - - - /{}", abs, factory)?;
+        }
+        count += 1;
+    }
+
+    let dest = rootfs.join(TMPFILES_DIR).join(TMPFILES_CONF);
+    if conf.is_empty() {
+        let _ = std::fs::remove_file(&dest);
+        return Ok(());
+    }
+    std::fs::create_dir_all(rootfs.join(TMPFILES_DIR))?;
+    std::fs::write(&dest, conf).with_context(|| format!("Writing {}", dest))?;
+    println!("Generated tmpfiles.d entries for {} path(s) under /var", count);
+    Ok(())
+}
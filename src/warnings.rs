@@ -0,0 +1,53 @@
+// Ostrzeżenia compose zbierane do raportu buildu (`warnings` w JSON, `--max-warnings`)
+
+use std::sync::Mutex;
+use serde::Serialize;
+
+/// Kod wyjścia, gdy build się udał, ale ostrzeżeń jest więcej niż pozwala próg
+pub const EXIT_TOO_MANY_WARNINGS: i32 = 3;
+
+static WARNINGS: Mutex<Vec<ComposeWarning>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ComposeWarning {
+    /// Rodzaj problemu, stały dla danego miejsca w kodzie (np. `missing-script`)
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// Zapisuje ostrzeżenie bez wypisywania (gdy zostało już pokazane w inny sposób)
+pub fn record(kind: &'static str, message: impl Into<String>) {
+    if let Ok(mut warnings) = WARNINGS.lock() {
+        warnings.push(ComposeWarning {
+            kind,
+            message: message.into(),
+        });
+    }
+}
+
+/// Wypisuje ostrzeżenie i zapisuje je do raportu
+pub fn warn(kind: &'static str, message: impl Into<String>) {
+    let message = message.into();
+    eprintln!("Warning: {}", message);
+    record(kind, message);
+}
+
+pub fn collected() -> Vec<ComposeWarning> {
+    WARNINGS.lock().map(|w| w.clone()).unwrap_or_default()
+}
+
+/// Błąd zwracany przez compose po przekroczeniu `--max-warnings`
+#[derive(Debug)]
+pub struct TooManyWarnings {
+    pub count: usize,
+    pub max: usize,
+}
+
+impl std::fmt::Display for TooManyWarnings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} compose warnings exceed the limit of {}", self.count, self.max)
+    }
+}
+
+impl std::error::Error for TooManyWarnings {}